@@ -0,0 +1,140 @@
+#![feature(test)]
+
+extern crate test;
+
+extern crate atom;
+extern crate pi_vm;
+extern crate handler;
+extern crate gray;
+
+use std::thread;
+use std::sync::{Arc, RwLock};
+use std::collections::HashMap;
+
+use test::Bencher;
+
+use atom::Atom;
+use handler::{Handler, Args};
+use gray::GrayVersion;
+
+use pi_vm::adapter::JSType;
+use pi_vm::channel_map::VMChannelMap;
+
+//注册的处理器数量
+const HANDLER_COUNT: usize = 20000;
+//每次迭代的查询次数
+const LOOKUP_COUNT: usize = 10000;
+//并发查询的线程数
+const THREAD_COUNT: usize = 4;
+
+struct EmptyHandler;
+
+impl Handler for EmptyHandler {
+    type A = Arc<Vec<u8>>;
+    type B = Vec<JSType>;
+    type C = Option<u32>;
+    type D = ();
+    type E = ();
+    type F = ();
+    type G = ();
+    type H = ();
+    type HandleResult = ();
+
+    fn handle(&self, _env: Arc<dyn GrayVersion>, _name: Atom, _args: Args<Self::A, Self::B, Self::C, Self::D, Self::E, Self::F, Self::G, Self::H>) -> Self::HandleResult {}
+}
+
+//单锁通道表的查询，作为分片通道表的对比基准
+#[bench]
+fn single_lock_lookup(b: &mut Bencher) {
+    let (map, names) = init_single_lock();
+
+    b.iter(|| {
+        for i in 0..LOOKUP_COUNT {
+            let name = &names[i % HANDLER_COUNT];
+            assert!(map.read().unwrap().get(name).is_some());
+        }
+    });
+}
+
+//分片通道表的查询
+#[bench]
+fn sharded_lookup(b: &mut Bencher) {
+    let (map, names) = init_sharded();
+
+    b.iter(|| {
+        for i in 0..LOOKUP_COUNT {
+            assert!(map.get(&names[i % HANDLER_COUNT]).is_some());
+        }
+    });
+}
+
+//单锁通道表的并发查询
+#[bench]
+fn single_lock_concurrent_lookup(b: &mut Bencher) {
+    let (map, names) = init_single_lock();
+    let map = Arc::new(map);
+    let names = Arc::new(names);
+
+    b.iter(|| {
+        let mut handles = Vec::with_capacity(THREAD_COUNT);
+        for t in 0..THREAD_COUNT {
+            let map = map.clone();
+            let names = names.clone();
+            handles.push(thread::spawn(move || {
+                for i in 0..LOOKUP_COUNT {
+                    let name = &names[(i * THREAD_COUNT + t) % HANDLER_COUNT];
+                    assert!(map.read().unwrap().get(name).is_some());
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    });
+}
+
+//分片通道表的并发查询
+#[bench]
+fn sharded_concurrent_lookup(b: &mut Bencher) {
+    let (map, names) = init_sharded();
+    let map = Arc::new(map);
+    let names = Arc::new(names);
+
+    b.iter(|| {
+        let mut handles = Vec::with_capacity(THREAD_COUNT);
+        for t in 0..THREAD_COUNT {
+            let map = map.clone();
+            let names = names.clone();
+            handles.push(thread::spawn(move || {
+                for i in 0..LOOKUP_COUNT {
+                    assert!(map.get(&names[(i * THREAD_COUNT + t) % HANDLER_COUNT]).is_some());
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    });
+}
+
+fn init_names() -> Vec<Atom> {
+    (0..HANDLER_COUNT).map(|i| Atom::from(format!("bench_handler_{}", i))).collect()
+}
+
+fn init_single_lock() -> (RwLock<HashMap<Atom, Arc<EmptyHandler>>>, Vec<Atom>) {
+    let names = init_names();
+    let mut map = HashMap::new();
+    for name in &names {
+        map.insert(name.clone(), Arc::new(EmptyHandler));
+    }
+    (RwLock::new(map), names)
+}
+
+fn init_sharded() -> (VMChannelMap, Vec<Atom>) {
+    let names = init_names();
+    let map = VMChannelMap::new(0);
+    for name in &names {
+        map.set(name.clone(), Arc::new(EmptyHandler));
+    }
+    (map, names)
+}
@@ -0,0 +1,165 @@
+#![feature(test)]
+
+extern crate test;
+
+extern crate atom;
+extern crate pi_vm;
+extern crate worker;
+
+use std::sync::{Arc, Once};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use test::Bencher;
+
+use atom::Atom;
+
+use pi_vm::pi_vm_impl::{VMFactory, push_callback};
+use pi_vm::adapter::{register_native_object, dukc_pop, JS, JSType};
+use pi_vm::channel_map::{VMChannel, VMChannelMap};
+use pi_vm::bonmgr::NativeObjsAuth;
+use pi_vm::bundle::compile_source;
+use pi_vm::task_info::TaskInfo;
+use pi_vm::names::{FactoryName, PortName};
+
+use worker::worker_pool::WorkerPool;
+use worker::impls::{JS_WORKER_WALKER, JS_TASK_POOL};
+use worker::worker::WorkerType;
+
+//每次迭代的操作次数
+const OP_COUNT: usize = 1000;
+//转换的二进制数据大小
+const BINARY_SIZE: usize = 4096;
+
+static WORKER_POOL: Once = Once::new();
+
+//虚拟机工厂调用的派发延迟，包括任务派发、取出虚拟机、构建参数、调用和归还虚拟机
+#[bench]
+fn factory_call_dispatch(b: &mut Bencher) {
+    start_worker_pool();
+    let factory = create_factory("bench_factory_call_dispatch", "function call(x) { return x; }");
+    let port = PortName::new("call").unwrap();
+
+    b.iter(|| {
+        for _ in 0..OP_COUNT {
+            factory.call(None, port.clone(), Box::new(|vm: Arc<JS>| {
+                vm.new_u32(1);
+                1
+            }), TaskInfo::from("bench factory call")).unwrap();
+        }
+        while factory.in_flight() > 0 {}
+    });
+}
+
+//虚拟机池的取出和归还
+#[bench]
+fn pool_checkout(b: &mut Bencher) {
+    let factory = create_factory("bench_pool_checkout", "var x = 0;");
+
+    b.iter(|| {
+        for _ in 0..OP_COUNT {
+            let vm = factory.try_acquire().unwrap();
+            drop(vm); //归还虚拟机
+        }
+    });
+}
+
+//异步回调的推送和执行吞吐量
+#[bench]
+fn push_callback_throughput(b: &mut Bencher) {
+    start_worker_pool();
+    let js = create_js();
+    let done = Arc::new(AtomicUsize::new(0));
+
+    b.iter(|| {
+        done.store(0, Ordering::SeqCst);
+        for _ in 0..OP_COUNT {
+            let callback = js.eval("callbacks.register(function(x) { return x; })".to_string()).get_u32();
+            let done_copy = done.clone();
+            push_callback(js.clone(), callback, Box::new(move |vm: Arc<JS>| {
+                vm.new_u32(1);
+                done_copy.fetch_add(1, Ordering::SeqCst);
+                1
+            }), None, TaskInfo::from("bench push callback"));
+        }
+        while done.load(Ordering::SeqCst) < OP_COUNT {}
+    });
+}
+
+//JSON文本在虚拟机中的解码和编码
+#[bench]
+fn json_conversion(b: &mut Bencher) {
+    let js = create_js();
+    let json = format!("{{\"id\": 1, \"name\": \"bench\", \"tags\": [\"a\", \"b\", \"c\"], \"data\": [{}]}}", vec!["0.5"; 256].join(", "));
+    assert!(js.set_global_var("json".to_string(), js.new_str(json).unwrap()));
+
+    b.iter(|| {
+        for _ in 0..OP_COUNT / 10 {
+            let r = js.eval("JSON.stringify(JSON.parse(json)).length".to_string());
+            assert!(r.is_number());
+        }
+    });
+}
+
+//二进制数据与虚拟机Uint8Array之间的复制
+#[bench]
+fn binary_conversion(b: &mut Bencher) {
+    let js = create_js();
+    let bin = vec![0xff; BINARY_SIZE];
+
+    b.iter(|| {
+        for _ in 0..OP_COUNT {
+            let buffer = js.new_uint8_array(BINARY_SIZE as u32);
+            buffer.from_bytes(bin.as_slice());
+            assert_eq!(buffer.to_bytes().len(), BINARY_SIZE);
+            unsafe { dukc_pop(js.get_vm()); } //移除构建的Uint8Array
+        }
+    });
+}
+
+//通道请求的处理器路由
+#[bench]
+fn channel_request_routing(b: &mut Bencher) {
+    let js = create_js();
+    let map = VMChannelMap::new(0);
+    let names: Vec<Atom> = (0..100).map(|index| Atom::from(format!("bench_handler_{}", index))).collect();
+    for name in &names {
+        map.set_handler(name.clone(), Arc::new(|_: Arc<VMChannel>, _: Atom, _: Arc<Vec<u8>>, _: Vec<JSType>, _: Option<u32>| {}));
+    }
+    let msg = Arc::new(vec![0xff; 64]);
+
+    b.iter(|| {
+        for index in 0..OP_COUNT {
+            assert!(map.request(js.clone(), names[index % names.len()].clone(), msg.clone(), vec![], None));
+        }
+    });
+}
+
+//启动全局js工作者池，只启动一次
+fn start_worker_pool() {
+    WORKER_POOL.call_once(|| {
+        let worker_pool = Box::new(WorkerPool::new("Bench Dispatch".to_string(), WorkerType::Js, 2, 1024 * 1024, 100000, JS_WORKER_WALKER.clone()));
+        worker_pool.run(JS_TASK_POOL.clone());
+        Box::leak(worker_pool);
+    });
+}
+
+//构建并加载指定代码的虚拟机工厂，并生成一个虚拟机
+fn create_factory(name: &str, source: &str) -> VMFactory {
+    register_native_object();
+
+    let code = compile_source(&format!("{}.js", name), source).unwrap();
+    let factory = VMFactory::new(FactoryName::new(name).unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(code);
+    factory.produce(1).unwrap();
+    factory
+}
+
+//构建虚拟机
+fn create_js() -> Arc<JS> {
+    register_native_object();
+
+    if let Some(js) = JS::new(0, Atom::from("dispatch benches"), Arc::new(NativeObjsAuth::new(None, None)), None) {
+        return js;
+    }
+    panic!("!!!> Create Vm Error");
+}
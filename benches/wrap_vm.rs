@@ -30,6 +30,8 @@ use gray::{GrayVersion, Gray, GrayTab};
 use pi_vm::pi_vm_impl::{VMFactory, BlockError, block_set_global_var, block_reply, block_throw, push_callback, register_async_request, async_request};
 use pi_vm::adapter::{register_native_object, JS, JSType};
 use pi_vm::channel_map::VMChannel;
+use pi_vm::task_info::TaskInfo;
+use pi_vm::names::{FactoryName, PortName};
 use pi_vm::bonmgr::{BON_MGR, NativeObjsAuth, FnMeta, CallResult, ptr_jstype, jstype_ptr};
 
 use worker::task::TaskType;
@@ -265,7 +267,7 @@ fn js_sync_block_call_set_global_var_return(js: Arc<JS>, _args: Vec<JSType>) ->
                 let result = Box::new(|vm: Arc<JS>| {
                     vm.new_u32(0xffffffff);
                 });
-                block_reply(js, result, Atom::from("block reply task"));
+                block_reply(js, result, TaskInfo::from("block reply task"));
             },
             Err(BlockError::SetGlobalVar(name)) => {
                 panic!("!!!!!!set global var failed, name: {:?}", name);
@@ -276,7 +278,7 @@ fn js_sync_block_call_set_global_var_return(js: Arc<JS>, _args: Vec<JSType>) ->
         }
     });
 
-    block_set_global_var(js, "_$tmp_var".to_string(), var, next, Atom::from("js_sync_block_call_set_global_var_return task"));
+    block_set_global_var(js, "_$tmp_var".to_string(), var, next, TaskInfo::from("js_sync_block_call_set_global_var_return task"));
     None
 }
 
@@ -284,7 +286,7 @@ fn js_sync_block_call_return_small(js: Arc<JS>, _args: Vec<JSType>) -> Option<Ca
     let result = Box::new(|vm: Arc<JS>| {
         vm.new_u32(0xffffffff);
     });
-    block_reply(js, result, Atom::from("block reply task"));
+    block_reply(js, result, TaskInfo::from("block reply task"));
     None
 }
 
@@ -293,12 +295,12 @@ fn js_sync_block_call_return_big(js: Arc<JS>, _args: Vec<JSType>) -> Option<Call
         let buffer = vm.new_array_buffer(4096);
         buffer.from_bytes(BINARY.as_slice());
     });
-    block_reply(js, result, Atom::from("block reply task"));
+    block_reply(js, result, TaskInfo::from("block reply task"));
     None
 }
 
 fn js_sync_block_call_return_error(js: Arc<JS>, _args: Vec<JSType>) -> Option<CallResult> {
-    block_throw(js, "What is Error?".to_string(), Atom::from("block throw task"));
+    block_throw(js, "What is Error?".to_string(), TaskInfo::from("block throw task"));
     None
 }
 
@@ -346,7 +348,7 @@ fn js_async_callback_register_push(js: Arc<JS>, args: Vec<JSType>) -> Option<Cal
         vm.new_u32(callback);
         3
     });
-    push_callback(js.clone(), args[0].get_u32(), func, None, Atom::from("register callback task"));
+    push_callback(js.clone(), args[0].get_u32(), func, None, TaskInfo::from("register callback task"));
     js.new_boolean(true);
     Some(CallResult::Ok)
 }
@@ -576,7 +578,7 @@ fn init_async_request_env(port: &str, file: &str) {
                 ptr_jstype(vm.get_objs(), vm.clone(), ptr, 3366364668);
                 6
             });
-            gray.factory.call(None, Atom::from("_$async"), real_args, Atom::from((*name).to_string() + " rpc task"));
+            gray.factory.call(None, PortName::new("_$async").unwrap(), real_args, TaskInfo::from((*name).to_string() + " rpc task")).unwrap();
         }
     }
 
@@ -589,7 +591,7 @@ fn init_async_request_env(port: &str, file: &str) {
         }
     }
 
-    let mut factory = VMFactory::new("wrap vm benches", 1000, 1000, 8388608, 67108864, Arc::new(NativeObjsAuth::new(None, None)));
+    let mut factory = VMFactory::new(FactoryName::new("wrap vm benches").unwrap(), 1000, 1000, 8388608, 67108864, Arc::new(NativeObjsAuth::new(None, None)));
     let js = JS::new(0, Atom::from("wrap vm benches"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
     let file_name = &String::from("benches/core.js");
     if let Ok(mut file) = File::open("benches/core.js") {
@@ -0,0 +1,120 @@
+extern crate cc;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/*
+* 虚拟机库构建脚本
+* 默认链接预编译的dukc库，库目录优先使用目标平台专用的环境变量DUKC_LIB_DIR_<TARGET>，例如DUKC_LIB_DIR_AARCH64_LINUX_ANDROID，
+* 其次使用DUKC_LIB_DIR；设置DUKC_SRC_DIR时，使用目标平台的Duktape编译选项从源码构建静态dukc库，交叉编译时由cc使用目标平台的编译器
+*/
+fn main() {
+    let target = env::var("TARGET").unwrap();
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    let target_key = target.to_uppercase().replace('-', "_");
+
+    println!("cargo:rerun-if-env-changed=DUKC_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=DUKC_LIB_DIR_{}", target_key);
+    println!("cargo:rerun-if-env-changed=DUKC_SRC_DIR");
+    println!("cargo:rerun-if-env-changed=DUKC_CFLAGS");
+    println!("cargo:rerun-if-env-changed=DUKC_STATIC");
+
+    let lowmem = env::var("CARGO_FEATURE_LOWMEM").is_ok();
+    if lowmem && env::var("DUKC_SRC_DIR").is_err() {
+        //预编译的dukc库没有使用低内存编译选项，链接后虚拟机堆的布局与低内存配置不一致
+        panic!("the lowmem feature requires building dukc from source, set DUKC_SRC_DIR to the dukc source dir");
+    }
+
+    let flags = duk_flags(&arch, &os, lowmem);
+    let build = match env::var("DUKC_SRC_DIR") {
+        Ok(dir) => {
+            build_from_source(PathBuf::from(dir), &flags);
+            "source"
+        },
+        Err(_) => {
+            let lib_dir = env::var(format!("DUKC_LIB_DIR_{}", target_key)).or_else(|_| env::var("DUKC_LIB_DIR"));
+            if let Ok(dir) = lib_dir {
+                println!("cargo:rustc-link-search=native={}", dir);
+            }
+            if env::var("DUKC_STATIC").is_ok() {
+                println!("cargo:rustc-link-lib=static=dukc");
+            }
+            "prebuilt"
+        },
+    };
+
+    if os == "android" {
+        println!("cargo:rustc-link-lib=log"); //安卓平台的虚拟机库使用系统日志输出
+    }
+
+    //记录构建信息，用于运行时的能力报告
+    println!("cargo:rustc-env=PI_VM_TARGET={}", target);
+    println!("cargo:rustc-env=PI_VM_DUKC_BUILD={}", build);
+    println!("cargo:rustc-env=PI_VM_DUKC_FLAGS={}", flags.join(" "));
+}
+
+//获取目标平台的Duktape编译选项
+fn duk_flags(arch: &str, os: &str, lowmem: bool) -> Vec<String> {
+    let mut flags = Vec::new();
+    match arch {
+        "aarch64" => {
+            flags.push("DUK_USE_ALIGN_8".to_string());
+        },
+        "arm" => {
+            //32位ARM需要对齐访问，并使用压缩的值槽减少内存占用
+            flags.push("DUK_USE_ALIGN_4".to_string());
+            flags.push("DUK_USE_PACKED_TVAL".to_string());
+        },
+        _ => (),
+    }
+
+    if os == "android" {
+        flags.push("DUK_USE_DATE_NOW_GETTIMEOFDAY".to_string());
+    }
+
+    if lowmem {
+        //低内存配置，同时导出低内存虚拟机堆的构建接口，压缩的堆指针由dukc源码中低内存堆的分配器编码和解码
+        flags.push("DUKC_LOWMEM".to_string());
+        flags.push("DUK_USE_REFCOUNT16".to_string());
+        flags.push("DUK_USE_STRHASH16".to_string());
+        flags.push("DUK_USE_STRLEN16".to_string());
+        flags.push("DUK_USE_BUFLEN16".to_string());
+        flags.push("DUK_USE_OBJSIZES16".to_string());
+        flags.push("DUK_USE_HEAPPTR16".to_string());
+        flags.push("DUK_USE_HEAPPTR_ENC16(ud,p)=dukc_heapptr_enc16((ud),(p))".to_string());
+        flags.push("DUK_USE_HEAPPTR_DEC16(ud,x)=dukc_heapptr_dec16((ud),(x))".to_string());
+        if !flags.iter().any(|flag| flag == "DUK_USE_PACKED_TVAL") {
+            flags.push("DUK_USE_PACKED_TVAL".to_string());
+        }
+    }
+
+    flags
+}
+
+//使用指定编译选项从源码构建静态dukc库
+fn build_from_source(dir: PathBuf, flags: &[String]) {
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    let mut build = cc::Build::new();
+    build.include(&dir).warnings(false).opt_level(2);
+    for entry in fs::read_dir(&dir).expect("read dukc source dir failed") {
+        let path = entry.expect("read dukc source file failed").path();
+        if path.extension().map(|ext| ext == "c").unwrap_or(false) {
+            build.file(path);
+        }
+    }
+    for flag in flags {
+        //带值的编译选项格式为名称=值
+        let mut parts = flag.splitn(2, '=');
+        let name = parts.next().unwrap();
+        build.define(name, parts.next());
+    }
+    if let Ok(cflags) = env::var("DUKC_CFLAGS") {
+        for flag in cflags.split_whitespace() {
+            build.flag(flag);
+        }
+    }
+    build.compile("dukc");
+}
@@ -1,7 +1,7 @@
 use libc::{c_void as c_void_ptr, c_uchar, c_char, c_int, size_t, c_double, memcpy};
 use std::slice::{from_raw_parts_mut, from_raw_parts};
 use std::sync::atomic::{Ordering, AtomicUsize, AtomicIsize, AtomicI32, AtomicBool};
-use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::fmt::{self, Debug, Formatter, Result as FmtResult};
 use std::string::FromUtf8Error;
 use std::ffi::{CStr, CString};
 use std::collections::{VecDeque, HashMap};
@@ -10,7 +10,7 @@ use std::time::{Duration, SystemTime, Instant};
 use std::cell::RefCell;
 use std::sync::{Arc, Mutex, RwLock};
 use std::ops::Drop;
-use std::thread;
+use std::thread::{self, ThreadId};
 
 #[cfg(not(unix))]
 use kernel32;
@@ -20,7 +20,7 @@ use rand::{Rng, SeedableRng};
 use rand::rngs::SmallRng;
 
 use worker::task::TaskType;
-use worker::impls::{create_js_task_queue, js_static_sync_task_size, js_dyn_sync_task_size, js_static_async_task_size, js_dyn_async_task_size, lock_js_task_queue, unlock_js_task_queue, cast_js_task, cast_js_delay_task};
+use worker::impls::{create_js_task_queue, js_static_sync_task_size, js_dyn_sync_task_size, js_static_async_task_size, js_dyn_async_task_size, lock_js_task_queue, unlock_js_task_queue, cast_js_task, cast_js_delay_task, remove_js_task_queue};
 use apm::common::SysStat;
 use apm::allocator::{VM_ALLOCATED, get_max_alloced_limit, is_alloced_limit, vm_alloced_size, all_alloced_size};
 use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter, PrefTimer};
@@ -30,7 +30,15 @@ use lfstack::{CollectResult, LFStack};
 
 use native_object_impl::*;
 use bonmgr::{NativeObjs, NObject, NativeObjsAuth};
-use pi_vm_impl::VMFactory;
+use pi_vm_impl::{VMFactory, CallReport, LateCallbackPolicy, default_task_priority};
+use native_bind::{register_builtin_natives, js_string};
+use task_info::{TaskInfo, CATCH_THROW_TASK};
+use bytecode::{check_bytecode, duk_version};
+use vm_registry::unregister_vm;
+use health::{lock_state, read_state, write_state};
+use maintenance::cast_maintenance_task;
+use quota::QuotaPermit;
+use thread_audit::{VmBorrow, VmBorrowGuard, borrow_vm};
 
 /*
 * 多余的空闲内存上限，单位B，默认512MB
@@ -42,6 +50,16 @@ pub const FREE_SYSTEM_MEMORY_MAX_LIMIT: u64 = 536870912;
 */
 const JS_ASYNC_MSG_QUEUE_PRIORITY: usize = 1000;
 
+/*
+* 虚拟机垃圾回收时压缩堆的标记
+*/
+const DUK_GC_COMPACT: u32 = 1;
+
+/*
+* 虚拟机全局状态快照的魔数，快照的格式为魔数和全局状态的JSON
+*/
+pub const VM_STATE_MAGIC: &'static [u8] = b"PIVMST\0";
+
 /*
 * 虚拟机线程全局变量名
 */
@@ -50,6 +68,10 @@ const JS_THREAD_GLOBAL_VAR_NAME: &'static str = "__curr_block_thread";
 lazy_static! {
     //虚拟机超时时长，单位us, 默认5分钟
     static ref VM_TIMEOUT: AtomicUsize = AtomicUsize::new(300000000);
+    //是否已构建虚拟机堆
+    static ref VM_HEAP_CREATED: AtomicBool = AtomicBool::new(false);
+    //所有虚拟机共享的只读字符串表，设置后不会释放，以保证虚拟机库可以一直访问
+    static ref VM_PINNED_STRINGS: Mutex<Option<Vec<CString>>> = Mutex::new(None);
     //虚拟机工厂注册表
     pub static ref VM_FACTORY_REGISTERS: Arc<RwLock<HashMap<String, VMFactory>>> = Arc::new(RwLock::new(HashMap::new()));
     //虚拟机整理队列
@@ -67,8 +89,42 @@ lazy_static! {
     static ref VM_FINISH_TASK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_finish_task_count"), 0).unwrap();
     //虚拟机弹出异步回调的数量
     static ref VM_POP_CALLBACK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_pop_callback_count"), 0).unwrap();
+    //虚拟机拒绝执行动态代码的数量
+    static ref VM_EVAL_DENY_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_eval_deny_count"), 0).unwrap();
+    //虚拟机被中断执行的数量
+    static ref VM_INTERRUPT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_interrupt_count"), 0).unwrap();
+    //虚拟机调用燃料耗尽的数量
+    static ref VM_FUEL_EXHAUSTED_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_fuel_exhausted_count"), 0).unwrap();
+    //虚拟机被终止执行的数量
+    static ref VM_TERMINATE_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_terminate_count"), 0).unwrap();
+    //虚拟机销毁时取消的等待执行任务数量
+    static ref VM_CANCEL_TASK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_cancel_task_count"), 0).unwrap();
+    //虚拟机取消等待执行的回调数量
+    static ref VM_CANCEL_CALLBACK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_cancel_callback_count"), 0).unwrap();
+    //虚拟机显式销毁数量
+    static ref VM_DESTROY_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_destroy_count"), 0).unwrap();
+    //虚拟机达到回收策略限制后被丢弃的数量
+    static ref VM_EXPIRE_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_expire_count"), 0).unwrap();
+    //虚拟机堆超过高水位后被替换的数量
+    static ref VM_HIGH_WATER_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_high_water_count"), 0).unwrap();
+    //虚拟机分片回调的剩余分片重新加入消息队列的次数
+    static ref VM_SLICE_REQUEUE_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_slice_requeue_count"), 0).unwrap();
+    //虚拟机暂存缓冲区被占用时重新分配的次数
+    static ref VM_SCRATCH_ALLOC_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_scratch_alloc_count"), 0).unwrap();
+    //虚拟机重置全局环境后，拒绝执行重置前推送的异步回调的次数
+    static ref VM_LATE_CALLBACK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_late_callback_count"), 0).unwrap();
 }
 
+/*
+* 虚拟机暂存缓冲区在使用后保留的最大容量，超过则释放，以避免偶尔的大参数长期占用内存
+*/
+const MAX_SCRATCH_CAPACITY: usize = 64 * 1024;
+
+/*
+* 单次调用允许记录的最大检查点数量，超过则忽略，以避免循环内记录检查点时无限增长
+*/
+pub const MAX_CALL_CHECKPOINTS: usize = 64;
+
 #[link(name = "dukc")]
 extern "C" {
     fn dukc_manual_free() -> c_int;
@@ -140,6 +196,71 @@ extern "C" {
     fn dukc_vm_destroy(vm: *const c_void_ptr);
 }
 
+#[cfg(feature = "evalcheck")]
+#[link(name = "dukc")]
+extern "C" {
+    fn dukc_register_eval_check(func: extern fn(*const c_void_ptr, c_int, *const c_char) -> c_int);
+}
+
+#[cfg(feature = "interruptcheck")]
+#[link(name = "dukc")]
+extern "C" {
+    fn dukc_register_interrupt_check(func: extern fn(*const c_void_ptr) -> c_int);
+}
+
+#[cfg(feature = "vmgc")]
+#[link(name = "dukc")]
+extern "C" {
+    fn dukc_vm_gc(vm: *const c_void_ptr, flags: u32);
+}
+
+#[cfg(feature = "stacklimit")]
+#[link(name = "dukc")]
+extern "C" {
+    fn dukc_vm_set_stack_limit(vm: *const c_void_ptr, limit: u32);
+}
+
+#[cfg(feature = "pinnedstrings")]
+#[link(name = "dukc")]
+extern "C" {
+    fn dukc_set_pinned_strings(strings: *const *const c_char, len: u32) -> u32;
+}
+
+#[cfg(feature = "lowmem")]
+#[link(name = "dukc")]
+extern "C" {
+    fn dukc_heap_create_lowmem() -> *const c_void_ptr;
+}
+
+#[cfg(feature = "pipelined")]
+#[link(name = "dukc")]
+extern "C" {
+    fn dukc_wakeup_switch(vm: *const c_void_ptr, old_status: i8, new_status: i8, error: c_int) -> i8;
+    fn dukc_vm_status_batch(vms: *const *const c_void_ptr, len: u32, status: *mut i8);
+}
+
+#[cfg(feature = "nativelimit")]
+#[link(name = "dukc")]
+extern "C" {
+    fn dukc_vm_set_native_limit(vm: *const c_void_ptr, limit: u32);
+}
+
+#[cfg(feature = "funcdump")]
+#[link(name = "dukc")]
+extern "C" {
+    fn dukc_dump_function(vm: *const c_void_ptr, file: *const c_char, source: *const c_char, len: *mut u32) -> *const u8;
+    fn dukc_dump_function_free(code: *const u8);
+    fn dukc_load_function(vm: *const c_void_ptr, code: *const u8, len: u32) -> i32;
+}
+
+#[cfg(feature = "snapshot")]
+#[link(name = "dukc")]
+extern "C" {
+    fn dukc_heap_snapshot(vm: *const c_void_ptr, len: *mut u32) -> *const u8;
+    fn dukc_heap_snapshot_free(snapshot: *const u8);
+    fn dukc_heap_restore(vm: *const c_void_ptr, snapshot: *const u8, len: u32) -> c_int;
+}
+
 #[cfg(all(feature="unstable", any(target_arch = "x86", target_arch = "x86_64")))]
 #[inline(always)]
 pub fn pause() {
@@ -182,13 +303,28 @@ pub extern "C" fn js_reply_callback(handler: *const c_void_ptr, status: c_int, e
     unsafe {
         js = JS::from_raw(handler);
         vm = js.get_vm();
+        js.end_run(); //累计本次在虚拟机内的执行时长
 
         //处理执行异常
         if status != 0 {
             //有异常，则重置虚拟机线程全局变量，保证虚拟机可以继续运行
             VM_RUN_PANIC_COUNT.sum(1);
+            js.stat.errors.fetch_add(1, Ordering::Relaxed);
 
-            let error_info = CStr::from_ptr(err as *const c_char).to_string_lossy().into_owned();
+            let mut error_info = CStr::from_ptr(err as *const c_char).to_string_lossy().into_owned();
+            if let Some(reason) = js.interrupted() {
+                //被中断执行的异常，则在异常信息中记录中断原因
+                error_info = format!("vm interrupted, reason: {:?}, err: {}", reason, error_info);
+            }
+            js.set_finish_error(error_info.clone());
+            *lock_state("vm_last_error", &js.last_error) = Some(error_info.clone());
+            if is_oom_error(&error_info) {
+                //内存不足的异常，则通知虚拟机工厂，由虚拟机工厂决定虚拟机的处理方式
+                if let Some(factory) = js.get_factory() {
+                    let port = read_state("vm_call_stat", &js.stat.port).as_ref().map(|port| port.to_string());
+                    factory.notify_oom(&js, port, error_info.clone());
+                }
+            }
             match js.catcher.load(Ordering::Relaxed) {
                 catcher if catcher < 0 => {
                     //没有设置异常捕获回调
@@ -201,12 +337,13 @@ pub extern "C" fn js_reply_callback(handler: *const c_void_ptr, status: c_int, e
                         vm_arg.new_str(error_info);
                         1
                     });
-                    JS::push(js.clone(), TaskType::Sync(true), catcher as u32, args, Atom::from("js catch throw task"));
+                    JS::push(js.clone(), TaskType::Sync(true), catcher as u32, args, TaskInfo::from(&*CATCH_THROW_TASK));
                 }
             }
         }
 
         js.update_last_heap_size(); //在js当前任务执行完成后，更新虚拟机堆大小和内存占用
+        js.finish_task(); //在js当前任务执行完成后，清除正在执行的任务
         js.queue.size.fetch_sub(1, Ordering::SeqCst); //减少消息队列长度
         if dukc_vm_status_check(vm, JSStatus::WaitBlock as i8) > 0 {
             //当前虚拟机任务已执行完成且当前虚拟机状态是等待状态，则需要改变状态，保证虚拟机异步任务被执行
@@ -218,6 +355,9 @@ pub extern "C" fn js_reply_callback(handler: *const c_void_ptr, status: c_int, e
             if js.ret.borrow().is_some() {
                 *js.ret.borrow_mut() = js.stack_top_string(); //返回值缓存不为空，则将当前执行结果更新返回值缓存
             }
+            if js.ret_value.borrow().is_some() {
+                *js.ret_value.borrow_mut() = Some(js.stack_top_value()); //返回值缓存不为空，则将当前执行结果转换后更新返回值缓存
+            }
             dukc_pop(vm); //移除上次同步任务、异步任务或回调函数的执行结果
             handle_async_callback(js.clone(), vm);
 
@@ -230,6 +370,61 @@ pub extern "C" fn js_reply_callback(handler: *const c_void_ptr, status: c_int, e
     Arc::into_raw(js);
 }
 
+/*
+* js动态代码检查回调函数，在执行eval或new Function前调用，返回0表示禁止执行，返回1表示允许执行
+*/
+#[no_mangle]
+pub extern "C" fn js_eval_check(handler: *const c_void_ptr, kind: c_int, source: *const c_char) -> c_int {
+    if handler.is_null() {
+        //未绑定的虚拟机，则允许执行
+        return 1;
+    }
+
+    let js = unsafe { JS::from_raw(handler) };
+    let kind = if kind == DynamicCodeKind::Function as c_int {
+        DynamicCodeKind::Function
+    } else {
+        DynamicCodeKind::Eval
+    };
+    let source = if source.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(source).to_string_lossy().into_owned() }
+    };
+
+    let result = if js.check_eval(kind, &source) {
+        1
+    } else {
+        VM_EVAL_DENY_COUNT.sum(1);
+        warn!("!!!> JS Eval Denied, vm: {:?}, kind: {:?}", js, kind);
+        0
+    };
+    Arc::into_raw(js);
+    result
+}
+
+/*
+* js中断检查回调函数，在虚拟机执行字节码时周期性调用，每次调用消耗1单位燃料，返回1表示中断当前执行，中断后虚拟机会抛出异常
+*/
+#[no_mangle]
+pub extern "C" fn js_interrupt_check(handler: *const c_void_ptr) -> c_int {
+    if handler.is_null() {
+        //未绑定的虚拟机，则不中断
+        return 0;
+    }
+
+    let js = unsafe { JS::from_raw(handler) };
+    js.consume_fuel();
+    let result = if js.interrupted().is_some() {
+        VM_INTERRUPT_COUNT.sum(1);
+        1
+    } else {
+        0
+    };
+    Arc::into_raw(js);
+    result
+}
+
 /*
 * 处理异步回调，只有虚拟机当前同步任务、异步任务或异步回调已执行完成，才允许开始处理其它异步回调，特别的如果正在处理异步任务时，调用任何关于异步回调的非安全函数，都会导致异常
 */
@@ -269,6 +464,7 @@ pub unsafe fn handle_async_callback(js: Arc<JS>, vm: *const c_void_ptr) {
         is_collect = true;
     }
 
+    js.thread_borrow.hand_off(); //本次执行完成，当前线程交出虚拟机，之后虚拟机可以被其它线程访问
     if js.exist_tasks() {
         //解锁当前虚拟机锁住的同步任务队列, 保证当前虚拟机回收或其它虚拟机执行下一个任务
         let tasks = js.get_tasks();
@@ -281,8 +477,24 @@ pub unsafe fn handle_async_callback(js: Arc<JS>, vm: *const c_void_ptr) {
     js.update_last_time();
 
     if is_collect {
-        //当前虚拟机可以整理
+        //当前虚拟机可以整理，则先提交本次调用的资源使用报告
+        let factory = if js.report_call() { js.get_factory() } else { None };
+        let finish_value = js.take_finish_value();
+        let finish = js.take_finish();
         collect_vm(js);
+
+        if let Some((callback, result)) = finish {
+            //在整理虚拟机后，通知调用完成，以保证后续调用可以复用当前虚拟机
+            callback(result);
+        }
+        if let Some((callback, result)) = finish_value {
+            callback(result);
+        }
+
+        if let Some(factory) = factory {
+            //在通知调用完成后，完成虚拟机工厂已接收的调用
+            factory.complete_call();
+        }
     }
 }
 
@@ -297,6 +509,43 @@ pub fn now_utc() -> usize {
 
 //整理虚拟机，处理虚拟机丢弃和复用
 fn collect_vm(js: Arc<JS>) {
+    if js.is_destroyed() {
+        //已显式销毁的虚拟机，已在销毁时处理虚拟机工厂的统计
+        return;
+    }
+    js.served.fetch_add(1, Ordering::Relaxed); //增加虚拟机已完成的调用次数
+
+    if let Some(reason) = js.interrupted() {
+        //被中断执行的虚拟机的执行状态不可信，则标记为等待丢弃
+        warn!("!!!> Vm Interrupted, vm will be thrown, vm: {:?}, reason: {:?}", js, reason);
+        js.wait_throw.store(true, Ordering::Relaxed);
+    } else if let Some(reason) = js.recycle_requested() {
+        //虚拟机请求回收，则标记为等待丢弃
+        info!("===> Vm Recycle, vm will be thrown, vm: {:?}, reason: {:?}", js, reason);
+        js.wait_throw.store(true, Ordering::Relaxed);
+    } else if js.get_factory().map_or(false, |factory| factory.is_stale(&js)) {
+        //虚拟机使用旧字节码，则标记为等待丢弃
+        info!("===> Vm Stale, vm will be thrown, vm: {:?}, generation: {}", js, js.code_generation());
+        js.wait_throw.store(true, Ordering::Relaxed);
+    } else if let Some(reason) = js.get_factory().and_then(|factory| factory.expired(&js)) {
+        //虚拟机达到虚拟机工厂回收策略的限制，则标记为等待丢弃
+        info!("===> Vm Expired, vm will be thrown, vm: {:?}, reason: {:?}", js, reason);
+        VM_EXPIRE_COUNT.sum(1);
+        js.wait_throw.store(true, Ordering::Relaxed);
+    } else if let Some(factory) = js.get_factory().filter(|factory| factory.over_high_water(&js)) {
+        //虚拟机调用后的堆超过虚拟机工厂的高水位，则标记为等待丢弃，并在后台生成新的虚拟机替换
+        warn!("!!!> Vm Heap Over High Water, vm will be replaced, vm: {:?}, heap: {}, high water: {}",
+              js, js.last_heap_size(), factory.heap_high_water());
+        VM_HIGH_WATER_COUNT.sum(1);
+        js.wait_throw.store(true, Ordering::Relaxed);
+        factory.replenish(1);
+    } else if js.get_factory().map_or(false, |factory| factory.late_callback_policy() == LateCallbackPolicy::Throw)
+        && !lock_state("vm_pending_callbacks", &js.pending_callbacks).is_empty() {
+        //虚拟机有未执行的异步回调，且虚拟机工厂要求异步回调在原全局环境中执行，则标记为等待丢弃，不重置全局环境
+        info!("===> Vm Has Pending Callbacks, vm will be thrown, vm: {:?}, callbacks: {}", js, lock_state("vm_pending_callbacks", &js.pending_callbacks).len());
+        js.wait_throw.store(true, Ordering::Relaxed);
+    }
+
     if js.wait_throw.load(Ordering::Relaxed) {
         //丢弃标记为等待丢弃的虚拟机
         if let Some((lock, factory)) = js.collection.clone() {
@@ -346,10 +595,12 @@ fn collect_vm(js: Arc<JS>) {
                             js.queue.size.store(0, Ordering::Relaxed); //重置虚拟机当前消息队列
                             factory.reuse(js); //复用当前虚拟机
                         } else {
+                            factory.add_poison_count();
                             warn!("!!!> Vm Collection Error, vm: {:?}, e: alloc global failed", copy);
                         }
                     } else {
                         //复用预处理失败，则立即丢弃当前虚拟机
+                        factory.add_poison_count();
                         warn!("!!!> Vm Collection Error, vm: {:?}, e: clear global failed", copy);
                     }
                 }
@@ -366,8 +617,29 @@ pub fn register_native_object() {
         dukc_register_native_object_function_call(native_object_function_call);
         dukc_register_native_object_free(native_object_function_free);
     }
+    register_eval_check();
+    register_interrupt_check();
+    register_builtin_natives();
+}
+
+//注册动态代码执行检查回调，当前构建不支持则忽略，虚拟机库不会检查动态代码执行策略
+#[cfg(feature = "evalcheck")]
+fn register_eval_check() {
+    unsafe { dukc_register_eval_check(js_eval_check); }
 }
 
+#[cfg(not(feature = "evalcheck"))]
+fn register_eval_check() {}
+
+//注册中断检查回调，当前构建不支持则忽略，虚拟机执行时不会检查中断请求和消耗燃料
+#[cfg(feature = "interruptcheck")]
+fn register_interrupt_check() {
+    unsafe { dukc_register_interrupt_check(js_interrupt_check); }
+}
+
+#[cfg(not(feature = "interruptcheck"))]
+fn register_interrupt_check() {}
+
 /*
 * 执行njsc测试代码
 */
@@ -390,6 +662,7 @@ pub fn load_lib_backtrace() {
 /*
 * js状态
 */
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum JSStatus {
     Destroy = -1,
     NoTask,
@@ -399,6 +672,347 @@ pub enum JSStatus {
     WaitCallBack,
 }
 
+impl JSStatus {
+    //从虚拟机状态值构建js状态，未知的状态值返回None
+    pub fn from_raw(status: i8) -> Option<Self> {
+        match status {
+            -1 => Some(JSStatus::Destroy),
+            0 => Some(JSStatus::NoTask),
+            1 => Some(JSStatus::SingleTask),
+            2 => Some(JSStatus::MultiTask),
+            3 => Some(JSStatus::WaitBlock),
+            4 => Some(JSStatus::WaitCallBack),
+            _ => None,
+        }
+    }
+}
+
+//批量获取指定虚拟机的状态，已销毁的虚拟机不会访问虚拟机库，用于监控时扫描大量虚拟机，启用pipelined特性时在一次调用中获取所有虚拟机的状态
+pub fn vm_status_batch(vms: &[Arc<JS>]) -> Vec<Option<JSStatus>> {
+    let mut status = vec![JSStatus::Destroy as i8; vms.len()];
+    let alive: Vec<usize> = (0..vms.len()).filter(|index| !vms[*index].is_destroyed()).collect();
+    if !alive.is_empty() {
+        let ptrs: Vec<*const c_void_ptr> = alive.iter().map(|index| unsafe { vms[*index].get_vm() }).collect();
+        let mut alive_status = vec![JSStatus::Destroy as i8; alive.len()];
+        status_batch(&ptrs, &mut alive_status);
+        for (index, s) in alive.iter().zip(alive_status.into_iter()) {
+            status[*index] = s;
+        }
+    }
+
+    status.into_iter().map(JSStatus::from_raw).collect()
+}
+
+#[cfg(feature = "pipelined")]
+fn status_batch(vms: &[*const c_void_ptr], status: &mut [i8]) {
+    unsafe { dukc_vm_status_batch(vms.as_ptr(), vms.len() as u32, status.as_mut_ptr()); }
+}
+
+//未启用pipelined特性时，逐个获取虚拟机状态，使用状态值不变的交换在一次调用中读取当前状态
+#[cfg(not(feature = "pipelined"))]
+fn status_batch(vms: &[*const c_void_ptr], status: &mut [i8]) {
+    for (vm, s) in vms.iter().zip(status.iter_mut()) {
+        *s = unsafe { dukc_vm_status_switch(*vm, JSStatus::NoTask as i8, JSStatus::NoTask as i8) };
+    }
+}
+
+/*
+* 动态代码类型
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DynamicCodeKind {
+    Eval = 0,   //eval
+    Function,   //new Function
+}
+
+/*
+* 虚拟机中断原因
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InterruptReason {
+    Timeout = 1,        //调用执行超时
+    FuelExhausted = 2,  //调用执行燃料耗尽
+    Canceled = 3,       //调用被取消
+    Terminated = 4,     //虚拟机被终止执行
+}
+
+impl InterruptReason {
+    //从中断原因值构建中断原因，为0或未知值表示未中断
+    fn from_usize(reason: usize) -> Option<Self> {
+        match reason {
+            1 => Some(InterruptReason::Timeout),
+            2 => Some(InterruptReason::FuelExhausted),
+            3 => Some(InterruptReason::Canceled),
+            4 => Some(InterruptReason::Terminated),
+            _ => None,
+        }
+    }
+}
+
+/*
+* 虚拟机错误
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmError {
+    Invalid,    //虚拟机无效
+    Busy(i8),   //虚拟机正在执行任务，无法销毁，(虚拟机当前状态)
+    Stale(usize, usize),    //虚拟机已被回收或销毁，持有的虚拟机引用已过期，(持有的代数, 虚拟机当前代数)
+    Unsupported(&'static str),  //当前构建未启用指定特性，链接的虚拟机库不提供对应接口，(特性名)
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            VmError::Invalid => write!(f, "invalid vm"),
+            VmError::Busy(status) => write!(f, "vm busy, status: {}", status),
+            VmError::Stale(expected, current) => write!(f, "stale vm, generation: {}, current: {}", expected, current),
+            VmError::Unsupported(feature) => write!(f, "unsupported, {} feature disabled", feature),
+        }
+    }
+}
+
+/*
+* 虚拟机等待执行的回调
+*/
+#[derive(Debug, Clone)]
+pub struct PendingCallback {
+    pub id:         usize,      //回调任务唯一id
+    pub callback:   u32,        //回调函数
+    pub info:       TaskInfo,   //回调任务元信息
+    pub age:        Duration,   //回调已等待的时长
+}
+
+/*
+* 虚拟机等待执行的任务守护者，任务被投递时由任务持有，任务执行结束或被丢弃时释放，
+* 同一任务的所有守护者都释放后，仍未开始执行的任务会从虚拟机等待执行的任务表中移除
+*/
+pub struct PendingTask {
+    id:     usize,                                          //任务唯一id
+    tasks:  Arc<Mutex<HashMap<usize, (TaskInfo, usize)>>>,  //虚拟机等待执行的任务表
+}
+
+impl Drop for PendingTask {
+    fn drop(&mut self) {
+        let mut tasks = lock_state("vm_pending_tasks", &self.tasks);
+        let is_last = match tasks.get_mut(&self.id) {
+            None => false, //任务已开始执行或已取消
+            Some(entry) => {
+                entry.1 -= 1;
+                entry.1 == 0
+            },
+        };
+        if is_last {
+            tasks.remove(&self.id);
+        }
+    }
+}
+
+impl PendingTask {
+    //获取任务唯一id
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
+/*
+* 虚拟机等待执行的回调守护者，回调被投递时由回调任务持有，回调任务执行结束或被丢弃时释放，
+* 释放时从虚拟机等待执行的回调表中移除回调，保证被丢弃的回调不会残留在等待执行的回调表中
+*/
+struct PendingCallbackGuard {
+    id:         usize,                                      //回调任务唯一id
+    callbacks:  Arc<Mutex<HashMap<usize, (u32, bool)>>>,    //虚拟机等待执行的回调表
+}
+
+impl Drop for PendingCallbackGuard {
+    fn drop(&mut self) {
+        lock_state("vm_pending_callbacks", &self.callbacks).remove(&self.id);
+    }
+}
+
+/*
+* 动态代码执行策略
+*/
+#[derive(Clone)]
+pub enum EvalPolicy {
+    Allow,                                                      //允许执行动态代码
+    Deny(Option<Arc<Fn(&JS, DynamicCodeKind, &str) -> bool>>),  //禁止执行动态代码，可以设置豁免回调，豁免回调返回true则允许执行
+}
+
+/*
+* 虚拟机堆配置
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmProfile {
+    Standard,   //标准配置，优先执行速度
+    LowMemory,  //低内存配置，使用指针压缩和更小的值槽，优先内存占用，需要启用lowmem特性构建
+}
+
+impl Default for VmProfile {
+    fn default() -> Self {
+        VmProfile::Standard
+    }
+}
+
+impl VmProfile {
+    //当前构建是否支持指定的虚拟机堆配置
+    pub fn is_supported(&self) -> bool {
+        match self {
+            VmProfile::Standard => true,
+            VmProfile::LowMemory => cfg!(feature = "lowmem"),
+        }
+    }
+}
+
+//使用指定配置构建虚拟机堆，当前构建不支持低内存配置，则使用标准配置
+fn create_heap(profile: VmProfile) -> *const c_void_ptr {
+    match profile {
+        VmProfile::Standard => unsafe { dukc_heap_create() },
+        VmProfile::LowMemory => create_lowmem_heap(),
+    }
+}
+
+#[cfg(feature = "lowmem")]
+fn create_lowmem_heap() -> *const c_void_ptr {
+    unsafe { dukc_heap_create_lowmem() }
+}
+
+#[cfg(not(feature = "lowmem"))]
+fn create_lowmem_heap() -> *const c_void_ptr {
+    warn!("!!!> Create Low Memory Vm Heap Ignored, lowmem feature disabled, use standard profile");
+    unsafe { dukc_heap_create() }
+}
+
+//对指定虚拟机执行垃圾回收，当前构建不支持则返回VmError::Unsupported
+#[cfg(feature = "vmgc")]
+fn vm_gc(vm: *const c_void_ptr, flags: u32) -> Result<(), VmError> {
+    unsafe { dukc_vm_gc(vm, flags); }
+    Ok(())
+}
+
+#[cfg(not(feature = "vmgc"))]
+fn vm_gc(_vm: *const c_void_ptr, _flags: u32) -> Result<(), VmError> {
+    Err(VmError::Unsupported("vmgc"))
+}
+
+//设置所有虚拟机共享的只读字符串表，返回虚拟机库接受的字符串数量，当前构建不支持则返回VmError::Unsupported
+#[cfg(feature = "pinnedstrings")]
+fn pin_strings(strings: &'static [*const c_char]) -> Result<usize, VmError> {
+    Ok(unsafe { dukc_set_pinned_strings(strings.as_ptr(), strings.len() as u32) as usize })
+}
+
+#[cfg(not(feature = "pinnedstrings"))]
+fn pin_strings(_strings: &'static [*const c_char]) -> Result<usize, VmError> {
+    Err(VmError::Unsupported("pinnedstrings"))
+}
+
+//设置指定虚拟机的调用栈深度限制，当前构建不支持则返回VmError::Unsupported
+#[cfg(feature = "stacklimit")]
+fn stack_limit(vm: *const c_void_ptr, limit: usize) -> Result<(), VmError> {
+    unsafe { dukc_vm_set_stack_limit(vm, limit as u32); }
+    Ok(())
+}
+
+#[cfg(not(feature = "stacklimit"))]
+fn stack_limit(_vm: *const c_void_ptr, _limit: usize) -> Result<(), VmError> {
+    Err(VmError::Unsupported("stacklimit"))
+}
+
+//设置指定虚拟机的本地调用递归深度限制，当前构建不支持则返回VmError::Unsupported
+#[cfg(feature = "nativelimit")]
+fn native_limit(vm: *const c_void_ptr, limit: usize) -> Result<(), VmError> {
+    unsafe { dukc_vm_set_native_limit(vm, limit as u32); }
+    Ok(())
+}
+
+#[cfg(not(feature = "nativelimit"))]
+fn native_limit(_vm: *const c_void_ptr, _limit: usize) -> Result<(), VmError> {
+    Err(VmError::Unsupported("nativelimit"))
+}
+
+//在指定虚拟机中编译函数源码并导出函数的字节码，编译失败则返回None，当前构建不支持则返回VmError::Unsupported
+#[cfg(feature = "funcdump")]
+fn dump_function(vm: *const c_void_ptr, file: &CStr, source: &CStr) -> Result<Option<Vec<u8>>, VmError> {
+    unsafe {
+        let mut len: u32 = 0;
+        let ptr = dukc_dump_function(vm, file.as_ptr(), source.as_ptr(), &mut len);
+        if ptr.is_null() {
+            return Ok(None);
+        }
+
+        let code = from_raw_parts(ptr, len as usize).to_vec();
+        dukc_dump_function_free(ptr);
+        Ok(Some(code))
+    }
+}
+
+#[cfg(not(feature = "funcdump"))]
+fn dump_function(_vm: *const c_void_ptr, _file: &CStr, _source: &CStr) -> Result<Option<Vec<u8>>, VmError> {
+    Err(VmError::Unsupported("funcdump"))
+}
+
+//在指定虚拟机中加载函数的字节码，并将函数压入虚拟机栈，返回函数的值，加载失败则返回None，当前构建不支持则返回VmError::Unsupported
+#[cfg(feature = "funcdump")]
+fn load_function(vm: *const c_void_ptr, code: &[u8]) -> Result<Option<u32>, VmError> {
+    match unsafe { dukc_load_function(vm, code.as_ptr(), code.len() as u32) } {
+        ptr if ptr < 0 => Ok(None),
+        ptr => Ok(Some(ptr as u32)),
+    }
+}
+
+#[cfg(not(feature = "funcdump"))]
+fn load_function(_vm: *const c_void_ptr, _code: &[u8]) -> Result<Option<u32>, VmError> {
+    Err(VmError::Unsupported("funcdump"))
+}
+
+//序列化指定虚拟机的堆快照，序列化失败则返回None，当前构建不支持堆快照则返回VmError::Unsupported
+#[cfg(feature = "snapshot")]
+fn heap_snapshot(vm: *const c_void_ptr) -> Result<Option<Vec<u8>>, VmError> {
+    unsafe {
+        let mut len: u32 = 0;
+        let ptr = dukc_heap_snapshot(vm, &mut len);
+        if ptr.is_null() {
+            return Ok(None);
+        }
+
+        let snapshot = from_raw_parts(ptr, len as usize).to_vec();
+        dukc_heap_snapshot_free(ptr);
+        Ok(Some(snapshot))
+    }
+}
+
+#[cfg(not(feature = "snapshot"))]
+fn heap_snapshot(_vm: *const c_void_ptr) -> Result<Option<Vec<u8>>, VmError> {
+    Err(VmError::Unsupported("snapshot"))
+}
+
+//使用堆快照恢复指定虚拟机的堆，返回是否恢复成功，当前构建不支持堆快照则返回VmError::Unsupported
+#[cfg(feature = "snapshot")]
+fn heap_restore(vm: *const c_void_ptr, snapshot: &[u8]) -> Result<bool, VmError> {
+    Ok(unsafe { dukc_heap_restore(vm, snapshot.as_ptr(), snapshot.len() as u32) != 0 })
+}
+
+#[cfg(not(feature = "snapshot"))]
+fn heap_restore(_vm: *const c_void_ptr, _snapshot: &[u8]) -> Result<bool, VmError> {
+    Err(VmError::Unsupported("snapshot"))
+}
+
+//只有虚拟机已被同步任务阻塞时，才切换为单任务状态并唤醒虚拟机，返回是否唤醒成功，启用pipelined特性时在一次调用中完成切换和唤醒
+#[cfg(feature = "pipelined")]
+fn wakeup_block(vm: *const c_void_ptr, error: c_int) -> bool {
+    unsafe { dukc_wakeup_switch(vm, JSStatus::MultiTask as i8, JSStatus::SingleTask as i8, error) == JSStatus::MultiTask as i8 }
+}
+
+#[cfg(not(feature = "pipelined"))]
+fn wakeup_block(vm: *const c_void_ptr, error: c_int) -> bool {
+    unsafe {
+        if dukc_vm_status_switch(vm, JSStatus::MultiTask as i8, JSStatus::SingleTask as i8) != JSStatus::MultiTask as i8 {
+            return false;
+        }
+        dukc_wakeup(vm, error);
+    }
+    true
+}
+
 /*
 * js消息队列
 */
@@ -408,6 +1022,44 @@ struct JSMsgQueue {
     size:   Arc<AtomicUsize>,   //虚拟机消息队列长度
 }
 
+/*
+* js调用统计，记录虚拟机工厂的单次调用从开始到虚拟机可以整理时的资源使用
+*/
+struct JSCallStat {
+    port:       RwLock<Option<Atom>>,   //当前调用的端口，为空表示当前没有统计中的调用
+    submit_time: AtomicUsize,           //调用提交到虚拟机工厂的时间，单位us
+    start_time: AtomicUsize,            //调用开始时间，单位us
+    run_start:  AtomicUsize,            //本次在虚拟机内开始执行的时间，单位us，为0表示未在执行
+    run_time:   AtomicUsize,            //调用在虚拟机内的累计执行时长，单位us
+    start_heap: AtomicIsize,            //调用开始时的虚拟机堆大小
+    callbacks:  AtomicUsize,            //调用推送的异步回调数量
+    errors:     AtomicUsize,            //调用及其异步回调执行异常的次数
+    bytes_in:   AtomicUsize,            //调用通过虚拟机通道收到的字节数
+    bytes_out:  AtomicUsize,            //调用通过虚拟机通道发送的字节数
+    checkpoints: Mutex<Vec<(Atom, usize)>>, //调用内的命名检查点，值为阶段名和阶段开始时间，单位us
+    run_lock:   Mutex<usize>,           //虚拟机执行锁，值为正在执行的脚本求值的嵌套深度，开始或结束执行和请求中断都需要持有
+}
+
+impl JSCallStat {
+    //构建一个js调用统计
+    fn new() -> Self {
+        JSCallStat {
+            port: RwLock::new(None),
+            submit_time: AtomicUsize::new(0),
+            start_time: AtomicUsize::new(0),
+            run_start: AtomicUsize::new(0),
+            run_time: AtomicUsize::new(0),
+            start_heap: AtomicIsize::new(0),
+            callbacks: AtomicUsize::new(0),
+            errors: AtomicUsize::new(0),
+            bytes_in: AtomicUsize::new(0),
+            bytes_out: AtomicUsize::new(0),
+            checkpoints: Mutex::new(Vec::new()),
+            run_lock: Mutex::new(0),
+        }
+    }
+}
+
 /*
 * js运行环境
 */
@@ -428,13 +1080,47 @@ pub struct JS {
     last_time:          Arc<AtomicUsize>,                           //虚拟机最近运行时间
     wait_throw:         Arc<AtomicBool>,                            //虚拟机等待被丢弃，下次运行后丢弃
     catcher:            Arc<AtomicI32>,                             //虚拟机异常捕获器
+    eval_policy:        Arc<RwLock<EvalPolicy>>,                    //虚拟机动态代码执行策略
+    factory:            Arc<RwLock<Option<Arc<VMFactory>>>>,        //虚拟机所属的虚拟机工厂
+    stat:               Arc<JSCallStat>,                            //虚拟机当前调用的统计
+    finish:             Arc<RefCell<Option<Box<FnOnce(Result<Option<String>, String>)>>>>,  //虚拟机当前调用的完成回调
+    finish_error:       Arc<RefCell<Option<String>>>,               //虚拟机当前调用的执行异常
+    ret_value:          Arc<RefCell<Option<CallValue>>>,            //虚拟机当前调用的返回值缓存
+    finish_value:       Arc<RefCell<Option<Box<FnOnce(Result<CallValue, String>)>>>>,   //虚拟机当前调用的返回值回调
+    pending_tasks:      Arc<Mutex<HashMap<usize, (TaskInfo, usize)>>>, //虚拟机等待执行的任务表，键为任务唯一id，值为任务元信息和任务守护者数量
+    running_task:       Arc<Mutex<Option<TaskInfo>>>,               //虚拟机正在执行的任务
+    labels:             Arc<RwLock<HashMap<String, String>>>,       //虚拟机标签
+    interrupt:          Arc<AtomicUsize>,                           //虚拟机中断原因，为0表示未中断
+    fuel_limit:         Arc<AtomicUsize>,                           //虚拟机当前调用的燃料预算，为0表示不计量
+    fuel_used:          Arc<AtomicUsize>,                           //虚拟机当前调用已消耗的燃料
+    quota_permit:       Arc<Mutex<Option<QuotaPermit>>>,            //虚拟机当前调用持有的源配额许可，在调用完成时释放
+    cancel_flag:        Arc<Mutex<Option<Arc<AtomicBool>>>>,        //虚拟机当前调用的取消标记，为空表示当前调用不可取消
+    thread_borrow:      Arc<VmBorrow>,                              //虚拟机的线程持有记录，用于审计多个线程同时访问虚拟机
+    recycle:            Arc<RwLock<Option<String>>>,                //虚拟机请求回收的原因，为空表示未请求回收
+    destroyed:          Arc<AtomicBool>,                            //虚拟机是否已被销毁
+    destroy_hooks:      Arc<Mutex<Vec<Box<FnOnce(Atom, usize)>>>>,  //虚拟机销毁时的通知列表
+    pending_callbacks:  Arc<Mutex<HashMap<usize, (u32, bool)>>>,    //虚拟机等待执行的回调表，键为任务唯一id，值为(回调函数, 是否已取消)
+    last_gc:            Arc<AtomicUsize>,                           //虚拟机上次垃圾回收时间，单位us
+    code_generation:    Arc<AtomicUsize>,                           //虚拟机加载的字节码代数
+    created:            usize,                                      //虚拟机构建时间，单位us
+    served:             Arc<AtomicUsize>,                           //虚拟机已完成的调用次数
+    peak_heap_size:     Arc<AtomicUsize>,                           //虚拟机堆大小的峰值
+    affinity_src:       Arc<AtomicIsize>,                           //虚拟机当前调用的亲和源，为负数表示没有亲和源
+    scratch:            Arc<Mutex<Vec<u8>>>,                        //虚拟机暂存缓冲区，用于构建参数和字符串转换，每次使用前清空
+    last_error:         Arc<Mutex<Option<String>>>,                 //虚拟机最近一次执行异常，包括加载字节码的异常
+    recycle_epoch:      Arc<AtomicUsize>,                           //虚拟机重置全局环境的次数，用于识别重置前推送的异步回调
+}
+
+//判断虚拟机执行异常是否是内存不足，虚拟机堆达到最大堆大小后分配失败会抛出alloc failed异常
+fn is_oom_error(error: &str) -> bool {
+    error.contains("alloc failed") || error.contains("out of memory")
 }
 
 /*
 * 尝试destroy虚拟机
 */
 pub unsafe fn try_js_destroy(js: &JS) {
-    if js.vm == 0 {
+    if js.vm == 0 || js.is_destroyed() {
         return;
     }
 
@@ -442,7 +1128,12 @@ pub unsafe fn try_js_destroy(js: &JS) {
     if old_status == JSStatus::NoTask as i8 {
         //当前js虚拟机无任务，则可以destroy
         info!("===> Vm Destroy Ok, vm: {:?}", js);
+        js.destroyed.store(true, Ordering::SeqCst);
+        js.destroy_barrier();
         VM_ALLOCATED.fetch_sub(js.last_heap_size.load(Ordering::Relaxed), Ordering::Relaxed); //减少虚拟机占用内存
+        if let Some(factory) = js.get_factory() {
+            factory.sub_total_heap(js.last_heap_size.load(Ordering::Relaxed)); //减少虚拟机工厂的总堆大小
+        }
         dukc_vm_destroy(js.vm as *const c_void_ptr);
     }
 }
@@ -452,6 +1143,7 @@ unsafe impl Sync for JS {}
 
 impl Drop for JS {
     fn drop(&mut self) {
+        unregister_vm(&self.name, self.id); //从虚拟机注册表中注销
         unsafe { try_js_destroy(self); }
     }
 }
@@ -472,8 +1164,17 @@ impl JS {
                name: Atom,
                auth: Arc<NativeObjsAuth>,
                collection: Option<(Arc<AtomicBool>, Arc<VMFactory>)>) -> Option<Arc<Self>> {
-        let ptr: *const c_void_ptr;
-        unsafe { ptr = dukc_heap_create() }
+        JS::with_profile(vm_id, name, auth, collection, VmProfile::Standard)
+    }
+
+    //使用指定的虚拟机堆配置构建一个虚拟机
+    pub fn with_profile(vm_id: usize,
+                        name: Atom,
+                        auth: Arc<NativeObjsAuth>,
+                        collection: Option<(Arc<AtomicBool>, Arc<VMFactory>)>,
+                        profile: VmProfile) -> Option<Arc<Self>> {
+        VM_HEAP_CREATED.store(true, Ordering::SeqCst); //构建虚拟机堆后不允许再设置共享的只读字符串表
+        let ptr = create_heap(profile);
         if ptr.is_null() {
             None
         } else {
@@ -488,6 +1189,13 @@ impl JS {
             let id = create_js_task_queue(JS_ASYNC_MSG_QUEUE_PRIORITY, true); //为指定虚拟机创建对应的消息队列
             //初始化时锁住虚拟机消息队列
             if !lock_js_task_queue(id) {
+                if cfg!(feature = "nopanic") {
+                    //不允许中止进程，则释放已构建的虚拟机和消息队列，并返回构建失败
+                    warn!("!!!> New Vm Error, lock async callback queue failed");
+                    remove_js_task_queue(id);
+                    unsafe { dukc_vm_destroy(ptr); }
+                    return None;
+                }
                 panic!("!!!> New Vm Error, lock async callback queue failed");
             }
             let arc = Arc::new(JS {
@@ -509,6 +1217,35 @@ impl JS {
                 last_time: Arc::new(AtomicUsize::new(now_utc())),
                 wait_throw: Arc::new(AtomicBool::new(false)),
                 catcher: Arc::new(AtomicI32::new(-1)),
+                eval_policy: Arc::new(RwLock::new(EvalPolicy::Allow)),
+                factory: Arc::new(RwLock::new(None)),
+                stat: Arc::new(JSCallStat::new()),
+                finish: Arc::new(RefCell::new(None)),
+                finish_error: Arc::new(RefCell::new(None)),
+                ret_value: Arc::new(RefCell::new(None)),
+                finish_value: Arc::new(RefCell::new(None)),
+                pending_tasks: Arc::new(Mutex::new(HashMap::new())),
+                running_task: Arc::new(Mutex::new(None)),
+                labels: Arc::new(RwLock::new(HashMap::new())),
+                interrupt: Arc::new(AtomicUsize::new(0)),
+                fuel_limit: Arc::new(AtomicUsize::new(0)),
+                fuel_used: Arc::new(AtomicUsize::new(0)),
+                quota_permit: Arc::new(Mutex::new(None)),
+                cancel_flag: Arc::new(Mutex::new(None)),
+                thread_borrow: Arc::new(VmBorrow::new()),
+                recycle: Arc::new(RwLock::new(None)),
+                destroyed: Arc::new(AtomicBool::new(false)),
+                destroy_hooks: Arc::new(Mutex::new(Vec::new())),
+                pending_callbacks: Arc::new(Mutex::new(HashMap::new())),
+                last_gc: Arc::new(AtomicUsize::new(0)),
+                code_generation: Arc::new(AtomicUsize::new(0)),
+                created: now_utc(),
+                served: Arc::new(AtomicUsize::new(0)),
+                peak_heap_size: Arc::new(AtomicUsize::new(0)),
+                affinity_src: Arc::new(AtomicIsize::new(-1)),
+                scratch: Arc::new(Mutex::new(Vec::new())),
+                last_error: Arc::new(Mutex::new(None)),
+                recycle_epoch: Arc::new(AtomicUsize::new(0)),
             });
             unsafe {
                 let handler = Arc::into_raw(arc.clone()) as *const c_void_ptr;
@@ -524,139 +1261,1031 @@ impl JS {
         Arc::from_raw(ptr as *const JS)
     }
 
+    //恢复被同步任务阻塞的虚拟机，唤醒虚拟机后压入阻塞调用的结果，并继续同步执行，虚拟机未阻塞则返回结果构建函数，由调用者重新投递
+    pub fn resume_block<F: FnOnce(Arc<JS>)>(js: Arc<JS>, task_id: usize, error: bool, push: F) -> Result<(), F> {
+        let vm = unsafe { js.get_vm() };
+        if !wakeup_block(vm, if error { 1 } else { 0 }) {
+            //同步任务还未阻塞虚拟机
+            return Err(push);
+        }
+
+        js.start_task(task_id);
+        push(js.clone());
+        js.begin_run();
+        unsafe { dukc_continue(vm, js_reply_callback); }
+        Ok(())
+    }
+
     //回调指定虚拟机的指定回调函数，回调成功，则移除回调函数
     pub fn callback(js: Arc<JS>, task_type: TaskType, callback: u32,
-                args: Box<FnOnce(Arc<JS>) -> usize>, timeout: Option<u32>, info: Atom) -> Option<isize> {
-        let js_copy = js.clone();
+                args: Box<FnOnce(Arc<JS>) -> usize>, timeout: Option<u32>, info: TaskInfo) -> Option<isize> {
+        JS::callback_with_reject(js, task_type, callback, args, timeout, info, None)
+    }
+
+    //回调指定虚拟机的指定回调函数，回调成功，则移除回调函数，
+    //如果执行回调时虚拟机已重置推送回调时的全局环境，则拒绝执行回调，并使用原因调用拒绝回调
+    pub fn callback_with_reject(js: Arc<JS>, task_type: TaskType, callback: u32,
+                                args: Box<FnOnce(Arc<JS>) -> usize>, timeout: Option<u32>, info: TaskInfo,
+                                reject: Option<Box<FnOnce(String)>>) -> Option<isize> {
+        if js.is_destroyed() {
+            //虚拟机已销毁，则忽略回调
+            return None;
+        }
+
+        let js_copy = js.clone();
+        let task = js.enqueue_task(&info);
+        let task_id = task.id();
+        let task_name = info.name();
+        let epoch = js.recycle_epoch();
+        lock_state("vm_pending_callbacks", &js.pending_callbacks).insert(task_id, (callback, false));
+        let pending = PendingCallbackGuard {
+            id: task_id,
+            callbacks: js.pending_callbacks.clone(),
+        };
         let func = Box::new(move |_lock| {
+            let _task = task; //任务执行结束或被丢弃时释放任务守护者
+            let _pending = pending; //回调任务执行结束或被丢弃时从等待执行的回调表中移除回调
+            if js_copy.is_destroyed() {
+                //虚拟机已销毁，则忽略已推送的回调
+                return;
+            }
+
+            let _borrow = js_copy.borrow_thread("callback");
             let vm: *const c_void_ptr;
+            js_copy.start_task(task_id);
+            if js_copy.recycle_epoch() != epoch {
+                //推送回调后虚拟机已重置全局环境，回调函数已不属于当前全局环境，则拒绝执行回调，并立即退出当前同步任务
+                js_copy.finish_task();
+                VM_LATE_CALLBACK_COUNT.sum(1);
+                let reason = format!("late callback rejected, vm recycled, vm: {}, callback: {}, task: {:?}", js_copy.get_id(), callback, (*task_name).to_string());
+                warn!("!!!> Vm Callback Error, {}", reason);
+                if let Some(reject) = reject {
+                    reject(reason);
+                }
+                return;
+            }
+            let is_canceled = match lock_state("vm_pending_callbacks", &js_copy.pending_callbacks).remove(&task_id) {
+                Some((_, canceled)) => canceled,
+                None => false,
+            };
             //不需要改变虚拟机状态，以保证当前虚拟机可以线程安全的执行回调函数
             unsafe {
                 vm = js_copy.get_vm();
                 if dukc_get_callback(vm, callback) == 0 {
                     //当前回调函数不存在，则立即退出当前同步任务，以获取下一个异步消息
+                    js_copy.finish_task();
                     return;
                 }
                 dukc_remove_callback(vm, callback); //移除虚拟机注册的指定回调函数
             }
 
+            if is_canceled {
+                //回调已被取消，则不执行回调函数，并通知js回调已被取消，没有通知函数则调用一定存在的函数，保证虚拟机可以自动退出
+                let args_len = if js_copy.get_link_function("__pi_vm.__notifyCanceled".to_string()) {
+                    js_copy.new_u32(callback);
+                    let _ = js_copy.new_str((*task_name).to_string());
+                    2
+                } else {
+                    js_copy.get_link_function("Math.abs".to_string());
+                    js_copy.new_u32(0);
+                    1
+                };
+                js_copy.begin_run();
+                unsafe { dukc_call(vm, args_len, js_reply_callback); }
+                return;
+            }
+
             //将回调函数的参数压栈，并执行回调函数
             let args_len = (args)(js_copy.clone());
+            js_copy.begin_run();
             unsafe { dukc_call(vm, args_len as u8, js_reply_callback); }
         });
         js.queue.size.fetch_add(1, Ordering::SeqCst); //增加消息队列长度，并返回
 
         if let Some(time) = timeout {
             //向指定虚拟机的消息队列推送延迟异步回调任务
-            cast_js_delay_task(task_type, 0, Some(js.get_queue()), func, time, info)
+            cast_js_delay_task(task_type, 0, Some(js.get_queue()), func, time, info.name())
         } else {
             //向指定虚拟机的消息队列推送异步回调任务
-            cast_js_task(task_type, 0, Some(js.get_queue()), func, info)
+            cast_js_task(task_type, 0, Some(js.get_queue()), func, info.name())
         }
     }
 
     //向指定虚拟机的消息队列中推送消息，由指定的回调函数处理，处理后默认不移除回调函数
-    pub fn push(js: Arc<JS>, task_type: TaskType, callback: u32, args: Box<FnOnce(Arc<JS>) -> usize>, info: Atom) -> Option<isize> {
+    pub fn push(js: Arc<JS>, task_type: TaskType, callback: u32, args: Box<FnOnce(Arc<JS>) -> usize>, info: TaskInfo) -> Option<isize> {
+        if js.is_destroyed() {
+            //虚拟机已销毁，则忽略消息
+            return None;
+        }
+
         let js_copy = js.clone();
+        let task = js.enqueue_task(&info);
+        let task_id = task.id();
         let func = Box::new(move |_lock| {
+            let _task = task; //任务执行结束或被丢弃时释放任务守护者
+            if js_copy.is_destroyed() {
+                //虚拟机已销毁，则忽略已推送的消息
+                return;
+            }
+
             let vm: *const c_void_ptr;
+            js_copy.start_task(task_id);
             //不需要改变虚拟机状态，以保证当前虚拟机可以线程安全的执行回调函数
             unsafe {
                 vm = js_copy.get_vm();
                 if dukc_get_callback(vm, callback as u32) == 0 {
                     //当前回调函数不存在，则立即退出当前同步任务，以获取下一个异步消息
+                    js_copy.finish_task();
                     return;
                 }
             }
 
             //将回调函数的参数压栈，并执行回调函数
             let args_len = (args)(js_copy.clone());
+            js_copy.begin_run();
             unsafe { dukc_call(vm, args_len as u8, js_reply_callback); }
         });
         js.queue.size.fetch_add(1, Ordering::SeqCst); //增加消息队列长度，并返回
 
-        //向指定虚拟机的消息队列推送异步回调任务
-        cast_js_task(task_type, 0, Some(js.get_queue()), func, info)
+        //向指定虚拟机的消息队列推送异步回调任务
+        cast_js_task(task_type, 0, Some(js.get_queue()), func, info.name())
+    }
+
+    //向虚拟机分片推送回调，将回调数据按指定大小分片，每个分片作为一个独立的任务执行回调函数，执行当前分片时才将剩余分片重新加入虚拟机的消息队列，
+    //以保证虚拟机可以在分片之间执行其它任务，回调函数的参数为(分片数据, 分片偏移, 数据总长度, 是否是最后一个分片)，执行最后一个分片后移除回调函数
+    pub fn callback_sliced(js: Arc<JS>, callback: u32, payload: Arc<Vec<u8>>, slice_size: usize, info: TaskInfo) -> Option<isize> {
+        let slice_size = if slice_size == 0 { payload.len().max(1) } else { slice_size };
+        JS::push_slice(js, callback, payload, 0, slice_size, info)
+    }
+
+    //推送指定偏移的回调数据分片
+    fn push_slice(js: Arc<JS>, callback: u32, payload: Arc<Vec<u8>>, offset: usize, slice_size: usize, info: TaskInfo) -> Option<isize> {
+        let total = payload.len();
+        let end = (offset + slice_size).min(total);
+        let is_last = end >= total;
+        let next_info = info.clone();
+        let args = Box::new(move |vm: Arc<JS>| -> usize {
+            let buffer = vm.new_uint8_array((end - offset) as u32);
+            buffer.from_bytes(&payload[offset..end]);
+            vm.new_f64(offset as f64);
+            vm.new_f64(total as f64);
+            vm.new_boolean(is_last);
+
+            if !is_last {
+                //在执行当前分片时，将剩余分片重新加入虚拟机的消息队列
+                VM_SLICE_REQUEUE_COUNT.sum(1);
+                JS::push_slice(vm, callback, payload.clone(), end, slice_size, next_info);
+            }
+            4
+        });
+
+        if is_last {
+            JS::callback(js, TaskType::Sync(true), callback, args, None, info)
+        } else {
+            JS::push(js, TaskType::Sync(true), callback, args, info)
+        }
+    }
+
+    //移除虚拟机注册的指定长驻回调函数
+    pub fn remove_callback(js: Arc<JS>, task_type: TaskType, callback: u32, info: TaskInfo) -> Option<isize> {
+        //向指定虚拟机的消息队列推送异步回调任务
+        if js.is_destroyed() {
+            //虚拟机已销毁，则忽略
+            return None;
+        }
+
+        let js_copy = js.clone();
+        let task = js.enqueue_task(&info);
+        let task_id = task.id();
+        let func = Box::new(move |_lock| {
+            let _task = task; //任务执行结束或被丢弃时释放任务守护者
+            if js_copy.is_destroyed() {
+                //虚拟机已销毁，则忽略已推送的任务
+                return;
+            }
+
+            js_copy.start_task(task_id);
+            unsafe {
+                let vm = js_copy.get_vm();
+                dukc_remove_callback(vm, callback); //移除虚拟机注册的指定回调函数
+
+                //调用一定存在的函数，保证虚拟机可以自动退出
+                js_copy.get_link_function("Math.abs".to_string());
+                js_copy.new_u32(0);
+                js_copy.begin_run();
+                dukc_call(vm, 1, js_reply_callback);
+            }
+        });
+        js.queue.size.fetch_add(1, Ordering::SeqCst) + 1; //增加消息队列长度，并返回
+
+        //向指定虚拟机的消息队列推送异步回调任务
+        cast_js_task(task_type, 0, Some(js.get_queue()), func, info.name())
+    }
+
+    //获取内部虚拟机
+    pub unsafe fn get_vm(&self) -> *const c_void_ptr {
+        self.vm as *const c_void_ptr
+    }
+
+    //获取虚拟机堆大小
+    pub fn heap_size(&self) -> usize {
+        if self.is_destroyed() {
+            return 0;
+        }
+
+        unsafe { dukc_vm_size(self.vm as *const c_void_ptr) }
+    }
+
+    //对空闲的虚拟机执行垃圾回收，compact为true则同时压缩堆，并更新虚拟机堆大小，返回是否已回收，虚拟机正在执行任务则返回false，
+    //需要启用vmgc特性构建，当前构建不支持则返回VmError::Unsupported，虚拟机已销毁则返回VmError::Invalid
+    pub fn gc(&self, compact: bool) -> Result<bool, VmError> {
+        if !cfg!(feature = "vmgc") {
+            return Err(VmError::Unsupported("vmgc"));
+        }
+        if self.vm == 0 || self.is_destroyed() {
+            return Err(VmError::Invalid);
+        }
+
+        unsafe {
+            let status = dukc_vm_status_switch(self.vm as *const c_void_ptr, JSStatus::NoTask as i8, JSStatus::SingleTask as i8);
+            if status != JSStatus::NoTask as i8 {
+                //当前虚拟机正在执行任务，无法回收
+                return Ok(false);
+            }
+
+            let result = vm_gc(self.vm as *const c_void_ptr, if compact { DUK_GC_COMPACT } else { 0 });
+            dukc_vm_status_switch(self.vm as *const c_void_ptr, JSStatus::SingleTask as i8, JSStatus::NoTask as i8);
+            result?;
+        }
+
+        self.last_gc.store(now_utc(), Ordering::SeqCst);
+        self.update_last_heap_size();
+        Ok(true)
+    }
+
+    //设置虚拟机的调用栈深度限制，超过限制的调用会抛出异常，需要启用stacklimit特性构建，当前构建不支持则返回VmError::Unsupported
+    pub fn set_stack_limit(&self, limit: usize) -> Result<(), VmError> {
+        if self.vm == 0 || self.is_destroyed() {
+            return Err(VmError::Invalid);
+        }
+
+        stack_limit(self.vm as *const c_void_ptr, limit)
+    }
+
+    //设置虚拟机的本地调用递归深度限制，包括js与本地函数的相互调用、正则表达式和JSON的递归，超过限制的递归会抛出RangeError，
+    //而不是耗尽线程的本地栈，需要启用nativelimit特性构建，当前构建不支持则返回VmError::Unsupported
+    pub fn set_native_limit(&self, limit: usize) -> Result<(), VmError> {
+        if self.vm == 0 || self.is_destroyed() {
+            return Err(VmError::Invalid);
+        }
+
+        native_limit(self.vm as *const c_void_ptr, limit)
+    }
+
+    //设置虚拟机加载的字节码代数
+    pub fn set_code_generation(&self, generation: usize) {
+        self.code_generation.store(generation, Ordering::SeqCst);
+    }
+
+    //获取虚拟机加载的字节码代数
+    pub fn code_generation(&self) -> usize {
+        self.code_generation.load(Ordering::SeqCst)
+    }
+
+    //获取虚拟机构建后的存活时长
+    pub fn age(&self) -> Duration {
+        Duration::from_micros(now_utc().saturating_sub(self.created) as u64)
+    }
+
+    //获取虚拟机已完成的调用次数
+    pub fn served_count(&self) -> usize {
+        self.served.load(Ordering::Relaxed)
+    }
+
+    //判断虚拟机在上次垃圾回收后是否运行过
+    pub fn need_gc(&self) -> bool {
+        self.last_gc.load(Ordering::SeqCst) < self.last_time()
+    }
+
+    //更新虚拟机上次堆大小，并更新所有虚拟机占用内存大小
+    pub fn update_last_heap_size(&self) {
+        let cur_size = self.heap_size() as isize;
+        let last_size = self.last_heap_size.swap(cur_size, Ordering::Relaxed);
+        self.peak_heap_size.fetch_max(cur_size as usize, Ordering::Relaxed); //更新虚拟机堆大小的峰值
+
+        if last_size > cur_size {
+            //当前虚拟机堆变小了，从所有虚拟机占用内存中减去内存减量
+            VM_ALLOCATED.fetch_sub(last_size - cur_size, Ordering::Relaxed);
+        } if last_size < cur_size {
+            //当前虚拟机堆变大了，在所有虚拟机占用内存中增加内存增量
+            VM_ALLOCATED.fetch_add(cur_size - last_size, Ordering::Relaxed);
+        }
+
+        if last_size != cur_size {
+            if let Some(factory) = self.get_factory() {
+                //同步更新虚拟机所属虚拟机工厂的总堆大小
+                factory.add_total_heap(cur_size - last_size);
+            }
+        }
+    }
+
+    //获取虚拟机最近堆大小
+    pub fn last_heap_size(&self) -> usize {
+        self.last_heap_size.load(Ordering::Relaxed) as usize
+    }
+
+    //获取虚拟机堆大小的峰值
+    pub fn peak_heap_size(&self) -> usize {
+        self.peak_heap_size.load(Ordering::Relaxed)
+    }
+
+    //设置虚拟机当前调用的亲和源
+    pub fn set_affinity_src(&self, src: Option<usize>) {
+        self.affinity_src.store(src.map_or(-1, |src| src as isize), Ordering::Relaxed);
+    }
+
+    //取出虚拟机当前调用的亲和源
+    pub fn take_affinity_src(&self) -> Option<usize> {
+        match self.affinity_src.swap(-1, Ordering::Relaxed) {
+            src if src < 0 => None,
+            src => Some(src as usize),
+        }
+    }
+
+    //使用虚拟机暂存缓冲区构建参数，缓冲区在调用前被清空，在重入或并发使用时会使用新分配的缓冲区
+    pub fn with_scratch<R, F: FnOnce(&mut Vec<u8>) -> R>(&self, func: F) -> R {
+        match self.scratch.try_lock() {
+            Ok(mut buf) => {
+                buf.clear();
+                let r = func(&mut buf);
+                buf.clear();
+                if buf.capacity() > MAX_SCRATCH_CAPACITY {
+                    buf.shrink_to_fit();
+                }
+                r
+            },
+            Err(_) => {
+                VM_SCRATCH_ALLOC_COUNT.sum(1);
+                func(&mut Vec::new())
+            },
+        }
+    }
+
+    //使用虚拟机暂存缓冲区将字符串转换为以空字符结尾的C字符串，C字符串只在调用期间有效，字符串包含空字符则返回错误
+    pub fn with_c_str<R, F: FnOnce(*const c_char) -> R>(&self, str: &str, func: F) -> Result<R, String> {
+        if let Some(pos) = str.bytes().position(|b| b == 0) {
+            return Err(format!("nul byte found in provided data at position: {}", pos));
+        }
+
+        Ok(self.with_scratch(|buf| {
+            buf.extend_from_slice(str.as_bytes());
+            buf.push(0);
+            func(buf.as_ptr() as *const c_char)
+        }))
+    }
+
+    //获取虚拟机id
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+
+    //获取虚拟机名
+    pub fn get_name(&self) -> Atom {
+        self.name.clone()
+    }
+
+    //设置虚拟机标签，返回标签的旧值
+    pub fn set_label(&self, key: &str, value: &str) -> Option<String> {
+        write_state("vm_labels", &self.labels).insert(key.to_string(), value.to_string())
+    }
+
+    //获取虚拟机指定标签的值
+    pub fn get_label(&self, key: &str) -> Option<String> {
+        read_state("vm_labels", &self.labels).get(key).cloned()
+    }
+
+    //获取虚拟机所有标签
+    pub fn labels(&self) -> HashMap<String, String> {
+        read_state("vm_labels", &self.labels).clone()
+    }
+
+    //判断虚拟机是否与所有指定标签匹配
+    pub fn match_labels(&self, labels: &[(&str, &str)]) -> bool {
+        let map = read_state("vm_labels", &self.labels);
+        labels.iter().all(|(key, value)| {
+            map.get(*key).map_or(false, |v| v == value)
+        })
+    }
+
+    //获取虚拟机上次运行时间
+    pub fn last_time(&self) -> usize {
+        self.last_time.load(Ordering::Relaxed)
+    }
+
+    //设置虚拟机上次运行时间，单位ms，返回上次运行时间
+    pub fn set_last_time(&self, time: usize) -> usize {
+        self.last_time.swap(time * 1000, Ordering::SeqCst)
+    }
+
+    //更新虚拟机上次运行时间，并返回上次运行时间
+    pub fn update_last_time(&self) -> usize {
+        self.last_time.swap(now_utc(), Ordering::SeqCst)
+    }
+
+    //初始化虚拟机字符输出
+    pub fn init_char_output(&self, output: extern fn(*const c_char)) {
+        unsafe {
+            dukc_init_char_output(self.vm as *const c_void_ptr, output);
+        }
+    }
+
+    //解锁虚拟机回收器
+    pub fn unlock_collection(&self) {
+        if let Some((lock, _)) = &self.collection {
+            //有回收器，则解锁
+            lock.swap(true, Ordering::SeqCst);
+        }
+    }
+
+    //设置虚拟机异常捕获器
+    pub fn set_catcher(&self, catcher: i32) {
+        self.catcher.store(catcher, Ordering::SeqCst);
+    }
+
+    //设置虚拟机动态代码执行策略
+    pub fn set_eval_policy(&self, policy: EvalPolicy) {
+        *write_state("vm_eval_policy", &self.eval_policy) = policy;
+    }
+
+    //检查虚拟机是否允许执行指定的动态代码
+    pub fn check_eval(&self, kind: DynamicCodeKind, source: &str) -> bool {
+        match &*read_state("vm_eval_policy", &self.eval_policy) {
+            EvalPolicy::Allow => true,
+            EvalPolicy::Deny(None) => false,
+            EvalPolicy::Deny(Some(exempt)) => exempt(self, kind, source),
+        }
+    }
+
+    //设置虚拟机所属的虚拟机工厂
+    pub fn set_factory(&self, factory: Arc<VMFactory>) {
+        *write_state("vm_factory", &self.factory) = Some(factory);
+    }
+
+    //获取虚拟机所属的虚拟机工厂
+    pub fn get_factory(&self) -> Option<Arc<VMFactory>> {
+        read_state("vm_factory", &self.factory).clone()
+    }
+
+    //开始统计虚拟机工厂的指定端口的调用
+    pub fn start_call(&self, port: Atom, submit_time: usize) {
+        let _lock = lock_state("vm_run_lock", &self.stat.run_lock); //持有执行锁直到初始化完成，保证之前调用的中断请求不会作用于本次调用
+        let now = now_utc();
+        self.clear_interrupt(); //清除之前调用遗留的中断请求
+        *write_state("vm_call_stat", &self.stat.port) = Some(port);
+        self.stat.submit_time.store(submit_time.min(now), Ordering::Relaxed);
+        self.stat.start_time.store(now, Ordering::Relaxed);
+        self.stat.run_time.store(0, Ordering::Relaxed);
+        self.stat.start_heap.store(self.last_heap_size.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.stat.callbacks.store(0, Ordering::Relaxed);
+        self.stat.errors.store(0, Ordering::Relaxed);
+        self.stat.bytes_in.store(0, Ordering::Relaxed);
+        self.stat.bytes_out.store(0, Ordering::Relaxed);
+        lock_state("vm_call_checkpoints", &self.stat.checkpoints).clear();
+        self.fuel_limit.store(0, Ordering::Relaxed);
+        self.fuel_used.store(0, Ordering::Relaxed);
+        lock_state("vm_cancel_flag", &self.cancel_flag).take();
+    }
+
+    //获取当前调用已开始的时长，当前没有统计中的调用则返回None
+    pub fn call_elapsed(&self) -> Option<Duration> {
+        if read_state("vm_call_stat", &self.stat.port).is_none() {
+            return None;
+        }
+
+        Some(Duration::from_micros(now_utc().saturating_sub(self.stat.start_time.load(Ordering::Relaxed)) as u64))
+    }
+
+    //在当前调用内记录指定名称的检查点，检查点标记一个阶段的开始，阶段在下个检查点或调用完成时结束，返回是否成功记录
+    pub fn checkpoint(&self, name: &str) -> bool {
+        if read_state("vm_call_stat", &self.stat.port).is_none() {
+            return false;
+        }
+
+        let mut checkpoints = lock_state("vm_call_checkpoints", &self.stat.checkpoints);
+        if checkpoints.len() >= MAX_CALL_CHECKPOINTS {
+            return false;
+        }
+        checkpoints.push((Atom::from(name), now_utc()));
+        true
+    }
+
+    //获取当前调用的开始时间，单位us，当前没有统计中的调用则返回None
+    pub fn call_start_time(&self) -> Option<usize> {
+        if read_state("vm_call_stat", &self.stat.port).is_none() {
+            return None;
+        }
+
+        Some(self.stat.start_time.load(Ordering::Relaxed))
+    }
+
+    //请求中断虚拟机的当前执行，虚拟机会在下次中断检查时抛出异常，已被中断则忽略，返回是否成功请求中断，
+    //只允许在虚拟机执行线程内调用，其它线程需要调用interrupt_call，以保证中断请求不会遗留到空闲的虚拟机
+    pub fn interrupt(&self, reason: InterruptReason) -> bool {
+        self.interrupt.compare_and_swap(0, reason as usize, Ordering::SeqCst) == 0
+    }
+
+    //线程安全的请求中断虚拟机正在执行的调用，指定了调用开始时间则只中断在该时间开始且仍未完成的调用，
+    //检查和请求中断在执行锁内完成，虚拟机没有正在执行的调用或脚本则忽略，返回是否成功请求中断
+    pub fn interrupt_call(&self, reason: InterruptReason, start_time: Option<usize>) -> bool {
+        let depth = lock_state("vm_run_lock", &self.stat.run_lock);
+        let in_call = read_state("vm_call_stat", &self.stat.port).is_some();
+        match start_time {
+            Some(start) if !in_call || self.stat.start_time.load(Ordering::Relaxed) != start => {
+                //指定的调用已完成
+                return false;
+            },
+            None if !in_call && *depth == 0 && self.stat.run_start.load(Ordering::Relaxed) == 0 => {
+                //虚拟机空闲
+                return false;
+            },
+            _ => (),
+        }
+
+        self.interrupt(reason)
+    }
+
+    //线程安全的终止虚拟机正在执行的脚本，虚拟机会在下次中断检查时抛出终止异常并展开当前脚本，用于在不结束进程的情况下结束失控的死循环，
+    //被终止的虚拟机在本次执行完成后会被丢弃，正在执行本地函数时需要等待本地函数返回，虚拟机空闲或已被中断则忽略，返回是否成功请求终止
+    pub fn terminate(&self) -> bool {
+        if !self.interrupt_call(InterruptReason::Terminated, None) {
+            return false;
+        }
+
+        VM_TERMINATE_COUNT.sum(1);
+        warn!("!!!> Vm Terminate, vm: {:?}, task: {:?}", self, self.running_task().map(|info| info.to_string()));
+        true
+    }
+
+    //设置虚拟机当前调用的燃料预算，并重置已消耗的燃料，为0表示不计量，燃料单位为中断检查次数，虚拟机每执行固定数量的字节码指令进行一次中断检查，
+    //燃料耗尽时中断虚拟机执行
+    pub fn set_fuel(&self, fuel: usize) {
+        self.fuel_used.store(0, Ordering::Relaxed);
+        self.fuel_limit.store(fuel, Ordering::Relaxed);
+    }
+
+    //为虚拟机当前调用持有源配额许可，许可在调用完成时释放
+    pub fn hold_permit(&self, permit: QuotaPermit) {
+        *lock_state("vm_quota_permit", &self.quota_permit) = Some(permit);
+    }
+
+    //为虚拟机当前调用设置取消标记，js可以通过__pi_vm.signal.aborted查询当前调用是否已被取消
+    pub fn set_cancel_flag(&self, flag: Arc<AtomicBool>) {
+        *lock_state("vm_cancel_flag", &self.cancel_flag) = Some(flag);
+    }
+
+    //判断虚拟机当前调用是否已被取消
+    pub fn is_call_canceled(&self) -> bool {
+        lock_state("vm_cancel_flag", &self.cancel_flag).as_ref().map_or(false, |flag| flag.load(Ordering::SeqCst))
+    }
+
+    //将已压栈的当前调用的函数替换为报告调用已被取消的函数，用于跳过开始执行前已被取消的调用，返回替换后函数的参数数量
+    pub fn replace_canceled_call(&self, port: &str) -> usize {
+        unsafe { dukc_pop(self.vm as *const c_void_ptr); } //移除已压栈的当前调用的函数
+        if self.get_link_function("__pi_vm.__callCanceled".to_string()) {
+            let _ = self.new_str(port.to_string());
+            1
+        } else {
+            //没有报告函数则调用一定存在的函数，保证虚拟机可以自动退出
+            self.get_link_function("Math.abs".to_string());
+            self.new_u32(0);
+            1
+        }
+    }
+
+    //设置是否审计虚拟机的线程访问，需要启用threadaudit特性构建
+    pub fn set_thread_audit(&self, enabled: bool) {
+        self.thread_borrow.set_enabled(enabled);
+    }
+
+    //当前线程在指定位置持有虚拟机，审计时其它线程正在持有虚拟机则断言失败，返回的守护者被释放时释放持有
+    pub fn borrow_thread(&self, site: &'static str) -> VmBorrowGuard {
+        borrow_vm(&self.thread_borrow, self.id, site)
+    }
+
+    //获取当前持有虚拟机的线程和持有位置，未审计或没有线程持有则返回None
+    pub fn thread_owner(&self) -> Option<(ThreadId, &'static str)> {
+        self.thread_borrow.owner()
+    }
+
+    //获取虚拟机当前调用已消耗的燃料
+    pub fn fuel_used(&self) -> usize {
+        self.fuel_used.load(Ordering::Relaxed)
+    }
+
+    //获取虚拟机当前调用剩余的燃料，不计量则返回None
+    pub fn fuel_remaining(&self) -> Option<usize> {
+        match self.fuel_limit.load(Ordering::Relaxed) {
+            0 => None,
+            limit => Some(limit.saturating_sub(self.fuel_used())),
+        }
+    }
+
+    //消耗1单位燃料，燃料耗尽则请求中断虚拟机执行
+    fn consume_fuel(&self) {
+        let used = self.fuel_used.fetch_add(1, Ordering::Relaxed) + 1;
+        let limit = self.fuel_limit.load(Ordering::Relaxed);
+        if limit > 0 && used >= limit && self.interrupt(InterruptReason::FuelExhausted) {
+            VM_FUEL_EXHAUSTED_COUNT.sum(1);
+            warn!("!!!> Vm Fuel Exhausted, vm: {:?}, fuel: {}", self, limit);
+        }
+    }
+
+    //获取虚拟机的中断原因，未被中断则返回None
+    pub fn interrupted(&self) -> Option<InterruptReason> {
+        InterruptReason::from_usize(self.interrupt.load(Ordering::SeqCst))
+    }
+
+    //清除虚拟机的中断请求，返回被清除的中断原因
+    pub fn clear_interrupt(&self) -> Option<InterruptReason> {
+        InterruptReason::from_usize(self.interrupt.swap(0, Ordering::SeqCst))
+    }
+
+    //请求在当前调用完成后回收虚拟机，回收的虚拟机不会再被复用，已请求则忽略，返回是否是首次请求
+    pub fn request_recycle(&self, reason: String) -> bool {
+        let mut recycle = write_state("vm_recycle", &self.recycle);
+        if recycle.is_some() {
+            return false;
+        }
+
+        *recycle = Some(reason);
+        true
+    }
+
+    //获取虚拟机请求回收的原因，未请求回收则返回None
+    pub fn recycle_requested(&self) -> Option<String> {
+        read_state("vm_recycle", &self.recycle).clone()
+    }
+
+    //判断虚拟机是否已被销毁
+    pub fn is_destroyed(&self) -> bool {
+        self.destroyed.load(Ordering::SeqCst)
+    }
+
+    //显式销毁空闲的虚拟机，会移除虚拟机消息队列中等待执行的回调和定时任务，从虚拟机注册表中注销，回调当前调用的完成回调，
+    //并减少所属虚拟机工厂的统计，已销毁则忽略，正在执行任务的虚拟机无法销毁
+    pub fn destroy(&self) -> Result<(), VmError> {
+        if self.vm == 0 {
+            return Err(VmError::Invalid);
+        }
+
+        if self.destroyed.compare_and_swap(false, true, Ordering::SeqCst) {
+            //已销毁
+            return Ok(());
+        }
+
+        let old_status = unsafe { dukc_vm_status_switch(self.vm as *const c_void_ptr, JSStatus::NoTask as i8, JSStatus::Destroy as i8) };
+        if old_status == JSStatus::Destroy as i8 {
+            //已被隐式销毁
+            return Ok(());
+        } else if old_status != JSStatus::NoTask as i8 {
+            //虚拟机正在执行任务，则恢复销毁标记
+            self.destroyed.store(false, Ordering::SeqCst);
+            return Err(VmError::Busy(old_status));
+        }
+
+        self.destroy_barrier();
+        VM_ALLOCATED.fetch_sub(self.last_heap_size.load(Ordering::Relaxed), Ordering::Relaxed); //减少虚拟机占用内存
+        if let Some(factory) = self.get_factory() {
+            factory.sub_total_heap(self.last_heap_size.load(Ordering::Relaxed)); //减少虚拟机工厂的总堆大小
+        }
+        if let Some((ref lock, ref factory)) = self.collection {
+            if lock.load(Ordering::SeqCst) {
+                //属于虚拟机池的虚拟机，则减少虚拟机工厂的虚拟机数量
+                factory.throw(1);
+            }
+        }
+
+        info!("===> Vm Destroy Ok, vm: {:?}", self);
+        unsafe { dukc_vm_destroy(self.vm as *const c_void_ptr); }
+        VM_DESTROY_COUNT.sum(1);
+        Ok(())
+    }
+
+    //注册虚拟机销毁时的通知，虚拟机已销毁则忽略，返回是否注册成功
+    pub fn on_destroy(&self, hook: Box<FnOnce(Atom, usize)>) -> bool {
+        let mut hooks = lock_state("vm_destroy_hooks", &self.destroy_hooks);
+        if self.is_destroyed() {
+            return false;
+        }
+
+        hooks.push(hook);
+        true
+    }
+
+    //销毁屏障，在虚拟机被显式或隐式销毁前调用，取消所有等待执行的回调、消息和定时任务，回调当前调用的完成回调，
+    //从虚拟机注册表中注销，并通知所有通道对端，调用前必须已设置销毁标记，保证之后不会有新的任务被推送
+    fn destroy_barrier(&self) {
+        //增加虚拟机代数，保证销毁前获取的虚拟机代数都已过期
+        self.recycle_epoch.fetch_add(1, Ordering::SeqCst);
+
+        //移除虚拟机消息队列，等待执行的回调、消息和定时任务会被丢弃，已推送的任务在执行时检查销毁标记后忽略
+        let queue = self.queue.id.swap(0, Ordering::SeqCst);
+        if queue != 0 {
+            remove_js_task_queue(queue);
+        }
+        self.queue.size.store(0, Ordering::SeqCst);
+        let canceled = {
+            let mut pending = lock_state("vm_pending_tasks", &self.pending_tasks);
+            let len = pending.len();
+            pending.clear();
+            len
+        };
+        lock_state("vm_running_task", &self.running_task).take();
+        lock_state("vm_pending_callbacks", &self.pending_callbacks).clear();
+        if canceled > 0 {
+            VM_CANCEL_TASK_COUNT.sum(canceled);
+            info!("===> Vm Destroy Barrier, pending tasks canceled, vm: {}, name: {:?}, canceled: {}",
+                  self.id, (&self.name).to_string(), canceled);
+        }
+
+        //回调当前调用的完成回调
+        if let Some((callback, _)) = self.take_finish() {
+            callback(Err(format!("vm destroyed, vm: {}", self.id)));
+        }
+        if let Some((callback, _)) = self.take_finish_value() {
+            callback(Err(format!("vm destroyed, vm: {}", self.id)));
+        }
+
+        unregister_vm(&self.name, self.id);
+
+        //通知所有通道对端
+        let hooks: Vec<Box<FnOnce(Atom, usize)>> = lock_state("vm_destroy_hooks", &self.destroy_hooks).drain(..).collect();
+        for hook in hooks {
+            hook(self.name.clone(), self.id);
+        }
+    }
+
+    //记录开始在虚拟机内执行，在调用dukc_call或dukc_continue前调用，不属于任何调用的执行会清除之前执行遗留的中断请求
+    pub fn begin_run(&self) {
+        let depth = lock_state("vm_run_lock", &self.stat.run_lock);
+        if *depth == 0 && read_state("vm_call_stat", &self.stat.port).is_none() {
+            self.clear_interrupt();
+        }
+        self.stat.run_start.store(now_utc(), Ordering::Relaxed);
+    }
+
+    //记录结束在虚拟机内执行，并累计执行时长
+    pub fn end_run(&self) {
+        let start = {
+            let _lock = lock_state("vm_run_lock", &self.stat.run_lock);
+            self.stat.run_start.swap(0, Ordering::Relaxed)
+        };
+        if start > 0 {
+            let time = now_utc().saturating_sub(start);
+            self.stat.run_time.fetch_add(time, Ordering::Relaxed);
+            if let Some(factory) = self.get_factory() {
+                //累计虚拟机所属虚拟机工厂在当前统计窗口内的执行时长
+                factory.add_cpu_time(time);
+            }
+        }
+    }
+
+    //增加当前调用推送的异步回调数量
+    pub fn add_callback_count(&self) {
+        self.stat.callbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //增加当前调用通过虚拟机通道收到的字节数
+    pub fn add_bytes_in(&self, size: usize) {
+        self.stat.bytes_in.fetch_add(size, Ordering::Relaxed);
     }
 
-    //移除虚拟机注册的指定长驻回调函数
-    pub fn remove_callback(js: Arc<JS>, task_type: TaskType, callback: u32, info: Atom) -> Option<isize> {
-        //向指定虚拟机的消息队列推送异步回调任务
-        let js_copy = js.clone();
-        let func = Box::new(move |_lock| {
-            unsafe {
-                let vm = js_copy.get_vm();
-                dukc_remove_callback(vm, callback); //移除虚拟机注册的指定回调函数
+    //增加当前调用通过虚拟机通道发送的字节数
+    pub fn add_bytes_out(&self, size: usize) {
+        self.stat.bytes_out.fetch_add(size, Ordering::Relaxed);
+    }
 
-                //调用一定存在的函数，保证虚拟机可以自动退出
-                js_copy.get_link_function("Math.abs".to_string());
-                js_copy.new_u32(0);
-                dukc_call(vm, 1, js_reply_callback);
-            }
-        });
-        js.queue.size.fetch_add(1, Ordering::SeqCst) + 1; //增加消息队列长度，并返回
+    //结束当前调用的统计，并返回资源使用报告，当前没有统计中的调用则返回None
+    pub fn finish_call(&self) -> Option<CallReport> {
+        let lock = lock_state("vm_run_lock", &self.stat.run_lock); //在执行锁内结束调用，之后的中断请求会被忽略
+        let port = match write_state("vm_call_stat", &self.stat.port).take() {
+            None => return None,
+            Some(port) => port,
+        };
 
-        //向指定虚拟机的消息队列推送异步回调任务
-        cast_js_task(task_type, 0, Some(js.get_queue()), func, info)
+        let now = now_utc();
+        let mut phases: Vec<(Atom, Duration)> = Vec::new();
+        let checkpoints: Vec<(Atom, usize)> = lock_state("vm_call_checkpoints", &self.stat.checkpoints).drain(..).collect();
+        for (index, (name, start)) in checkpoints.iter().enumerate() {
+            let end = checkpoints.get(index + 1).map_or(now, |next| next.1);
+            phases.push((name.clone(), Duration::from_micros(end.saturating_sub(*start) as u64)));
+        }
+        drop(lock);
+
+        Some(CallReport {
+            vm_id: self.id,
+            port,
+            latency: Duration::from_micros(now.saturating_sub(self.stat.submit_time.load(Ordering::Relaxed)) as u64),
+            wall_time: Duration::from_micros(now.saturating_sub(self.stat.start_time.load(Ordering::Relaxed)) as u64),
+            cpu_time: Duration::from_micros(self.stat.run_time.load(Ordering::Relaxed) as u64),
+            heap_delta: self.last_heap_size.load(Ordering::Relaxed) - self.stat.start_heap.load(Ordering::Relaxed),
+            callbacks: self.stat.callbacks.load(Ordering::Relaxed),
+            errors: self.stat.errors.load(Ordering::Relaxed),
+            bytes_in: self.stat.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.stat.bytes_out.load(Ordering::Relaxed),
+            phases,
+            fuel: self.fuel_used(),
+        })
+    }
+
+    //记录虚拟机等待执行的任务，重复记录同一任务只增加任务守护者数量，返回任务守护者，任务守护者应该由投递的任务持有
+    pub fn enqueue_task(&self, info: &TaskInfo) -> PendingTask {
+        lock_state("vm_pending_tasks", &self.pending_tasks)
+            .entry(info.id())
+            .or_insert_with(|| (info.clone(), 0))
+            .1 += 1;
+
+        PendingTask {
+            id: info.id(),
+            tasks: self.pending_tasks.clone(),
+        }
     }
 
-    //获取内部虚拟机
-    pub unsafe fn get_vm(&self) -> *const c_void_ptr {
-        self.vm as *const c_void_ptr
+    //开始执行虚拟机等待执行的指定任务
+    pub fn start_task(&self, id: usize) {
+        let task = lock_state("vm_pending_tasks", &self.pending_tasks).remove(&id);
+        if let Some((info, _)) = task {
+            *lock_state("vm_running_task", &self.running_task) = Some(info);
+        }
     }
 
-    //获取虚拟机堆大小
-    pub fn heap_size(&self) -> usize {
-        unsafe { dukc_vm_size(self.vm as *const c_void_ptr) }
+    //完成虚拟机正在执行的任务
+    pub fn finish_task(&self) -> Option<TaskInfo> {
+        lock_state("vm_running_task", &self.running_task).take()
     }
 
-    //更新虚拟机上次堆大小，并更新所有虚拟机占用内存大小
-    pub fn update_last_heap_size(&self) {
-        let cur_size = self.heap_size() as isize;
-        let last_size = self.last_heap_size.swap(cur_size, Ordering::Relaxed);
+    //获取虚拟机正在执行的任务
+    pub fn running_task(&self) -> Option<TaskInfo> {
+        lock_state("vm_running_task", &self.running_task).clone()
+    }
 
-        if last_size > cur_size {
-            //当前虚拟机堆变小了，从所有虚拟机占用内存中减去内存减量
-            VM_ALLOCATED.fetch_sub(last_size - cur_size, Ordering::Relaxed);
-        } if last_size < cur_size {
-            //当前虚拟机堆变大了，在所有虚拟机占用内存中增加内存增量
-            VM_ALLOCATED.fetch_add(cur_size - last_size, Ordering::Relaxed);
+    //降低虚拟机正在执行的任务的优先级，之后由当前任务发起的请求和回应回调使用新的优先级，只允许降低到不为0的优先级，
+    //返回实际使用的优先级，没有正在执行的任务则返回None
+    pub fn lower_task_priority(&self, priority: usize) -> Option<usize> {
+        let mut running = lock_state("vm_running_task", &self.running_task);
+        let info = running.as_mut()?;
+        let current = match info.priority() {
+            0 => self.get_factory().map_or_else(default_task_priority, |factory| factory.task_priority()),
+            priority => priority,
+        };
+
+        let priority = priority.max(1).min(current);
+        *info = info.clone().with_priority(priority);
+        Some(priority)
+    }
+
+    //获取虚拟机等待执行的任务列表，按任务投递顺序排序
+    pub fn pending_tasks(&self) -> Vec<TaskInfo> {
+        let mut list: Vec<TaskInfo> = lock_state("vm_pending_tasks", &self.pending_tasks)
+            .values()
+            .map(|(info, _)| info.clone())
+            .collect();
+        list.sort_by_key(|info| info.id());
+        list
+    }
+
+    //获取虚拟机等待执行的回调列表，不包括已取消的回调，按等待时长从长到短排序
+    pub fn pending_callbacks(&self) -> Vec<PendingCallback> {
+        let callbacks = lock_state("vm_pending_callbacks", &self.pending_callbacks);
+        let mut list: Vec<PendingCallback> = lock_state("vm_pending_tasks", &self.pending_tasks)
+            .values()
+            .filter_map(|(info, _)| {
+                match callbacks.get(&info.id()) {
+                    Some(&(callback, false)) => Some(PendingCallback {
+                        id: info.id(),
+                        callback,
+                        age: Duration::from_micros(info.elapsed() as u64),
+                        info: info.clone(),
+                    }),
+                    _ => None,
+                }
+            })
+            .collect();
+        list.sort_by(|x, y| y.age.cmp(&x.age));
+        list
+    }
+
+    //在回调被执行前取消虚拟机等待执行的指定回调，被取消的回调不会被执行，如果js设置了__pi_vm.oncanceled，则会以回调函数和任务名为参数通知js，
+    //回调不存在、已被执行或已被取消则返回false
+    pub fn cancel_callback(&self, id: usize) -> bool {
+        {
+            let mut callbacks = lock_state("vm_pending_callbacks", &self.pending_callbacks);
+            match callbacks.get_mut(&id) {
+                Some(entry) if !entry.1 => entry.1 = true,
+                _ => return false,
+            }
+        }
+
+        let task = lock_state("vm_pending_tasks", &self.pending_tasks).remove(&id);
+        if let Some((info, _)) = task {
+            VM_CANCEL_CALLBACK_COUNT.sum(1);
+            info!("===> Vm Cancel Callback Ok, vm: {}, name: {:?}, task: {}, age: {}us",
+                  self.id, (&self.name).to_string(), info, info.elapsed());
         }
+        true
     }
 
-    //获取虚拟机上次运行时间
-    pub fn last_time(&self) -> usize {
-        self.last_time.load(Ordering::Relaxed)
+    //设置当前调用的完成回调，调用完成后回调执行结果，执行结果为js调用的返回值的字符串，如果执行异常，则回调异常信息
+    pub fn set_finish(&self, callback: Box<FnOnce(Result<Option<String>, String>)>) {
+        *self.finish_error.borrow_mut() = None;
+        *self.ret.borrow_mut() = Some(String::new()); //设置返回值缓存，以缓存当前调用的返回值
+        *self.finish.borrow_mut() = Some(callback);
     }
 
-    //设置虚拟机上次运行时间，单位ms，返回上次运行时间
-    pub fn set_last_time(&self, time: usize) -> usize {
-        self.last_time.swap(time * 1000, Ordering::SeqCst)
+    //追加当前调用的完成回调，已设置完成回调则先回调追加的完成回调，再回调已设置的完成回调，不会替换已设置的完成回调
+    pub fn chain_finish(&self, callback: Box<FnOnce(Result<Option<String>, String>)>) {
+        let prev = self.finish.borrow_mut().take();
+        match prev {
+            None => self.set_finish(callback),
+            Some(prev) => {
+                *self.finish.borrow_mut() = Some(Box::new(move |result: Result<Option<String>, String>| {
+                    callback(result.clone());
+                    prev(result);
+                }));
+            },
+        }
     }
 
-    //更新虚拟机上次运行时间，并返回上次运行时间
-    pub fn update_last_time(&self) -> usize {
-        self.last_time.swap(now_utc(), Ordering::SeqCst)
+    //取出虚拟机最近一次执行异常，包括加载字节码的异常
+    pub fn take_last_error(&self) -> Option<String> {
+        lock_state("vm_last_error", &self.last_error).take()
     }
 
-    //初始化虚拟机字符输出
-    pub fn init_char_output(&self, output: extern fn(*const c_char)) {
-        unsafe {
-            dukc_init_char_output(self.vm as *const c_void_ptr, output);
+    //记录当前调用的执行异常，当前调用没有完成回调则忽略
+    pub fn set_finish_error(&self, error: String) {
+        if self.finish.borrow().is_some() || self.finish_value.borrow().is_some() {
+            *self.finish_error.borrow_mut() = Some(error);
         }
     }
 
-    //解锁虚拟机回收器
-    pub fn unlock_collection(&self) {
-        if let Some((lock, _)) = &self.collection {
-            //有回收器，则解锁
-            lock.swap(true, Ordering::SeqCst);
-        }
+    //取出当前调用的完成回调和执行结果，并重置返回值缓存
+    pub fn take_finish(&self) -> Option<(Box<FnOnce(Result<Option<String>, String>)>, Result<Option<String>, String>)> {
+        let callback = match self.finish.borrow_mut().take() {
+            None => return None,
+            Some(callback) => callback,
+        };
+
+        let ret = self.ret.borrow_mut().take();
+        let result = match self.finish_error.borrow_mut().take() {
+            None => Ok(ret),
+            Some(e) => Err(e),
+        };
+        Some((callback, result))
     }
 
-    //设置虚拟机异常捕获器
-    pub fn set_catcher(&self, catcher: i32) {
-        self.catcher.store(catcher, Ordering::SeqCst);
+    //设置当前调用的返回值回调，调用完成后回调js调用的返回值，如果执行异常，则回调异常信息
+    pub fn set_finish_value(&self, callback: Box<FnOnce(Result<CallValue, String>)>) {
+        *self.finish_error.borrow_mut() = None;
+        *self.ret_value.borrow_mut() = Some(CallValue::Undefined); //设置返回值缓存，以缓存当前调用的返回值
+        *self.finish_value.borrow_mut() = Some(callback);
+    }
+
+    //取出当前调用的返回值回调和返回值，并重置返回值缓存
+    pub fn take_finish_value(&self) -> Option<(Box<FnOnce(Result<CallValue, String>)>, Result<CallValue, String>)> {
+        let callback = match self.finish_value.borrow_mut().take() {
+            None => return None,
+            Some(callback) => callback,
+        };
+
+        let ret = self.ret_value.borrow_mut().take().unwrap_or(CallValue::Undefined);
+        let error = if self.finish.borrow().is_some() {
+            //同时设置了完成回调，则保留执行异常
+            self.finish_error.borrow().clone()
+        } else {
+            self.finish_error.borrow_mut().take()
+        };
+        let result = match error {
+            None => Ok(ret),
+            Some(e) => Err(e),
+        };
+        Some((callback, result))
+    }
+
+    //结束当前调用的统计，并将资源使用报告提交给所属虚拟机工厂的报告回调，返回是否有统计中的调用
+    pub fn report_call(&self) -> bool {
+        lock_state("vm_quota_permit", &self.quota_permit).take(); //调用完成，释放当前调用持有的源配额许可
+        lock_state("vm_cancel_flag", &self.cancel_flag).take();
+        if let Some(report) = self.finish_call() {
+            if let Some(factory) = self.get_factory() {
+                factory.record_latency(report.latency);
+                factory.record_phases(&report);
+                factory.record_outcome(&report);
+                factory.report(report);
+            }
+            return true;
+        }
+
+        false
     }
 
     //为当前虚拟机创建全局环境模板，如果已存在，则忽略
@@ -674,6 +2303,170 @@ impl JS {
         }
     }
 
+    //编译函数表达式源码，并导出函数的字节码，用于在虚拟机间共享频繁执行的代码片段的编译结果，需要启用funcdump特性构建，当前构建不支持则返回错误
+    pub fn dump_function(&self, file: &str, source: &str) -> Result<Vec<u8>, String> {
+        if !cfg!(feature = "funcdump") {
+            return Err(format!("dump function failed, e: {}", VmError::Unsupported("funcdump")));
+        }
+        let file = CString::new(file).map_err(|e| format!("dump function failed, e: {:?}", e))?;
+        let source = CString::new(source).map_err(|e| format!("dump function failed, e: {:?}", e))?;
+
+        unsafe {
+            let status = dukc_vm_status_switch(self.vm as *const c_void_ptr, JSStatus::NoTask as i8, JSStatus::SingleTask as i8);
+            if status == JSStatus::SingleTask as i8 {
+                //当前虚拟机状态错误，无法编译
+                return Err("dump function failed, e: vm busy".to_string());
+            }
+            let result = dump_function(self.vm as *const c_void_ptr, &file, &source);
+            dukc_vm_status_switch(self.vm as *const c_void_ptr, JSStatus::SingleTask as i8, JSStatus::NoTask as i8);
+            match result {
+                Err(e) => Err(format!("dump function failed, e: {}", e)),
+                Ok(None) => Err(format!("dump function failed, e: {}", self.stack_top_string().unwrap_or("compile error".to_string()))),
+                Ok(Some(code)) => Ok(code),
+            }
+        }
+    }
+
+    //加载已导出的函数字节码，并设置为指定名称的全局函数，需要启用funcdump特性构建，当前构建不支持则返回错误
+    pub fn load_function(&self, name: &str, code: &[u8]) -> Result<(), String> {
+        let ptr = match load_function(self.vm as *const c_void_ptr, code) {
+            Err(e) => return Err(format!("load function failed, name: {:?}, e: {}", name, e)),
+            Ok(None) => {
+                warn!("!!!> JS Load Function Error, vm: {:?}, name: {:?}", self, name);
+                return Err(format!("load function failed, name: {:?}, e: invalid code", name));
+            },
+            Ok(Some(ptr)) => ptr,
+        };
+
+        let value = JSType {
+            type_id: JSValueType::Object as u8,
+            is_drop: false,
+            vm: self.vm,
+            value: ptr as usize,
+        };
+        if !self.set_global_var(name.to_string(), value) {
+            return Err(format!("load function failed, name: {:?}, e: set global failed", name));
+        }
+        Ok(())
+    }
+
+    //序列化当前虚拟机的堆快照，用于快速构建加载了相同字节码的虚拟机，需要启用snapshot特性构建，当前构建不支持则返回错误
+    pub fn heap_snapshot(&self) -> Result<Vec<u8>, String> {
+        if !cfg!(feature = "snapshot") {
+            return Err(format!("heap snapshot failed, e: {}", VmError::Unsupported("snapshot")));
+        }
+
+        unsafe {
+            let status = dukc_vm_status_switch(self.vm as *const c_void_ptr, JSStatus::NoTask as i8, JSStatus::SingleTask as i8);
+            if status == JSStatus::SingleTask as i8 {
+                //当前虚拟机状态错误，无法序列化
+                return Err("heap snapshot failed, e: vm busy".to_string());
+            }
+            let result = heap_snapshot(self.vm as *const c_void_ptr);
+            dukc_vm_status_switch(self.vm as *const c_void_ptr, JSStatus::SingleTask as i8, JSStatus::NoTask as i8);
+            match result {
+                Err(e) => Err(format!("heap snapshot failed, e: {}", e)),
+                Ok(None) => Err("heap snapshot failed, e: serialize error".to_string()),
+                Ok(Some(snapshot)) => Ok(snapshot),
+            }
+        }
+    }
+
+    //使用堆快照恢复当前虚拟机的堆，恢复后等同于已加载快照对应的字节码，只允许在新构建的虚拟机上恢复，需要启用snapshot特性构建，当前构建不支持则返回错误
+    pub fn restore_heap_snapshot(&self, snapshot: &[u8]) -> Result<(), String> {
+        if !cfg!(feature = "snapshot") {
+            return Err(format!("restore heap snapshot failed, e: {}", VmError::Unsupported("snapshot")));
+        }
+
+        unsafe {
+            let status = dukc_vm_status_switch(self.vm as *const c_void_ptr, JSStatus::NoTask as i8, JSStatus::SingleTask as i8);
+            if status == JSStatus::SingleTask as i8 {
+                //当前虚拟机状态错误，无法恢复
+                return Err("restore heap snapshot failed, e: vm busy".to_string());
+            }
+            let result = heap_restore(self.vm as *const c_void_ptr, snapshot);
+            dukc_vm_status_switch(self.vm as *const c_void_ptr, JSStatus::SingleTask as i8, JSStatus::NoTask as i8);
+            match result {
+                Err(e) => Err(format!("restore heap snapshot failed, e: {}", e)),
+                Ok(false) => Err("restore heap snapshot failed, e: invalid snapshot".to_string()),
+                Ok(true) => Ok(()),
+            }
+        }
+    }
+
+    //序列化当前虚拟机的全局状态快照，用于在崩溃或迁移后将全局状态恢复到新的虚拟机，
+    //只包括可以JSON序列化的全局变量，函数、以“__”开始的内部全局变量和无法序列化的值会被忽略，全局变量存在循环引用则失败
+    pub fn snapshot(&self) -> Result<Vec<u8>, String> {
+        let json = self.eval_state("(function(g) {\
+                                        var state = {};\
+                                        for (var key in g) {\
+                                            if (!Object.prototype.hasOwnProperty.call(g, key) || key.indexOf('__') === 0 || typeof g[key] === 'function') continue;\
+                                            state[key] = g[key];\
+                                        }\
+                                        return JSON.stringify(state);\
+                                    })(this)".to_string(), "snapshot")?;
+
+        let mut snapshot = Vec::with_capacity(VM_STATE_MAGIC.len() + json.len());
+        snapshot.extend_from_slice(VM_STATE_MAGIC);
+        snapshot.extend_from_slice(json.as_bytes());
+        Ok(snapshot)
+    }
+
+    //使用全局状态快照恢复当前虚拟机的全局变量，快照中的全局变量会覆盖当前虚拟机的同名全局变量，但不会覆盖全局函数
+    pub fn restore(&self, snapshot: &[u8]) -> Result<(), String> {
+        if !snapshot.starts_with(VM_STATE_MAGIC) {
+            return Err("restore vm state failed, e: invalid snapshot".to_string());
+        }
+        let json = match String::from_utf8(snapshot[VM_STATE_MAGIC.len()..].to_vec()) {
+            Err(e) => return Err(format!("restore vm state failed, e: {:?}", e)),
+            Ok(json) => json,
+        };
+
+        self.eval_state(format!("(function(g, state) {{\
+                                    for (var key in state) {{\
+                                        if (typeof g[key] === 'function') continue;\
+                                        g[key] = state[key];\
+                                    }}\
+                                    return '';\
+                                }})(this, JSON.parse({}))", js_string(&json)), "restore")?;
+        Ok(())
+    }
+
+    //在当前虚拟机空闲时执行全局状态脚本，并返回脚本执行结果的字符串
+    fn eval_state(&self, script: String, op: &str) -> Result<String, String> {
+        unsafe {
+            let status = dukc_vm_status_switch(self.vm as *const c_void_ptr, JSStatus::NoTask as i8, JSStatus::SingleTask as i8);
+            if status == JSStatus::SingleTask as i8 {
+                //当前虚拟机状态错误，无法执行
+                return Err(format!("{} vm state failed, e: vm busy, status: {}", op, status));
+            }
+
+            let value = self.eval(script);
+            let result = if value.is_string() {
+                Ok(value.get_str())
+            } else {
+                Err(format!("{} vm state failed, e: {}", op, self.stack_top_string().unwrap_or("eval error".to_string())))
+            };
+            dukc_vm_status_switch(self.vm as *const c_void_ptr, JSStatus::SingleTask as i8, JSStatus::NoTask as i8);
+            result
+        }
+    }
+
+    //获取虚拟机重置全局环境的次数，即虚拟机代数，虚拟机每次被回收重置或销毁时增加，
+    //跨越异步边界持有虚拟机的调用者应在持有时记录代数，并在使用前检查代数
+    pub fn recycle_epoch(&self) -> usize {
+        self.recycle_epoch.load(Ordering::SeqCst)
+    }
+
+    //检查持有的虚拟机代数是否仍然有效，虚拟机已被回收重置或销毁则返回过期错误
+    pub fn check_epoch(&self, epoch: usize) -> Result<(), VmError> {
+        let current = self.recycle_epoch();
+        if current != epoch {
+            return Err(VmError::Stale(epoch, current));
+        }
+        Ok(())
+    }
+
     //为当前虚拟机分配新的全局环境
     pub fn alloc_global(&self) -> bool {
         unsafe {
@@ -697,6 +2490,8 @@ impl JS {
                 //当前虚拟机状态错误，无法清理
                 false
             } else {
+                //清理前增加重置次数，保证清理前推送的异步回调在执行时被拒绝
+                self.recycle_epoch.fetch_add(1, Ordering::SeqCst);
                 let result = dukc_vm_global_clear(self.vm as *const c_void_ptr) != 0;
                 dukc_vm_status_switch(self.vm as *const c_void_ptr, JSStatus::SingleTask as i8, JSStatus::NoTask as i8);
                 result
@@ -769,6 +2564,11 @@ impl JS {
         self.objs_ref.clone()
     }
 
+    //获取虚拟机拥有所有权的本地对象数量，本地对象表正在被修改时返回None
+    pub fn native_object_count(&self) -> Option<usize> {
+        self.objs.0.try_borrow().ok().map(|objs| objs.len())
+    }
+
     //获取虚拟机最近执行结果
     pub fn get_ret(&self) -> Option<String> {
         if self.ret.borrow().is_none() {
@@ -799,6 +2599,17 @@ impl JS {
         unsafe { dukc_vm_status_check(self.vm as *const c_void_ptr, JSStatus::WaitCallBack as i8) > 0 }
     }
 
+    //获取js虚拟机的当前状态
+    pub fn status(&self) -> Option<JSStatus> {
+        if self.is_destroyed() {
+            return Some(JSStatus::Destroy);
+        }
+
+        let mut status = [JSStatus::Destroy as i8];
+        status_batch(&[self.vm as *const c_void_ptr], &mut status);
+        JSStatus::from_raw(status[0])
+    }
+
     //编译指定脚本
     pub fn compile(&self, file: String, script: String) -> Option<Vec<u8>> {
         let mut len = 0u32;
@@ -825,6 +2636,15 @@ impl JS {
 
     //加载指定代码
     pub fn load(&self, codes: &[u8]) -> bool {
+        let codes = match check_bytecode(codes, duk_version) {
+            Err(e) => {
+                //字节码格式错误，则不交给虚拟机库加载，避免虚拟机库加载不兼容的字节码时崩溃
+                warn!("!!!> JS Load Error, vm: {:?}, e: {}", self, e);
+                *lock_state("vm_last_error", &self.last_error) = Some(e.to_string());
+                return false;
+            },
+            Ok(codes) => codes,
+        };
         let size = codes.len() as u32;
         let bytes = codes.as_ptr() as *const c_void_ptr;
         unsafe {
@@ -1037,25 +2857,13 @@ impl JS {
 
     //构建字符串，注意rust的字符串默认是UTF8编码，而JS是UTF16编码
     pub fn new_str(&self, str: String) -> Result<JSType, String> {
-        match CString::new(str) {
-            Err(e) => {
-                Err(e.to_string())
-            },
-            Ok(cstring) => {
-                let ptr: u32;
-                let str_ptr = CString::into_raw(cstring);
-                unsafe {
-                    ptr = dukc_new_string(self.vm as *const c_void_ptr, str_ptr as *const c_char);
-                    CString::from_raw(str_ptr);
-                }
-                Ok(JSType {
-                    type_id: JSValueType::String as u8,
-                    is_drop: false,
-                    vm: self.vm,
-                    value: ptr as usize,
-                })
-            }
-        }
+        let ptr = self.with_c_str(&str, |str_ptr| unsafe { dukc_new_string(self.vm as *const c_void_ptr, str_ptr) })?;
+        Ok(JSType {
+            type_id: JSValueType::String as u8,
+            is_drop: false,
+            vm: self.vm,
+            value: ptr as usize,
+        })
     }
 
     //构建对象
@@ -1108,21 +2916,15 @@ impl JS {
             //如果对象和值不是在指定虚拟机上创建的，则忽略
             return false;
         }
-        unsafe {
-            let key_ptr = CString::into_raw(CString::new(key).unwrap());
-            if dukc_set_object_field(self.vm as *const c_void_ptr, object.value as u32, key_ptr as *const c_char,
-                value.value as u32) == 0 {
-                CString::from_raw(key_ptr);
-                return false;
-            }
-            CString::from_raw(key_ptr);
+        if !self.with_c_str(&key, |key_ptr| unsafe { dukc_set_object_field(self.vm as *const c_void_ptr, object.value as u32, key_ptr, value.value as u32) != 0 }).unwrap_or(false) {
+            return false;
+        }
 
-            if value.is_drop {
-                //已使用，则设置为不自动释放
-                value.is_drop = false;
-            }
-            true
+        if value.is_drop {
+            //已使用，则设置为不自动释放
+            value.is_drop = false;
         }
+        true
     }
 
     //构建数组
@@ -1191,44 +2993,20 @@ impl JS {
 
     //获取指定函数
     pub fn get_js_function(&self, func: String) -> bool {
-        let func_ptr = CString::into_raw(CString::new(func).unwrap());
-        unsafe {
-            if dukc_get_js_function(self.vm as *const c_void_ptr, func_ptr as *const c_char) == 0 {
-                CString::from_raw(func_ptr);
-                return false;
-            }
-
-            CString::from_raw(func_ptr);
-            true
-        }
+        self.with_c_str(&func, |func_ptr| unsafe { dukc_get_js_function(self.vm as *const c_void_ptr, func_ptr) != 0 })
+            .unwrap_or(false)
     }
 
     //链式获取指定函数
     pub fn get_link_function(&self, func: String) -> bool {
-        let func_ptr = CString::into_raw(CString::new(func).unwrap());
-        unsafe {
-            if dukc_link_js_function(self.vm as *const c_void_ptr, func_ptr as *const c_char) == 0 {
-                CString::from_raw(func_ptr);
-                return false;
-            }
-
-            CString::from_raw(func_ptr);
-            true
-        }
+        self.with_c_str(&func, |func_ptr| unsafe { dukc_link_js_function(self.vm as *const c_void_ptr, func_ptr) != 0 })
+            .unwrap_or(false)
     }
 
     //链式检查指定函数
     pub fn check_function(&self, func: String) -> bool {
-        let func_ptr = CString::into_raw(CString::new(func).unwrap());
-        unsafe {
-            if dukc_check_js_function(self.vm as *const c_void_ptr, func_ptr as *const c_char) == 0 {
-                CString::from_raw(func_ptr);
-                return false;
-            }
-
-            CString::from_raw(func_ptr);
-            true
-        }
+        self.with_c_str(&func, |func_ptr| unsafe { dukc_check_js_function(self.vm as *const c_void_ptr, func_ptr) != 0 })
+            .unwrap_or(false)
     }
 
     //调用指定函数
@@ -1242,6 +3020,7 @@ impl JS {
             } else {
                 //增加当前虚拟机消息队列长度，并开始执行任务
                 self.add_queue_len();
+                self.begin_run();
                 dukc_call(self.vm as *const c_void_ptr, len as u8, js_reply_callback);
             }
         }
@@ -1249,21 +3028,16 @@ impl JS {
 
     //设置指定全局变量的值，需要传递值的所有权，所以只读的值不允许设置为全局变量
     pub fn set_global_var(&self, key: String, value: JSType) -> bool {
-        unsafe {
-            let key_ptr = CString::into_raw(CString::new(key).unwrap());
-            if dukc_set_global_var(self.vm as *const c_void_ptr, key_ptr as *const c_char) == 0 {
-                CString::from_raw(key_ptr);
-                return false;
-            }
-            CString::from_raw(key_ptr);
+        if !self.with_c_str(&key, |key_ptr| unsafe { dukc_set_global_var(self.vm as *const c_void_ptr, key_ptr) != 0 }).unwrap_or(false) {
+            return false;
+        }
 
-            if value.is_drop {
-                //已使用，则设置为不自动释放
-                let mut value_mut = value;
-                value_mut.is_drop = false;
-            }
-            true
+        if value.is_drop {
+            //已使用，则设置为不自动释放
+            let mut value_mut = value;
+            value_mut.is_drop = false;
         }
+        true
     }
 
     //调用指定函数，并返回
@@ -1297,7 +3071,9 @@ impl JS {
         let vm = self.vm as *const c_void_ptr;
         unsafe {
             let script_ptr = CString::into_raw(CString::new(script).unwrap());
+            self.begin_eval();
             ptr = dukc_eval(vm, script_ptr as *const c_char);
+            self.end_eval();
             if ptr <= 0 {
                 CString::from_raw(script_ptr);
                 Arc::new(JSType {
@@ -1319,6 +3095,21 @@ impl JS {
         }
     }
 
+    //记录开始执行脚本求值，不属于任何调用或执行的求值会清除之前遗留的中断请求
+    fn begin_eval(&self) {
+        let mut depth = lock_state("vm_run_lock", &self.stat.run_lock);
+        if *depth == 0 && read_state("vm_call_stat", &self.stat.port).is_none() && self.stat.run_start.load(Ordering::Relaxed) == 0 {
+            self.clear_interrupt();
+        }
+        *depth += 1;
+    }
+
+    //记录结束执行脚本求值
+    fn end_eval(&self) {
+        let mut depth = lock_state("vm_run_lock", &self.stat.run_lock);
+        *depth = depth.saturating_sub(1);
+    }
+
     //获取当前虚拟机栈顶数据信息
     pub fn stack_top_string(&self) -> Option<String> {
         let value;
@@ -1337,6 +3128,24 @@ impl JS {
         }
     }
 
+    //获取当前虚拟机栈顶的值，并转换为调用返回值
+    pub fn stack_top_value(&self) -> CallValue {
+        unsafe {
+            let value = dukc_top(self.vm as *const c_void_ptr);
+            if value < 0 {
+                return CallValue::Undefined;
+            }
+
+            let top = JSType {
+                type_id: dukc_get_value_type(self.vm as *const c_void_ptr, value as u32),
+                is_drop: false, //栈顶的值由虚拟机移除，不需要回收
+                vm: self.vm,
+                value: value as usize,
+            };
+            CallValue::from(&top)
+        }
+    }
+
     //获取当前虚拟机堆栈信息
     pub fn dump_stack(&self) -> String {
         unsafe { CStr::from_ptr(dukc_dump_stack(self.vm as *const c_void_ptr)).to_string_lossy().into_owned() }
@@ -1427,6 +3236,40 @@ impl JS {
     }
 }
 
+/*
+* 调用返回值，由js类型转换，不依赖虚拟机，可以在调用完成后使用
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallValue {
+    Undefined,
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Bytes(Vec<u8>), //ArrayBuffer或Uint8Array
+    Other(String),  //其它js类型，值为js对象的字符串
+}
+
+impl<'a> From<&'a JSType> for CallValue {
+    fn from(value: &'a JSType) -> Self {
+        if value.is_none() || value.is_undefined() {
+            CallValue::Undefined
+        } else if value.is_null() {
+            CallValue::Null
+        } else if value.is_boolean() {
+            CallValue::Boolean(value.get_boolean())
+        } else if value.is_number() {
+            CallValue::Number(value.get_f64())
+        } else if value.is_string() {
+            CallValue::String(value.get_str())
+        } else if value.is_array_buffer() || value.is_uint8_array() {
+            CallValue::Bytes(value.to_bytes().to_vec())
+        } else {
+            CallValue::Other(value.to_string().unwrap_or_default())
+        }
+    }
+}
+
 /*
 * 值类型
 */
@@ -2155,15 +3998,69 @@ pub fn set_vm_timeout(timeout: usize) -> usize {
     VM_TIMEOUT.swap(timeout * 1000, Ordering::SeqCst) / 1000
 }
 
+/*
+* 设置所有虚拟机共享的只读字符串表，例如代码包常用的属性名，表中的字符串被固定在共享的只读字符串表中，虚拟机使用时不再在各自的堆中分配，
+* 必须在构建第一个虚拟机前设置，且只允许设置一次，需要启用pinnedstrings特性构建，当前构建不支持则返回错误，
+* 且虚拟机库启用外部字符串支持，返回虚拟机库接受的字符串数量，为0表示虚拟机库不支持
+*/
+pub fn set_pinned_strings(strings: &[&str]) -> Result<usize, String> {
+    if !cfg!(feature = "pinnedstrings") {
+        return Err(format!("set pinned strings failed, e: {}", VmError::Unsupported("pinnedstrings")));
+    }
+    if VM_HEAP_CREATED.load(Ordering::SeqCst) {
+        return Err("set pinned strings failed, e: vm already created".to_string());
+    }
+
+    let mut pinned = lock_state("vm_pinned_strings", &VM_PINNED_STRINGS);
+    if pinned.is_some() {
+        return Err("set pinned strings failed, e: already set".to_string());
+    }
+
+    let mut table: Vec<CString> = Vec::with_capacity(strings.len());
+    for s in strings {
+        if s.is_empty() {
+            continue;
+        }
+        let string = match CString::new(*s) {
+            Err(_) => return Err(format!("set pinned strings failed, string: {:?}, e: invalid string", s)),
+            Ok(string) => string,
+        };
+        if !table.contains(&string) {
+            table.push(string);
+        }
+    }
+
+    //字符串指针表会被虚拟机库持有，所以不会释放
+    let ptrs: &'static [*const c_char] = Box::leak(table.iter().map(|s| s.as_ptr()).collect::<Vec<*const c_char>>().into_boxed_slice());
+    let accepted = pin_strings(ptrs).map_err(|e| format!("set pinned strings failed, e: {}", e))?;
+    if accepted == 0 && !table.is_empty() {
+        warn!("!!!> Set Pinned Strings Ignored, external strings unsupported, count: {}", table.len());
+    } else {
+        info!("===> Set Pinned Strings Ok, count: {}, accepted: {}", table.len(), accepted);
+    }
+    *pinned = Some(table);
+    Ok(accepted)
+}
+
+/*
+* 获取所有虚拟机共享的只读字符串表
+*/
+pub fn pinned_strings() -> Vec<String> {
+    match lock_state("vm_pinned_strings", &VM_PINNED_STRINGS).as_ref() {
+        None => Vec::new(),
+        Some(table) => table.iter().map(|s| s.to_string_lossy().into_owned()).collect(),
+    }
+}
+
 /*
 * 线程安全的注册全局虚拟机堆整理定时器，同一时间应该只有一个全局堆整理
 */
 pub fn register_global_vm_heap_collect_timer(collect_timeout: usize) {
     //初始化虚拟机整理队列
-    let vm_coolect_queue_len = VM_COLLECT_QUEUE.lock().unwrap().len();
+    let vm_coolect_queue_len = lock_state("vm_collect_queue", &VM_COLLECT_QUEUE).len();
     if vm_coolect_queue_len == 0 {
-        let mut vm_collect_queue = VM_COLLECT_QUEUE.lock().unwrap();
-        for name in VM_FACTORY_REGISTERS.read().unwrap().keys() {
+        let mut vm_collect_queue = lock_state("vm_collect_queue", &VM_COLLECT_QUEUE);
+        for name in read_state("vm_factory_registers", &VM_FACTORY_REGISTERS).keys() {
             vm_collect_queue.push_back(name.clone());
         }
         warn!("!!!> Init Vm Collect Queue Ok, len: {}", vm_coolect_queue_len);
@@ -2177,7 +4074,7 @@ pub fn register_global_vm_heap_collect_timer(collect_timeout: usize) {
         VM_TIMEOUT.load(Ordering::Relaxed)
     };
     let runner = FuncRuner::new(Box::new(move || {
-        let func = Box::new(move |_lock| {
+        let func = Box::new(move || {
             let start_time = Instant::now();
             let mut factory_collect_time = Duration::from_millis(0);
             let last_heap_size = all_alloced_size();
@@ -2189,12 +4086,17 @@ pub fn register_global_vm_heap_collect_timer(collect_timeout: usize) {
             let mut factory_buf_free_vm_count = 0;
             let mut timeout_count = Arc::new(AtomicUsize::new(0));
             {
-                let mut vm_collect_queue = VM_COLLECT_QUEUE.lock().unwrap();
+                let mut vm_collect_queue = lock_state("vm_collect_queue", &VM_COLLECT_QUEUE);
                 if let Some(factory_name) = vm_collect_queue.pop_front() {
-                    if let Some(factory) = VM_FACTORY_REGISTERS.read().unwrap().get(&factory_name) {
+                    if let Some(factory) = read_state("vm_factory_registers", &VM_FACTORY_REGISTERS).get(&factory_name) {
                         let now = now_utc();
                         let timeout_count_copy = timeout_count.clone();
                         let factory_copy = factory.clone();
+                        let vm_timeout = match factory.idle_ttl() {
+                            //虚拟机工厂设置了空闲虚拟机的存活时长，且当前已分配内存未达最大堆限制，则使用虚拟机工厂的存活时长
+                            Some(ttl) if !is_alloced_limit() => ttl.as_micros() as usize,
+                            _ => vm_timeout,
+                        };
 
                         let start_factory_collect_time = Instant::now();
 
@@ -2258,7 +4160,7 @@ pub fn register_global_vm_heap_collect_timer(collect_timeout: usize) {
                 let mut high_loads = Vec::new();
 
                 //过滤出不同负载的虚拟机工厂
-                let mut vm_factory_registers = VM_FACTORY_REGISTERS.write().unwrap(); //为了保证在出现多个全局堆整理时，仍然可以安全整理，只获取写锁
+                let mut vm_factory_registers = write_state("vm_factory_registers", &VM_FACTORY_REGISTERS); //为了保证在出现多个全局堆整理时，仍然可以安全整理，只获取写锁
                 for factory in vm_factory_registers.values() {
                     factory.init_limit_capacity(); //为了限流整理，初始化虚拟机工厂限制容量
                     vm_factory_task_queue_len += factory.queue_len();
@@ -2364,7 +4266,12 @@ pub fn register_global_vm_heap_collect_timer(collect_timeout: usize) {
                   js_static_sync_task_size(), js_dyn_sync_task_size(), js_static_async_task_size(),
                   js_dyn_async_task_size(), Instant::now() - start_time);
         });
-        cast_js_task(TaskType::Async(false), 100, None, func, Atom::from("vm global collect task"));
+        if is_alloced_limit() {
+            //当前已分配内存已达最大堆限制，则使用虚拟机任务全局基础优先级立即整理，不让出
+            cast_js_task(TaskType::Async(false), default_task_priority(), None, Box::new(move |_lock: Option<isize>| func()), Atom::from("vm global collect task"));
+        } else {
+            cast_maintenance_task(func, Atom::from("vm global collect task"));
+        }
     }));
 
     TIMER.set_timeout(runner, collect_timeout as u32);
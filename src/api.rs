@@ -0,0 +1,92 @@
+/*
+* 虚拟机稳定接口，下游库应优先通过本模块使用虚拟机，adapter、pi_vm_impl等模块的内部接口可能在后续版本中变化
+* 实验性子系统需要通过对应的特性开启，未开启特性时不会导出
+*/
+
+/*
+* 稳定接口版本，只有在稳定接口发生不兼容修改时才会增加主版本
+* 2.0: VMFactory::call的端口名和任务信息改为PortName和TaskInfo，并返回调用是否被接收，旧的调用方式保留为VMFactory::call_atom
+*/
+pub const API_VERSION: (u32, u32) = (2, 0);
+
+pub use adapter::{VM_STATE_MAGIC, JS, JSType, JSStatus, JSValueType, JSBuffer, CallValue, VmProfile, DynamicCodeKind, EvalPolicy, InterruptReason, VmError, PendingCallback, PendingTask, register_native_object, set_vm_timeout, set_pinned_strings, pinned_strings, register_global_vm_heap_collect_timer, vm_status_batch};
+pub use pi_vm_impl::{VMFactory, VMFactoryError, LoadError, CallError, CallHandle, ArgsFn, FactoryDrain, FactoryShutdown, VMFactoryLoader, FactoryLimits, PendingLimits, PendingPolicy, LateCallbackPolicy, WarmupCall, OomInfo, OomAction, RecyclePolicy, BudgetExhausted, CallReport, PhaseStats, FactoryStats, ProduceReport, BlockError, PooledVm, Acquire, AcquireTimeout,
+                     block_set_global_var, block_reply, block_throw, push_callback, push_callback_with_reject, push_callback_checked, push_callback_sliced, push_msg,
+                     default_task_priority, set_default_task_priority, adjust_factory_task_priority, watch_call,
+                     register_async_request, register_async_request_with_version, register_channel_handler, is_async_request_registered, unregister_async_request, async_request, async_request_msg, async_request_with_receipt, set_channel_services, channel_handler_stats, set_channels_explain, explain_channel_requests};
+pub use bonmgr::{BON_MGR, NativeObjsAuth, FnMeta, CallResult, StructMeta, ptr_jstype, jstype_ptr};
+pub use channel_map::{INLINE_MSG_SIZE, ChannelMsg, VMChannel, VMChannelPeer, RequestStatus, RequestReceipt, HandlerStats, RouteTrace, ChannelHandler, HandlerAdapter, GenericChannelHandler};
+pub use task_info::TaskInfo;
+pub use health::{HealthReport, health, reset_health};
+pub use histogram::{LatencyHistogram, LatencySnapshot};
+pub use metrics::{factory_stats, export_metrics};
+pub use failover::{FactoryPair, FailoverEvent};
+pub use manifest::{HandlerVersion, VersionMismatch, ManifestReport, provide_handler_version, get_handler_version, read_manifest};
+pub use vm_registry::{VmStat, find_vms, vm_stats, vm_count, terminate_vm};
+pub use pool_controller::{PoolController, PoolEvent, PoolAdjustReason};
+pub use idle_gc::IdleCollector;
+pub use factory_builder::VMFactoryBuilder;
+pub use scratch::{ScratchLimits, ScratchValue, scratch_eval, scratch_eval_with, scratch_eval_code, reset_scratch_vm};
+pub use expression::{ExpressionEngine, CompiledExpr};
+pub use bundle::{BUNDLES_GLOBAL, wrap_bundle, compile_bundle, compile_source, bundle_port};
+pub use services::{SERVICES_ATTR, ServiceRegistry, find_service_registry};
+pub use capability::{Capabilities, capabilities};
+pub use code_cache::{code_hash, intern_code, intern_codes, code_cache_size, purge_code_cache};
+pub use code_source::{CodeSource, FileSource, DirSource, GlobSource, LoadOrder, FnSource, SourceReport, fetch_sources, fetch_sources_async};
+pub use code_verify::{CodeVerifier, SignatureVerifier, VerifiedCodes};
+pub use code_crypt::{CodeCipher, CodeDecryptor, encrypted_code, is_encrypted_code};
+pub use bytecode::{BytecodeError, duk_version, stamp_bytecode, bytecode_version, check_bytecode};
+pub use compile_cache::{COMPILE_CACHE_EXT, CompileCache};
+pub use maintenance::{MAINTENANCE_TASK_PRIORITY, maintenance_priority, maintenance_busy_threshold, set_maintenance_busy_threshold, cast_maintenance_task};
+pub use quota::{SourceQuota, Throttled, QuotaManager, QuotaPermit, set_quota_manager, quota_manager};
+pub use signature::{ValueType, SignatureError, PortCallError, PortSignature};
+pub use breaker::{BreakerConfig, BreakerState, CircuitBreaker};
+pub use func_cache::FunctionCache;
+pub use leak_detector::{LeakSample, LeakTrend, LeakReport, LeakDetector, leak_sample};
+pub use names::{FactoryName, PortName};
+pub use factory_registry::{RegistryStats, register_factory, unregister_factory, remove_factory, get_factory, factory_count, factory_names, factories, registered_factory_stats, registry_stats};
+pub use pipeline::{Pipeline, PipeStage, PipeNext, PipeCompensator, PipelineError, CompensateResult,
+                   register_pipeline, unregister_pipeline, get_pipeline, call_pipeline};
+
+/*
+* 调试器，提供虚拟机交互式调试
+*/
+#[cfg(feature = "debugger")]
+pub mod debugger {
+    pub use shell::{SHELL_MANAGER, ShellManager, ShellStatus, Shell};
+}
+
+/*
+* 集群，提供基于虚拟机的进程和进程间通信
+*/
+#[cfg(feature = "cluster")]
+pub mod cluster {
+    pub use proc::{ProcStatus, ProcInfo, Process, ProcessFactory};
+    pub use proc_pool::{set_factory, spawn_process, register_process, name_to_pid, get_status, queue_len,
+                        set_receiver, unset_receiver, set_catcher, unset_catcher, pid_send, name_send, close_process};
+    pub use duk_proc::{DukProcess, DukProcessFactory};
+}
+
+/*
+* 模糊测试，提供适配器不安全接口的模糊测试入口
+*/
+#[cfg(feature = "fuzzing")]
+pub mod fuzz {
+    pub use fuzz::{fuzz_load_code, fuzz_decode_json, fuzz_decode_cbor, fuzz_channel_payload};
+}
+
+/*
+* 压力测试，提供虚拟机池并发不变式的压力测试
+*/
+#[cfg(feature = "stress")]
+pub mod stress {
+    pub use stress::{StressConfig, StressReport, stress_factory};
+}
+
+/*
+* 热加载，提供虚拟机工厂代码文件修改后的热加载
+*/
+#[cfg(feature = "hotreload")]
+pub mod hot_reload {
+    pub use hot_reload::HotReloader;
+}
@@ -0,0 +1,199 @@
+use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use adapter::now_utc;
+use health::lock_state;
+
+lazy_static! {
+    //端口熔断器打开的次数
+    static ref VM_BREAKER_OPEN_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_breaker_open_count"), 0).unwrap();
+    //端口熔断器拒绝调用的次数
+    static ref VM_BREAKER_REJECT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_breaker_reject_count"), 0).unwrap();
+}
+
+/*
+* 端口熔断器配置
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct BreakerConfig {
+    pub error_rate:         f64,        //统计窗口内调用异常的比例达到此值时打开熔断器，取值范围(0, 1]
+    pub min_calls:          usize,      //统计窗口内至少完成此数量的调用才会判断异常比例，以避免少量调用误打开熔断器
+    pub window:             Duration,   //调用异常比例的统计窗口
+    pub cool_down:          Duration,   //熔断器打开后拒绝调用的时长，之后进入半开状态
+    pub half_open_calls:    usize,      //半开状态允许的探测调用数量，探测调用全部成功则关闭熔断器
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig {
+            error_rate: 0.5,
+            min_calls: 20,
+            window: Duration::from_secs(10),
+            cool_down: Duration::from_secs(30),
+            half_open_calls: 1,
+        }
+    }
+}
+
+/*
+* 端口熔断器状态
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakerState {
+    Closed,     //关闭，允许调用
+    Open,       //打开，拒绝调用
+    HalfOpen,   //半开，允许少量探测调用
+}
+
+/*
+* 端口的熔断统计
+*/
+struct PortBreaker {
+    state:          BreakerState,   //熔断器状态
+    window_start:   usize,          //当前统计窗口的开始时间，单位us
+    calls:          usize,          //当前统计窗口内完成的调用数量
+    failures:       usize,          //当前统计窗口内异常的调用数量
+    changed_at:     usize,          //熔断器状态改变的时间，单位us
+    probes:         usize,          //半开状态已允许的探测调用数量
+    successes:      usize,          //半开状态已成功的探测调用数量
+}
+
+impl PortBreaker {
+    //构建一个关闭的端口熔断统计
+    fn new(now: usize) -> Self {
+        PortBreaker {
+            state: BreakerState::Closed,
+            window_start: now,
+            calls: 0,
+            failures: 0,
+            changed_at: now,
+            probes: 0,
+            successes: 0,
+        }
+    }
+
+    //改变熔断器状态，并重置统计
+    fn change(&mut self, state: BreakerState, now: usize) {
+        self.state = state;
+        self.window_start = now;
+        self.calls = 0;
+        self.failures = 0;
+        self.changed_at = now;
+        self.probes = 0;
+        self.successes = 0;
+    }
+}
+
+/*
+* 端口熔断器，按端口统计调用异常的比例，异常比例超过阈值时打开熔断器并在冷却时长内直接拒绝调用，
+* 冷却后进入半开状态，允许少量探测调用，探测调用成功则关闭熔断器，失败则重新打开，以避免持续异常的端口浪费虚拟机和任务
+*/
+pub struct CircuitBreaker {
+    config: BreakerConfig,                          //熔断器配置
+    ports:  Mutex<HashMap<Atom, PortBreaker>>,      //端口的熔断统计
+}
+
+impl CircuitBreaker {
+    //构建一个指定配置的端口熔断器
+    pub fn new(config: BreakerConfig) -> Self {
+        CircuitBreaker {
+            config,
+            ports: Mutex::new(HashMap::new()),
+        }
+    }
+
+    //获取熔断器配置
+    pub fn config(&self) -> &BreakerConfig {
+        &self.config
+    }
+
+    //获取指定端口的熔断器状态
+    pub fn state(&self, port: &Atom) -> BreakerState {
+        lock_state("vm_breaker", &self.ports).get(port).map_or(BreakerState::Closed, |breaker| breaker.state)
+    }
+
+    //判断是否允许调用指定端口，不允许则返回熔断器剩余的冷却时长
+    pub fn allow(&self, port: &Atom) -> Result<(), Duration> {
+        let now = now_utc();
+        let cool_down = self.config.cool_down.as_micros() as usize;
+        let mut ports = lock_state("vm_breaker", &self.ports);
+        let breaker = match ports.get_mut(port) {
+            None => return Ok(()),
+            Some(breaker) => breaker,
+        };
+
+        let elapsed = now.saturating_sub(breaker.changed_at);
+        match breaker.state {
+            BreakerState::Closed => return Ok(()),
+            BreakerState::Open if elapsed < cool_down => {
+                VM_BREAKER_REJECT_COUNT.sum(1);
+                return Err(Duration::from_micros((cool_down - elapsed) as u64));
+            },
+            BreakerState::Open => {
+                //冷却结束，则进入半开状态
+                info!("===> Vm Breaker Half Open, port: {:?}", port.as_str());
+                breaker.change(BreakerState::HalfOpen, now);
+            },
+            BreakerState::HalfOpen if breaker.probes >= self.config.half_open_calls.max(1) && elapsed >= cool_down => {
+                //探测调用在冷却时长内都未完成，可能已被丢弃，则重新开始探测
+                breaker.change(BreakerState::HalfOpen, now);
+            },
+            BreakerState::HalfOpen => (),
+        }
+
+        if breaker.probes >= self.config.half_open_calls.max(1) {
+            VM_BREAKER_REJECT_COUNT.sum(1);
+            return Err(Duration::from_micros(cool_down.saturating_sub(now.saturating_sub(breaker.changed_at)) as u64));
+        }
+        breaker.probes += 1;
+        Ok(())
+    }
+
+    //记录指定端口的一次调用结果
+    pub fn record(&self, port: &Atom, is_ok: bool) {
+        let now = now_utc();
+        let mut ports = lock_state("vm_breaker", &self.ports);
+        let breaker = ports.entry(port.clone()).or_insert_with(|| PortBreaker::new(now));
+        match breaker.state {
+            BreakerState::Open => (), //打开状态下完成的调用是打开前已接收的调用，则忽略
+            BreakerState::HalfOpen if is_ok => {
+                breaker.successes += 1;
+                if breaker.successes >= self.config.half_open_calls.max(1) {
+                    info!("===> Vm Breaker Closed, port: {:?}", port.as_str());
+                    breaker.change(BreakerState::Closed, now);
+                }
+            },
+            BreakerState::HalfOpen => {
+                warn!("!!!> Vm Breaker Reopen, port: {:?}", port.as_str());
+                VM_BREAKER_OPEN_COUNT.sum(1);
+                breaker.change(BreakerState::Open, now);
+            },
+            BreakerState::Closed => {
+                if now.saturating_sub(breaker.window_start) >= self.config.window.as_micros() as usize {
+                    //当前统计窗口已结束，则开始新的统计窗口
+                    breaker.change(BreakerState::Closed, now);
+                }
+
+                breaker.calls += 1;
+                if !is_ok {
+                    breaker.failures += 1;
+                }
+                if breaker.calls >= self.config.min_calls && breaker.failures as f64 >= breaker.calls as f64 * self.config.error_rate {
+                    warn!("!!!> Vm Breaker Open, port: {:?}, calls: {}, failures: {}, cool down: {:?}",
+                          port.as_str(), breaker.calls, breaker.failures, self.config.cool_down);
+                    VM_BREAKER_OPEN_COUNT.sum(1);
+                    breaker.change(BreakerState::Open, now);
+                }
+            },
+        }
+    }
+
+    //重置指定端口的熔断器，返回是否存在指定端口的熔断统计
+    pub fn reset(&self, port: &Atom) -> bool {
+        lock_state("vm_breaker", &self.ports).remove(port).is_some()
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+
+use atom::Atom;
+
+use adapter::JS;
+use bonmgr::NativeObjsAuth;
+use native_bind::js_string;
+use names::PortName;
+use bytecode::{duk_version, stamp_bytecode};
+
+/*
+* 所有模块的命名空间所在的全局对象名
+*/
+pub const BUNDLES_GLOBAL: &'static str = "__bundles";
+
+/*
+* 将指定模块的源码包装在独立的模块作用域中，模块通过exports或module.exports导出的成员会合并到全局的__bundles[name]，
+* 模块内声明的变量和函数不会成为全局变量，以避免同一个虚拟机工厂加载的不同模块之间的全局冲突
+*/
+pub fn wrap_bundle(name: &str, source: &str) -> Result<String, String> {
+    check_bundle_name(name)?;
+
+    Ok(format!("var {global} = {global} || {{}};\n\
+                (function(__ns) {{\n\
+                var module = {{ exports: {{}} }};\n\
+                (function(exports, module) {{\n\
+                {source}\n\
+                }})(module.exports, module);\n\
+                for (var key in module.exports) {{ __ns[key] = module.exports[key]; }}\n\
+                }})({global}[{name}] = {global}[{name}] || {{}});\n",
+               global = BUNDLES_GLOBAL,
+               name = js_string(name),
+               source = source))
+}
+
+/*
+* 将指定模块的源码包装在独立的模块作用域中并编译为字节码，字节码可以通过VMFactory::append加载
+*/
+pub fn compile_bundle(name: &str, source: &str) -> Result<Arc<Vec<u8>>, String> {
+    let wrapped = wrap_bundle(name, source)?;
+    compile_source(&format!("{}.bundle.js", name), &wrapped)
+        .map_err(|e| format!("compile bundle failed, bundle: {:?}, e: {}", name, e))
+}
+
+/*
+* 将指定文件名的js源码编译为字节码，源码在全局作用域中执行，字节码可以通过VMFactory::append加载，
+* 字节码带有编译时的Duktape版本头，加载时版本不匹配则快速失败
+*/
+pub fn compile_source(file: &str, source: &str) -> Result<Arc<Vec<u8>>, String> {
+    if file.contains('\0') || source.contains('\0') {
+        return Err(format!("compile source failed, file: {:?}, e: invalid nul char", file));
+    }
+
+    let tmp = match JS::new(1, Atom::from("tmp vm"), Arc::new(NativeObjsAuth::new(None, None)), None) {
+        None => return Err(format!("compile source failed, file: {:?}, e: create vm failed", file)),
+        Some(vm) => vm,
+    };
+
+    match tmp.compile(file.to_string(), source.to_string()) {
+        None => Err(format!("compile source failed, file: {:?}, e: {}", file, tmp.stack_top_string().unwrap_or("compile error".to_string()))),
+        Some(code) => match duk_version() {
+            None => Ok(Arc::new(code)),
+            Some(version) => Ok(Arc::new(stamp_bytecode(&code, version))),
+        },
+    }
+}
+
+/*
+* 获取指定模块导出的函数的端口名，例如"__bundles.auth.login"
+*/
+pub fn bundle_port(name: &str, func: &str) -> Result<PortName, String> {
+    check_bundle_name(name)?;
+    PortName::new(&format!("{}.{}.{}", BUNDLES_GLOBAL, name, func))
+}
+
+//检查模块名，模块名只允许字母、数字、“_”和“$”，且不允许以数字开始，以保证可以作为端口路径
+fn check_bundle_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("invalid bundle name, e: empty".to_string());
+    }
+
+    if name.starts_with(|c: char| c.is_ascii_digit())
+        || name.chars().any(|c| !(c.is_ascii_alphanumeric() || c == '_' || c == '$')) {
+        return Err(format!("invalid bundle name, name: {:?}", name));
+    }
+
+    Ok(())
+}
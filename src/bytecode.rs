@@ -0,0 +1,128 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use atom::Atom;
+
+use adapter::JS;
+use bonmgr::NativeObjsAuth;
+
+/*
+* Duktape字节码的起始标记
+*/
+pub const BYTECODE_MARKER: u8 = 0xbf;
+
+/*
+* 字节码版本头的魔数，版本头的格式为魔数和4字节小端的Duktape版本，版本头之后为Duktape字节码
+*/
+pub const BYTECODE_HEADER_MAGIC: &'static [u8] = b"PIVMBC\0";
+
+/*
+* 字节码版本头长度
+*/
+pub const BYTECODE_HEADER_SIZE: usize = 11;
+
+lazy_static! {
+    //当前链接的虚拟机库的Duktape版本，为0表示未获取，为usize::MAX表示无法获取
+    static ref DUK_VERSION: AtomicUsize = AtomicUsize::new(0);
+}
+
+/*
+* 字节码格式错误
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum BytecodeError {
+    Empty,                      //字节码为空
+    InvalidMarker(u8),          //字节码起始标记错误，参数为实际的起始字节
+    VersionMismatch(u32, u32),  //字节码版本不匹配，依次为期望的版本和实际的版本
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BytecodeError::Empty => write!(f, "invalid bytecode, empty"),
+            BytecodeError::InvalidMarker(found) => write!(f, "invalid bytecode marker, expected: {:#04x}, found: {:#04x}", BYTECODE_MARKER, found),
+            BytecodeError::VersionMismatch(expected, found) => write!(f, "bytecode version mismatch, expected: {}, found: {}", version_string(*expected), version_string(*found)),
+        }
+    }
+}
+
+//将Duktape版本号转换为字符串，例如20500转换为2.5.0
+pub fn version_string(version: u32) -> String {
+    format!("{}.{}.{}", version / 10000, version / 100 % 100, version % 100)
+}
+
+//获取当前链接的虚拟机库的Duktape版本，只在首次调用时使用临时虚拟机获取，无法获取则返回None
+pub fn duk_version() -> Option<u32> {
+    match DUK_VERSION.load(Ordering::Relaxed) {
+        0 => (),
+        version if version == usize::max_value() => return None,
+        version => return Some(version as u32),
+    }
+
+    let version = match JS::new(1, Atom::from("tmp vm"), Arc::new(NativeObjsAuth::new(None, None)), None) {
+        None => return None, //构建临时虚拟机失败，下次重试
+        Some(tmp) => {
+            let value = tmp.eval("typeof Duktape === 'object' && Duktape.version ? Duktape.version : 0".to_string());
+            if value.is_number() { value.get_u32() } else { 0 }
+        },
+    };
+    if version == 0 {
+        DUK_VERSION.store(usize::max_value(), Ordering::Relaxed);
+        return None;
+    }
+    DUK_VERSION.store(version as usize, Ordering::Relaxed);
+    Some(version)
+}
+
+//为字节码增加指定Duktape版本的版本头，已有版本头则替换
+pub fn stamp_bytecode(code: &[u8], version: u32) -> Vec<u8> {
+    let code = match parse_header(code) {
+        Some((_, payload)) => payload,
+        None => code,
+    };
+
+    let mut stamped = Vec::with_capacity(BYTECODE_HEADER_SIZE + code.len());
+    stamped.extend_from_slice(BYTECODE_HEADER_MAGIC);
+    stamped.extend_from_slice(&[version as u8, (version >> 8) as u8, (version >> 16) as u8, (version >> 24) as u8]);
+    stamped.extend_from_slice(code);
+    stamped
+}
+
+//获取字节码版本头中的Duktape版本，没有版本头则返回None
+pub fn bytecode_version(code: &[u8]) -> Option<u32> {
+    parse_header(code).map(|(version, _)| version)
+}
+
+//检查字节码格式，有版本头则检查版本头中的版本与期望的版本是否相同，无法获取期望的版本则不检查版本，
+//然后检查Duktape字节码的起始标记，成功则返回去除版本头的Duktape字节码
+pub fn check_bytecode<'a, F: FnOnce() -> Option<u32>>(code: &'a [u8], expected: F) -> Result<&'a [u8], BytecodeError> {
+    let code = match parse_header(code) {
+        None => code,
+        Some((found, payload)) => {
+            if let Some(expected) = expected() {
+                if expected != found {
+                    return Err(BytecodeError::VersionMismatch(expected, found));
+                }
+            }
+            payload
+        },
+    };
+
+    match code.first() {
+        None => Err(BytecodeError::Empty),
+        Some(marker) if *marker != BYTECODE_MARKER => Err(BytecodeError::InvalidMarker(*marker)),
+        _ => Ok(code),
+    }
+}
+
+//解析字节码版本头，返回版本和版本头之后的字节码
+fn parse_header(code: &[u8]) -> Option<(u32, &[u8])> {
+    if code.len() < BYTECODE_HEADER_SIZE || !code.starts_with(BYTECODE_HEADER_MAGIC) {
+        return None;
+    }
+
+    let v = &code[BYTECODE_HEADER_MAGIC.len()..BYTECODE_HEADER_SIZE];
+    let version = v[0] as u32 | (v[1] as u32) << 8 | (v[2] as u32) << 16 | (v[3] as u32) << 24;
+    Some((version, &code[BYTECODE_HEADER_SIZE..]))
+}
@@ -0,0 +1,38 @@
+use adapter::VmProfile;
+
+/*
+* 虚拟机运行时能力报告，用于确认当前部署的构建目标和虚拟机库配置
+*/
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    pub target:         String,         //构建目标
+    pub arch:           String,         //目标架构
+    pub os:             String,         //目标操作系统
+    pub pointer_width:  usize,          //指针位宽
+    pub dukc_build:     String,         //虚拟机库构建方式，source表示从源码构建，prebuilt表示链接预编译库
+    pub dukc_flags:     Vec<String>,    //虚拟机库的Duktape编译选项
+    pub lowmem:         bool,           //是否支持低内存虚拟机堆配置
+    pub debugger:       bool,           //是否导出虚拟机交互式调试接口
+    pub cluster:        bool,           //是否导出虚拟机进程和进程间通信接口
+    pub snapshot:       bool,           //是否支持通过恢复堆快照快速构建虚拟机
+    pub funcdump:       bool,           //是否支持导出和加载单个函数的字节码
+}
+
+/*
+* 获取虚拟机运行时能力报告
+*/
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        target: option_env!("PI_VM_TARGET").unwrap_or("unknown").to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        os: std::env::consts::OS.to_string(),
+        pointer_width: std::mem::size_of::<usize>() * 8,
+        dukc_build: option_env!("PI_VM_DUKC_BUILD").unwrap_or("prebuilt").to_string(),
+        dukc_flags: option_env!("PI_VM_DUKC_FLAGS").unwrap_or("").split_whitespace().map(|flag| flag.to_string()).collect(),
+        lowmem: VmProfile::LowMemory.is_supported(),
+        debugger: cfg!(feature = "debugger"),
+        cluster: cfg!(feature = "cluster"),
+        snapshot: cfg!(feature = "snapshot"),
+        funcdump: cfg!(feature = "funcdump"),
+    }
+}
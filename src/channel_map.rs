@@ -1,15 +1,25 @@
-use std::sync::Arc;
+use std::any::Any;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::clone::Clone;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::collections::hash_map::Entry;
 use std::cell::RefCell;
+use std::panic::{catch_unwind, AssertUnwindSafe};
 
+use crossbeam_epoch::{self as epoch, Atomic, Owned};
 use atom::Atom;
 use handler::{Env, GenType, Handler, Args};
 use gray::GrayVersion;
 
-use adapter::{JS, JSType};
-use pi_vm_impl::{block_reply, push_callback};
+use adapter::{JS, JSType, now_utc};
+use pi_vm_impl::{block_reply, push_callback_with_reject};
+use task_info::{TaskInfo, ASYNC_CALL_RESPONSE_TASK, ASYNC_BLOCK_CALL_RESPONSE_TASK};
+use health::lock_state;
+use services::{SERVICES_ATTR, ServiceRegistry, find_service_registry};
+use quota::{QuotaPermit, acquire_quota};
 
 /*
 * 通道对端
@@ -19,6 +29,191 @@ pub enum VMChannelPeer {
     VM(Arc<JS>),    //指定虚拟机
 }
 
+/*
+* 虚拟机通道内联消息的最大长度，不超过的消息直接保存在消息内
+*/
+pub const INLINE_MSG_SIZE: usize = 128;
+
+/*
+* 虚拟机通道消息，不超过内联长度的消息直接保存在消息内，以避免为小消息分配共享缓冲区，
+* 可以通过解引用作为字节切片使用，需要共享缓冲区时再转换
+*/
+#[derive(Clone)]
+pub enum ChannelMsg {
+    Inline(usize, [u8; INLINE_MSG_SIZE]),   //内联消息，(消息长度, 内联缓冲区)
+    Shared(Arc<Vec<u8>>),                   //共享消息
+}
+
+impl ChannelMsg {
+    //复制指定字节构建通道消息，不超过内联长度则构建内联消息
+    pub fn new(bytes: &[u8]) -> Self {
+        if bytes.len() > INLINE_MSG_SIZE {
+            return ChannelMsg::Shared(Arc::new(bytes.to_vec()));
+        }
+
+        let mut buf = [0; INLINE_MSG_SIZE];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        ChannelMsg::Inline(bytes.len(), buf)
+    }
+
+    //判断是否是内联消息
+    pub fn is_inline(&self) -> bool {
+        match self {
+            ChannelMsg::Inline(_, _) => true,
+            ChannelMsg::Shared(_) => false,
+        }
+    }
+
+    //获取消息的字节切片
+    pub fn as_slice(&self) -> &[u8] {
+        match self {
+            ChannelMsg::Inline(len, buf) => &buf[..*len],
+            ChannelMsg::Shared(msg) => msg.as_slice(),
+        }
+    }
+
+    //转换为共享消息的缓冲区，内联消息会被复制
+    pub fn into_shared(self) -> Arc<Vec<u8>> {
+        match self {
+            ChannelMsg::Inline(len, buf) => Arc::new(buf[..len].to_vec()),
+            ChannelMsg::Shared(msg) => msg,
+        }
+    }
+}
+
+impl Deref for ChannelMsg {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl From<Arc<Vec<u8>>> for ChannelMsg {
+    fn from(msg: Arc<Vec<u8>>) -> Self {
+        ChannelMsg::Shared(msg)
+    }
+}
+
+impl From<Vec<u8>> for ChannelMsg {
+    fn from(msg: Vec<u8>) -> Self {
+        if msg.len() > INLINE_MSG_SIZE {
+            ChannelMsg::Shared(Arc::new(msg))
+        } else {
+            ChannelMsg::new(msg.as_slice())
+        }
+    }
+}
+
+impl<'a> From<&'a [u8]> for ChannelMsg {
+    fn from(msg: &'a [u8]) -> Self {
+        ChannelMsg::new(msg)
+    }
+}
+
+/*
+* 虚拟机通道请求的处理状态
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestStatus {
+    NotFound,               //处理器不存在
+    Delivered,              //请求已投递到处理器
+    HandlerError(String),   //处理器执行异常，(异常信息)
+    Responded,              //已回应请求
+    PeerDestroyed,          //回应时请求源虚拟机已销毁
+    Rejected(String),       //回应回调执行时请求源虚拟机已重置全局环境，回调被拒绝，(拒绝原因)
+    Throttled(String),      //请求源超过配额，请求未投递到处理器，(限流原因)
+}
+
+impl RequestStatus {
+    //判断是否是请求的最终状态，最终状态后不会再有新的状态
+    pub fn is_final(&self) -> bool {
+        match self {
+            RequestStatus::Delivered => false,
+            _ => true,
+        }
+    }
+}
+
+/*
+* 虚拟机通道请求的回执，记录请求的处理状态，并在状态变化时通知回调
+*/
+#[derive(Clone)]
+pub struct RequestReceipt {
+    name:   Atom,                                                                   //处理器名
+    inner:  Arc<Mutex<(Vec<RequestStatus>, Vec<Arc<Fn(&RequestStatus) + Send + Sync>>)>>,  //(已记录的状态, 状态回调)
+}
+
+impl RequestReceipt {
+    //构建一个虚拟机通道请求的回执
+    fn new(name: Atom) -> Self {
+        RequestReceipt {
+            name,
+            inner: Arc::new(Mutex::new((Vec::new(), Vec::new()))),
+        }
+    }
+
+    //获取处理器名
+    pub fn name(&self) -> &Atom {
+        &self.name
+    }
+
+    //获取已记录的所有状态
+    pub fn statuses(&self) -> Vec<RequestStatus> {
+        lock_state("vm_channel_receipt", &self.inner).0.clone()
+    }
+
+    //获取最近的状态
+    pub fn status(&self) -> Option<RequestStatus> {
+        lock_state("vm_channel_receipt", &self.inner).0.last().cloned()
+    }
+
+    //判断请求是否已处理完成
+    pub fn is_finished(&self) -> bool {
+        self.status().map_or(false, |status| status.is_final())
+    }
+
+    //注册状态回调，会立即通知已记录的所有状态
+    pub fn on_status(&self, hook: Arc<Fn(&RequestStatus) + Send + Sync>) {
+        let statuses = {
+            let mut inner = lock_state("vm_channel_receipt", &self.inner);
+            inner.1.push(hook.clone());
+            inner.0.clone()
+        };
+
+        for status in statuses.iter() {
+            hook(status);
+        }
+    }
+
+    //记录状态，并通知所有状态回调，回调在锁外执行
+    fn record(&self, status: RequestStatus) {
+        let hooks = {
+            let mut inner = lock_state("vm_channel_receipt", &self.inner);
+            inner.0.push(status.clone());
+            inner.1.clone()
+        };
+
+        for hook in hooks.iter() {
+            hook(&status);
+        }
+    }
+}
+
+/*
+* 虚拟机通道请求的路由解释，记录请求选择处理器时的灰度和路由决策，用于排查灰度发布期间部分会话使用新或旧行为的原因
+*/
+#[derive(Debug, Clone)]
+pub struct RouteTrace {
+    pub correlation_id: Option<u64>,    //发起请求的虚拟机正在执行的任务的关联id
+    pub name:           String,         //处理器名
+    pub found:          bool,           //是否找到处理器
+    pub gray:           usize,          //请求时虚拟机通道表的灰度值
+    pub rule:           Option<String>, //处理器选择的灰度规则或路由决策，由处理器通过虚拟机通道记录
+    pub alternatives:   Vec<String>,    //处理器未选择的其它灰度规则或路由决策
+    pub time:           usize,          //请求时间，单位us
+}
+
 /*
 * 虚拟机通道
 */
@@ -27,6 +222,11 @@ pub struct VMChannel {
     dst: VMChannelPeer,                         //目标
     attrs: RefCell<HashMap<Atom, GenType>>,     //属性表
     gray: Option<usize>,                        //灰度
+    receipt: Option<RequestReceipt>,            //请求的回执
+    source: Option<usize>,                      //发起请求的虚拟机正在执行的任务的源，回应回调会携带此源
+    priority: usize,                            //发起请求的虚拟机正在执行的任务的优先级，为0表示未指定，回应回调会使用此优先级
+    trace: Option<Arc<Mutex<RouteTrace>>>,      //请求的路由解释，为空表示未开启解释模式
+    permit: Option<QuotaPermit>,                //请求持有的源配额许可，在虚拟机通道释放时完成请求
 }
 
 impl GrayVersion for VMChannel {
@@ -71,11 +271,90 @@ impl Env for VMChannel {
 impl VMChannel {
     //构建一个虚拟机通道
     pub fn new(src: VMChannelPeer, dst: VMChannelPeer) -> Self {
+        let (source, priority) = match &src {
+            VMChannelPeer::VM(js) => match js.running_task() {
+                None => (None, 0),
+                Some(info) => (info.source(), info.priority()),
+            },
+            _ => (None, 0),
+        };
         VMChannel {
             src: src,
             dst: dst,
             gray: None,
             attrs: RefCell::new(HashMap::new()),
+            receipt: None,
+            source,
+            priority,
+            trace: None,
+            permit: None,
+        }
+    }
+
+    //获取发起请求的虚拟机正在执行的任务的源
+    pub fn source(&self) -> Option<usize> {
+        self.source
+    }
+
+    //获取发起请求的虚拟机正在执行的任务的优先级，为0表示未指定
+    pub fn priority(&self) -> usize {
+        self.priority
+    }
+
+    //为请求的路由解释记录处理器选择的灰度规则或路由决策，以及未选择的其它灰度规则或路由决策，未开启解释模式则忽略，返回是否记录
+    pub fn explain(&self, rule: &str, alternatives: &[&str]) -> bool {
+        match &self.trace {
+            None => false,
+            Some(trace) => {
+                let mut trace = lock_state("vm_channel_trace", trace);
+                trace.rule = Some(rule.to_string());
+                trace.alternatives = alternatives.iter().map(|alternative| alternative.to_string()).collect();
+                true
+            },
+        }
+    }
+
+    //获取请求的回执
+    pub fn receipt(&self) -> Option<&RequestReceipt> {
+        self.receipt.as_ref()
+    }
+
+    //为通道设置服务注册表
+    pub fn set_services(&self, registry: &ServiceRegistry) {
+        self.set_attr(Atom::from(SERVICES_ATTR), GenType::USize(registry.id()));
+    }
+
+    //获取通道的服务注册表
+    pub fn services(&self) -> Option<ServiceRegistry> {
+        match self.get_attr(Atom::from(SERVICES_ATTR)) {
+            Some(GenType::USize(id)) => find_service_registry(id),
+            _ => None,
+        }
+    }
+
+    //获取通道的服务注册表中指定类型的服务
+    pub fn service<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.services().and_then(|registry| registry.get::<T>())
+    }
+
+    //获取通道的服务注册表中指定类型和名称的服务
+    pub fn named_service<T: Any + Send + Sync>(&self, name: &str) -> Option<Arc<T>> {
+        self.services().and_then(|registry| registry.get_named::<T>(name))
+    }
+
+    //判断请求源虚拟机是否已销毁，已销毁则不需要再回应请求
+    pub fn is_peer_destroyed(&self) -> bool {
+        match self.src {
+            VMChannelPeer::VM(ref js) => js.is_destroyed(),
+            _ => false,
+        }
+    }
+
+    //注册请求源虚拟机销毁时的通知，用于在虚拟机销毁时释放为请求保留的资源，请求源虚拟机已销毁或不是指定虚拟机则忽略，返回是否注册成功
+    pub fn on_peer_destroy(&self, hook: Box<FnOnce(Atom, usize)>) -> bool {
+        match self.src {
+            VMChannelPeer::VM(ref js) => js.on_destroy(hook),
+            _ => false,
         }
     }
 
@@ -87,8 +366,27 @@ impl VMChannel {
 
     //回应请求
     pub fn response(&self, callback: Option<u32>, result: Arc<Vec<u8>>, native_objs: Vec<usize>) -> bool {
+        self.response_msg(callback, ChannelMsg::Shared(result), native_objs)
+    }
+
+    //使用指定字节回应请求，不超过内联长度的回应不会分配共享缓冲区
+    pub fn response_bytes(&self, callback: Option<u32>, result: &[u8], native_objs: Vec<usize>) -> bool {
+        self.response_msg(callback, ChannelMsg::new(result), native_objs)
+    }
+
+    //使用通道消息回应请求
+    pub fn response_msg(&self, callback: Option<u32>, result: ChannelMsg, native_objs: Vec<usize>) -> bool {
         match self.src {
             VMChannelPeer::VM(ref js) => {
+                if js.is_destroyed() {
+                    //请求的虚拟机已销毁，则忽略回应
+                    if let Some(receipt) = &self.receipt {
+                        receipt.record(RequestStatus::PeerDestroyed);
+                    }
+                    return false;
+                }
+
+                js.add_bytes_in(result.len());
                 match callback {
                     None => {
                         //同步阻塞返回
@@ -105,7 +403,7 @@ impl VMChannel {
                             }
                             vm.set_index(&array, 1, &mut sub_array);
                         });
-                        block_reply(js.clone(), result, Atom::from("vm async block call response task"));
+                        block_reply(js.clone(), result, TaskInfo::from(&*ASYNC_BLOCK_CALL_RESPONSE_TASK));
                     },
                     Some(index) => {
                         //异步回调
@@ -120,9 +418,22 @@ impl VMChannel {
                             }
                             2
                         });
-                        push_callback(js.clone(), index, args, None, Atom::from("vm async call response task"));
+                        let info = TaskInfo::from(&*ASYNC_CALL_RESPONSE_TASK).with_priority(self.priority);
+                        let reject: Option<Box<FnOnce(String)>> = match self.receipt.clone() {
+                            None => None,
+                            Some(receipt) => Some(Box::new(move |reason: String| {
+                                receipt.record(RequestStatus::Rejected(reason));
+                            })),
+                        };
+                        push_callback_with_reject(js.clone(), index, args, None, match self.source {
+                            Some(src) => info.with_source(src),
+                            None => info,
+                        }, reject);
                     }
                 }
+                if let Some(receipt) = &self.receipt {
+                    receipt.record(RequestStatus::Responded);
+                }
                 true
             },
             _ => false
@@ -131,65 +442,330 @@ impl VMChannel {
 }
 
 /*
-* 虚拟机通道表
+* 虚拟机通道表默认分片数量，必须为2的幂
+*/
+pub const DEFAULT_CHANNEL_SHARD_COUNT: usize = 64;
+
+/*
+* 虚拟机通道表在解释模式下保留的最大路由解释数量，超过则移除最早的路由解释
+*/
+pub const MAX_ROUTE_TRACES: usize = 1024;
+
+/*
+* 通用的虚拟机通道处理器，处理器会在任意线程上执行，所以必须可以在线程间安全的共享
+*/
+pub type GenericChannelHandler = Arc<Handler<A = Arc<Vec<u8>>, B = Vec<JSType>, C = Option<u32>, D = (), E = (), F = (), G = (), H = (), HandleResult = ()> + Send + Sync>;
+
+/*
+* 简化的虚拟机通道处理器，参数依次为请求的通道、处理器名、请求数据、请求的本地对象和异步回调，异步回调为空表示同步阻塞请求
+*/
+pub trait ChannelHandler: Send + Sync {
+    fn handle(&self, channel: Arc<VMChannel>, name: Atom, payload: Arc<Vec<u8>>, objs: Vec<JSType>, callback: Option<u32>);
+
+    //处理通道消息，默认将通道消息转换为共享消息后处理，需要避免为小消息分配共享缓冲区的处理器可以直接处理通道消息
+    fn handle_msg(&self, channel: Arc<VMChannel>, name: Atom, payload: ChannelMsg, objs: Vec<JSType>, callback: Option<u32>) {
+        self.handle(channel, name, payload.into_shared(), objs, callback)
+    }
+}
+
+impl<T> ChannelHandler for T
+    where T: Fn(Arc<VMChannel>, Atom, Arc<Vec<u8>>, Vec<JSType>, Option<u32>) + Send + Sync {
+    fn handle(&self, channel: Arc<VMChannel>, name: Atom, payload: Arc<Vec<u8>>, objs: Vec<JSType>, callback: Option<u32>) {
+        self(channel, name, payload, objs, callback)
+    }
+}
+
+/*
+* 通用的虚拟机通道处理器的适配器，将通用的处理器适配为简化的处理器
+*/
+pub struct HandlerAdapter(GenericChannelHandler);
+
+impl ChannelHandler for HandlerAdapter {
+    fn handle(&self, channel: Arc<VMChannel>, name: Atom, payload: Arc<Vec<u8>>, objs: Vec<JSType>, callback: Option<u32>) {
+        self.0.handle(channel, name, Args::ThreeArgs(payload, objs, callback));
+    }
+}
+
+impl HandlerAdapter {
+    //构建一个通用的虚拟机通道处理器的适配器
+    pub fn new(handler: GenericChannelHandler) -> Self {
+        HandlerAdapter(handler)
+    }
+
+    //获取被适配的通用的虚拟机通道处理器
+    pub fn inner(&self) -> &GenericChannelHandler {
+        &self.0
+    }
+}
+
+/*
+* 虚拟机通道处理器的执行统计
+*/
+#[derive(Debug, Clone)]
+pub struct HandlerStats {
+    pub name:       String,     //处理器名
+    pub calls:      usize,      //处理器执行次数
+    pub errors:     usize,      //处理器执行异常次数
+    pub bytes_in:   usize,      //处理器收到的请求数据字节数
+    pub total_time: Duration,   //处理器累计执行时长
+    pub max_time:   Duration,   //处理器单次最大执行时长
+}
+
+impl HandlerStats {
+    //获取处理器平均执行时长
+    pub fn mean_time(&self) -> Duration {
+        if self.calls == 0 {
+            Duration::from_micros(0)
+        } else {
+            self.total_time / self.calls as u32
+        }
+    }
+}
+
+/*
+* 虚拟机通道处理器的执行计数器
+*/
+struct HandlerCounter {
+    calls:      AtomicUsize,    //处理器执行次数
+    errors:     AtomicUsize,    //处理器执行异常次数
+    bytes_in:   AtomicUsize,    //处理器收到的请求数据字节数
+    total_time: AtomicUsize,    //处理器累计执行时长，单位us
+    max_time:   AtomicUsize,    //处理器单次最大执行时长，单位us
+}
+
+impl HandlerCounter {
+    //构建一个虚拟机通道处理器的执行计数器
+    fn new() -> Self {
+        HandlerCounter {
+            calls: AtomicUsize::new(0),
+            errors: AtomicUsize::new(0),
+            bytes_in: AtomicUsize::new(0),
+            total_time: AtomicUsize::new(0),
+            max_time: AtomicUsize::new(0),
+        }
+    }
+
+    //记录处理器的一次执行
+    fn record(&self, bytes_in: usize, time: Duration, is_error: bool) {
+        let time = time.as_micros() as usize;
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.bytes_in.fetch_add(bytes_in, Ordering::Relaxed);
+        self.total_time.fetch_add(time, Ordering::Relaxed);
+        self.max_time.fetch_max(time, Ordering::Relaxed);
+        if is_error {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    //获取处理器的执行统计
+    fn snapshot(&self, name: &Atom) -> HandlerStats {
+        HandlerStats {
+            name: name.to_string(),
+            calls: self.calls.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            total_time: Duration::from_micros(self.total_time.load(Ordering::Relaxed) as u64),
+            max_time: Duration::from_micros(self.max_time.load(Ordering::Relaxed) as u64),
+        }
+    }
+}
+
+/*
+* 虚拟机通道表中的处理器，处理器的执行计数器与处理器保存在同一快照中，请求时不需要访问全局的计数器表
+*/
+#[derive(Clone)]
+struct HandlerEntry {
+    handler:    Arc<ChannelHandler>,    //处理器
+    counter:    Arc<HandlerCounter>,    //处理器的执行计数器，替换同名处理器时保留
+}
+
+/*
+* 虚拟机通道表分片，读取无锁的访问当前快照，写入时在写锁内复制快照并原子替换
+*/
+struct ChannelShard {
+    lock:       Mutex<()>,                              //写锁
+    snapshot:   Atomic<HashMap<Atom, HandlerEntry>>,    //当前快照
+}
+
+/*
+* 虚拟机通道表，按处理器名的hash分片，查询处理器和灰度值时无锁，
+* 被替换的快照在所有读取前已固定纪元的读者都离开后才回收
 */
 pub struct VMChannelMap {
-    gray: usize,                                                                                                                                        //灰度值
-    map: HashMap<Atom, Arc<Handler<A = Arc<Vec<u8>>, B = Vec<JSType>, C = Option<u32>, D = (), E = (), F = (), G = (), H = (), HandleResult = ()>>>,    //通道表
+    gray:       AtomicUsize,                                //灰度值
+    size:       AtomicUsize,                                //处理器数量
+    mask:       usize,                                      //分片掩码
+    shards:     Vec<ChannelShard>,                          //通道表分片
+    services:   Atomic<Option<ServiceRegistry>>,            //默认的服务注册表的快照，请求源虚拟机所属的虚拟机工厂没有服务注册表时使用
+    explain:    AtomicBool,                                 //是否开启解释模式，开启后记录每个请求的路由解释
+    traces:     Mutex<VecDeque<Arc<Mutex<RouteTrace>>>>,    //最近请求的路由解释
+}
+
+impl Drop for VMChannelMap {
+    fn drop(&mut self) {
+        //已独占虚拟机通道表，不会有其它读者
+        for shard in &self.shards {
+            unsafe {
+                drop(shard.snapshot.load(Ordering::SeqCst, epoch::unprotected()).into_owned());
+            }
+        }
+        unsafe {
+            drop(self.services.load(Ordering::SeqCst, epoch::unprotected()).into_owned());
+        }
+    }
 }
 
 impl VMChannelMap {
     //构建一个虚拟机通道表
     pub fn new(gray: usize) -> Self {
+        VMChannelMap::with_shard(gray, DEFAULT_CHANNEL_SHARD_COUNT)
+    }
+
+    //构建一个指定分片数量的虚拟机通道表，分片数量会向上取整为2的幂
+    pub fn with_shard(gray: usize, count: usize) -> Self {
+        let count = if count == 0 {
+            1
+        } else {
+            count.next_power_of_two()
+        };
+
+        let mut shards = Vec::with_capacity(count);
+        for _ in 0..count {
+            shards.push(ChannelShard {
+                lock: Mutex::new(()),
+                snapshot: Atomic::new(HashMap::new()),
+            });
+        }
+
         VMChannelMap {
-            gray: gray,
-            map: HashMap::new(),
+            gray: AtomicUsize::new(gray),
+            size: AtomicUsize::new(0),
+            mask: count - 1,
+            shards: shards,
+            services: Atomic::new(None),
+            explain: AtomicBool::new(false),
+            traces: Mutex::new(VecDeque::new()),
         }
     }
 
     //获取灰度值
     pub fn get_gray(&self) -> usize {
-        self.gray
+        self.gray.load(Ordering::Relaxed)
     }
 
     //设置灰度值
-    pub fn set_gray(&mut self, gray: usize) -> usize {
-        let old = self.gray;
-        self.gray = gray;
+    pub fn set_gray(&self, gray: usize) -> usize {
+        self.gray.swap(gray, Ordering::Relaxed)
+    }
+
+    //判断是否开启解释模式
+    pub fn is_explain(&self) -> bool {
+        self.explain.load(Ordering::Relaxed)
+    }
+
+    //设置是否开启解释模式，关闭时清除已记录的路由解释，返回上次是否开启
+    pub fn set_explain(&self, explain: bool) -> bool {
+        let old = self.explain.swap(explain, Ordering::Relaxed);
+        if !explain {
+            lock_state("vm_channels", &self.traces).clear();
+        }
         old
     }
 
+    //获取指定关联id的所有请求的路由解释，按请求顺序排列
+    pub fn explain(&self, correlation_id: u64) -> Vec<RouteTrace> {
+        lock_state("vm_channels", &self.traces)
+            .iter()
+            .map(|trace| lock_state("vm_channel_trace", trace).clone())
+            .filter(|trace| trace.correlation_id == Some(correlation_id))
+            .collect()
+    }
+
     //获取处理器数量
     pub fn size(&self) -> usize {
-        self.map.len()
+        self.size.load(Ordering::Relaxed)
     }
 
-    //设置指定名称的处理器，返回同名的上一个处理器
-    pub fn set(&mut self, name: Atom, handler: Arc<Handler<A = Arc<Vec<u8>>, B = Vec<JSType>, C = Option<u32>, D = (), E = (), F = (), G = (), H = (), HandleResult = ()>>) -> Option<Arc<Handler<A = Arc<Vec<u8>>, B = Vec<JSType>, C = Option<u32>, D = (), E = (), F = (), G = (), H = (), HandleResult = ()>>> {
-        match self.map.entry(name) {
-            Entry::Occupied(ref mut e) => {
-                Some(e.insert(handler))
-            },
-            Entry::Vacant(e) => {
-                e.insert(handler);
-                None
-            },
+    //获取分片数量
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    //设置默认的服务注册表，返回上一个默认的服务注册表，被替换的快照延迟到所有可能访问它的读者离开后回收
+    pub fn set_services(&self, registry: Option<ServiceRegistry>) -> Option<ServiceRegistry> {
+        let guard = epoch::pin();
+        let old = self.services.swap(Owned::new(registry), Ordering::SeqCst, &guard);
+        unsafe {
+            let services = old.deref().clone();
+            guard.defer_destroy(old);
+            services
+        }
+    }
+
+    //无锁的获取默认的服务注册表
+    pub fn services(&self) -> Option<ServiceRegistry> {
+        let guard = epoch::pin();
+        unsafe { self.services.load(Ordering::SeqCst, &guard).deref().clone() }
+    }
+
+    //设置指定名称的通用的处理器，返回同名的上一个处理器
+    pub fn set(&self, name: Atom, handler: GenericChannelHandler) -> Option<Arc<ChannelHandler>> {
+        self.set_handler(name, Arc::new(HandlerAdapter::new(handler)))
+    }
+
+    //设置指定名称的简化的处理器，返回同名的上一个处理器
+    pub fn set_handler(&self, name: Atom, handler: Arc<ChannelHandler>) -> Option<Arc<ChannelHandler>> {
+        let key = name.clone();
+        let r = self.update(&key, move |map| {
+            let counter = map.get(&name).map_or_else(|| Arc::new(HandlerCounter::new()), |entry| entry.counter.clone());
+            map.insert(name, HandlerEntry {
+                handler,
+                counter,
+            }).map(|entry| entry.handler)
+        });
+        if r.is_none() {
+            self.size.fetch_add(1, Ordering::Relaxed);
         }
+        r
+    }
+
+    //判断是否存在指定名称的处理器
+    pub fn contains(&self, name: &Atom) -> bool {
+        self.read(name, |map| map.contains_key(name))
+    }
+
+    //获取指定名称的处理器
+    pub fn get(&self, name: &Atom) -> Option<Arc<ChannelHandler>> {
+        self.read(name, |map| map.get(name).map(|entry| entry.handler.clone()))
     }
 
     //移除指定名称的处理器，返回处理器
-    pub fn remove(&mut self, name: Atom) -> Option<Arc<Handler<A = Arc<Vec<u8>>, B = Vec<JSType>, C = Option<u32>, D = (), E = (), F = (), G = (), H = (), HandleResult = ()>>> {
-        self.map.remove(&name)
+    pub fn remove(&self, name: Atom) -> Option<Arc<ChannelHandler>> {
+        if !self.contains(&name) {
+            return None;
+        }
+
+        let r = self.update(&name, |map| map.remove(&name).map(|entry| entry.handler));
+        if r.is_some() {
+            self.size.fetch_sub(1, Ordering::Relaxed);
+        }
+        r
     }
 
-    //请求
+    //请求，处理器在快照外执行，避免处理器执行时阻塞快照的回收
     pub fn request(&self, js: Arc<JS>, name: Atom, msg: Arc<Vec<u8>>, native_objs: Vec<usize>, callback: Option<u32>) -> bool {
-        let handler = match self.map.get(&name) {
+        self.request_msg(js, name, ChannelMsg::Shared(msg), native_objs, callback)
+    }
+
+    //使用通道消息请求
+    pub fn request_msg(&self, js: Arc<JS>, name: Atom, msg: ChannelMsg, native_objs: Vec<usize>, callback: Option<u32>) -> bool {
+        let trace = self.trace(&js, &name);
+        let HandlerEntry { handler, counter } = match self.entry(&name) {
             None => {
                 return false;
             },
-            Some(h) => {
-                h
+            Some(entry) => {
+                entry
             },
         };
 
@@ -198,8 +774,162 @@ impl VMChannelMap {
             objs.push(js.new_native_object(native_objs[index]));
         }
 
-        let channel = VMChannel::new(VMChannelPeer::VM(js), VMChannelPeer::Any);
-        handler.handle(Arc::new(channel), name, Args::ThreeArgs(msg, objs, callback));
+        let mut channel = self.new_channel(js);
+        channel.trace = trace;
+        match acquire_quota(channel.source) {
+            Err(e) => {
+                warn!("!!!> Vm Channel Request Throttled, name: {:?}, e: {}", (&name).to_string(), e);
+                return false;
+            },
+            Ok(permit) => channel.permit = permit,
+        }
+        let bytes_in = msg.len();
+        let start = Instant::now();
+        if cfg!(feature = "nopanic") {
+            //不允许中止进程，则捕获处理器执行异常，并返回请求失败
+            let r = catch_unwind(AssertUnwindSafe(|| handler.handle_msg(Arc::new(channel), name.clone(), msg, objs, callback)));
+            counter.record(bytes_in, start.elapsed(), r.is_err());
+            if let Err(e) = r {
+                warn!("!!!> Vm Channel Request Error, handler panic, name: {:?}, e: {}", (&name).to_string(), panic_reason(e));
+                return false;
+            }
+        } else {
+            handler.handle_msg(Arc::new(channel), name, msg, objs, callback);
+            counter.record(bytes_in, start.elapsed(), false);
+        }
         true
     }
-}
\ No newline at end of file
+
+    //请求，并返回请求的回执，处理器执行异常会被捕获并记录在回执中，以便调用者记录或重试失败的请求
+    pub fn request_with_receipt(&self, js: Arc<JS>, name: Atom, msg: Arc<Vec<u8>>, native_objs: Vec<usize>, callback: Option<u32>) -> RequestReceipt {
+        let receipt = RequestReceipt::new(name.clone());
+        let trace = self.trace(&js, &name);
+        let HandlerEntry { handler, counter } = match self.entry(&name) {
+            None => {
+                receipt.record(RequestStatus::NotFound);
+                return receipt;
+            },
+            Some(entry) => {
+                entry
+            },
+        };
+
+        let mut objs = Vec::new();
+        for index in 0..native_objs.len() {
+            objs.push(js.new_native_object(native_objs[index]));
+        }
+
+        let mut channel = self.new_channel(js);
+        channel.receipt = Some(receipt.clone());
+        channel.trace = trace;
+        match acquire_quota(channel.source) {
+            Err(e) => {
+                warn!("!!!> Vm Channel Request Throttled, name: {:?}, e: {}", (&name).to_string(), e);
+                receipt.record(RequestStatus::Throttled(e.to_string()));
+                return receipt;
+            },
+            Ok(permit) => channel.permit = permit,
+        }
+        receipt.record(RequestStatus::Delivered);
+        let bytes_in = msg.len();
+        let start = Instant::now();
+        let r = catch_unwind(AssertUnwindSafe(|| handler.handle(Arc::new(channel), name.clone(), msg, objs, callback)));
+        counter.record(bytes_in, start.elapsed(), r.is_err());
+        if let Err(e) = r {
+            let reason = panic_reason(e);
+            warn!("!!!> Vm Channel Request Error, handler panic, name: {:?}, e: {}", (&name).to_string(), reason);
+            receipt.record(RequestStatus::HandlerError(reason));
+        }
+        receipt
+    }
+
+    //获取所有已执行过的处理器的执行统计，按处理器名排序，被移除的处理器不再统计
+    pub fn handler_stats(&self) -> Vec<HandlerStats> {
+        let guard = epoch::pin();
+        let mut stats = Vec::new();
+        for shard in &self.shards {
+            let snapshot = unsafe { shard.snapshot.load(Ordering::SeqCst, &guard).deref() };
+            for (name, entry) in snapshot.iter() {
+                if entry.counter.calls.load(Ordering::Relaxed) > 0 {
+                    stats.push(entry.counter.snapshot(name));
+                }
+            }
+        }
+        stats.sort_by(|x: &HandlerStats, y: &HandlerStats| x.name.cmp(&y.name));
+        stats
+    }
+
+    //获取指定名称的处理器和处理器的执行计数器
+    fn entry(&self, name: &Atom) -> Option<HandlerEntry> {
+        self.read(name, |map| map.get(name).cloned())
+    }
+
+    //开启解释模式时，为请求源虚拟机的请求记录路由解释，超过最大数量则移除最早的路由解释
+    fn trace(&self, js: &Arc<JS>, name: &Atom) -> Option<Arc<Mutex<RouteTrace>>> {
+        if !self.is_explain() {
+            return None;
+        }
+
+        let trace = Arc::new(Mutex::new(RouteTrace {
+            correlation_id: js.running_task().and_then(|info| info.correlation_id()),
+            name: (name).to_string(),
+            found: self.contains(name),
+            gray: self.get_gray(),
+            rule: None,
+            alternatives: Vec::new(),
+            time: now_utc(),
+        }));
+
+        let mut traces = lock_state("vm_channels", &self.traces);
+        while traces.len() >= MAX_ROUTE_TRACES {
+            traces.pop_front();
+        }
+        traces.push_back(trace.clone());
+        Some(trace)
+    }
+
+    //为请求源虚拟机构建虚拟机通道，并设置请求源虚拟机所属的虚拟机工厂的服务注册表或默认的服务注册表
+    fn new_channel(&self, js: Arc<JS>) -> VMChannel {
+        let services = js.get_factory().and_then(|factory| factory.services()).or_else(|| self.services());
+        let channel = VMChannel::new(VMChannelPeer::VM(js), VMChannelPeer::Any);
+        if let Some(registry) = services {
+            channel.set_services(&registry);
+        }
+        channel
+    }
+
+    //获取指定名称所在的分片
+    fn shard(&self, name: &Atom) -> &ChannelShard {
+        &self.shards[name.get_hash() & self.mask]
+    }
+
+    //无锁的读取指定名称所在分片的当前快照，读取期间固定当前线程的纪元，保证快照不会被回收
+    fn read<R, F: FnOnce(&HashMap<Atom, HandlerEntry>) -> R>(&self, name: &Atom, f: F) -> R {
+        let guard = epoch::pin();
+        let snapshot = self.shard(name).snapshot.load(Ordering::SeqCst, &guard);
+        f(unsafe { snapshot.deref() })
+    }
+
+    //在写锁内复制指定名称所在分片的当前快照，修改后替换当前快照，被替换的快照延迟到所有可能访问它的读者离开后回收
+    fn update<R, F: FnOnce(&mut HashMap<Atom, HandlerEntry>) -> R>(&self, name: &Atom, f: F) -> R {
+        let shard = self.shard(name);
+        let _lock = lock_state("vm_channels", &shard.lock);
+        let guard = epoch::pin();
+        let mut map = unsafe { shard.snapshot.load(Ordering::SeqCst, &guard).deref().clone() };
+        let r = f(&mut map);
+        let old = shard.snapshot.swap(Owned::new(map), Ordering::SeqCst, &guard);
+        unsafe { guard.defer_destroy(old); }
+        r
+    }
+}
+
+//获取处理器执行异常的原因
+fn panic_reason(e: Box<Any + Send>) -> String {
+    if let Some(s) = e.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = e.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
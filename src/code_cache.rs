@@ -0,0 +1,73 @@
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Weak, RwLock};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use health::{read_state, write_state};
+
+lazy_static! {
+    //全局字节码缓存，键为字节码内容的hash，只弱引用字节码，没有虚拟机工厂使用的字节码会被释放
+    static ref CODE_CACHE: Arc<RwLock<HashMap<u64, Vec<Weak<Vec<u8>>>>>> = Arc::new(RwLock::new(HashMap::new()));
+    //全局字节码缓存命中次数
+    static ref VM_CODE_CACHE_HIT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_code_cache_hit_count"), 0).unwrap();
+}
+
+//计算字节码内容的hash
+pub fn code_hash(code: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    code.hash(&mut hasher);
+    hasher.finish()
+}
+
+/*
+* 线程安全的在全局字节码缓存中查找内容相同的字节码，存在则返回缓存的字节码，否则缓存并返回指定的字节码，
+* 内容相同的字节码在进程内只保留一份
+*/
+pub fn intern_code(code: Arc<Vec<u8>>) -> Arc<Vec<u8>> {
+    let hash = code_hash(code.as_slice());
+    let mut cache = write_state("vm_code_cache", &CODE_CACHE);
+    let entries = cache.entry(hash).or_insert_with(Vec::new);
+    entries.retain(|entry| entry.upgrade().is_some()); //移除已释放的字节码
+
+    for entry in entries.iter() {
+        if let Some(cached) = entry.upgrade() {
+            //hash相同时还需要比较内容，以避免hash冲突
+            if Arc::ptr_eq(&cached, &code) || cached.as_slice() == code.as_slice() {
+                VM_CODE_CACHE_HIT_COUNT.sum(1);
+                return cached;
+            }
+        }
+    }
+
+    entries.push(Arc::downgrade(&code));
+    code
+}
+
+//线程安全的在全局字节码缓存中查找内容相同的字节码列表
+pub fn intern_codes(codes: Vec<Arc<Vec<u8>>>) -> Vec<Arc<Vec<u8>>> {
+    codes.into_iter().map(intern_code).collect()
+}
+
+//获取全局字节码缓存中未释放的字节码数量
+pub fn code_cache_size() -> usize {
+    read_state("vm_code_cache", &CODE_CACHE)
+        .values()
+        .map(|entries| entries.iter().filter(|entry| entry.upgrade().is_some()).count())
+        .sum()
+}
+
+//线程安全的移除全局字节码缓存中已释放的字节码，返回移除的数量
+pub fn purge_code_cache() -> usize {
+    let mut cache = write_state("vm_code_cache", &CODE_CACHE);
+    let mut purged = 0;
+    for entries in cache.values_mut() {
+        let len = entries.len();
+        entries.retain(|entry| entry.upgrade().is_some());
+        purged += len - entries.len();
+    }
+    cache.retain(|_, entries| !entries.is_empty());
+    purged
+}
@@ -0,0 +1,118 @@
+use std::ptr;
+use std::str;
+use std::sync::Arc;
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+/*
+* 加密字节码的魔数，加密字节码的格式为魔数、1字节的密钥id长度、密钥id和密文
+*/
+pub const ENCRYPTED_CODE_MAGIC: &'static [u8] = b"PIVMENC\0";
+
+lazy_static! {
+    //加密字节码解密的次数
+    static ref VM_CODE_DECRYPT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_code_decrypt_count"), 0).unwrap();
+    //加密字节码解密失败的次数
+    static ref VM_CODE_DECRYPT_FAILED_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_code_decrypt_failed_count"), 0).unwrap();
+}
+
+/*
+* 字节码解密算法，由使用者提供，例如AES-GCM或ChaCha20-Poly1305
+*/
+pub trait CodeCipher: Send + Sync {
+    //使用指定密钥解密密文，失败则返回原因
+    fn decrypt(&self, key: &[u8], cipher_text: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+//使用指定密钥id和密文构建加密字节码，密钥id不允许超过255字节
+pub fn encrypted_code(key_id: &str, cipher_text: &[u8]) -> Result<Vec<u8>, String> {
+    if key_id.len() > u8::max_value() as usize {
+        return Err(format!("key id too long, len: {}", key_id.len()));
+    }
+
+    let mut code = Vec::with_capacity(ENCRYPTED_CODE_MAGIC.len() + 1 + key_id.len() + cipher_text.len());
+    code.extend_from_slice(ENCRYPTED_CODE_MAGIC);
+    code.push(key_id.len() as u8);
+    code.extend_from_slice(key_id.as_bytes());
+    code.extend_from_slice(cipher_text);
+    Ok(code)
+}
+
+//判断指定字节码是否是加密字节码
+pub fn is_encrypted_code(code: &[u8]) -> bool {
+    parse_encrypted_code(code).is_some()
+}
+
+//解析加密字节码，返回密钥id和密文，不是加密字节码则返回None
+pub fn parse_encrypted_code(code: &[u8]) -> Option<(&str, &[u8])> {
+    if !code.starts_with(ENCRYPTED_CODE_MAGIC) || code.len() <= ENCRYPTED_CODE_MAGIC.len() {
+        return None;
+    }
+
+    let start = ENCRYPTED_CODE_MAGIC.len() + 1;
+    let end = start + code[ENCRYPTED_CODE_MAGIC.len()] as usize;
+    if end > code.len() {
+        return None;
+    }
+    match str::from_utf8(&code[start..end]) {
+        Err(_) => None,
+        Ok(key_id) => Some((key_id, &code[end..])),
+    }
+}
+
+//清除明文字节码，避免解密后的字节码残留在内存中
+pub fn clear_code(code: &mut Vec<u8>) {
+    for b in code.iter_mut() {
+        unsafe { ptr::write_volatile(b, 0); }
+    }
+    code.clear();
+}
+
+/*
+* 字节码解密器，由密钥提供者按密钥id提供密钥，并使用解密算法在内存中解密，密钥不会被缓存
+*/
+#[derive(Clone)]
+pub struct CodeDecryptor {
+    cipher: Arc<CodeCipher>,                                //解密算法
+    keys:   Arc<Fn(&str) -> Option<Vec<u8>> + Send + Sync>, //密钥提供者，参数为密钥id，密钥不存在则返回None
+}
+
+impl CodeDecryptor {
+    //构建一个字节码解密器
+    pub fn new(cipher: Arc<CodeCipher>, keys: Arc<Fn(&str) -> Option<Vec<u8>> + Send + Sync>) -> Self {
+        CodeDecryptor {
+            cipher,
+            keys,
+        }
+    }
+
+    //解密指定字节码，不是加密字节码则返回None，解密失败则返回原因
+    pub fn decrypt(&self, code: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let (key_id, cipher_text) = match parse_encrypted_code(code) {
+            None => return Ok(None),
+            Some(r) => r,
+        };
+
+        let mut key = match (self.keys)(key_id) {
+            None => {
+                VM_CODE_DECRYPT_FAILED_COUNT.sum(1);
+                return Err(format!("key not found, key id: {:?}", key_id));
+            },
+            Some(key) => key,
+        };
+        let result = self.cipher.decrypt(key.as_slice(), cipher_text);
+        clear_code(&mut key);
+
+        match result {
+            Err(e) => {
+                VM_CODE_DECRYPT_FAILED_COUNT.sum(1);
+                Err(format!("decrypt failed, key id: {:?}, e: {}", key_id, e))
+            },
+            Ok(code) => {
+                VM_CODE_DECRYPT_COUNT.sum(1);
+                Ok(Some(code))
+            },
+        }
+    }
+}
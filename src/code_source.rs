@@ -0,0 +1,397 @@
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+
+use atom::Atom;
+use worker::task::TaskType;
+use worker::impls::cast_js_task;
+
+use health::lock_state;
+use bundle::compile_source;
+
+/*
+* 字节码源，虚拟机工厂从字节码源获取字节码，获取可能阻塞，例如读取文件或网络请求，所以会在工作者中执行
+*/
+pub trait CodeSource: Send + Sync {
+    //获取字节码源的名称，用于报告
+    fn name(&self) -> String;
+
+    //获取字节码源的所有字节码，按加载顺序排列
+    fn fetch(&self) -> Result<Vec<Arc<Vec<u8>>>, String>;
+
+    //获取字节码源依赖的本地文件或目录，目录包括其下的所有文件，用于监视字节码源的修改，为空表示字节码源不依赖本地文件
+    fn paths(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+}
+
+/*
+* 文件字节码源，一个文件为一个字节码
+*/
+pub struct FileSource {
+    path: PathBuf,  //文件路径
+}
+
+impl FileSource {
+    //构建文件字节码源
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        FileSource {
+            path: path.into(),
+        }
+    }
+}
+
+impl CodeSource for FileSource {
+    fn name(&self) -> String {
+        format!("file:{}", self.path.display())
+    }
+
+    fn fetch(&self) -> Result<Vec<Arc<Vec<u8>>>, String> {
+        match fs::read(&self.path) {
+            Err(e) => Err(format!("read code file failed, path: {:?}, e: {}", self.path, e)),
+            Ok(code) => Ok(vec![Arc::new(code)]),
+        }
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+}
+
+/*
+* 目录字节码源，目录下的每个文件为一个字节码，按文件名排序，不递归子目录
+*/
+pub struct DirSource {
+    path:       PathBuf,        //目录路径
+    extension:  Option<String>, //文件扩展名，为空表示目录下的所有文件
+}
+
+impl DirSource {
+    //构建目录字节码源
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        DirSource {
+            path: path.into(),
+            extension: None,
+        }
+    }
+
+    //只加载指定扩展名的文件
+    pub fn with_extension(mut self, extension: &str) -> Self {
+        self.extension = Some(extension.trim_start_matches('.').to_string());
+        self
+    }
+}
+
+impl CodeSource for DirSource {
+    fn name(&self) -> String {
+        format!("dir:{}", self.path.display())
+    }
+
+    fn fetch(&self) -> Result<Vec<Arc<Vec<u8>>>, String> {
+        let entries = match fs::read_dir(&self.path) {
+            Err(e) => return Err(format!("read code dir failed, path: {:?}, e: {}", self.path, e)),
+            Ok(entries) => entries,
+        };
+
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = match entry {
+                Err(e) => return Err(format!("read code dir failed, path: {:?}, e: {}", self.path, e)),
+                Ok(entry) => entry.path(),
+            };
+            if !path.is_file() {
+                continue;
+            }
+            if let Some(extension) = &self.extension {
+                if path.extension().map_or(true, |ext| ext.to_string_lossy() != extension.as_str()) {
+                    continue;
+                }
+            }
+            paths.push(path);
+        }
+        paths.sort();
+
+        let mut codes = Vec::with_capacity(paths.len());
+        for path in paths {
+            match fs::read(&path) {
+                Err(e) => return Err(format!("read code file failed, path: {:?}, e: {}", path, e)),
+                Ok(code) => codes.push(Arc::new(code)),
+            }
+        }
+        Ok(codes)
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+}
+
+/*
+* 匹配文件的加载顺序
+*/
+#[derive(Debug, Clone)]
+pub enum LoadOrder {
+    Lexical,            //按相对路径的字典序
+    Manifest(PathBuf),  //按清单文件中的顺序，清单文件每行为一个相对路径，忽略空行和以“#”开始的行，不在清单中的匹配文件按字典序排在之后
+}
+
+/*
+* 模式匹配字节码源，递归匹配目录下相对路径符合任一模式的文件，扩展名为js的文件会被编译为字节码，其它文件作为字节码加载，
+* 模式使用“/”分隔路径，“*”匹配路径段内的任意字符，“?”匹配路径段内的一个字符，“**”匹配任意层目录
+*/
+pub struct GlobSource {
+    path:       PathBuf,        //目录路径
+    patterns:   Vec<String>,    //文件模式
+    order:      LoadOrder,      //加载顺序
+}
+
+impl GlobSource {
+    //构建模式匹配字节码源，默认按字典序加载
+    pub fn new<P: Into<PathBuf>>(path: P, patterns: &[&str]) -> Self {
+        GlobSource {
+            path: path.into(),
+            patterns: patterns.iter().map(|pattern| pattern.trim_start_matches("./").to_string()).collect(),
+            order: LoadOrder::Lexical,
+        }
+    }
+
+    //设置加载顺序
+    pub fn with_order(mut self, order: LoadOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    //获取按加载顺序排列的匹配文件的相对路径
+    pub fn matched(&self) -> Result<Vec<String>, String> {
+        let mut files = Vec::new();
+        walk_dir(&self.path, "", &mut files)?;
+        let mut files: Vec<String> = files.into_iter()
+            .filter(|file| self.patterns.iter().any(|pattern| glob_match(pattern, file)))
+            .collect();
+        files.sort();
+
+        if let LoadOrder::Manifest(manifest) = &self.order {
+            let text = match fs::read_to_string(self.path.join(manifest)) {
+                Err(e) => return Err(format!("read code manifest failed, path: {:?}, e: {}", manifest, e)),
+                Ok(text) => text,
+            };
+
+            let mut ordered = Vec::with_capacity(files.len());
+            for line in text.lines().map(|line| line.trim()).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+                let line = line.trim_start_matches("./");
+                match files.iter().position(|file| file == line) {
+                    None => return Err(format!("code manifest entry not matched, manifest: {:?}, entry: {:?}", manifest, line)),
+                    Some(index) => ordered.push(files.remove(index)),
+                }
+            }
+            ordered.append(&mut files);
+            files = ordered;
+        }
+
+        Ok(files)
+    }
+}
+
+impl CodeSource for GlobSource {
+    fn name(&self) -> String {
+        format!("glob:{}/{{{}}}", self.path.display(), self.patterns.join(","))
+    }
+
+    fn fetch(&self) -> Result<Vec<Arc<Vec<u8>>>, String> {
+        let mut codes = Vec::new();
+        for file in self.matched()? {
+            let path = self.path.join(&file);
+            let code = if file.ends_with(".js") {
+                match fs::read_to_string(&path) {
+                    Err(e) => return Err(format!("read code file failed, path: {:?}, e: {}", path, e)),
+                    Ok(source) => compile_source(&file, &source)?,
+                }
+            } else {
+                match fs::read(&path) {
+                    Err(e) => return Err(format!("read code file failed, path: {:?}, e: {}", path, e)),
+                    Ok(code) => Arc::new(code),
+                }
+            };
+            codes.push(code);
+        }
+        Ok(codes)
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        match &self.order {
+            LoadOrder::Manifest(manifest) if !self.path.join(manifest).starts_with(&self.path) => {
+                //清单文件不在目录下
+                vec![self.path.clone(), self.path.join(manifest)]
+            },
+            _ => vec![self.path.clone()],
+        }
+    }
+}
+
+//递归获取目录下所有文件的相对路径
+fn walk_dir(dir: &Path, prefix: &str, files: &mut Vec<String>) -> Result<(), String> {
+    let entries = match fs::read_dir(dir) {
+        Err(e) => return Err(format!("read code dir failed, path: {:?}, e: {}", dir, e)),
+        Ok(entries) => entries,
+    };
+
+    for entry in entries {
+        let path = match entry {
+            Err(e) => return Err(format!("read code dir failed, path: {:?}, e: {}", dir, e)),
+            Ok(entry) => entry.path(),
+        };
+        let name = match path.file_name() {
+            None => continue,
+            Some(name) => name.to_string_lossy().into_owned(),
+        };
+        let relative = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+        if path.is_dir() {
+            walk_dir(&path, &relative, files)?;
+        } else if path.is_file() {
+            files.push(relative);
+        }
+    }
+    Ok(())
+}
+
+//判断相对路径是否匹配指定模式
+fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    match_segments(&pattern, &path)
+}
+
+//匹配路径段
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+        Some((segment, rest)) => match path.split_first() {
+            None => false,
+            Some((name, path_rest)) => match_segment(segment.as_bytes(), name.as_bytes()) && match_segments(rest, path_rest),
+        },
+    }
+}
+
+//匹配路径段内的字符
+fn match_segment(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_segment(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && match_segment(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_segment(rest, &name[1..]),
+    }
+}
+
+/*
+* 函数字节码源，由调用者提供获取字节码的函数，用于从网络或数据库等外部存储获取字节码
+*/
+pub struct FnSource {
+    name:   String,                                                         //字节码源名称
+    func:   Arc<Fn() -> Result<Vec<Arc<Vec<u8>>>, String> + Send + Sync>,   //获取字节码的函数
+}
+
+impl FnSource {
+    //构建函数字节码源
+    pub fn new(name: &str, func: Arc<Fn() -> Result<Vec<Arc<Vec<u8>>>, String> + Send + Sync>) -> Self {
+        FnSource {
+            name: name.to_string(),
+            func,
+        }
+    }
+}
+
+impl CodeSource for FnSource {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn fetch(&self) -> Result<Vec<Arc<Vec<u8>>>, String> {
+        (self.func)()
+    }
+}
+
+/*
+* 字节码源的加载报告
+*/
+#[derive(Debug, Clone)]
+pub struct SourceReport {
+    pub factory:    String,                                 //虚拟机工厂名
+    pub results:    Vec<(String, Result<usize, String>)>,   //按字节码源顺序的字节码源名称和获取结果，成功为字节码数量，失败为失败原因
+    pub generation: Option<usize>,                          //替换字节码后的字节码代数，为空表示未替换
+    pub error:      Option<String>,                         //获取字节码后的失败原因，例如替换字节码失败
+}
+
+impl SourceReport {
+    //获取失败的字节码源数量
+    pub fn failed(&self) -> usize {
+        self.results.iter().filter(|(_, r)| r.is_err()).count()
+    }
+
+    //判断是否全部加载成功
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none() && self.failed() == 0
+    }
+}
+
+//顺序获取指定字节码源的字节码，返回按字节码源顺序的字节码和每个字节码源的获取结果
+pub fn fetch_sources(sources: &[Arc<CodeSource>]) -> (Vec<Arc<Vec<u8>>>, Vec<(String, Result<usize, String>)>) {
+    collect_sources(sources.iter().map(|source| (source.name(), source.fetch())).collect())
+}
+
+//在工作者中并发获取指定字节码源的字节码，每个字节码源的获取是一个独立的任务，全部完成后按字节码源顺序回调字节码和每个字节码源的获取结果
+pub fn fetch_sources_async(sources: Vec<Arc<CodeSource>>,
+                           priority: usize,
+                           on_done: Box<FnOnce(Vec<Arc<Vec<u8>>>, Vec<(String, Result<usize, String>)>)>) {
+    let count = sources.len();
+    if count == 0 {
+        on_done(Vec::new(), Vec::new());
+        return;
+    }
+
+    let slots: Vec<Option<(String, Result<Vec<Arc<Vec<u8>>>, String>)>> = (0..count).map(|_| None).collect();
+    let state = Arc::new(Mutex::new((slots, 0, Some(on_done))));
+    for (index, source) in sources.into_iter().enumerate() {
+        let state_copy = state.clone();
+        let func = Box::new(move |_lock: Option<isize>| {
+            let result = source.fetch();
+            if let Err(e) = &result {
+                warn!("!!!> Fetch Code Source Error, source: {:?}, e: {}", source.name(), e);
+            }
+
+            let finished = {
+                let mut state = lock_state("vm_code_sources", &state_copy);
+                state.0[index] = Some((source.name(), result));
+                state.1 += 1;
+                if state.1 == count {
+                    let slots = state.0.drain(..).filter_map(|slot| slot).collect();
+                    state.2.take().map(|on_done| (on_done, slots))
+                } else {
+                    None
+                }
+            };
+            if let Some((on_done, slots)) = finished {
+                //所有字节码源获取完成
+                let (codes, results) = collect_sources(slots);
+                on_done(codes, results);
+            }
+        });
+        cast_js_task(TaskType::Async(false), priority, None, func, Atom::from("vm code source fetch task"));
+    }
+}
+
+//按字节码源顺序合并字节码源的获取结果
+fn collect_sources(fetched: Vec<(String, Result<Vec<Arc<Vec<u8>>>, String>)>) -> (Vec<Arc<Vec<u8>>>, Vec<(String, Result<usize, String>)>) {
+    let mut codes = Vec::new();
+    let mut results = Vec::with_capacity(fetched.len());
+    for (name, result) in fetched {
+        match result {
+            Err(e) => results.push((name, Err(e))),
+            Ok(mut vec) => {
+                results.push((name, Ok(vec.len())));
+                codes.append(&mut vec);
+            },
+        }
+    }
+    (codes, results)
+}
@@ -0,0 +1,121 @@
+use std::sync::{Arc, Mutex, RwLock};
+use std::collections::HashMap;
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use health::{lock_state, read_state, write_state};
+use code_cache::code_hash;
+
+lazy_static! {
+    //字节码校验失败的次数
+    static ref VM_CODE_VERIFY_FAILED_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_code_verify_failed_count"), 0).unwrap();
+}
+
+/*
+* 字节码校验器，在虚拟机加载字节码前校验字节码，用于拒绝未签名或被篡改的字节码，校验算法由实现者提供
+*/
+pub trait CodeVerifier: Send + Sync {
+    //校验指定字节码，失败则返回原因
+    fn verify(&self, code: &[u8]) -> Result<(), String>;
+}
+
+/*
+* 签名字节码校验器，按字节码内容查找发布时的签名，并使用指定的签名算法校验，例如ed25519的公钥验签，
+* 没有签名的字节码和签名不匹配的字节码都会被拒绝
+*/
+pub struct SignatureVerifier {
+    signatures: RwLock<HashMap<u64, Vec<u8>>>,              //字节码内容hash到签名的表
+    check:      Arc<Fn(&[u8], &[u8]) -> bool + Send + Sync>, //签名算法，参数依次为字节码和签名，返回签名是否有效
+}
+
+impl CodeVerifier for SignatureVerifier {
+    fn verify(&self, code: &[u8]) -> Result<(), String> {
+        match read_state("vm_code_signatures", &self.signatures).get(&code_hash(code)) {
+            None => Err(format!("unsigned code, hash: {:x}", code_hash(code))),
+            Some(signature) => {
+                //hash只用于查找签名，签名必须对完整的字节码有效
+                if (self.check)(code, signature.as_slice()) {
+                    Ok(())
+                } else {
+                    Err(format!("invalid signature, hash: {:x}", code_hash(code)))
+                }
+            },
+        }
+    }
+}
+
+impl SignatureVerifier {
+    //构建一个使用指定签名算法的签名字节码校验器
+    pub fn new(check: Arc<Fn(&[u8], &[u8]) -> bool + Send + Sync>) -> Self {
+        SignatureVerifier {
+            signatures: RwLock::new(HashMap::new()),
+            check,
+        }
+    }
+
+    //增加指定字节码的签名，返回上个签名
+    pub fn add_signature(&self, code: &[u8], signature: Vec<u8>) -> Option<Vec<u8>> {
+        write_state("vm_code_signatures", &self.signatures).insert(code_hash(code), signature)
+    }
+
+    //移除指定字节码的签名
+    pub fn remove_signature(&self, code: &[u8]) -> Option<Vec<u8>> {
+        write_state("vm_code_signatures", &self.signatures).remove(&code_hash(code))
+    }
+
+    //获取签名数量
+    pub fn len(&self) -> usize {
+        read_state("vm_code_signatures", &self.signatures).len()
+    }
+}
+
+/*
+* 已校验的字节码缓存，同一个字节码只校验一次，缓存持有已校验的字节码，以保证字节码地址不会被其它字节码复用
+*/
+pub struct VerifiedCodes {
+    verifier:   Arc<CodeVerifier>,                          //字节码校验器
+    verified:   Mutex<HashMap<usize, Arc<Vec<u8>>>>,        //字节码地址到已校验字节码的表
+}
+
+impl VerifiedCodes {
+    //构建一个使用指定校验器的已校验字节码缓存
+    pub fn new(verifier: Arc<CodeVerifier>) -> Self {
+        VerifiedCodes {
+            verifier,
+            verified: Mutex::new(HashMap::new()),
+        }
+    }
+
+    //获取字节码校验器
+    pub fn verifier(&self) -> Arc<CodeVerifier> {
+        self.verifier.clone()
+    }
+
+    //校验指定字节码，已校验的字节码直接通过，失败则返回原因
+    pub fn verify(&self, code: &Arc<Vec<u8>>) -> Result<(), String> {
+        let key = code.as_ptr() as usize;
+        if let Some(verified) = lock_state("vm_verified_codes", &self.verified).get(&key) {
+            if Arc::ptr_eq(verified, code) {
+                return Ok(());
+            }
+        }
+
+        if let Err(e) = self.verifier.verify(code.as_slice()) {
+            VM_CODE_VERIFY_FAILED_COUNT.sum(1);
+            return Err(e);
+        }
+        lock_state("vm_verified_codes", &self.verified).insert(key, code.clone());
+        Ok(())
+    }
+
+    //获取已校验的字节码数量
+    pub fn len(&self) -> usize {
+        lock_state("vm_verified_codes", &self.verified).len()
+    }
+
+    //清空已校验的字节码，用于更换签名后重新校验
+    pub fn clear(&self) {
+        lock_state("vm_verified_codes", &self.verified).clear();
+    }
+}
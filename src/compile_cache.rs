@@ -0,0 +1,188 @@
+use std::fs;
+use std::process;
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use bundle::compile_source;
+use bytecode::{duk_version, check_bytecode};
+
+/*
+* 编译缓存文件的扩展名
+*/
+pub const COMPILE_CACHE_EXT: &'static str = "pbc";
+
+/*
+* 编译缓存的格式版本，缓存文件格式变化时修改，以保证旧的缓存文件不会被使用
+*/
+const COMPILE_CACHE_FORMAT: u32 = 2;
+
+/*
+* FNV-1a 64位hash的初始值和乘数
+*/
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+lazy_static! {
+    //编译缓存命中的次数
+    static ref VM_COMPILE_CACHE_HIT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_compile_cache_hit_count"), 0).unwrap();
+    //编译缓存未命中，需要编译源码的次数
+    static ref VM_COMPILE_CACHE_MISS_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_compile_cache_miss_count"), 0).unwrap();
+}
+
+/*
+* 磁盘编译缓存，将源码编译后的字节码保存在指定目录下，键为文件名、源码、编译选项和Duktape版本的hash，
+* 用于避免每次启动时重复编译append_source加载的源码或转换后的源码，缓存文件损坏或版本不匹配时重新编译
+*/
+#[derive(Debug, Clone)]
+pub struct CompileCache {
+    dir:        PathBuf,    //缓存目录
+    options:    String,     //编译选项，例如源码转换器的名称、版本和配置，编译选项不同的源码不会共享缓存
+}
+
+impl CompileCache {
+    //构建一个指定缓存目录的磁盘编译缓存，缓存目录不存在则创建
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self, String> {
+        let dir = dir.as_ref().to_path_buf();
+        if let Err(e) = fs::create_dir_all(&dir) {
+            return Err(format!("create compile cache dir failed, dir: {:?}, e: {:?}", dir, e));
+        }
+
+        Ok(CompileCache {
+            dir,
+            options: String::new(),
+        })
+    }
+
+    //设置编译选项，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn with_options(mut self, options: &str) -> Self {
+        self.options = options.to_string();
+        self
+    }
+
+    //获取缓存目录
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    //获取编译选项
+    pub fn options(&self) -> &str {
+        &self.options
+    }
+
+    //获取指定文件名和源码的缓存键，使用算法固定的FNV-1a hash，保证持久化的缓存键不会因为工具链升级而变化
+    pub fn key(&self, file: &str, source: &str) -> u64 {
+        let mut hasher = StableHasher::new();
+        hasher.write_field(&COMPILE_CACHE_FORMAT.to_le_bytes());
+        match duk_version() {
+            None => hasher.write_field(&[]),
+            Some(version) => hasher.write_field(&version.to_le_bytes()),
+        }
+        hasher.write_field(self.options.as_bytes());
+        hasher.write_field(file.as_bytes());
+        hasher.write_field(source.as_bytes());
+        hasher.finish()
+    }
+
+    //获取指定文件名和源码的缓存字节码，缓存不存在或字节码格式错误则返回None，格式错误的缓存文件会被移除
+    pub fn get(&self, file: &str, source: &str) -> Option<Arc<Vec<u8>>> {
+        let path = self.path(self.key(file, source));
+        let code = match fs::read(&path) {
+            Err(_) => return None,
+            Ok(code) => code,
+        };
+
+        if let Err(e) = check_bytecode(&code, duk_version) {
+            warn!("!!!> Compile Cache Invalid, file: {:?}, path: {:?}, e: {}", file, path, e);
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        Some(Arc::new(code))
+    }
+
+    //保存指定文件名和源码的字节码，先写入临时文件再替换，以保证并发构建的进程不会读取到不完整的缓存文件
+    pub fn put(&self, file: &str, source: &str, code: &[u8]) -> Result<(), String> {
+        let path = self.path(self.key(file, source));
+        let tmp = path.with_extension(format!("{}.{}.tmp", COMPILE_CACHE_EXT, process::id()));
+        if let Err(e) = fs::write(&tmp, code) {
+            let _ = fs::remove_file(&tmp);
+            return Err(format!("write compile cache failed, path: {:?}, e: {:?}", tmp, e));
+        }
+
+        if let Err(e) = fs::rename(&tmp, &path) {
+            let _ = fs::remove_file(&tmp);
+            return Err(format!("write compile cache failed, path: {:?}, e: {:?}", path, e));
+        }
+        Ok(())
+    }
+
+    //将指定文件名的js源码编译为字节码，缓存命中则直接返回缓存的字节码，否则编译并保存到缓存，保存失败不影响编译结果
+    pub fn compile(&self, file: &str, source: &str) -> Result<Arc<Vec<u8>>, String> {
+        if let Some(code) = self.get(file, source) {
+            VM_COMPILE_CACHE_HIT_COUNT.sum(1);
+            return Ok(code);
+        }
+
+        VM_COMPILE_CACHE_MISS_COUNT.sum(1);
+        let code = compile_source(file, source)?;
+        if let Err(e) = self.put(file, source, code.as_slice()) {
+            warn!("!!!> Compile Cache Error, file: {:?}, e: {}", file, e);
+        }
+        Ok(code)
+    }
+
+    //移除缓存目录下的所有缓存文件，返回移除的数量
+    pub fn clear(&self) -> usize {
+        let entries = match fs::read_dir(&self.dir) {
+            Err(_) => return 0,
+            Ok(entries) => entries,
+        };
+
+        let mut removed = 0;
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            if path.extension().map_or(false, |ext| ext == COMPILE_CACHE_EXT) && fs::remove_file(&path).is_ok() {
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    //获取指定缓存键的缓存文件路径
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.{}", key, COMPILE_CACHE_EXT))
+    }
+}
+
+/*
+* 稳定的FNV-1a 64位hash，算法和初始值固定，不依赖标准库的默认hash，计算结果在不同的编译器版本和进程间保持一致
+*/
+struct StableHasher(u64);
+
+impl StableHasher {
+    //构建一个稳定hash
+    fn new() -> Self {
+        StableHasher(FNV_OFFSET_BASIS)
+    }
+
+    //写入字节
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    //写入带长度前缀的字段，保证相邻字段的边界不会混淆
+    fn write_field(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+
+    //获取hash
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -13,6 +13,7 @@ use hash::XHashMap;
 
 use adapter::{pause, JS};
 use pi_vm_impl::push_msg;
+use task_info::TaskInfo;
 use bonmgr::{NativeObjsAuth, ptr_jstype};
 use proc::{ProcStatus, ProcInfo, Process, ProcessFactory};
 use proc_pool::register_process;
@@ -132,7 +133,7 @@ impl Process<(Arc<NativeObjsAuth>, Arc<Vec<Vec<u8>>>), Box<FnOnce(Arc<JS>) -> us
                 let args = Box::new(move |vm: Arc<JS>| {
                     gen_args_to_js_args(vm, Some(info.source()), info.payload())
                 });
-                push_msg(self.vm.clone(), self.receiver.load(Ordering::Relaxed), args, Atom::from(format!("DukProcess Info Task, pid: {:?}, name: {:?}", self.pid, self.name)));
+                push_msg(self.vm.clone(), self.receiver.load(Ordering::Relaxed), args, TaskInfo::from(format!("DukProcess Info Task, pid: {:?}, name: {:?}", self.pid, self.name)));
                 Ok(())
             },
             status => {
@@ -178,7 +179,7 @@ impl DukProcess {
 
     //取消进程虚拟机，接收异步消息的回调入口，设置为负数，虚拟机将在执行完所有任务后自动退出
     pub fn unset_receiver(&self) {
-        JS::remove_callback(self.vm.clone(), TaskType::Sync(true), self.receiver.load(Ordering::Relaxed), Atom::from(format!("DukProcess Remove Reciver Task, pid: {:?}, name: {:?}", self.pid, self.name)));
+        JS::remove_callback(self.vm.clone(), TaskType::Sync(true), self.receiver.load(Ordering::Relaxed), TaskInfo::from(format!("DukProcess Remove Reciver Task, pid: {:?}, name: {:?}", self.pid, self.name)));
     }
 
     //设置进程虚拟机，捕获异常的回调入口，设置为正数，虚拟机将无法自动退出
@@ -190,7 +191,7 @@ impl DukProcess {
     //取消进程虚拟机，捕获异常的回调入口，设置为负数，虚拟机将在执行完所有任务后自动退出
     pub fn unset_catcher(&self) {
         self.vm.set_catcher(-1);
-        JS::remove_callback(self.vm.clone(), TaskType::Sync(true), self.catcher.load(Ordering::Relaxed) as u32, Atom::from(format!("DukProcess Remove Catcher Task, pid: {:?}, name: {:?}", self.pid, self.name)));
+        JS::remove_callback(self.vm.clone(), TaskType::Sync(true), self.catcher.load(Ordering::Relaxed) as u32, TaskInfo::from(format!("DukProcess Remove Catcher Task, pid: {:?}, name: {:?}", self.pid, self.name)));
     }
 
     //在当前进程中抛出一个异常
@@ -204,7 +205,7 @@ impl DukProcess {
                     vm.new_str(error);
                     1
                 });
-                push_msg(self.vm.clone(), self.catcher.load(Ordering::Relaxed) as u32, args, Atom::from(format!("DukProcess Throw Task, pid: {:?}, name: {:?}", self.pid, self.name)));
+                push_msg(self.vm.clone(), self.catcher.load(Ordering::Relaxed) as u32, args, TaskInfo::from(format!("DukProcess Throw Task, pid: {:?}, name: {:?}", self.pid, self.name)));
                 Ok(())
             },
             status => {
@@ -0,0 +1,224 @@
+use std::sync::{Arc, Mutex};
+use std::hash::{Hash, Hasher};
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use adapter::JS;
+use bonmgr::NativeObjsAuth;
+use native_bind::js_string;
+use scratch::{ScratchLimits, ScratchValue, scratch_eval_code, scratch_eval_function};
+use health::lock_state;
+
+/*
+* 表达式编译后的文件名
+*/
+const EXPR_FILE: &'static str = "expression.js";
+
+/*
+* 表达式函数名前缀
+*/
+const EXPR_FUNCTION_PREFIX: &'static str = "__expr_";
+
+/*
+* 默认的表达式缓存容量
+*/
+const DEFAULT_EXPR_CACHE_CAPACITY: usize = 1024;
+
+lazy_static! {
+    //表达式编译次数
+    static ref EXPR_COMPILE_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("expr_compile_count"), 0).unwrap();
+    //表达式缓存命中次数
+    static ref EXPR_CACHE_HIT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("expr_cache_hit_count"), 0).unwrap();
+    //表达式执行次数
+    static ref EXPR_EVAL_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("expr_eval_count"), 0).unwrap();
+}
+
+/*
+* 已编译的表达式
+*/
+#[derive(Debug)]
+pub struct CompiledExpr {
+    hash:   u64,                //表达式hash
+    source: String,             //表达式源码
+    code:   Arc<Vec<u8>>,       //表达式函数的字节码
+    func:   Option<Vec<u8>>,    //导出的表达式函数的字节码，不支持导出单个函数时为空
+}
+
+impl CompiledExpr {
+    //获取表达式hash
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    //获取表达式源码
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    //判断表达式函数是否已导出，已导出的表达式函数执行时不需要加载并执行整个字节码
+    pub fn is_dumped(&self) -> bool {
+        self.func.is_some()
+    }
+}
+
+/*
+* 表达式缓存
+*/
+struct ExprCache {
+    exprs:  HashMap<u64, Arc<CompiledExpr>>,    //已编译的表达式表
+    order:  VecDeque<u64>,                      //表达式的缓存顺序，缓存已满时淘汰最早缓存的表达式
+}
+
+/*
+* 表达式引擎，将用户提供的简单表达式编译为函数并按hash缓存，在当前线程的临时虚拟机中以指定的上下文对象执行，
+* 适用于规则、过滤器等需要执行大量小表达式的场景，而不需要为每组表达式构建虚拟机工厂
+*/
+#[derive(Clone)]
+pub struct ExpressionEngine {
+    limits:     ScratchLimits,          //表达式的执行限制
+    capacity:   usize,                  //表达式缓存容量
+    cache:      Arc<Mutex<ExprCache>>,  //表达式缓存
+}
+
+impl ExpressionEngine {
+    //构建一个表达式引擎
+    pub fn new() -> Self {
+        ExpressionEngine {
+            limits: ScratchLimits::default(),
+            capacity: DEFAULT_EXPR_CACHE_CAPACITY,
+            cache: Arc::new(Mutex::new(ExprCache {
+                exprs: HashMap::new(),
+                order: VecDeque::new(),
+            })),
+        }
+    }
+
+    //设置表达式的执行限制
+    pub fn set_limits(mut self, limits: ScratchLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    //设置表达式缓存容量
+    pub fn set_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = if capacity == 0 { 1 } else { capacity };
+        self
+    }
+
+    //获取已缓存的表达式数量
+    pub fn cached_count(&self) -> usize {
+        lock_state("vm_expression_cache", &self.cache).exprs.len()
+    }
+
+    //清空表达式缓存
+    pub fn clear(&self) {
+        let mut cache = lock_state("vm_expression_cache", &self.cache);
+        cache.exprs.clear();
+        cache.order.clear();
+    }
+
+    //编译指定表达式，已缓存则直接返回
+    pub fn compile(&self, expr: &str) -> Result<Arc<CompiledExpr>, String> {
+        let hash = expr_to_hash(expr);
+        if let Some(compiled) = lock_state("vm_expression_cache", &self.cache).exprs.get(&hash) {
+            if compiled.source == expr {
+                //表达式内容相同
+                EXPR_CACHE_HIT_COUNT.sum(1);
+                return Ok(compiled.clone());
+            }
+        }
+
+        //使用临时虚拟机编译表达式函数
+        let tmp = match JS::new(1, Atom::from("tmp vm"), Arc::new(NativeObjsAuth::new(None, None)), None) {
+            None => return Err("compile expression failed, e: create vm failed".to_string()),
+            Some(vm) => vm,
+        };
+        let source = expr_to_func(hash, expr);
+        let code = match tmp.compile(EXPR_FILE.to_string(), source.clone()) {
+            None => return Err(format!("compile expression failed, expr: {:?}", expr)),
+            Some(code) => code,
+        };
+        //支持导出单个函数时，同时导出表达式函数的字节码
+        let func = if cfg!(feature = "funcdump") {
+            tmp.dump_function(EXPR_FILE, &source).ok()
+        } else {
+            None
+        };
+        EXPR_COMPILE_COUNT.sum(1);
+
+        let compiled = Arc::new(CompiledExpr {
+            hash,
+            source: expr.to_string(),
+            code: Arc::new(code),
+            func,
+        });
+        let mut cache = lock_state("vm_expression_cache", &self.cache);
+        if cache.exprs.insert(hash, compiled.clone()).is_none() {
+            cache.order.push_back(hash);
+        }
+        while cache.exprs.len() > self.capacity {
+            //缓存已满，则淘汰最早缓存的表达式
+            match cache.order.pop_front() {
+                None => break,
+                Some(key) => {
+                    cache.exprs.remove(&key);
+                },
+            }
+        }
+
+        Ok(compiled)
+    }
+
+    //以指定的上下文执行已编译的表达式，上下文中的值可以在表达式中直接通过名称访问
+    pub fn eval(&self, expr: &CompiledExpr, context: &[(&str, ScratchValue)]) -> Result<ScratchValue, String> {
+        EXPR_EVAL_COUNT.sum(1);
+        let name = format!("{}{}", EXPR_FUNCTION_PREFIX, expr.hash);
+        let script = format!("{}({})", name, context_to_object(context));
+        match expr.func {
+            Some(ref func) => scratch_eval_function(&name, func.as_slice(), &script, &self.limits),
+            None => scratch_eval_code(expr.code.as_slice(), &script, &self.limits),
+        }
+    }
+
+    //编译并以指定的上下文执行表达式
+    pub fn eval_str(&self, expr: &str, context: &[(&str, ScratchValue)]) -> Result<ScratchValue, String> {
+        let compiled = self.compile(expr)?;
+        self.eval(&compiled, context)
+    }
+}
+
+//将表达式转换为表达式函数
+fn expr_to_func(hash: u64, expr: &str) -> String {
+    format!("function {}{}(ctx) {{ with(ctx) {{ return ({}\n); }} }}", EXPR_FUNCTION_PREFIX, hash, expr)
+}
+
+//计算表达式hash
+fn expr_to_hash(expr: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    expr.hash(&mut hasher);
+    hasher.finish()
+}
+
+//将上下文转换为js对象字面量
+fn context_to_object(context: &[(&str, ScratchValue)]) -> String {
+    let fields: Vec<String> = context.iter()
+        .map(|(key, value)| format!("{}: {}", js_string(key), value_to_js(value)))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+//将值转换为js字面量
+fn value_to_js(value: &ScratchValue) -> String {
+    match value {
+        ScratchValue::Undefined => "undefined".to_string(),
+        ScratchValue::Null => "null".to_string(),
+        ScratchValue::Boolean(b) => b.to_string(),
+        ScratchValue::Number(n) if n.is_nan() => "NaN".to_string(),
+        ScratchValue::Number(n) if n.is_infinite() => if *n > 0.0 { "Infinity".to_string() } else { "-Infinity".to_string() },
+        ScratchValue::Number(n) => n.to_string(),
+        ScratchValue::String(s) => js_string(s),
+    }
+}
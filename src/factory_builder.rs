@@ -0,0 +1,363 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use atom::Atom;
+
+use adapter::{JS, CallValue, EvalPolicy, VmProfile};
+use bonmgr::NativeObjsAuth;
+use pi_vm_impl::{VMFactory, FactoryLimits, PendingLimits, CallReport, RecyclePolicy, WarmupCall};
+use names::{FactoryName, PortName};
+use services::ServiceRegistry;
+use bundle::compile_source;
+
+/*
+* 虚拟机工厂构建器加载的代码
+*/
+enum BuildCode {
+    Bytes(Arc<Vec<u8>>),    //字节码
+    Source(String, String), //待编译的js源码，包括文件名和源码
+}
+
+/*
+* 虚拟机工厂构建器，在一处配置虚拟机工厂的所有选项，构建后的虚拟机工厂不可再修改
+*/
+pub struct VMFactoryBuilder {
+    name:           FactoryName,                    //虚拟机工厂名
+    size:           usize,                          //虚拟机池大小，为0表示不复用虚拟机
+    heap_size:      usize,                          //虚拟机初始堆大小
+    recycle:        RecyclePolicy,                  //虚拟机回收策略
+    high_water:     usize,                          //虚拟机堆高水位，为0表示不限制
+    auth:           Arc<NativeObjsAuth>,            //虚拟机本地对象授权
+    codes:          Vec<BuildCode>,                 //虚拟机加载的字节码和待编译的源码，按加载顺序排列
+    depends:        Vec<String>,                    //虚拟机依赖的模块
+    code_version:   Option<String>,                 //字节码版本
+    labels:         Vec<(String, String)>,          //虚拟机标签
+    eval_policy:    Option<EvalPolicy>,             //动态代码执行策略
+    limits:         Option<FactoryLimits>,          //聚合资源限制
+    pending:        Option<PendingLimits>,          //等待调度的任务队列限制
+    report_hook:    Option<Arc<Fn(CallReport)>>,    //调用资源使用报告回调
+    task_priority:  Option<usize>,                  //任务优先级
+    task_queues:    usize,                          //独占的同步任务队列数量，为0表示不独占
+    stack_limit:    Option<usize>,                  //调用栈深度限制
+    native_limit:   Option<usize>,                  //本地调用递归深度限制
+    fuel:           Option<usize>,                  //每次调用的燃料预算
+    idle_ttl:       Option<Duration>,               //空闲虚拟机的存活时长
+    call_timeout:   Option<Duration>,               //调用的默认执行超时时长
+    checkout_retry: Option<(usize, Duration)>,      //取出虚拟机的重试次数和退避时长
+    create_hook:    Option<Arc<Fn(&Arc<JS>)>>,      //构建虚拟机后的回调
+    destroy_hook:   Option<Arc<Fn(Atom, usize)>>,   //虚拟机销毁时的回调
+    affinity:       usize,                          //源固定的空闲虚拟机的最大数量，为0表示不启用源亲和
+    services:       Option<ServiceRegistry>,        //服务注册表
+    profile:        VmProfile,                      //虚拟机堆配置
+    warmups:        Vec<WarmupCall>,                //构建虚拟机后执行的预热调用列表
+}
+
+impl VMFactoryBuilder {
+    //构建一个虚拟机工厂构建器
+    pub fn new(name: FactoryName, auth: Arc<NativeObjsAuth>) -> Self {
+        VMFactoryBuilder {
+            name,
+            size: 0,
+            heap_size: 0,
+            recycle: RecyclePolicy::default(),
+            high_water: 0,
+            auth,
+            codes: Vec::new(),
+            depends: Vec::new(),
+            code_version: None,
+            labels: Vec::new(),
+            eval_policy: None,
+            limits: None,
+            pending: None,
+            report_hook: None,
+            task_priority: None,
+            task_queues: 0,
+            stack_limit: None,
+            native_limit: None,
+            fuel: None,
+            idle_ttl: None,
+            call_timeout: None,
+            checkout_retry: None,
+            create_hook: None,
+            destroy_hook: None,
+            affinity: 0,
+            services: None,
+            profile: VmProfile::default(),
+            warmups: Vec::new(),
+        }
+    }
+
+    //设置虚拟机池大小，为0表示不复用虚拟机
+    pub fn pool_size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    //设置虚拟机初始堆大小
+    pub fn heap_size(mut self, size: usize) -> Self {
+        self.heap_size = size;
+        self
+    }
+
+    //设置虚拟机回收策略
+    pub fn recycle_policy(mut self, policy: RecyclePolicy) -> Self {
+        self.recycle = policy;
+        self
+    }
+
+    //设置虚拟机堆高水位，调用后虚拟机堆超过则替换虚拟机
+    pub fn heap_high_water(mut self, size: usize) -> Self {
+        self.high_water = size;
+        self
+    }
+
+    //增加虚拟机加载的字节码
+    pub fn code(mut self, code: Arc<Vec<u8>>) -> Self {
+        self.codes.push(BuildCode::Bytes(code));
+        self
+    }
+
+    //增加虚拟机加载的多个字节码
+    pub fn codes(mut self, codes: Vec<Arc<Vec<u8>>>) -> Self {
+        self.codes.extend(codes.into_iter().map(BuildCode::Bytes));
+        self
+    }
+
+    //增加虚拟机加载的js源码，源码在构建虚拟机工厂时编译为字节码
+    pub fn source(mut self, file: &str, source: &str) -> Self {
+        self.codes.push(BuildCode::Source(file.to_string(), source.to_string()));
+        self
+    }
+
+    //增加虚拟机依赖的模块
+    pub fn depend(mut self, module: String) -> Self {
+        self.depends.push(module);
+        self
+    }
+
+    //设置字节码版本
+    pub fn code_version(mut self, version: &str) -> Self {
+        self.code_version = Some(version.to_string());
+        self
+    }
+
+    //增加虚拟机标签
+    pub fn label(mut self, key: &str, value: &str) -> Self {
+        self.labels.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    //设置动态代码执行策略，禁止执行动态代码需要启用evalcheck特性构建，否则构建虚拟机工厂失败
+    pub fn eval_policy(mut self, policy: EvalPolicy) -> Self {
+        self.eval_policy = Some(policy);
+        self
+    }
+
+    //设置聚合资源限制
+    pub fn limits(mut self, limits: FactoryLimits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    //设置等待调度的任务队列限制
+    pub fn pending_limits(mut self, limits: PendingLimits) -> Self {
+        self.pending = Some(limits);
+        self
+    }
+
+    //设置调用资源使用报告回调
+    pub fn report_hook(mut self, hook: Arc<Fn(CallReport)>) -> Self {
+        self.report_hook = Some(hook);
+        self
+    }
+
+    //设置任务优先级
+    pub fn task_priority(mut self, priority: usize) -> Self {
+        self.task_priority = Some(priority);
+        self
+    }
+
+    //设置独占的同步任务队列数量，即调用的最大并发数
+    pub fn task_queues(mut self, concurrency: usize) -> Self {
+        self.task_queues = concurrency;
+        self
+    }
+
+    //设置调用栈深度限制，需要启用stacklimit特性构建，否则构建虚拟机工厂失败
+    pub fn stack_limit(mut self, limit: usize) -> Self {
+        self.stack_limit = Some(limit);
+        self
+    }
+
+    //设置本地调用递归深度限制，需要启用nativelimit特性构建，否则构建虚拟机工厂失败
+    pub fn native_limit(mut self, limit: usize) -> Self {
+        self.native_limit = Some(limit);
+        self
+    }
+
+    //设置每次调用的燃料预算
+    pub fn fuel(mut self, fuel: usize) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    //设置空闲虚拟机的存活时长
+    pub fn idle_ttl(mut self, ttl: Duration) -> Self {
+        self.idle_ttl = Some(ttl);
+        self
+    }
+
+    //设置调用的默认执行超时时长，需要启用interruptcheck特性构建，否则构建虚拟机工厂失败
+    pub fn call_timeout(mut self, timeout: Duration) -> Self {
+        self.call_timeout = Some(timeout);
+        self
+    }
+
+    //设置取出虚拟机的重试次数和退避时长
+    pub fn checkout_retry(mut self, retries: usize, backoff: Duration) -> Self {
+        self.checkout_retry = Some((retries, backoff));
+        self
+    }
+
+    //设置构建虚拟机后的回调
+    pub fn on_create(mut self, hook: Arc<Fn(&Arc<JS>)>) -> Self {
+        self.create_hook = Some(hook);
+        self
+    }
+
+    //设置虚拟机销毁时的回调
+    pub fn on_destroy(mut self, hook: Arc<Fn(Atom, usize)>) -> Self {
+        self.destroy_hook = Some(hook);
+        self
+    }
+
+    //设置源固定的空闲虚拟机的最大数量
+    pub fn affinity(mut self, capacity: usize) -> Self {
+        self.affinity = capacity;
+        self
+    }
+
+    //设置服务注册表
+    pub fn services(mut self, registry: ServiceRegistry) -> Self {
+        self.services = Some(registry);
+        self
+    }
+
+    //设置虚拟机堆配置，低内存配置适用于大量小虚拟机的部署
+    pub fn profile(mut self, profile: VmProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    //增加构建虚拟机后执行的预热调用，参数按顺序压入虚拟机栈
+    pub fn warmup(mut self, port: PortName, args: Vec<CallValue>, times: usize) -> Self {
+        self.warmups.push(WarmupCall {
+            port,
+            args,
+            times,
+        });
+        self
+    }
+
+    //兼容旧版本的构建接口
+    #[deprecated(note = "use VMFactoryBuilder::build, which reports compile errors")]
+    pub fn try_build(self) -> Result<VMFactory, String> {
+        self.build()
+    }
+
+    //构建虚拟机工厂，任务优先级会在创建独占的同步任务队列前设置，任意源码编译失败则返回错误，不会构建缺少代码的虚拟机工厂
+    pub fn build(self) -> Result<VMFactory, String> {
+        let mut codes = Vec::with_capacity(self.codes.len());
+        for code in self.codes {
+            codes.push(match code {
+                BuildCode::Bytes(code) => code,
+                BuildCode::Source(file, source) => compile_source(&file, &source)?,
+            });
+        }
+
+        let mut factory = VMFactory::new(self.name.clone(),
+                                         self.size,
+                                         self.recycle.max_reused_count,
+                                         self.heap_size,
+                                         self.recycle.max_heap_size,
+                                         self.auth);
+
+        if self.recycle.max_calls > 0 {
+            factory = factory.set_max_calls(self.recycle.max_calls);
+        }
+        if let Some(age) = self.recycle.max_age {
+            factory = factory.set_max_age(age);
+        }
+        if self.high_water > 0 {
+            factory = factory.set_heap_high_water(self.high_water);
+        }
+        for code in codes {
+            factory = factory.append(code);
+        }
+        for module in self.depends {
+            factory = factory.append_depend(module);
+        }
+        if let Some(version) = self.code_version {
+            factory = factory.set_code_version(&version);
+        }
+        for (key, value) in self.labels {
+            factory = factory.append_label(&key, &value);
+        }
+        if let Some(policy) = self.eval_policy {
+            factory = factory.set_eval_policy(policy).map_err(|e| e.to_string())?;
+        }
+        if let Some(limits) = self.limits {
+            factory = factory.set_limits(limits);
+        }
+        if let Some(limits) = self.pending {
+            factory = factory.set_pending_limits(limits);
+        }
+        if let Some(hook) = self.report_hook {
+            factory = factory.set_report_hook(hook);
+        }
+        if let Some(priority) = self.task_priority {
+            factory = factory.set_task_priority(priority);
+        }
+        if self.task_queues > 0 {
+            factory = factory.set_task_queues(self.task_queues);
+        }
+        if let Some(limit) = self.stack_limit {
+            factory = factory.set_stack_limit(limit).map_err(|e| e.to_string())?;
+        }
+        if let Some(limit) = self.native_limit {
+            factory = factory.set_native_limit(limit).map_err(|e| e.to_string())?;
+        }
+        if let Some(fuel) = self.fuel {
+            factory = factory.set_fuel(fuel).map_err(|e| e.to_string())?;
+        }
+        if let Some(ttl) = self.idle_ttl {
+            factory = factory.set_idle_ttl(ttl);
+        }
+        if let Some(timeout) = self.call_timeout {
+            factory = factory.set_call_timeout(timeout).map_err(|e| e.to_string())?;
+        }
+        if let Some((retries, backoff)) = self.checkout_retry {
+            factory = factory.set_checkout_retry(retries, backoff);
+        }
+        if let Some(hook) = self.create_hook {
+            factory = factory.set_create_hook(hook);
+        }
+        if let Some(hook) = self.destroy_hook {
+            factory = factory.set_destroy_hook(hook);
+        }
+        if self.affinity > 0 {
+            factory = factory.set_affinity(self.affinity);
+        }
+        if let Some(registry) = self.services {
+            factory = factory.set_services(registry);
+        }
+        if self.profile != VmProfile::default() {
+            factory = factory.set_profile(self.profile);
+        }
+        for warmup in self.warmups {
+            factory = factory.add_warmup(warmup.port, warmup.args, warmup.times);
+        }
+
+        Ok(factory)
+    }
+}
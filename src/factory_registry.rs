@@ -0,0 +1,100 @@
+use atom::Atom;
+
+use adapter::VM_FACTORY_REGISTERS;
+use pi_vm_impl::{VMFactory, FactoryStats};
+use health::{read_state, write_state};
+
+/*
+* 虚拟机工厂注册表的查询接口，注册表为适配器的虚拟机工厂注册表，包括主动注册和首次生成虚拟机时自动注册的虚拟机工厂，
+* 用于在不同模块间按名称查找虚拟机工厂
+*/
+
+/*
+* 已注册虚拟机工厂的汇总统计
+*/
+#[derive(Debug, Clone, Default)]
+pub struct RegistryStats {
+    pub factory_count:  usize,  //已注册虚拟机工厂数量
+    pub size:           usize,  //所有虚拟机工厂当前虚拟机数量
+    pub free_size:      usize,  //所有虚拟机工厂空闲虚拟机数量
+    pub checked_out:    usize,  //所有虚拟机工厂被守护者取出的虚拟机数量
+    pub queue_len:      usize,  //所有虚拟机工厂等待调度的任务数量
+    pub refuse_count:   usize,  //所有虚拟机工厂拒绝任务次数
+    pub total_heap:     usize,  //所有虚拟机工厂的总堆大小
+}
+
+//注册虚拟机工厂，同名的其它虚拟机工厂已注册则返回错误，重复注册同一个虚拟机工厂则忽略
+pub fn register_factory(factory: &VMFactory) -> Result<(), String> {
+    let name = factory.name();
+    let mut factories = write_state("vm_factory_registers", &VM_FACTORY_REGISTERS);
+    if let Some(registered) = factories.get(&name) {
+        if registered.is_same(factory) {
+            return Ok(());
+        }
+        return Err(format!("register vm factory failed, factory: {:?}, e: name already registered", factory.name()));
+    }
+
+    factories.insert(name, factory.clone());
+    info!("===> Vm Factory Register Ok, factory: {:?}", factory.name());
+    Ok(())
+}
+
+//注销指定名称的虚拟机工厂，返回被注销的虚拟机工厂
+pub fn unregister_factory(name: &Atom) -> Option<VMFactory> {
+    write_state("vm_factory_registers", &VM_FACTORY_REGISTERS).remove(name.as_str())
+}
+
+//注销指定虚拟机工厂，只有同名的已注册虚拟机工厂是同一个虚拟机工厂时才注销，返回是否注销
+pub fn remove_factory(factory: &VMFactory) -> bool {
+    let name = factory.name();
+    let mut factories = write_state("vm_factory_registers", &VM_FACTORY_REGISTERS);
+    if factories.get(&name).map_or(false, |registered| registered.is_same(factory)) {
+        factories.remove(&name);
+        return true;
+    }
+    false
+}
+
+//获取指定名称的已注册虚拟机工厂
+pub fn get_factory(name: &Atom) -> Option<VMFactory> {
+    read_state("vm_factory_registers", &VM_FACTORY_REGISTERS).get(name.as_str()).cloned()
+}
+
+//获取已注册虚拟机工厂数量
+pub fn factory_count() -> usize {
+    read_state("vm_factory_registers", &VM_FACTORY_REGISTERS).len()
+}
+
+//获取所有已注册虚拟机工厂名，按名称排序
+pub fn factory_names() -> Vec<Atom> {
+    let mut names: Vec<Atom> = read_state("vm_factory_registers", &VM_FACTORY_REGISTERS).keys().map(|name| Atom::from(name.as_str())).collect();
+    names.sort_by(|x, y| x.as_str().cmp(y.as_str()));
+    names
+}
+
+//获取所有已注册虚拟机工厂
+pub fn factories() -> Vec<VMFactory> {
+    read_state("vm_factory_registers", &VM_FACTORY_REGISTERS).values().cloned().collect()
+}
+
+//获取每个已注册虚拟机工厂的统计，按虚拟机工厂名排序
+pub fn registered_factory_stats() -> Vec<FactoryStats> {
+    let mut stats: Vec<FactoryStats> = factories().iter().map(|factory| factory.stats()).collect();
+    stats.sort_by(|x, y| x.name.cmp(&y.name));
+    stats
+}
+
+//获取所有已注册虚拟机工厂的汇总统计
+pub fn registry_stats() -> RegistryStats {
+    let mut total = RegistryStats::default();
+    for stats in registered_factory_stats() {
+        total.factory_count += 1;
+        total.size += stats.size;
+        total.free_size += stats.free_size;
+        total.checked_out += stats.checked_out;
+        total.queue_len += stats.queue_len;
+        total.refuse_count += stats.refuse_count;
+        total.total_heap += stats.total_heap;
+    }
+    total
+}
@@ -0,0 +1,260 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use adapter::JS;
+use pi_vm_impl::{VMFactory, VMFactoryError};
+use task_info::TaskInfo;
+use names::PortName;
+
+lazy_static! {
+    //虚拟机工厂主备切换次数
+    static ref VM_FACTORY_FAILOVER_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_factory_failover_count"), 0).unwrap();
+    //虚拟机工厂主备回切次数
+    static ref VM_FACTORY_FAILBACK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_factory_failback_count"), 0).unwrap();
+}
+
+/*
+* 主备切换事件
+*/
+#[derive(Debug, Clone)]
+pub enum FailoverEvent {
+    Failover(String, f64, f64), //切换到备用虚拟机工厂，(主备名, 主虚拟机工厂调用错误率, 主虚拟机工厂虚拟机损坏率)
+    Failback(String),           //回切到主虚拟机工厂，(主备名)
+}
+
+/*
+* 统计窗口
+*/
+struct FailoverWindow {
+    calls:          usize,  //窗口内的调用次数
+    errors:         usize,  //窗口内的调用错误次数
+    poison_base:    usize,  //窗口开始时主虚拟机工厂的虚拟机损坏数量
+}
+
+/*
+* 主备虚拟机工厂，保持备用虚拟机工厂预热，当主虚拟机工厂在一个统计窗口内的调用错误率或虚拟机损坏率超过阈值时，
+* 自动将调用切换到备用虚拟机工厂，切换后需要手动回切
+*/
+#[derive(Clone)]
+pub struct FactoryPair {
+    name:               Atom,                           //主备名
+    primary:            VMFactory,                      //主虚拟机工厂
+    standby:            VMFactory,                      //备用虚拟机工厂，应与主虚拟机工厂加载相同的代码
+    on_standby:         Arc<AtomicBool>,                //是否已切换到备用虚拟机工厂
+    error_threshold:    f64,                            //调用错误率阈值
+    poison_threshold:   f64,                            //虚拟机损坏率阈值，为窗口内损坏的虚拟机数量与调用次数的比值
+    window_size:        usize,                          //统计窗口的调用次数
+    window:             Arc<Mutex<FailoverWindow>>,     //当前统计窗口
+    failover_count:     Arc<AtomicUsize>,               //切换到备用虚拟机工厂的次数
+    listener:           Option<Arc<Fn(FailoverEvent) + Send + Sync>>,   //切换事件监听器
+}
+
+impl FactoryPair {
+    //构建一个主备虚拟机工厂
+    pub fn new(name: &str, primary: VMFactory, standby: VMFactory) -> Self {
+        if primary.code_version() != standby.code_version() {
+            warn!("!!!> Factory Pair Code Version Mismatch, pair: {}, primary: {:?}, standby: {:?}",
+                  name, primary.code_version(), standby.code_version());
+        }
+
+        let poison_base = primary.poison_count();
+        FactoryPair {
+            name: Atom::from(name),
+            primary,
+            standby,
+            on_standby: Arc::new(AtomicBool::new(false)),
+            error_threshold: 0.5,
+            poison_threshold: 0.1,
+            window_size: 100,
+            window: Arc::new(Mutex::new(FailoverWindow {
+                calls: 0,
+                errors: 0,
+                poison_base,
+            })),
+            failover_count: Arc::new(AtomicUsize::new(0)),
+            listener: None,
+        }
+    }
+
+    //设置调用错误率阈值
+    pub fn set_error_threshold(mut self, threshold: f64) -> Self {
+        self.error_threshold = threshold;
+        self
+    }
+
+    //设置虚拟机损坏率阈值
+    pub fn set_poison_threshold(mut self, threshold: f64) -> Self {
+        self.poison_threshold = threshold;
+        self
+    }
+
+    //设置统计窗口的调用次数，窗口内调用次数达到后才会判断是否切换
+    pub fn set_window_size(mut self, size: usize) -> Self {
+        self.window_size = if size == 0 { 1 } else { size };
+        self
+    }
+
+    //设置切换事件监听器
+    pub fn set_listener(mut self, listener: Arc<Fn(FailoverEvent) + Send + Sync>) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    //预热备用虚拟机工厂，生成指定数量的虚拟机
+    pub fn warm(&self, count: usize) -> Result<usize, String> {
+        self.standby.produce(count)
+    }
+
+    //获取主备名
+    pub fn name(&self) -> String {
+        (*self.name).to_string()
+    }
+
+    //获取主虚拟机工厂
+    pub fn primary(&self) -> &VMFactory {
+        &self.primary
+    }
+
+    //获取备用虚拟机工厂
+    pub fn standby(&self) -> &VMFactory {
+        &self.standby
+    }
+
+    //获取当前接收调用的虚拟机工厂
+    pub fn active(&self) -> &VMFactory {
+        if self.is_failed_over() {
+            &self.standby
+        } else {
+            &self.primary
+        }
+    }
+
+    //判断是否已切换到备用虚拟机工厂
+    pub fn is_failed_over(&self) -> bool {
+        self.on_standby.load(Ordering::SeqCst)
+    }
+
+    //获取切换到备用虚拟机工厂的次数
+    pub fn failover_count(&self) -> usize {
+        self.failover_count.load(Ordering::Relaxed)
+    }
+
+    //通过当前接收调用的虚拟机工厂调用指定的js全局函数，调用参数中设置的完成回调会在统计调用结果后回调
+    pub fn call(&self, src: Option<usize>, port: PortName, args: Box<FnOnce(Arc<JS>) -> usize>, info: TaskInfo) -> Result<(), VMFactoryError> {
+        let on_primary = !self.is_failed_over();
+        let finish = self.track(on_primary, Box::new(|_| {}));
+        let args = Box::new(move |vm: Arc<JS>| {
+            let len = args(vm.clone());
+            vm.chain_finish(finish); //不替换调用参数中设置的完成回调
+            len
+        });
+
+        let r = if on_primary {
+            self.primary.call(src, port, args, info)
+        } else {
+            self.standby.call(src, port, args, info)
+        };
+        if r.is_err() && on_primary {
+            self.record(true);
+        }
+        r
+    }
+
+    //通过当前接收调用的虚拟机工厂调用指定的js全局函数，调用完成后回调执行结果
+    pub fn call_then(&self, src: Option<usize>, port: PortName, args: Box<FnOnce(Arc<JS>) -> usize>, info: TaskInfo, finish: Box<FnOnce(Result<Option<String>, String>)>) {
+        let on_primary = !self.is_failed_over();
+        let finish = self.track(on_primary, finish);
+        if on_primary {
+            self.primary.call_then(src, port, args, info, finish);
+        } else {
+            self.standby.call_then(src, port, args, info, finish);
+        }
+    }
+
+    //手动回切到主虚拟机工厂，并开始新的统计窗口，已在主虚拟机工厂则返回false
+    pub fn failback(&self) -> bool {
+        if !self.on_standby.compare_and_swap(true, false, Ordering::SeqCst) {
+            return false;
+        }
+
+        self.reset_window();
+        VM_FACTORY_FAILBACK_COUNT.sum(1);
+        info!("===> Factory Pair Failback Ok, pair: {:?}", self.name());
+        self.notify(FailoverEvent::Failback(self.name()));
+        true
+    }
+
+    //记录主虚拟机工厂的一次调用结果，并在窗口结束时判断是否需要切换
+    fn record(&self, is_error: bool) {
+        let (error_rate, poison_rate) = {
+            let mut window = self.window.lock().unwrap();
+            window.calls += 1;
+            if is_error {
+                window.errors += 1;
+            }
+
+            if window.calls < self.window_size {
+                return;
+            }
+
+            let calls = window.calls as f64;
+            let poisoned = self.primary.poison_count().saturating_sub(window.poison_base);
+            let rates = (window.errors as f64 / calls, poisoned as f64 / calls);
+
+            //开始新的统计窗口
+            window.calls = 0;
+            window.errors = 0;
+            window.poison_base = self.primary.poison_count();
+            rates
+        };
+
+        if error_rate >= self.error_threshold || poison_rate >= self.poison_threshold {
+            self.failover(error_rate, poison_rate);
+        }
+    }
+
+    //切换到备用虚拟机工厂
+    fn failover(&self, error_rate: f64, poison_rate: f64) {
+        if self.on_standby.compare_and_swap(false, true, Ordering::SeqCst) {
+            //已切换
+            return;
+        }
+
+        self.failover_count.fetch_add(1, Ordering::Relaxed);
+        VM_FACTORY_FAILOVER_COUNT.sum(1);
+        warn!("!!!> Factory Pair Failover, pair: {:?}, error rate: {}, poison rate: {}",
+              self.name(), error_rate, poison_rate);
+        self.notify(FailoverEvent::Failover(self.name(), error_rate, poison_rate));
+    }
+
+    //包装调用完成回调，只统计主虚拟机工厂的调用结果
+    fn track(&self, on_primary: bool, finish: Box<FnOnce(Result<Option<String>, String>)>) -> Box<FnOnce(Result<Option<String>, String>)> {
+        if !on_primary {
+            return finish;
+        }
+
+        let pair = self.clone();
+        Box::new(move |result: Result<Option<String>, String>| {
+            pair.record(result.is_err());
+            finish(result);
+        })
+    }
+
+    //开始新的统计窗口
+    fn reset_window(&self) {
+        let mut window = self.window.lock().unwrap();
+        window.calls = 0;
+        window.errors = 0;
+        window.poison_base = self.primary.poison_count();
+    }
+
+    //通知切换事件
+    fn notify(&self, event: FailoverEvent) {
+        if let Some(listener) = &self.listener {
+            listener(event);
+        }
+    }
+}
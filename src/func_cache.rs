@@ -0,0 +1,152 @@
+use std::sync::{Arc, Mutex};
+use std::hash::{Hash, Hasher};
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use adapter::JS;
+use bytecode::duk_version;
+use health::lock_state;
+
+/*
+* 默认的函数字节码缓存容量
+*/
+const DEFAULT_FUNCTION_CACHE_CAPACITY: usize = 1024;
+
+lazy_static! {
+    //函数字节码缓存命中的次数
+    static ref VM_FUNCTION_CACHE_HIT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_function_cache_hit_count"), 0).unwrap();
+    //函数字节码缓存未命中，需要编译并导出函数的次数
+    static ref VM_FUNCTION_CACHE_MISS_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_function_cache_miss_count"), 0).unwrap();
+}
+
+/*
+* 已缓存的函数字节码
+*/
+struct CachedFunction {
+    file:   String,         //函数所在文件名
+    source: String,         //函数源码
+    code:   Arc<Vec<u8>>,   //函数的字节码
+}
+
+/*
+* 函数字节码表
+*/
+struct FunctionTable {
+    functions:  HashMap<u64, CachedFunction>,   //已缓存的函数字节码表
+    order:      VecDeque<u64>,                  //函数的使用顺序，缓存已满时淘汰最久未使用的函数
+}
+
+/*
+* 函数字节码缓存，将频繁执行的代码片段编译后的单个函数的字节码按文件名、源码和Duktape版本的hash缓存在内存中，
+* 可以在任意虚拟机中加载，以避免表达式、模板等代码片段在每个虚拟机中重复编译，需要启用funcdump特性构建
+*/
+#[derive(Clone)]
+pub struct FunctionCache {
+    capacity:   usize,                      //函数字节码缓存容量
+    table:      Arc<Mutex<FunctionTable>>,  //函数字节码表
+}
+
+impl FunctionCache {
+    //构建一个函数字节码缓存
+    pub fn new() -> Self {
+        FunctionCache {
+            capacity: DEFAULT_FUNCTION_CACHE_CAPACITY,
+            table: Arc::new(Mutex::new(FunctionTable {
+                functions: HashMap::new(),
+                order: VecDeque::new(),
+            })),
+        }
+    }
+
+    //设置函数字节码缓存容量，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = if capacity == 0 { 1 } else { capacity };
+        self
+    }
+
+    //获取函数字节码缓存容量
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    //获取已缓存的函数数量
+    pub fn len(&self) -> usize {
+        lock_state("vm_function_cache", &self.table).functions.len()
+    }
+
+    //判断函数字节码缓存是否为空
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    //清空函数字节码缓存
+    pub fn clear(&self) {
+        let mut table = lock_state("vm_function_cache", &self.table);
+        table.functions.clear();
+        table.order.clear();
+    }
+
+    //获取指定文件名和源码的缓存键
+    pub fn key(file: &str, source: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        duk_version().hash(&mut hasher);
+        file.hash(&mut hasher);
+        source.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    //获取指定文件名和源码已缓存的函数字节码
+    pub fn get(&self, file: &str, source: &str) -> Option<Arc<Vec<u8>>> {
+        let key = FunctionCache::key(file, source);
+        let mut table = lock_state("vm_function_cache", &self.table);
+        let code = match table.functions.get(&key) {
+            Some(function) if function.file == file && function.source == source => function.code.clone(),
+            _ => return None,
+        };
+
+        //更新函数的使用顺序
+        if let Some(index) = table.order.iter().position(|k| *k == key) {
+            table.order.remove(index);
+        }
+        table.order.push_back(key);
+        Some(code)
+    }
+
+    //获取指定文件名和源码已缓存的函数字节码，未缓存则使用指定虚拟机编译并导出函数的字节码后缓存
+    pub fn get_or_dump(&self, vm: &JS, file: &str, source: &str) -> Result<Arc<Vec<u8>>, String> {
+        if let Some(code) = self.get(file, source) {
+            VM_FUNCTION_CACHE_HIT_COUNT.sum(1);
+            return Ok(code);
+        }
+
+        VM_FUNCTION_CACHE_MISS_COUNT.sum(1);
+        let code = Arc::new(vm.dump_function(file, source)?);
+        self.insert(file, source, code.clone());
+        Ok(code)
+    }
+
+    //缓存指定文件名和源码的函数字节码，缓存已满则淘汰最久未使用的函数
+    pub fn insert(&self, file: &str, source: &str, code: Arc<Vec<u8>>) {
+        let key = FunctionCache::key(file, source);
+        let mut table = lock_state("vm_function_cache", &self.table);
+        let function = CachedFunction {
+            file: file.to_string(),
+            source: source.to_string(),
+            code,
+        };
+        if table.functions.insert(key, function).is_none() {
+            table.order.push_back(key);
+        }
+        while table.functions.len() > self.capacity {
+            match table.order.pop_front() {
+                None => break,
+                Some(key) => {
+                    table.functions.remove(&key);
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::str;
+use std::sync::Arc;
+use std::f64::{INFINITY, NAN};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use atom::Atom;
+
+use adapter::{JS, JSType};
+use bonmgr::NativeObjsAuth;
+use channel_map::{VMChannel, VMChannelMap};
+
+/*
+* 模糊测试的输入在虚拟机中的全局变量名
+*/
+const FUZZ_INPUT_VAR: &'static str = "__fuzz_input";
+
+/*
+* 模糊测试的通道处理器名
+*/
+const FUZZ_CHANNEL_HANDLER: &'static str = "__fuzz_channel";
+
+/*
+* CBOR解码的最大嵌套深度，超过则拒绝输入，以保证恶意输入不会耗尽本地栈
+*/
+const FUZZ_CBOR_MAX_DEPTH: usize = 64;
+
+/*
+* 构建模糊测试使用的虚拟机，模糊测试入口的每次输入都在当前线程上使用新构建的虚拟机执行，不依赖任务池，用于使用cargo-fuzz测试适配器的不安全接口，
+* 入口只在输入导致内存错误或中止时失败，返回值只表示输入是否被虚拟机接受
+*/
+fn fuzz_vm() -> Option<Arc<JS>> {
+    JS::new(0, Atom::from("fuzz vm"), Arc::new(NativeObjsAuth::new(None, None)), None)
+}
+
+//使用任意字节作为字节码加载到虚拟机，返回虚拟机是否接受字节码
+pub fn fuzz_load_code(data: &[u8]) -> bool {
+    match fuzz_vm() {
+        None => false,
+        Some(vm) => vm.load(data),
+    }
+}
+
+//将任意字节作为JSON文本在虚拟机中解码，返回是否解码成功
+pub fn fuzz_decode_json(data: &[u8]) -> bool {
+    let vm = match fuzz_vm() {
+        None => return false,
+        Some(vm) => vm,
+    };
+
+    let input = match vm.new_str(String::from_utf8_lossy(data).into_owned()) {
+        Err(_) => return false, //包含空字符的输入无法构建为字符串
+        Ok(input) => input,
+    };
+    if !vm.set_global_var(FUZZ_INPUT_VAR.to_string(), input) {
+        return false;
+    }
+
+    let r = vm.eval(format!("(function() {{ try {{ JSON.parse({}); return true; }} catch(e) {{ return false; }} }})()", FUZZ_INPUT_VAR));
+    r.is_boolean() && r.get_boolean()
+}
+
+//将任意字节作为CBOR数据解码为虚拟机中的值，并设置为全局变量，返回是否解码成功，不支持不定长编码，映射只支持字符串键
+pub fn fuzz_decode_cbor(data: &[u8]) -> bool {
+    let vm = match fuzz_vm() {
+        None => return false,
+        Some(vm) => vm,
+    };
+
+    let mut offset = 0;
+    let value = match decode_cbor(&vm, data, &mut offset, 0) {
+        None => return false,
+        Some(value) => value,
+    };
+    if offset != data.len() {
+        //有未解码的尾部数据
+        return false;
+    }
+
+    vm.set_global_var(FUZZ_INPUT_VAR.to_string(), value)
+}
+
+//将任意字节作为通道请求的负载交给处理器，处理器按通道回应的方式将负载复制到虚拟机的Uint8Array中并校验，返回请求是否成功
+pub fn fuzz_channel_payload(data: &[u8]) -> bool {
+    let vm = match fuzz_vm() {
+        None => return false,
+        Some(vm) => vm,
+    };
+
+    let map = VMChannelMap::new(0);
+    let checked = Arc::new(AtomicBool::new(false));
+    let checked_copy = checked.clone();
+    let vm_copy = vm.clone();
+    map.set_handler(Atom::from(FUZZ_CHANNEL_HANDLER), Arc::new(move |_: Arc<VMChannel>, _: Atom, msg: Arc<Vec<u8>>, _: Vec<JSType>, _: Option<u32>| {
+        let buffer = vm_copy.new_uint8_array(msg.len() as u32);
+        buffer.from_bytes(msg.as_slice());
+        checked_copy.store(buffer.to_bytes() == msg.as_slice(), Ordering::SeqCst);
+    }));
+
+    map.request(vm, Atom::from(FUZZ_CHANNEL_HANDLER), Arc::new(data.to_vec()), vec![], None) && checked.load(Ordering::SeqCst)
+}
+
+//在虚拟机中构建指定偏移的CBOR数据项，并移动偏移到数据项之后，数据项无效则返回None
+fn decode_cbor(vm: &Arc<JS>, data: &[u8], offset: &mut usize, depth: usize) -> Option<JSType> {
+    if depth > FUZZ_CBOR_MAX_DEPTH {
+        return None;
+    }
+
+    let info = *data.get(*offset)? & 0x1f;
+    let (major, arg) = read_cbor_head(data, offset)?;
+    match major {
+        0 => Some(vm.new_f64(arg as f64)),
+        1 => Some(vm.new_f64(-1.0 - arg as f64)),
+        2 => {
+            let bytes = read_cbor_bytes(data, offset, arg)?;
+            let buffer = vm.new_uint8_array(bytes.len() as u32);
+            buffer.from_bytes(bytes);
+            Some(buffer)
+        },
+        3 => {
+            let text = str::from_utf8(read_cbor_bytes(data, offset, arg)?).ok()?;
+            vm.new_str(text.to_string()).ok()
+        },
+        4 => {
+            if arg > (data.len() - *offset) as u64 {
+                //每个元素至少占用1个字节
+                return None;
+            }
+
+            let array = vm.new_array();
+            for index in 0..arg as u32 {
+                let mut value = decode_cbor(vm, data, offset, depth + 1)?;
+                if !vm.set_index(&array, index, &mut value) {
+                    return None;
+                }
+            }
+            Some(array)
+        },
+        5 => {
+            if arg > ((data.len() - *offset) / 2) as u64 {
+                //每个键值对至少占用2个字节
+                return None;
+            }
+
+            let object = vm.new_object();
+            for _ in 0..arg {
+                let (key_major, key_len) = read_cbor_head(data, offset)?;
+                if key_major != 3 {
+                    return None;
+                }
+                let key = str::from_utf8(read_cbor_bytes(data, offset, key_len)?).ok()?.to_string();
+                let mut value = decode_cbor(vm, data, offset, depth + 1)?;
+                if !vm.set_field(&object, key, &mut value) {
+                    return None;
+                }
+            }
+            Some(object)
+        },
+        6 => decode_cbor(vm, data, offset, depth + 1), //忽略标签，只构建标签后的数据项
+        _ => match info {
+            20 => Some(vm.new_boolean(false)),
+            21 => Some(vm.new_boolean(true)),
+            22 => Some(vm.new_null()),
+            23 => Some(vm.new_undefined()),
+            25 => Some(vm.new_f64(half_to_f64(arg as u16))),
+            26 => Some(vm.new_f64(f64::from(f32::from_bits(arg as u32)))),
+            27 => Some(vm.new_f64(f64::from_bits(arg))),
+            _ => None, //不支持的简单值
+        },
+    }
+}
+
+//读取CBOR数据项的头部，返回主类型和参数，参数超出输入、使用保留值或不定长编码则返回None
+fn read_cbor_head(data: &[u8], offset: &mut usize) -> Option<(u8, u64)> {
+    let head = *data.get(*offset)?;
+    *offset += 1;
+
+    let (major, info) = (head >> 5, head & 0x1f);
+    let len = match info {
+        0..=23 => return Some((major, u64::from(info))),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return None,
+    };
+    let bytes = data.get(*offset..*offset + len)?;
+    *offset += len;
+    Some((major, bytes.iter().fold(0u64, |arg, byte| (arg << 8) | u64::from(*byte))))
+}
+
+//读取指定长度的CBOR字节串或文本串的内容，长度超出输入则返回None
+fn read_cbor_bytes<'a>(data: &'a [u8], offset: &mut usize, len: u64) -> Option<&'a [u8]> {
+    if len > (data.len() - *offset) as u64 {
+        return None;
+    }
+
+    let bytes = &data[*offset..*offset + len as usize];
+    *offset += len as usize;
+    Some(bytes)
+}
+
+//将半精度浮点数转换为双精度浮点数
+fn half_to_f64(half: u16) -> f64 {
+    let exp = i32::from((half >> 10) & 0x1f);
+    let mant = half & 0x3ff;
+    let value = match exp {
+        0 => f64::from(mant) * 2f64.powi(-24),
+        31 if mant == 0 => INFINITY,
+        31 => NAN,
+        _ => f64::from(mant + 1024) * 2f64.powi(exp - 25),
+    };
+
+    if half & 0x8000 == 0 {
+        value
+    } else {
+        -value
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::collections::HashMap;
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+lazy_static! {
+    //被毒化的全局状态表，记录全局状态名和被恢复的次数
+    static ref POISONED_STATES: Mutex<HashMap<&'static str, usize>> = Mutex::new(HashMap::new());
+}
+
+lazy_static! {
+    //被毒化的全局状态数量
+    static ref GLOBAL_STATE_POISON_RECOVER_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("global_state_poison_recover_count"), 0).unwrap();
+}
+
+/*
+* 全局状态健康报告
+*/
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+    pub degraded: Vec<(&'static str, usize)>,  //降级的全局状态名和被恢复的次数
+}
+
+impl HealthReport {
+    //判断全局状态是否健康
+    pub fn is_healthy(&self) -> bool {
+        self.degraded.is_empty()
+    }
+}
+
+/*
+* 获取全局状态的读锁，如果锁已被毒化，则恢复并记录指定全局状态为降级
+*/
+pub fn read_state<'a, T>(name: &'static str, lock: &'a RwLock<T>) -> RwLockReadGuard<'a, T> {
+    match lock.read() {
+        Ok(guard) => guard,
+        Err(e) => {
+            mark_degraded(name);
+            e.into_inner()
+        },
+    }
+}
+
+/*
+* 获取全局状态的写锁，如果锁已被毒化，则恢复并记录指定全局状态为降级
+*/
+pub fn write_state<'a, T>(name: &'static str, lock: &'a RwLock<T>) -> RwLockWriteGuard<'a, T> {
+    match lock.write() {
+        Ok(guard) => guard,
+        Err(e) => {
+            mark_degraded(name);
+            e.into_inner()
+        },
+    }
+}
+
+/*
+* 获取全局状态的互斥锁，如果锁已被毒化，则恢复并记录指定全局状态为降级
+*/
+pub fn lock_state<'a, T>(name: &'static str, lock: &'a Mutex<T>) -> MutexGuard<'a, T> {
+    match lock.lock() {
+        Ok(guard) => guard,
+        Err(e) => {
+            mark_degraded(name);
+            e.into_inner()
+        },
+    }
+}
+
+/*
+* 获取全局状态健康报告
+*/
+pub fn health() -> HealthReport {
+    let mut degraded: Vec<(&'static str, usize)> = poisoned_states().iter().map(|(name, count)| (*name, *count)).collect();
+    degraded.sort();
+    HealthReport {
+        degraded: degraded,
+    }
+}
+
+/*
+* 清除全局状态的降级记录，返回被清除的全局状态数量，只清除记录，被毒化的锁不会因此恢复，之后访问仍被毒化的全局状态会重新记录为降级
+*/
+pub fn reset_health() -> usize {
+    let mut states = poisoned_states();
+    let len = states.len();
+    states.clear();
+    len
+}
+
+//记录指定全局状态为降级，被毒化的锁会一直保持毒化，所以只在首次记录时警告，之后只增加恢复次数
+fn mark_degraded(name: &'static str) {
+    let mut states = poisoned_states();
+    let count = states.entry(name).or_insert(0);
+    if *count == 0 {
+        warn!("!!!> Global State Poisoned, recovered, state: {}", name);
+        GLOBAL_STATE_POISON_RECOVER_COUNT.sum(1);
+    }
+    *count += 1;
+}
+
+//获取被毒化的全局状态表，表本身被毒化时直接恢复
+fn poisoned_states() -> MutexGuard<'static, HashMap<&'static str, usize>> {
+    match POISONED_STATES.lock() {
+        Ok(guard) => guard,
+        Err(e) => e.into_inner(),
+    }
+}
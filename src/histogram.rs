@@ -0,0 +1,156 @@
+use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/*
+* 每个数量级内线性子桶数量的位数，子桶数量为32，相对误差不超过1/32
+*/
+const SUB_BUCKET_BITS: usize = 5;
+
+/*
+* 每个数量级内线性子桶数量
+*/
+const SUB_BUCKET_COUNT: usize = 1 << SUB_BUCKET_BITS;
+
+/*
+* 可记录的最大值的位数，单位us，超过的值记录为最大值，约12.7天
+*/
+const MAX_VALUE_BITS: usize = 40;
+
+/*
+* 桶数量
+*/
+const BUCKET_COUNT: usize = SUB_BUCKET_COUNT + (MAX_VALUE_BITS - SUB_BUCKET_BITS) * SUB_BUCKET_COUNT;
+
+/*
+* 延迟直方图，使用对数线性分桶的HDR风格直方图，单位us，记录和读取都无锁
+*/
+pub struct LatencyHistogram {
+    buckets:    Vec<AtomicUsize>,   //桶
+    count:      AtomicUsize,        //记录数量
+    sum:        AtomicUsize,        //记录值的和
+    min:        AtomicUsize,        //记录的最小值
+    max:        AtomicUsize,        //记录的最大值
+}
+
+impl LatencyHistogram {
+    //构建一个延迟直方图
+    pub fn new() -> Self {
+        let mut buckets = Vec::with_capacity(BUCKET_COUNT);
+        for _ in 0..BUCKET_COUNT {
+            buckets.push(AtomicUsize::new(0));
+        }
+
+        LatencyHistogram {
+            buckets,
+            count: AtomicUsize::new(0),
+            sum: AtomicUsize::new(0),
+            min: AtomicUsize::new(usize::max_value()),
+            max: AtomicUsize::new(0),
+        }
+    }
+
+    //记录一个延迟
+    pub fn record(&self, latency: Duration) {
+        let value = (latency.as_micros() as usize).min((1 << MAX_VALUE_BITS) - 1);
+        self.buckets[bucket_index(value)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.min.fetch_min(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    //获取记录数量
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    //获取指定分位的延迟，分位取值范围为[0.0, 1.0]，没有记录则返回0
+    pub fn value_at_quantile(&self, quantile: f64) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::from_micros(0);
+        }
+
+        let quantile = quantile.max(0.0).min(1.0);
+        let rank = ((quantile * count as f64).ceil() as usize).max(1);
+        let mut total = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            total += bucket.load(Ordering::Relaxed);
+            if total >= rank {
+                //使用桶的上界，并限制在已记录的最大值内
+                let value = bucket_upper(index).min(self.max.load(Ordering::Relaxed));
+                return Duration::from_micros(value as u64);
+            }
+        }
+
+        Duration::from_micros(self.max.load(Ordering::Relaxed) as u64)
+    }
+
+    //获取延迟直方图的快照
+    pub fn snapshot(&self) -> LatencySnapshot {
+        let count = self.count();
+        if count == 0 {
+            return LatencySnapshot::default();
+        }
+
+        LatencySnapshot {
+            count,
+            min: Duration::from_micros(self.min.load(Ordering::Relaxed) as u64),
+            max: Duration::from_micros(self.max.load(Ordering::Relaxed) as u64),
+            mean: Duration::from_micros((self.sum.load(Ordering::Relaxed) / count) as u64),
+            p50: self.value_at_quantile(0.5),
+            p90: self.value_at_quantile(0.9),
+            p99: self.value_at_quantile(0.99),
+            p999: self.value_at_quantile(0.999),
+        }
+    }
+
+    //重置延迟直方图
+    pub fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+        self.sum.store(0, Ordering::Relaxed);
+        self.min.store(usize::max_value(), Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+    }
+}
+
+/*
+* 延迟直方图快照
+*/
+#[derive(Debug, Clone, Default)]
+pub struct LatencySnapshot {
+    pub count:  usize,      //记录数量
+    pub min:    Duration,   //最小延迟
+    pub max:    Duration,   //最大延迟
+    pub mean:   Duration,   //平均延迟
+    pub p50:    Duration,   //50分位延迟
+    pub p90:    Duration,   //90分位延迟
+    pub p99:    Duration,   //99分位延迟
+    pub p999:   Duration,   //99.9分位延迟
+}
+
+//获取指定值所在的桶
+fn bucket_index(value: usize) -> usize {
+    if value < SUB_BUCKET_COUNT {
+        return value;
+    }
+
+    let magnitude = (0usize.leading_zeros() - value.leading_zeros() - 1) as usize; //最高位的位置
+    let shift = magnitude - SUB_BUCKET_BITS;
+    let sub = (value >> shift) - SUB_BUCKET_COUNT;
+    SUB_BUCKET_COUNT + shift * SUB_BUCKET_COUNT + sub
+}
+
+//获取指定桶可记录的最大值
+fn bucket_upper(index: usize) -> usize {
+    if index < SUB_BUCKET_COUNT {
+        return index;
+    }
+
+    let shift = (index - SUB_BUCKET_COUNT) / SUB_BUCKET_COUNT;
+    let sub = (index - SUB_BUCKET_COUNT) % SUB_BUCKET_COUNT;
+    ((SUB_BUCKET_COUNT + sub + 1) << shift) - 1
+}
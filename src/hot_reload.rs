@@ -0,0 +1,177 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use health::lock_state;
+use pi_vm_impl::VMFactory;
+use code_source::{CodeSource, SourceReport, fetch_sources};
+use maintenance::schedule_maintenance;
+
+lazy_static! {
+    //热加载替换字节码的次数
+    static ref VM_HOT_RELOAD_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_hot_reload_count"), 0).unwrap();
+}
+
+/*
+* 文件指纹，为文件路径、修改时间和文件大小，不存在的文件没有修改时间
+*/
+type FileStamp = (PathBuf, Option<SystemTime>, u64);
+
+/*
+* 虚拟机工厂热加载器，定时检查字节码源依赖的本地文件，文件被修改、增加或删除后，重新获取字节码源的字节码，
+* 替换虚拟机工厂的字节码并使用新字节码重新生成空闲虚拟机，用于开发时修改代码后不重启进程即可生效，
+* 获取或替换失败时保留原字节码，并在文件再次修改后重试
+*/
+#[derive(Clone)]
+pub struct HotReloader {
+    factory:    VMFactory,                                      //被热加载的虚拟机工厂
+    sources:    Vec<Arc<CodeSource>>,                           //按加载顺序的字节码源
+    interval:   usize,                                          //检查间隔时长，单位ms
+    stamps:     Arc<Mutex<Option<Vec<FileStamp>>>>,             //上次检查时的文件指纹，为空表示未检查
+    on_reload:  Option<Arc<Fn(SourceReport) + Send + Sync>>,    //热加载完成的回调
+    running:    Arc<AtomicBool>,                                //热加载器是否运行中
+}
+
+impl HotReloader {
+    //构建一个虚拟机工厂热加载器，字节码源的顺序即字节码的加载顺序
+    pub fn new(factory: VMFactory, sources: Vec<Arc<CodeSource>>) -> Self {
+        HotReloader {
+            factory,
+            sources,
+            interval: 500,
+            stamps: Arc::new(Mutex::new(None)),
+            on_reload: None,
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    //设置检查间隔时长，单位ms
+    pub fn set_interval(mut self, interval: usize) -> Self {
+        self.interval = if interval == 0 { 1 } else { interval };
+        self
+    }
+
+    //设置热加载完成的回调，无论热加载是否成功都会回调
+    pub fn set_on_reload(mut self, on_reload: Arc<Fn(SourceReport) + Send + Sync>) -> Self {
+        self.on_reload = Some(on_reload);
+        self
+    }
+
+    //获取监视的本地文件或目录
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.sources.iter().flat_map(|source| source.paths()).collect()
+    }
+
+    //判断热加载器是否运行中
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    //启动热加载器，启动时记录当前的文件指纹，之后的修改才会触发热加载，返回热加载器是否启动成功
+    pub fn start(&self) -> bool {
+        if self.running.compare_and_swap(false, true, Ordering::SeqCst) {
+            //已启动
+            return false;
+        }
+
+        *lock_state("vm_hot_reload", &self.stamps) = Some(self.file_stamps());
+        self.schedule();
+        true
+    }
+
+    //停止热加载器，下次检查时生效
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    //立即检查一次，文件已修改则热加载，返回热加载报告，文件未修改则返回None，首次检查只记录文件指纹
+    pub fn check(&self) -> Option<SourceReport> {
+        let stamps = self.file_stamps();
+        {
+            let mut last = lock_state("vm_hot_reload", &self.stamps);
+            match &*last {
+                Some(last) if *last == stamps => return None, //文件未修改
+                _ => (),
+            }
+            let first = last.is_none();
+            *last = Some(stamps);
+            if first {
+                return None;
+            }
+        }
+
+        Some(self.reload())
+    }
+
+    //立即重新获取字节码源的字节码并替换虚拟机工厂的字节码，返回热加载报告
+    pub fn reload(&self) -> SourceReport {
+        let (codes, results) = fetch_sources(&self.sources);
+        let mut report = SourceReport {
+            factory: self.factory.name(),
+            results,
+            generation: None,
+            error: None,
+        };
+        if report.failed() == 0 {
+            match self.factory.reload_codes(codes) {
+                Err(e) => report.error = Some(e.to_string()),
+                Ok(generation) => {
+                    VM_HOT_RELOAD_COUNT.sum(1);
+                    report.generation = Some(generation);
+                    info!("===> Vm Factory Hot Reload Ok, factory: {:?}, generation: {}", self.factory.name(), generation);
+                },
+            }
+        }
+        if !report.is_ok() {
+            warn!("!!!> Vm Factory Hot Reload Failed, factory: {:?}, failed: {}, e: {:?}", self.factory.name(), report.failed(), report.error);
+        }
+
+        if let Some(on_reload) = &self.on_reload {
+            on_reload(report.clone());
+        }
+        report
+    }
+
+    //获取所有监视文件的指纹，按路径排序
+    fn file_stamps(&self) -> Vec<FileStamp> {
+        let mut stamps = Vec::new();
+        for path in self.paths() {
+            stamp_path(&path, &mut stamps);
+        }
+        stamps.sort_by(|x, y| x.0.cmp(&y.0));
+        stamps
+    }
+
+    //注册周期性的检查
+    fn schedule(&self) {
+        let reloader = self.clone();
+        schedule_maintenance(self.running.clone(), self.interval as u32, "vm hot reload task", Arc::new(move || {
+            if !reloader.factory.is_closed() {
+                reloader.check();
+            }
+        }));
+    }
+}
+
+//递归获取指定路径下所有文件的指纹，不存在的路径也会记录，以发现文件的删除和创建
+fn stamp_path(path: &Path, stamps: &mut Vec<FileStamp>) {
+    match fs::metadata(path) {
+        Err(_) => stamps.push((path.to_path_buf(), None, 0)),
+        Ok(meta) => {
+            if meta.is_dir() {
+                if let Ok(entries) = fs::read_dir(path) {
+                    for entry in entries.filter_map(|entry| entry.ok()) {
+                        stamp_path(&entry.path(), stamps);
+                    }
+                }
+            } else {
+                stamps.push((path.to_path_buf(), meta.modified().ok(), meta.len()));
+            }
+        },
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use pi_vm_impl::VMFactory;
+use maintenance::schedule_maintenance;
+
+lazy_static! {
+    //虚拟机工厂空闲时垃圾回收的虚拟机数量
+    static ref VM_IDLE_GC_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_idle_gc_count"), 0).unwrap();
+}
+
+/*
+* 虚拟机工厂空闲垃圾回收器，定时观察虚拟机工厂，当虚拟机工厂没有调用的时长超过阈值时，在后台任务中每次对一个空闲虚拟机执行垃圾回收，
+* 以在空闲时承担回收的开销，而不是在调用中
+*/
+#[derive(Clone)]
+pub struct IdleCollector {
+    factory:    VMFactory,          //被回收的虚拟机工厂
+    idle:       Duration,           //虚拟机工厂没有调用的时长阈值，超过则开始回收
+    interval:   usize,              //观察间隔时长，单位ms，每次观察最多回收一个虚拟机
+    compact:    bool,               //回收时是否压缩堆
+    running:    Arc<AtomicBool>,    //回收器是否运行中
+}
+
+impl IdleCollector {
+    //构建一个虚拟机工厂空闲垃圾回收器
+    pub fn new(factory: VMFactory, idle: Duration) -> Self {
+        IdleCollector {
+            factory,
+            idle,
+            interval: 100,
+            compact: true,
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    //设置观察间隔时长，单位ms
+    pub fn set_interval(mut self, interval: usize) -> Self {
+        self.interval = if interval == 0 { 1 } else { interval };
+        self
+    }
+
+    //设置回收时是否压缩堆
+    pub fn set_compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
+    }
+
+    //判断回收器是否运行中
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    //启动回收器，返回回收器是否启动成功，需要启用vmgc特性构建，当前构建不支持则启动失败
+    pub fn start(&self) -> bool {
+        if !cfg!(feature = "vmgc") {
+            warn!("!!!> Vm Idle Gc Start Failed, vmgc feature disabled, factory: {:?}", self.factory.name());
+            return false;
+        }
+
+        if self.running.compare_and_swap(false, true, Ordering::SeqCst) {
+            //已启动
+            return false;
+        }
+
+        self.schedule();
+        true
+    }
+
+    //停止回收器，下次观察时生效
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    //立即观察一次，虚拟机工厂已空闲则回收一个空闲虚拟机，返回是否回收了虚拟机
+    pub fn collect(&self) -> bool {
+        if self.factory.is_closed() {
+            return false;
+        }
+
+        match self.factory.idle_time() {
+            Some(idle) if idle >= self.idle => {
+                if self.factory.gc_idle_vm(self.compact) {
+                    VM_IDLE_GC_COUNT.sum(1);
+                    return true;
+                }
+                false
+            },
+            _ => false, //虚拟机工厂未空闲
+        }
+    }
+
+    //注册周期性的观察
+    fn schedule(&self) {
+        let collector = self.clone();
+        schedule_maintenance(self.running.clone(), self.interval as u32, "vm idle gc task", Arc::new(move || {
+            collector.collect();
+        }));
+    }
+}
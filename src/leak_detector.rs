@@ -0,0 +1,197 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use health::lock_state;
+use vm_registry::find_vms;
+use pi_vm_impl::source_queue_count;
+use maintenance::schedule_maintenance;
+
+/*
+* 泄漏检测采样
+*/
+#[derive(Debug, Clone)]
+pub struct LeakSample {
+    pub time:               Instant,    //采样时间
+    pub vms:                usize,      //存活虚拟机数量
+    pub pending_callbacks:  usize,      //所有存活虚拟机等待执行的回调数量
+    pub source_queues:      usize,      //同步任务队列数量
+    pub native_objs:        usize,      //所有存活虚拟机拥有所有权的本地对象数量
+}
+
+impl LeakSample {
+    //获取指定指标的值
+    fn metric(&self, metric: &str) -> usize {
+        match metric {
+            "vms" => self.vms,
+            "pending_callbacks" => self.pending_callbacks,
+            "source_queues" => self.source_queues,
+            _ => self.native_objs,
+        }
+    }
+}
+
+/*
+* 泄漏检测的指标名
+*/
+const LEAK_METRICS: [&'static str; 4] = ["vms", "pending_callbacks", "source_queues", "native_objs"];
+
+/*
+* 单调增长的指标
+*/
+#[derive(Debug, Clone)]
+pub struct LeakTrend {
+    pub metric: String, //指标名
+    pub first:  usize,  //窗口内第一次采样的值
+    pub last:   usize,  //窗口内最后一次采样的值
+}
+
+/*
+* 泄漏检测报告
+*/
+#[derive(Debug, Clone)]
+pub struct LeakReport {
+    pub samples:    usize,          //窗口内的采样次数
+    pub elapsed:    Duration,       //窗口内第一次到最后一次采样的时长
+    pub growing:    Vec<LeakTrend>, //窗口内单调增长的指标
+}
+
+impl LeakReport {
+    //判断是否没有单调增长的指标
+    pub fn is_ok(&self) -> bool {
+        self.growing.is_empty()
+    }
+}
+
+//立即采样一次，正在修改本地对象表的虚拟机不计入本地对象数量
+pub fn leak_sample() -> LeakSample {
+    let vms = find_vms(&[]);
+    LeakSample {
+        time: Instant::now(),
+        vms: vms.len(),
+        pending_callbacks: vms.iter().map(|vm| vm.pending_callbacks().len()).sum(),
+        source_queues: source_queue_count(),
+        native_objs: vms.iter().filter_map(|vm| vm.native_object_count()).sum(),
+    }
+}
+
+/*
+* 泄漏检测器，定时采样存活虚拟机数量、等待执行的回调数量、同步任务队列数量和本地对象数量，
+* 当指标在整个采样窗口内只增不减且最终增长时，认为可能存在泄漏，用于在长时间运行的测试中自动发现新处理器引入的泄漏
+*/
+#[derive(Clone)]
+pub struct LeakDetector {
+    interval:   usize,                              //采样间隔时长，单位ms
+    window:     usize,                              //采样窗口大小，只保留最近的采样
+    samples:    Arc<Mutex<VecDeque<LeakSample>>>,   //窗口内的采样
+    running:    Arc<AtomicBool>,                    //检测器是否运行中
+}
+
+impl LeakDetector {
+    //构建一个泄漏检测器
+    pub fn new() -> Self {
+        LeakDetector {
+            interval: 60000,
+            window: 10,
+            samples: Arc::new(Mutex::new(VecDeque::new())),
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    //设置采样间隔时长，单位ms
+    pub fn set_interval(mut self, interval: usize) -> Self {
+        self.interval = if interval == 0 { 1 } else { interval };
+        self
+    }
+
+    //设置采样窗口大小，至少为2
+    pub fn set_window(mut self, window: usize) -> Self {
+        self.window = if window < 2 { 2 } else { window };
+        self
+    }
+
+    //判断检测器是否运行中
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    //启动检测器，返回检测器是否启动成功
+    pub fn start(&self) -> bool {
+        if self.running.compare_and_swap(false, true, Ordering::SeqCst) {
+            //已启动
+            return false;
+        }
+
+        self.schedule();
+        true
+    }
+
+    //停止检测器，下次采样时生效
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    //立即采样一次，并加入采样窗口，返回本次采样
+    pub fn sample(&self) -> LeakSample {
+        let sample = leak_sample();
+        let mut samples = lock_state("vm_leak_detector", &self.samples);
+        samples.push_back(sample.clone());
+        while samples.len() > self.window {
+            samples.pop_front();
+        }
+        sample
+    }
+
+    //获取窗口内的采样
+    pub fn samples(&self) -> Vec<LeakSample> {
+        lock_state("vm_leak_detector", &self.samples).iter().cloned().collect()
+    }
+
+    //清空采样窗口
+    pub fn clear(&self) {
+        lock_state("vm_leak_detector", &self.samples).clear();
+    }
+
+    //获取窗口内的泄漏检测报告，采样窗口未满时不报告增长的指标
+    pub fn report(&self) -> LeakReport {
+        let samples = lock_state("vm_leak_detector", &self.samples);
+        let elapsed = match (samples.front(), samples.back()) {
+            (Some(first), Some(last)) => last.time.duration_since(first.time),
+            _ => Duration::from_millis(0),
+        };
+
+        let mut growing = Vec::new();
+        if samples.len() >= self.window {
+            for metric in LEAK_METRICS.iter() {
+                let values: Vec<usize> = samples.iter().map(|sample| sample.metric(metric)).collect();
+                let (first, last) = (values[0], values[values.len() - 1]);
+                if last > first && values.windows(2).all(|pair| pair[1] >= pair[0]) {
+                    growing.push(LeakTrend {
+                        metric: metric.to_string(),
+                        first,
+                        last,
+                    });
+                }
+            }
+        }
+
+        LeakReport {
+            samples: samples.len(),
+            elapsed,
+            growing,
+        }
+    }
+
+    //注册周期性的采样
+    fn schedule(&self) {
+        let detector = self.clone();
+        schedule_maintenance(self.running.clone(), self.interval as u32, "vm leak sample task", Arc::new(move || {
+            detector.sample();
+            let report = detector.report();
+            for trend in &report.growing {
+                warn!("!!!> Vm Leak Detected, metric: {}, first: {}, last: {}, samples: {}, elapsed: {:?}", trend.metric, trend.first, trend.last, report.samples, report.elapsed);
+            }
+        }));
+    }
+}
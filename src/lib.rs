@@ -18,6 +18,7 @@ extern crate lazy_static;
 extern crate kernel32;
 
 extern crate crossbeam_channel;
+extern crate crossbeam_epoch;
 
 #[macro_use]
 extern crate log;
@@ -41,7 +42,50 @@ pub mod native_object_impl;
 pub mod pi_vm_impl;
 pub mod bonmgr;
 pub mod channel_map;
+#[cfg(feature = "debugger")]
 pub mod shell;
+#[cfg(feature = "cluster")]
 pub mod proc;
+#[cfg(feature = "cluster")]
 pub mod proc_pool;
-pub mod duk_proc;
\ No newline at end of file
+#[cfg(feature = "cluster")]
+pub mod duk_proc;
+pub mod native_bind;
+pub mod pipeline;
+pub mod task_info;
+pub mod vm_registry;
+pub mod pool_controller;
+pub mod manifest;
+pub mod health;
+pub mod histogram;
+pub mod metrics;
+pub mod failover;
+pub mod idle_gc;
+pub mod names;
+pub mod factory_builder;
+pub mod factory_registry;
+pub mod scratch;
+pub mod expression;
+pub mod bundle;
+pub mod services;
+pub mod capability;
+pub mod code_cache;
+pub mod code_source;
+pub mod code_verify;
+pub mod code_crypt;
+pub mod bytecode;
+pub mod compile_cache;
+pub mod maintenance;
+pub mod quota;
+pub mod signature;
+pub mod breaker;
+pub mod func_cache;
+pub mod thread_audit;
+pub mod leak_detector;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+#[cfg(feature = "stress")]
+pub mod stress;
+#[cfg(feature = "hotreload")]
+pub mod hot_reload;
+pub mod api;
\ No newline at end of file
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use atom::Atom;
+use timer::{TIMER, FuncRuner};
+use worker::task::TaskType;
+use worker::impls::{cast_js_task, js_static_sync_task_size, js_dyn_sync_task_size, js_static_async_task_size, js_dyn_async_task_size};
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use pi_vm_impl::default_task_priority;
+
+/*
+* 内部维护任务的优先级，例如垃圾回收、虚拟机池调整、健康检查和模板刷新，实际优先级总是低于虚拟机任务全局基础优先级
+*/
+pub const MAINTENANCE_TASK_PRIORITY: usize = 10;
+
+/*
+* 内部维护任务让出后重新投递的间隔，单位ms
+*/
+pub const MAINTENANCE_YIELD_INTERVAL: u32 = 10;
+
+/*
+* 内部维护任务的最大让出次数，超过后不再让出，以保证内部维护任务不会被持续的用户调用饿死
+*/
+pub const MAINTENANCE_MAX_YIELDS: usize = 100;
+
+lazy_static! {
+    //内部维护任务让出的等待任务数量阈值，等待执行的任务数量超过阈值时，内部维护任务让出
+    static ref MAINTENANCE_BUSY_THRESHOLD: AtomicUsize = AtomicUsize::new(0);
+    //内部维护任务执行的次数
+    static ref VM_MAINTENANCE_TASK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_maintenance_task_count"), 0).unwrap();
+    //内部维护任务因有等待执行的用户任务而让出的次数
+    static ref VM_MAINTENANCE_YIELD_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_maintenance_yield_count"), 0).unwrap();
+}
+
+//获取内部维护任务的实际优先级，总是低于虚拟机任务全局基础优先级
+pub fn maintenance_priority() -> usize {
+    MAINTENANCE_TASK_PRIORITY.min(default_task_priority().saturating_sub(1)).max(1)
+}
+
+//获取内部维护任务让出的等待任务数量阈值
+pub fn maintenance_busy_threshold() -> usize {
+    MAINTENANCE_BUSY_THRESHOLD.load(Ordering::Relaxed)
+}
+
+//设置内部维护任务让出的等待任务数量阈值，返回上个阈值
+pub fn set_maintenance_busy_threshold(threshold: usize) -> usize {
+    MAINTENANCE_BUSY_THRESHOLD.swap(threshold, Ordering::Relaxed)
+}
+
+//判断是否有超过阈值的等待执行的任务
+pub fn is_busy() -> bool {
+    let pending = js_static_sync_task_size() + js_dyn_sync_task_size() + js_static_async_task_size() + js_dyn_async_task_size();
+    pending > maintenance_busy_threshold()
+}
+
+/*
+* 线程安全的投递内部维护任务，内部维护任务使用低于用户调用的优先级，执行时如果有等待执行的任务则让出，
+* 并在间隔后重新投递，直到没有等待执行的任务或达到最大让出次数，以保证后台维护不会延迟用户可见的调用
+*/
+pub fn cast_maintenance_task(func: Box<FnOnce()>, name: Atom) {
+    cast_yield(func, name, 0);
+}
+
+/*
+* 周期性的投递内部维护任务，每隔指定间隔投递一次，执行后如果仍在运行则注册下次投递，运行标记被清除后在下次间隔到达时停止，
+* 用于后台控制器、回收器和检测器的定时执行，调用者负责设置运行标记
+*/
+pub fn schedule_maintenance(running: Arc<AtomicBool>, interval: u32, name: &'static str, tick: Arc<Fn() + Send + Sync>) {
+    let runner = FuncRuner::new(Box::new(move || {
+        if !running.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let func = Box::new(move || {
+            tick();
+            if running.load(Ordering::Relaxed) {
+                schedule_maintenance(running, interval, name, tick);
+            }
+        });
+        cast_maintenance_task(func, Atom::from(name));
+    }));
+    TIMER.set_timeout(runner, interval);
+}
+
+//投递内部维护任务，执行时根据已让出次数判断是否继续让出
+fn cast_yield(func: Box<FnOnce()>, name: Atom, yields: usize) {
+    let task_name = name.clone();
+    let task = Box::new(move |_lock: Option<isize>| {
+        if yields < MAINTENANCE_MAX_YIELDS && is_busy() {
+            //有等待执行的任务，则让出，并在间隔后重新投递
+            VM_MAINTENANCE_YIELD_COUNT.sum(1);
+            let runner = FuncRuner::new(Box::new(move || {
+                cast_yield(func, name, yields + 1);
+            }));
+            TIMER.set_timeout(runner, MAINTENANCE_YIELD_INTERVAL);
+            return;
+        }
+
+        VM_MAINTENANCE_TASK_COUNT.sum(1);
+        func();
+    });
+    cast_js_task(TaskType::Async(false), maintenance_priority(), None, task, task_name);
+}
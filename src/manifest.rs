@@ -0,0 +1,189 @@
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::collections::HashMap;
+
+use atom::Atom;
+
+use adapter::JS;
+use health::{read_state, write_state};
+
+/*
+* 读取js代码包的清单全局变量__manifest中依赖的本地异步处理器版本的脚本，格式为每行一个“处理器名=版本”
+*/
+const READ_MANIFEST_SCRIPT: &'static str =
+    r#"(function() {
+        if(typeof __manifest === "undefined" || !__manifest || !__manifest.requires) {
+            return "";
+        }
+        var r = [];
+        for(var k in __manifest.requires) {
+            r.push(k + "=" + __manifest.requires[k]);
+        }
+        return r.join("\n");
+    })()"#;
+
+lazy_static! {
+    //本地异步处理器提供的版本表
+    static ref HANDLER_VERSIONS: Arc<RwLock<HashMap<Atom, HandlerVersion>>> = Arc::new(RwLock::new(HashMap::new()));
+}
+
+/*
+* 本地异步处理器版本，格式为major.minor.patch，缺省部分为0
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HandlerVersion(pub u32, pub u32, pub u32);
+
+impl fmt::Display for HandlerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+impl HandlerVersion {
+    //解析版本字符串
+    pub fn parse(version: &str) -> Result<Self, String> {
+        let mut parts = [0u32; 3];
+        let mut count = 0;
+        for part in version.trim().split('.') {
+            if count >= 3 {
+                return Err(format!("invalid version, version: {:?}", version));
+            }
+            match part.parse::<u32>() {
+                Err(_) => return Err(format!("invalid version, version: {:?}", version)),
+                Ok(n) => parts[count] = n,
+            }
+            count += 1;
+        }
+
+        Ok(HandlerVersion(parts[0], parts[1], parts[2]))
+    }
+
+    //判断当前提供的版本是否满足指定的依赖版本，要求主版本相同，且提供的版本不低于依赖的版本
+    pub fn satisfies(&self, required: &HandlerVersion) -> bool {
+        self.0 == required.0 && self >= required
+    }
+}
+
+/*
+* 版本不匹配
+*/
+#[derive(Debug, Clone)]
+pub enum VersionMismatch {
+    Missing(Atom, HandlerVersion),                      //依赖的处理器未注册
+    Unversioned(Atom, HandlerVersion),                  //依赖的处理器未声明版本
+    Incompatible(Atom, HandlerVersion, HandlerVersion), //依赖的处理器版本不兼容，依次为依赖版本和提供版本
+    Invalid(String),                                    //清单中的版本无法解析
+}
+
+impl fmt::Display for VersionMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VersionMismatch::Missing(name, required) => write!(f, "handler {} required {}, but not registered", (*name).as_str(), required),
+            VersionMismatch::Unversioned(name, required) => write!(f, "handler {} required {}, but no version provided", (*name).as_str(), required),
+            VersionMismatch::Incompatible(name, required, provided) => write!(f, "handler {} required {}, but provided {}", (*name).as_str(), required, provided),
+            VersionMismatch::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/*
+* 清单校验报告
+*/
+#[derive(Debug, Clone)]
+pub struct ManifestReport {
+    pub factory:    Atom,                   //虚拟机工厂名
+    pub mismatches: Vec<VersionMismatch>,   //不匹配的依赖
+}
+
+impl fmt::Display for ManifestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "vm factory manifest mismatch, factory: {:?}", (*self.factory).as_str())?;
+        for mismatch in &self.mismatches {
+            write!(f, "\n    {}", mismatch)?;
+        }
+        Ok(())
+    }
+}
+
+/*
+* 声明指定本地异步处理器提供的版本，返回上个版本
+*/
+pub fn provide_handler_version(name: Atom, version: HandlerVersion) -> Option<HandlerVersion> {
+    write_state("handler_versions", &HANDLER_VERSIONS).insert(name, version)
+}
+
+/*
+* 移除指定本地异步处理器提供的版本
+*/
+pub fn remove_handler_version(name: &Atom) -> Option<HandlerVersion> {
+    write_state("handler_versions", &HANDLER_VERSIONS).remove(name)
+}
+
+/*
+* 获取指定本地异步处理器提供的版本
+*/
+pub fn get_handler_version(name: &Atom) -> Option<HandlerVersion> {
+    read_state("handler_versions", &HANDLER_VERSIONS).get(name).cloned()
+}
+
+/*
+* 读取指定虚拟机已加载代码中清单声明的依赖，没有清单则返回空
+*/
+pub fn read_manifest(vm: &Arc<JS>) -> Result<Vec<(Atom, HandlerVersion)>, String> {
+    let value = vm.eval(READ_MANIFEST_SCRIPT.to_string());
+    if value.is_none() || !value.is_string() {
+        return Err(format!("read manifest failed, vm: {:?}", vm));
+    }
+
+    let mut requires = Vec::new();
+    for line in value.get_str().lines() {
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut kv = line.splitn(2, '=');
+        let name = kv.next().unwrap_or("");
+        let version = kv.next().unwrap_or("");
+        requires.push((Atom::from(name), HandlerVersion::parse(version).map_err(|e| format!("handler {}: {}", name, e))?));
+    }
+
+    Ok(requires)
+}
+
+/*
+* 校验指定虚拟机清单声明的依赖是否都被已注册的本地异步处理器满足，is_registered用于判断处理器是否已注册
+*/
+pub fn check_manifest<F: Fn(&Atom) -> bool>(factory: &Atom, vm: &Arc<JS>, is_registered: F) -> Result<(), ManifestReport> {
+    let mut mismatches = Vec::new();
+    match read_manifest(vm) {
+        Err(e) => mismatches.push(VersionMismatch::Invalid(e)),
+        Ok(requires) => {
+            let versions = read_state("handler_versions", &HANDLER_VERSIONS);
+            for (name, required) in requires {
+                match versions.get(&name) {
+                    Some(provided) => {
+                        if !provided.satisfies(&required) {
+                            mismatches.push(VersionMismatch::Incompatible(name, required, *provided));
+                        }
+                    },
+                    None => {
+                        if is_registered(&name) {
+                            mismatches.push(VersionMismatch::Unversioned(name, required));
+                        } else {
+                            mismatches.push(VersionMismatch::Missing(name, required));
+                        }
+                    },
+                }
+            }
+        },
+    }
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(ManifestReport {
+            factory: factory.clone(),
+            mismatches,
+        })
+    }
+}
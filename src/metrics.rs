@@ -0,0 +1,78 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use pi_vm_impl::FactoryStats;
+use factory_registry::registered_factory_stats;
+
+/*
+* 导出的调用延迟分位
+*/
+const LATENCY_QUANTILES: &'static [&'static str] = &["0.5", "0.9", "0.99", "0.999"];
+
+/*
+* 获取所有已注册虚拟机工厂的统计，按虚拟机工厂名排序
+*/
+pub fn factory_stats() -> Vec<FactoryStats> {
+    registered_factory_stats()
+}
+
+/*
+* 以prometheus文本格式导出所有已注册虚拟机工厂的指标
+*/
+pub fn export_metrics() -> String {
+    let stats = factory_stats();
+    let mut out = String::new();
+
+    export_gauge(&mut out, "pi_vm_factory_size", "current vm count of factory", &stats, |s| s.size as f64);
+    export_gauge(&mut out, "pi_vm_factory_free_size", "idle vm count of factory", &stats, |s| s.free_size as f64);
+    export_gauge(&mut out, "pi_vm_factory_checked_out", "checked out vm count of factory", &stats, |s| s.checked_out as f64);
+    export_gauge(&mut out, "pi_vm_factory_queue_len", "waiting task count of factory", &stats, |s| s.queue_len as f64);
+    export_gauge(&mut out, "pi_vm_factory_refuse_count", "refused task count of factory", &stats, |s| s.refuse_count as f64);
+    export_gauge(&mut out, "pi_vm_factory_total_heap_bytes", "total heap size of factory vms", &stats, |s| s.total_heap as f64);
+
+    let _ = writeln!(out, "# HELP pi_vm_factory_call_latency_seconds call latency from submission to completion");
+    let _ = writeln!(out, "# TYPE pi_vm_factory_call_latency_seconds summary");
+    for s in &stats {
+        let values = [s.latency.p50, s.latency.p90, s.latency.p99, s.latency.p999];
+        for (quantile, value) in LATENCY_QUANTILES.iter().zip(values.iter()) {
+            let _ = writeln!(out, "pi_vm_factory_call_latency_seconds{{factory=\"{}\",quantile=\"{}\"}} {}",
+                             escape_label(&s.name), quantile, seconds(*value));
+        }
+        let _ = writeln!(out, "pi_vm_factory_call_latency_seconds_sum{{factory=\"{}\"}} {}",
+                         escape_label(&s.name), seconds(s.latency.mean) * s.latency.count as f64);
+        let _ = writeln!(out, "pi_vm_factory_call_latency_seconds_count{{factory=\"{}\"}} {}",
+                         escape_label(&s.name), s.latency.count);
+    }
+
+    let _ = writeln!(out, "# HELP pi_vm_factory_phase_seconds call phase time between checkpoints");
+    let _ = writeln!(out, "# TYPE pi_vm_factory_phase_seconds summary");
+    for s in &stats {
+        for phase in &s.phases {
+            let _ = writeln!(out, "pi_vm_factory_phase_seconds_sum{{factory=\"{}\",port=\"{}\",phase=\"{}\"}} {}",
+                             escape_label(&s.name), escape_label(&phase.port), escape_label(&phase.phase), seconds(phase.total_time));
+            let _ = writeln!(out, "pi_vm_factory_phase_seconds_count{{factory=\"{}\",port=\"{}\",phase=\"{}\"}} {}",
+                             escape_label(&s.name), escape_label(&phase.port), escape_label(&phase.phase), phase.calls);
+        }
+    }
+
+    out
+}
+
+//导出指定的仪表指标
+fn export_gauge<F: Fn(&FactoryStats) -> f64>(out: &mut String, name: &str, help: &str, stats: &[FactoryStats], value: F) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    for s in stats {
+        let _ = writeln!(out, "{}{{factory=\"{}\"}} {}", name, escape_label(&s.name), value(s));
+    }
+}
+
+//将时长转换为秒
+fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1_000_000_000.0
+}
+
+//转义标签值
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
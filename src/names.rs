@@ -0,0 +1,128 @@
+use std::fmt;
+use std::ops::Deref;
+
+use atom::Atom;
+
+/*
+* 名称的最大长度
+*/
+const MAX_NAME_LEN: usize = 256;
+
+/*
+* 虚拟机工厂名，只允许字母、数字、空格和“_-.:/”，且首尾不允许为空格
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FactoryName(Atom);
+
+impl fmt::Display for FactoryName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.as_str())
+    }
+}
+
+impl Deref for FactoryName {
+    type Target = Atom;
+
+    fn deref(&self) -> &Atom {
+        &self.0
+    }
+}
+
+impl From<FactoryName> for Atom {
+    fn from(name: FactoryName) -> Atom {
+        name.0
+    }
+}
+
+impl FactoryName {
+    //构建一个虚拟机工厂名，名称无效则返回错误
+    pub fn new(name: &str) -> Result<Self, String> {
+        check_name("factory", name, |c| c.is_ascii_alphanumeric() || " _-.:/".contains(c))?;
+        if name.trim() != name {
+            return Err(format!("invalid factory name, name: {:?}, e: leading or trailing whitespace", name));
+        }
+
+        Ok(FactoryName(Atom::from(name)))
+    }
+
+    //不检查名称，构建一个虚拟机工厂名，只用于兼容旧版本的构建接口
+    pub(crate) fn new_unchecked(name: &str) -> Self {
+        FactoryName(Atom::from(name))
+    }
+
+    //获取名称
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    //获取名称的Atom
+    pub fn as_atom(&self) -> &Atom {
+        &self.0
+    }
+}
+
+/*
+* 端口名，即js函数路径或端口路由的别名，只允许字母、数字和“_$.@-”，且不允许以数字或“.”开始、以“.”结束或包含连续的“.”，
+* 别名可以带版本，例如"user.get@v2"
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PortName(Atom);
+
+impl fmt::Display for PortName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.as_str())
+    }
+}
+
+impl Deref for PortName {
+    type Target = Atom;
+
+    fn deref(&self) -> &Atom {
+        &self.0
+    }
+}
+
+impl From<PortName> for Atom {
+    fn from(name: PortName) -> Atom {
+        name.0
+    }
+}
+
+impl PortName {
+    //构建一个端口名，名称无效则返回错误
+    pub fn new(name: &str) -> Result<Self, String> {
+        check_name("port", name, |c| c.is_ascii_alphanumeric() || "_$.@-".contains(c))?;
+        if name.starts_with(|c: char| c.is_ascii_digit() || c == '.') || name.ends_with('.') || name.contains("..") {
+            return Err(format!("invalid port name, name: {:?}, e: invalid path", name));
+        }
+
+        Ok(PortName(Atom::from(name)))
+    }
+
+    //获取名称
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    //获取名称的Atom
+    pub fn as_atom(&self) -> &Atom {
+        &self.0
+    }
+}
+
+//检查名称是否为空、过长或包含无效字符
+fn check_name<F: Fn(char) -> bool>(kind: &str, name: &str, is_valid: F) -> Result<(), String> {
+    if name.is_empty() {
+        return Err(format!("invalid {} name, e: empty", kind));
+    }
+
+    if name.len() > MAX_NAME_LEN {
+        return Err(format!("invalid {} name, name: {:?}, e: too long, limit: {}", kind, name, MAX_NAME_LEN));
+    }
+
+    if let Some(c) = name.chars().find(|c| !is_valid(*c)) {
+        return Err(format!("invalid {} name, name: {:?}, e: invalid char {:?}", kind, name, c));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,267 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use adapter::{JS, JSType, now_utc};
+use bonmgr::{BON_MGR, FnMeta, CallResult};
+use pi_vm_impl::channel_handler_stats;
+
+/*
+* 虚拟机内置本地函数的hash，使用保留的高位hash，以避免与构建代码生成的本地函数冲突
+*/
+pub const VM_USAGE_HASH: u32 = 0xffff0001;
+pub const VM_RECYCLE_HASH: u32 = 0xffff0002;
+pub const VM_HANDLER_STATS_HASH: u32 = 0xffff0003;
+pub const VM_SET_TASK_PRIORITY_HASH: u32 = 0xffff0004;
+pub const VM_CHECKPOINT_HASH: u32 = 0xffff0005;
+pub const VM_CALL_CANCELED_HASH: u32 = 0xffff0006;
+
+/*
+* 虚拟机内置js代码的全局对象名，全局对象不可枚举且不可替换，以避免与业务代码的全局变量冲突
+*/
+pub const VM_PRELUDE_GLOBAL: &'static str = "__pi_vm";
+
+/*
+* 虚拟机内置js代码模板，本地函数的hash在构建内置js代码时由对应的hash常量替换
+*/
+const VM_PRELUDE_TEMPLATE: &'static str =
+    r#"Object.defineProperty(this, "{VM_PRELUDE_GLOBAL}", { value: (function() {
+        var vm = {
+            usage: function() {
+                return NativeObject.call({VM_USAGE_HASH}, []);
+            },
+            recycle: function(reason) {
+                return NativeObject.call({VM_RECYCLE_HASH}, [reason === undefined ? "" : String(reason)]);
+            },
+            handlerStats: function() {
+                return NativeObject.call({VM_HANDLER_STATS_HASH}, []);
+            },
+            setTaskPriority: function(level) {
+                return NativeObject.call({VM_SET_TASK_PRIORITY_HASH}, [Number(level)]);
+            },
+            checkpoint: function(name) {
+                return NativeObject.call({VM_CHECKPOINT_HASH}, [String(name)]);
+            },
+            signal: {
+                get aborted() {
+                    return NativeObject.call({VM_CALL_CANCELED_HASH}, []);
+                }
+            },
+            oncanceled: null,
+            __notifyCanceled: function(callback, task) {
+                if(typeof vm.oncanceled === "function") {
+                    vm.oncanceled(callback, task);
+                }
+                return 0;
+            },
+            __callCanceled: function(port) {
+                throw new Error("call canceled, port: " + port);
+            }
+        };
+        return vm;
+    })(), writable: false, enumerable: false, configurable: false });"#;
+
+lazy_static! {
+    /*
+    * 虚拟机内置js代码，在虚拟机加载字节码前执行，用于为js代码提供访问内置本地函数的全局对象__pi_vm
+    */
+    pub static ref VM_PRELUDE: String = VM_PRELUDE_TEMPLATE
+        .replace("{VM_PRELUDE_GLOBAL}", VM_PRELUDE_GLOBAL)
+        .replace("{VM_USAGE_HASH}", &VM_USAGE_HASH.to_string())
+        .replace("{VM_RECYCLE_HASH}", &VM_RECYCLE_HASH.to_string())
+        .replace("{VM_HANDLER_STATS_HASH}", &VM_HANDLER_STATS_HASH.to_string())
+        .replace("{VM_SET_TASK_PRIORITY_HASH}", &VM_SET_TASK_PRIORITY_HASH.to_string())
+        .replace("{VM_CHECKPOINT_HASH}", &VM_CHECKPOINT_HASH.to_string())
+        .replace("{VM_CALL_CANCELED_HASH}", &VM_CALL_CANCELED_HASH.to_string());
+}
+
+lazy_static! {
+    //虚拟机查询资源使用数量
+    static ref VM_USAGE_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_usage_count"), 0).unwrap();
+    //虚拟机请求回收数量
+    static ref VM_RECYCLE_REQUEST_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_recycle_request_count"), 0).unwrap();
+    //虚拟机查询通道处理器统计数量
+    static ref VM_HANDLER_STATS_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_handler_stats_count"), 0).unwrap();
+    //虚拟机降低当前任务优先级数量
+    static ref VM_SET_TASK_PRIORITY_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_set_task_priority_count"), 0).unwrap();
+    //虚拟机记录调用内检查点数量
+    static ref VM_CHECKPOINT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_checkpoint_count"), 0).unwrap();
+}
+
+/*
+* 注册虚拟机内置本地函数
+*/
+pub fn register_builtin_natives() {
+    BON_MGR.regist_fun_meta(FnMeta::Call(vm_usage), VM_USAGE_HASH);
+    BON_MGR.regist_fun_meta(FnMeta::CallArg(vm_recycle), VM_RECYCLE_HASH);
+    BON_MGR.regist_fun_meta(FnMeta::Call(vm_handler_stats), VM_HANDLER_STATS_HASH);
+    BON_MGR.regist_fun_meta(FnMeta::CallArg(vm_set_task_priority), VM_SET_TASK_PRIORITY_HASH);
+    BON_MGR.regist_fun_meta(FnMeta::CallArg(vm_checkpoint), VM_CHECKPOINT_HASH);
+    BON_MGR.regist_fun_meta(FnMeta::Call(vm_call_canceled), VM_CALL_CANCELED_HASH);
+}
+
+/*
+* 为指定虚拟机执行虚拟机内置js代码，并注入只读的虚拟机标识全局对象__vm
+*/
+pub fn load_prelude(vm: &Arc<JS>, version: &str) -> bool {
+    if vm.eval(VM_PRELUDE.clone()).is_none() {
+        warn!("!!!> Vm Load Prelude Error, vm: {:?}", vm);
+        return false;
+    }
+
+    let identity = format!("Object.defineProperty(this, \"__vm\", {{ value: Object.freeze({{ id: {}, factory: {}, version: {}, createdAt: {} }}), writable: false, enumerable: false, configurable: false }});",
+                           vm.get_id(), js_string(&vm.get_name()), js_string(version), now_utc() / 1000);
+    if vm.eval(identity).is_none() {
+        warn!("!!!> Vm Load Identity Error, vm: {:?}", vm);
+        return false;
+    }
+
+    true
+}
+
+//将字符串转换为js字符串字面量
+pub fn js_string(s: &str) -> String {
+    let mut r = String::with_capacity(s.len() + 2);
+    r.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            '\r' => r.push_str("\\r"),
+            c if (c as u32) < 0x20 || c == '\u{2028}' || c == '\u{2029}' => r.push_str(&format!("\\u{:04x}", c as u32)),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+//获取当前虚拟机的资源限制和使用情况，单位为字节和毫秒，未限制的值为-1
+fn vm_usage(js: Arc<JS>) -> Option<CallResult> {
+    VM_USAGE_COUNT.sum(1);
+
+    let (heap_limit, total_heap, total_heap_limit, cpu_remaining, queue_len) = match js.get_factory() {
+        None => (-1.0, -1.0, -1.0, -1.0, 0.0),
+        Some(factory) => {
+            let heap_limit = if factory.max_heap_size() == 0 {
+                -1.0
+            } else {
+                factory.max_heap_size() as f64
+            };
+            let total_heap_limit = if factory.limits().max_total_heap == 0 {
+                -1.0
+            } else {
+                factory.limits().max_total_heap as f64
+            };
+            let cpu_remaining = match factory.limits().cpu_per_minute {
+                None => -1.0,
+                Some(budget) => {
+                    let used = factory.cpu_used();
+                    if used >= budget {
+                        0.0
+                    } else {
+                        (budget - used).as_millis() as f64
+                    }
+                },
+            };
+
+            (heap_limit, factory.total_heap() as f64, total_heap_limit, cpu_remaining, factory.queue_len() as f64)
+        },
+    };
+    let call_time = match js.call_elapsed() {
+        None => 0.0,
+        Some(time) => time.as_millis() as f64,
+    };
+
+    let object = js.new_object();
+    js.set_field(&object, "heapSize".to_string(), &mut js.new_f64(js.heap_size() as f64));
+    js.set_field(&object, "heapLimit".to_string(), &mut js.new_f64(heap_limit));
+    js.set_field(&object, "factoryHeapSize".to_string(), &mut js.new_f64(total_heap));
+    js.set_field(&object, "factoryHeapLimit".to_string(), &mut js.new_f64(total_heap_limit));
+    js.set_field(&object, "cpuRemaining".to_string(), &mut js.new_f64(cpu_remaining));
+    js.set_field(&object, "callTime".to_string(), &mut js.new_f64(call_time));
+    js.set_field(&object, "queueLength".to_string(), &mut js.new_f64(queue_len));
+    Some(CallResult::Ok)
+}
+
+//请求在当前调用完成后回收当前虚拟机，回收的虚拟机不会再被复用，返回是否是首次请求
+fn vm_recycle(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
+    let reason = match args.get(0) {
+        Some(arg) if arg.is_string() => arg.get_str(),
+        _ => String::new(),
+    };
+
+    let is_first = js.request_recycle(reason.clone());
+    if is_first {
+        VM_RECYCLE_REQUEST_COUNT.sum(1);
+        info!("===> Vm Recycle Requested, vm: {:?}, reason: {:?}", js, reason);
+    }
+    js.new_boolean(is_first);
+    Some(CallResult::Ok)
+}
+
+//获取所有虚拟机通道处理器的执行统计，返回以处理器名为键的对象，时长单位为毫秒
+fn vm_handler_stats(js: Arc<JS>) -> Option<CallResult> {
+    VM_HANDLER_STATS_COUNT.sum(1);
+
+    let object = js.new_object();
+    for stats in channel_handler_stats() {
+        let mut item = js.new_object();
+        js.set_field(&item, "calls".to_string(), &mut js.new_f64(stats.calls as f64));
+        js.set_field(&item, "errors".to_string(), &mut js.new_f64(stats.errors as f64));
+        js.set_field(&item, "bytesIn".to_string(), &mut js.new_f64(stats.bytes_in as f64));
+        js.set_field(&item, "totalTime".to_string(), &mut js.new_f64(millis(stats.total_time)));
+        js.set_field(&item, "maxTime".to_string(), &mut js.new_f64(millis(stats.max_time)));
+        js.set_field(&item, "meanTime".to_string(), &mut js.new_f64(millis(stats.mean_time())));
+        js.set_field(&object, stats.name, &mut item);
+    }
+    Some(CallResult::Ok)
+}
+
+//降低当前调用剩余工作的优先级，用于js代码在确定执行后台工作时主动让出，只允许降低，返回实际使用的优先级，没有正在执行的任务则返回-1
+fn vm_set_task_priority(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
+    let level = match args.get(0) {
+        Some(arg) if arg.is_number() && arg.get_f64() >= 1.0 => arg.get_f64() as usize,
+        _ => return Some(CallResult::Err("set task priority failed, invalid level".to_string())),
+    };
+
+    match js.lower_task_priority(level) {
+        None => {
+            js.new_f64(-1.0);
+        },
+        Some(priority) => {
+            VM_SET_TASK_PRIORITY_COUNT.sum(1);
+            js.new_f64(priority as f64);
+        },
+    }
+    Some(CallResult::Ok)
+}
+
+//在当前调用内记录指定名称的检查点，标记一个阶段的开始，阶段时长会汇总到调用报告和虚拟机工厂统计中，返回是否成功记录
+fn vm_checkpoint(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
+    let name = match args.get(0) {
+        Some(arg) if arg.is_string() && !arg.get_str().is_empty() => arg.get_str(),
+        _ => return Some(CallResult::Err("checkpoint failed, invalid name".to_string())),
+    };
+
+    let is_ok = js.checkpoint(&name);
+    if is_ok {
+        VM_CHECKPOINT_COUNT.sum(1);
+    }
+    js.new_boolean(is_ok);
+    Some(CallResult::Ok)
+}
+
+//查询当前调用是否已被取消
+fn vm_call_canceled(js: Arc<JS>) -> Option<CallResult> {
+    js.new_boolean(js.is_call_canceled());
+    Some(CallResult::Ok)
+}
+
+//将时长转换为毫秒
+fn millis(d: Duration) -> f64 {
+    d.as_micros() as f64 / 1000.0
+}
@@ -1,22 +1,47 @@
+use std::thread;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::pin::Pin;
+use std::ops::Deref;
 use std::ffi::CString;
-use std::sync::{Arc, RwLock};
-use std::collections::HashMap;
+use std::future::Future;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use std::sync::{Arc, Weak, Mutex, RwLock};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::atomic::{AtomicBool, AtomicUsize, AtomicIsize, Ordering};
 
-use libc::c_char;
-use crossbeam_channel::{Sender, Receiver, unbounded};
+use rand::{thread_rng, Rng};
+use crossbeam_channel::{Sender, Receiver, RecvTimeoutError, SendTimeoutError, TrySendError, unbounded, bounded};
 
 use worker::task::TaskType;
 use worker::impls::{create_js_task_queue, unlock_js_task_queue, cast_js_task, remove_js_task_queue};
-use handler::Handler;
 use atom::Atom;
+use timer::{TIMER, FuncRuner};
 use apm::allocator::{get_max_alloced_limit, is_alloced_limit, all_alloced_size};
 use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter, PrefTimer};
 use lfstack::{CollectResult, LFStack};
 
-use adapter::{VM_FACTORY_REGISTERS, JSStatus, JS, JSType, pause, js_reply_callback, handle_async_callback, try_js_destroy, dukc_vm_status_check, dukc_vm_status_switch, dukc_new_error, dukc_wakeup, dukc_continue, now_utc};
-use channel_map::VMChannelMap;
+use adapter::{VM_FACTORY_REGISTERS, JSStatus, JS, JSType, CallValue, EvalPolicy, VmProfile, InterruptReason, VmError, pause, handle_async_callback, try_js_destroy, dukc_vm_status_check, dukc_new_error, now_utc};
+use channel_map::{VMChannelMap, ChannelHandler, GenericChannelHandler, ChannelMsg, RequestReceipt, HandlerStats, RouteTrace};
 use bonmgr::NativeObjsAuth;
+use native_bind::load_prelude;
+use task_info::{TaskInfo, FACTORY_SYNC_CALL_TASK, FACTORY_REPLENISH_TASK, FACTORY_PRODUCE_TASK, FACTORY_PRODUCE_ASYNC_TASK};
+use vm_registry::register_vm;
+use health::{lock_state, read_state, write_state};
+use histogram::{LatencyHistogram, LatencySnapshot};
+use bundle::{wrap_bundle, compile_bundle, compile_source};
+use compile_cache::CompileCache;
+use quota::{Throttled, QuotaPermit, acquire_quota};
+use signature::{PortSignature, SignatureError, PortCallError, push_value};
+use breaker::{BreakerConfig, CircuitBreaker};
+use code_cache::{intern_code, intern_codes, code_hash};
+use code_source::{CodeSource, GlobSource, SourceReport, fetch_sources, fetch_sources_async};
+use code_verify::{CodeVerifier, VerifiedCodes};
+use code_crypt::{CodeCipher, CodeDecryptor, encrypted_code, is_encrypted_code, clear_code};
+use services::ServiceRegistry;
+use names::{FactoryName, PortName};
+use factory_registry::{register_factory, remove_factory};
+use manifest::{HandlerVersion, VersionMismatch, ManifestReport, provide_handler_version, remove_handler_version, check_manifest};
 use std::sync::atomic::Ordering::SeqCst;
 
 /*
@@ -24,11 +49,16 @@ use std::sync::atomic::Ordering::SeqCst;
 */
 const JS_TASK_PRIORITY: usize = 100;
 
+/*
+* 虚拟机任务全局基础优先级，未设置任务优先级的虚拟机工厂使用此优先级，只影响之后投递的任务
+*/
+static DEFAULT_JS_TASK_PRIORITY: AtomicUsize = AtomicUsize::new(JS_TASK_PRIORITY);
+
 /*
 * 虚拟机通道
 */
 lazy_static! {
-	pub static ref VM_CHANNELS: Arc<RwLock<VMChannelMap>> = Arc::new(RwLock::new(VMChannelMap::new(0)));
+	pub static ref VM_CHANNELS: Arc<VMChannelMap> = Arc::new(VMChannelMap::new(0));
 }
 
 /*
@@ -45,12 +75,532 @@ lazy_static! {
     static ref VM_NEW_TIME: PrefTimer = GLOBAL_PREF_COLLECT.new_static_timer(Atom::from("vm_new_time"), 0).unwrap();
     //虚拟机加载总时长
     static ref VM_LOAD_TIME: PrefTimer = GLOBAL_PREF_COLLECT.new_static_timer(Atom::from("vm_load_time"), 0).unwrap();
+    //虚拟机预热总时长
+    static ref VM_WARMUP_TIME: PrefTimer = GLOBAL_PREF_COLLECT.new_static_timer(Atom::from("vm_warmup_time"), 0).unwrap();
+    //虚拟机预热调用失败数量
+    static ref VM_WARMUP_ERROR_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_warmup_error_count"), 0).unwrap();
     //虚拟机调用数量
     static ref VM_CALL_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_call_count"), 0).unwrap();
     //虚拟机推送异步回调数量
     static ref VM_PUSH_CALLBACK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_push_callback_count"), 0).unwrap();
+    //虚拟机工厂通过恢复堆快照构建虚拟机的次数
+    static ref VM_SNAPSHOT_RESTORE_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_snapshot_restore_count"), 0).unwrap();
+    //虚拟机工厂生成或恢复堆快照失败的次数
+    static ref VM_SNAPSHOT_FAILED_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_snapshot_failed_count"), 0).unwrap();
+    //虚拟机工厂的虚拟机内存不足的次数
+    static ref VM_OOM_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_oom_count"), 0).unwrap();
+    //向已过期的虚拟机推送异步回调的次数
+    static ref VM_PUSH_STALE_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_push_stale_count"), 0).unwrap();
+    //虚拟机工厂重试获取空闲虚拟机成功数量
+    static ref VM_CHECKOUT_RETRY_HIT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_checkout_retry_hit_count"), 0).unwrap();
+    //虚拟机工厂重试获取空闲虚拟机失败数量
+    static ref VM_CHECKOUT_RETRY_MISS_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_checkout_retry_miss_count"), 0).unwrap();
+    //虚拟机工厂替换字节码次数
+    static ref VM_CODE_RELOAD_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_code_reload_count"), 0).unwrap();
+    //虚拟机调用执行超时数量
+    static ref VM_CALL_TIMEOUT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_call_timeout_count"), 0).unwrap();
+    //虚拟机调用被取消数量
+    static ref VM_CALL_CANCEL_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_call_cancel_count"), 0).unwrap();
     //虚拟机异步请求数量
     static ref VM_ASYNC_REQUEST_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_async_request_count"), 0).unwrap();
+    //源亲和命中次数
+    static ref VM_AFFINITY_HIT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_affinity_hit_count"), 0).unwrap();
+    //源固定的空闲虚拟机被驱逐次数
+    static ref VM_AFFINITY_EVICT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_affinity_evict_count"), 0).unwrap();
+    //等待调度的任务队列已满时拒绝的任务数量
+    static ref VM_PENDING_REJECT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_pending_reject_count"), 0).unwrap();
+    //等待调度的任务队列已满时丢弃的任务数量
+    static ref VM_PENDING_DROP_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_pending_drop_count"), 0).unwrap();
+}
+
+/*
+* 虚拟机工厂执行时长预算的统计窗口，单位us
+*/
+const CPU_BUDGET_WINDOW: usize = 60000000;
+
+/*
+* 虚拟机工厂总堆大小预算耗尽时，重试执行排队任务的间隔，单位ms
+*/
+const HEAP_BUDGET_RETRY_INTERVAL: u32 = 100;
+
+/*
+* 虚拟机工厂聚合资源预算耗尽时的处理方式
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BudgetExhausted {
+    Queue,  //将任务加入虚拟机工厂的任务调度队列，等待预算恢复后执行
+    Reject, //直接拒绝任务
+}
+
+/*
+* 虚拟机回收策略，虚拟机达到任一限制后会在调用完成时被丢弃，并由虚拟机工厂重新构建
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct RecyclePolicy {
+    pub max_reused_count:   usize,              //虚拟机最大执行次数，当达到虚拟机最大堆限制后才会检查
+    pub max_calls:          usize,              //虚拟机最大调用次数，达到后无条件丢弃虚拟机，为0表示不限制
+    pub max_heap_size:      usize,              //虚拟机最大堆大小，当达到限制后释放可回收的内存，为0表示不限制
+    pub max_age:            Option<Duration>,   //虚拟机最大存活时长，为空表示不限制
+}
+
+impl Default for RecyclePolicy {
+    fn default() -> Self {
+        RecyclePolicy {
+            max_reused_count: 0,
+            max_calls: 0,
+            max_heap_size: 0,
+            max_age: None,
+        }
+    }
+}
+
+/*
+* 虚拟机预热调用，在新构建的虚拟机进入虚拟机池前同步执行，用于预热热点端口的执行路径，减少新虚拟机首次调用的延迟抖动，
+* 预热调用应该没有副作用，可以复用的虚拟机在预热后会重置全局环境
+*/
+#[derive(Debug, Clone)]
+pub struct WarmupCall {
+    pub port:   PortName,   //预热调用的端口
+    pub args:   Vec<CallValue>, //预热调用的参数，按顺序压入虚拟机栈，不会作为脚本执行
+    pub times:  usize,      //预热调用的执行次数
+}
+
+/*
+* 虚拟机工厂聚合资源限制，限制虚拟机工厂下所有虚拟机的资源使用总和
+*/
+#[derive(Debug, Clone)]
+pub struct FactoryLimits {
+    pub max_total_heap: usize,              //虚拟机工厂所有虚拟机的最大总堆大小，为0表示不限制
+    pub cpu_per_minute: Option<Duration>,   //虚拟机工厂所有虚拟机每分钟的最大累计执行时长，为空表示不限制
+    pub on_exhausted:   BudgetExhausted,    //预算耗尽时的处理方式
+}
+
+impl Default for FactoryLimits {
+    fn default() -> Self {
+        FactoryLimits {
+            max_total_heap: 0,
+            cpu_per_minute: None,
+            on_exhausted: BudgetExhausted::Queue,
+        }
+    }
+}
+
+/*
+* 虚拟机工厂等待调度的任务队列已满时的处理方式
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PendingPolicy {
+    Reject,             //直接拒绝新任务
+    Block(Duration),    //阻塞调用者直到任务队列有空位，超过指定时长则拒绝新任务，不允许在js工作者线程中使用
+    DropOldest,         //丢弃最早等待调度的任务，并接收新任务
+}
+
+/*
+* 虚拟机内存不足的信息
+*/
+#[derive(Debug, Clone)]
+pub struct OomInfo {
+    pub vm:             usize,          //虚拟机id
+    pub factory:        String,         //虚拟机工厂名
+    pub port:           Option<String>, //内存不足时正在执行的端口，为空表示不在调用中
+    pub heap_size:      usize,          //虚拟机当前堆大小
+    pub peak_heap_size: usize,          //虚拟机堆大小的峰值
+    pub max_heap_size:  usize,          //虚拟机工厂的虚拟机最大堆大小，为0表示不限制
+    pub error:          String,         //虚拟机内存不足的异常信息
+}
+
+/*
+* 虚拟机内存不足后的处理方式
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OomAction {
+    Keep,       //继续使用虚拟机
+    Recycle,    //执行完等待的回调后回收虚拟机
+    Abort,      //取消所有等待的回调，并立即回收虚拟机
+}
+
+/*
+* 虚拟机重置全局环境后，才执行重置前推送的异步回调的处理方式
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LateCallbackPolicy {
+    Reject, //拒绝执行过期的异步回调，并通知推送者
+    Throw,  //整理时有未执行的异步回调，则丢弃虚拟机而不重置全局环境，保证异步回调在原全局环境中执行
+}
+
+impl Default for LateCallbackPolicy {
+    fn default() -> Self {
+        LateCallbackPolicy::Reject
+    }
+}
+
+/*
+* 虚拟机工厂等待调度的任务队列限制
+*/
+#[derive(Debug, Clone)]
+pub struct PendingLimits {
+    pub max_vms:    usize,          //虚拟机工厂的最大虚拟机数量，达到后没有空闲虚拟机时不再构建新虚拟机，而是将任务加入等待调度的任务队列，为0表示不限制
+    pub max_depth:  usize,          //等待调度的任务队列的最大长度，为0表示不限制
+    pub policy:     PendingPolicy,  //任务队列已满时的处理方式
+}
+
+impl Default for PendingLimits {
+    fn default() -> Self {
+        PendingLimits {
+            max_vms: 0,
+            max_depth: 0,
+            policy: PendingPolicy::Reject,
+        }
+    }
+}
+
+/*
+* 虚拟机工厂的单次调用的资源使用报告
+*/
+#[derive(Debug, Clone)]
+pub struct CallReport {
+    pub vm_id:      usize,      //执行调用的虚拟机id
+    pub port:       Atom,       //调用的端口
+    pub latency:    Duration,   //调用从提交到虚拟机工厂到完成的时长，包括排队等待的时长
+    pub wall_time:  Duration,   //调用从开始执行到完成的时长
+    pub cpu_time:   Duration,   //调用在虚拟机内的累计执行时长
+    pub heap_delta: isize,      //调用完成后虚拟机堆大小的变化
+    pub callbacks:  usize,      //调用推送的异步回调数量
+    pub errors:     usize,      //调用及其异步回调执行异常的次数
+    pub bytes_in:   usize,      //调用通过虚拟机通道收到的字节数
+    pub bytes_out:  usize,      //调用通过虚拟机通道发送的字节数
+    pub phases:     Vec<(Atom, Duration)>,  //调用内由检查点划分的阶段名和阶段时长，按记录顺序排列
+    pub fuel:       usize,      //调用消耗的燃料，单位为虚拟机中断检查次数
+}
+
+/*
+* 虚拟机工厂指定端口的调用内阶段统计
+*/
+#[derive(Debug, Clone)]
+pub struct PhaseStats {
+    pub port:       String,     //调用的端口
+    pub phase:      String,     //阶段名
+    pub calls:      usize,      //阶段执行次数
+    pub total_time: Duration,   //阶段累计时长
+    pub max_time:   Duration,   //阶段单次最大时长
+}
+
+impl PhaseStats {
+    //获取阶段平均时长
+    pub fn mean_time(&self) -> Duration {
+        if self.calls == 0 {
+            Duration::from_micros(0)
+        } else {
+            self.total_time / self.calls as u32
+        }
+    }
+}
+
+/*
+* 调用参数构建函数，将调用参数压入虚拟机栈，并返回参数数量
+*/
+pub type ArgsFn = Box<FnOnce(Arc<JS>) -> usize>;
+
+/*
+* 调用完成回调的守护者，持有回调的调用参数构建函数未执行就被丢弃时，例如等待调度的任务被丢弃，则以虚拟机工厂忙的异常回调，
+* 保证调用者一定会收到回调
+*/
+struct FinishGuard<T> {
+    factory:    String,                                     //虚拟机工厂名
+    finish:     Option<Box<FnOnce(Result<T, String>)>>,     //调用完成回调，已取出则为空
+}
+
+impl<T> Drop for FinishGuard<T> {
+    fn drop(&mut self) {
+        if let Some(finish) = self.finish.take() {
+            finish(Err(VMFactoryError::Busy(self.factory.clone()).to_string()));
+        }
+    }
+}
+
+impl<T> FinishGuard<T> {
+    //构建一个调用完成回调的守护者
+    fn new(factory: String, finish: Box<FnOnce(Result<T, String>)>) -> Self {
+        FinishGuard {
+            factory,
+            finish: Some(finish),
+        }
+    }
+
+    //取出调用完成回调，取出后守护者被丢弃时不再回调
+    fn take(&mut self) -> Option<Box<FnOnce(Result<T, String>)>> {
+        self.finish.take()
+    }
+}
+
+/*
+* 虚拟机工厂调用错误
+*/
+#[derive(Debug, Clone)]
+pub enum VMFactoryError {
+    NewVmFailed(String),        //构建虚拟机失败，参数为虚拟机工厂名
+    BudgetExhausted(String),    //聚合资源预算已耗尽，任务被拒绝，参数为虚拟机工厂名
+    QueueClosed(String),        //等待调度的任务队列已关闭，参数为虚拟机工厂名
+    Shutdown(String),           //虚拟机工厂已停止接收调用，参数为虚拟机工厂名
+    Busy(String),               //等待调度的任务队列已满，任务被拒绝，参数为虚拟机工厂名
+    LoadFailed(LoadError),      //构建虚拟机时加载字节码失败，参数为加载失败的字节码和虚拟机异常信息
+    Throttled(String, Throttled),   //调用源超过配额，任务被拒绝，参数为虚拟机工厂名和限流原因
+    CircuitOpen(String, String),    //调用端口的熔断器已打开，任务被拒绝，参数为虚拟机工厂名和端口
+    Unsupported(String, &'static str),  //当前构建无法执行虚拟机工厂的配置，参数为虚拟机工厂名和需要启用的特性
+}
+
+impl Display for VMFactoryError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            VMFactoryError::NewVmFailed(name) => write!(f, "vm factory call error, new vm failed, factory: {:?}", name),
+            VMFactoryError::BudgetExhausted(name) => write!(f, "vm factory call error, budget exhausted, factory: {:?}", name),
+            VMFactoryError::QueueClosed(name) => write!(f, "vm factory call error, task queue closed, factory: {:?}", name),
+            VMFactoryError::Shutdown(name) => write!(f, "vm factory call error, factory shutdown, factory: {:?}", name),
+            VMFactoryError::Busy(name) => write!(f, "vm factory call error, busy, factory: {:?}", name),
+            VMFactoryError::LoadFailed(e) => write!(f, "vm factory call error, {}", e),
+            VMFactoryError::Throttled(name, e) => write!(f, "vm factory call error, {}, factory: {:?}", e, name),
+            VMFactoryError::CircuitOpen(name, port) => write!(f, "vm factory call error, circuit open, factory: {:?}, port: {:?}", name, port),
+            VMFactoryError::Unsupported(name, feature) => write!(f, "vm factory config error, {} feature disabled, factory: {:?}", feature, name),
+        }
+    }
+}
+
+impl From<LoadError> for VMFactoryError {
+    fn from(e: LoadError) -> Self {
+        if e.index.is_some() {
+            VMFactoryError::LoadFailed(e)
+        } else {
+            VMFactoryError::NewVmFailed(e.factory)
+        }
+    }
+}
+
+/*
+* 虚拟机工厂构建虚拟机失败的原因
+*/
+#[derive(Debug, Clone)]
+pub struct LoadError {
+    pub factory:    String,         //虚拟机工厂名
+    pub index:      Option<usize>,  //加载失败的字节码在字节码列表中的序号，派生虚拟机工厂的序号包括基础虚拟机工厂的字节码，为空表示不是加载字节码失败
+    pub code_hash:  Option<u64>,    //加载失败的字节码hash，用于在字节码缓存和代码包中定位字节码
+    pub reason:     String,         //失败原因，加载字节码失败时为虚拟机的异常信息
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match (self.index, self.code_hash) {
+            (Some(index), Some(hash)) => write!(f, "new vm failed, factory: {:?}, code index: {}, code hash: {:x}, reason: {}", self.factory, index, hash, self.reason),
+            _ => write!(f, "new vm failed, factory: {:?}, reason: {}", self.factory, self.reason),
+        }
+    }
+}
+
+/*
+* 虚拟机工厂同步调用错误
+*/
+#[derive(Debug, Clone)]
+pub enum CallError {
+    Call(VMFactoryError),   //调用未被接收
+    Failed(String),         //调用执行异常，参数为异常信息
+    Timeout(Duration),      //等待调用完成超时，参数为超时时长
+    Disconnected,           //调用未执行就被丢弃
+}
+
+impl Display for CallError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            CallError::Call(e) => write!(f, "{}", e),
+            CallError::Failed(e) => write!(f, "vm factory sync call error, call failed, e: {}", e),
+            CallError::Timeout(timeout) => write!(f, "vm factory sync call error, timeout, limit: {:?}", timeout),
+            CallError::Disconnected => write!(f, "vm factory sync call error, call discarded"),
+        }
+    }
+}
+
+/*
+* 虚拟机工厂调用句柄，用于取消已接收的调用，调用开始执行前被取消则不执行js函数，直接以调用已取消的异常完成调用，
+* 调用已开始执行则设置js可见的取消标记__pi_vm.signal.aborted，并中断虚拟机执行
+*/
+#[derive(Clone)]
+pub struct CallHandle {
+    canceled:   Arc<AtomicBool>,                        //调用是否已被取消
+    running:    Arc<Mutex<Option<(Weak<JS>, usize)>>>,  //执行调用的虚拟机和调用的开始时间，调用未开始执行则为空
+}
+
+impl CallHandle {
+    //构建一个调用句柄
+    fn new() -> Self {
+        CallHandle {
+            canceled: Arc::new(AtomicBool::new(false)),
+            running: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    //判断调用是否已被取消
+    pub fn is_canceled(&self) -> bool {
+        self.canceled.load(Ordering::SeqCst)
+    }
+
+    //判断调用是否已开始执行
+    pub fn is_started(&self) -> bool {
+        lock_state("vm_call_handle", &self.running).is_some()
+    }
+
+    //取消调用，调用已开始执行且仍未完成则中断虚拟机执行，已取消则忽略，返回是否是首次取消
+    pub fn cancel(&self) -> bool {
+        if self.canceled.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        VM_CALL_CANCEL_COUNT.sum(1);
+
+        if let Some((vm, start)) = lock_state("vm_call_handle", &self.running).as_ref() {
+            if let Some(vm) = vm.upgrade() {
+                if vm.interrupt_call(InterruptReason::Canceled, Some(*start)) {
+                    //调用仍未完成，则中断虚拟机执行
+                    warn!("!!!> Vm Call Canceled, vm: {:?}", vm);
+                }
+            }
+        }
+        true
+    }
+
+    //在调用开始执行时记录执行调用的虚拟机，调用已被取消则替换为报告调用已取消的函数，返回调用的参数数量
+    fn start(&self, vm: Arc<JS>, port: &str, args: ArgsFn) -> usize {
+        let mut running = lock_state("vm_call_handle", &self.running);
+        if self.is_canceled() {
+            //开始执行前已被取消，则不执行调用
+            return vm.replace_canceled_call(port);
+        }
+
+        vm.set_cancel_flag(self.canceled.clone());
+        *running = vm.call_start_time().map(|start| (Arc::downgrade(&vm), start));
+        args(vm)
+    }
+}
+
+/*
+* 虚拟机工厂统计
+*/
+#[derive(Debug, Clone)]
+pub struct FactoryStats {
+    pub name:           String,                     //虚拟机工厂名
+    pub size:           usize,                      //虚拟机工厂当前虚拟机数量
+    pub limit_capacity: usize,                      //虚拟机限制容量
+    pub free_size:      usize,                      //虚拟机工厂空闲虚拟机数量
+    pub checked_out:    usize,                      //被守护者取出的虚拟机数量
+    pub queue_len:      usize,                      //等待调度的任务数量
+    pub refuse_count:   usize,                      //拒绝任务次数
+    pub total_heap:     usize,                      //所有虚拟机的总堆大小
+    pub cpu_used:       Duration,                   //当前执行时长统计窗口内的累计执行时长
+    pub latency:        LatencySnapshot,            //调用从提交到完成的延迟分布，平均延迟为latency.mean
+    pub total_calls:    usize,                      //已开始执行的调用总数
+    pub in_flight:      usize,                      //已接收但未完成的调用数量
+    pub new_vm_failed:  usize,                      //构建虚拟机失败次数
+    pub port_calls:     HashMap<String, usize>,     //每个端口已开始执行的调用数量
+    pub phases:         Vec<PhaseStats>,            //每个端口的调用内阶段统计，按端口和阶段名排序
+}
+
+/*
+* 虚拟机源亲和表，记录源固定的空闲虚拟机，同一个源的调用优先使用源固定的空闲虚拟机
+*/
+struct AffinityTable {
+    parked: HashMap<usize, Arc<JS>>,    //源固定的空闲虚拟机，键为源
+    order:  VecDeque<usize>,            //源固定空闲虚拟机的顺序，需要驱逐时优先驱逐最早固定的空闲虚拟机
+}
+
+impl AffinityTable {
+    //移除指定源固定的空闲虚拟机
+    fn remove(&mut self, src: usize) -> Option<Arc<JS>> {
+        let vm = self.parked.remove(&src)?;
+        if let Some(index) = self.order.iter().position(|key| *key == src) {
+            self.order.remove(index);
+        }
+        Some(vm)
+    }
+
+    //移除最早固定的空闲虚拟机
+    fn pop_oldest(&mut self) -> Option<Arc<JS>> {
+        while let Some(src) = self.order.pop_front() {
+            if let Some(vm) = self.parked.remove(&src) {
+                return Some(vm);
+            }
+        }
+        None
+    }
+}
+
+/*
+* 等待空闲虚拟机的异步获取者表，每个获取者只有一个唤醒器槽位，重复等待时替换唤醒器，按开始等待的顺序唤醒
+*/
+struct WaiterTable {
+    next_id:    usize,                  //下个获取者id
+    wakers:     HashMap<usize, Waker>,  //获取者的唤醒器，键为获取者id
+    order:      VecDeque<usize>,        //获取者开始等待的顺序
+}
+
+impl WaiterTable {
+    //分配获取者id
+    fn alloc_id(&mut self) -> usize {
+        self.next_id = self.next_id.wrapping_add(1);
+        self.next_id
+    }
+
+    //注册或替换指定获取者的唤醒器
+    fn register(&mut self, id: usize, waker: Waker) {
+        if self.wakers.insert(id, waker).is_none() {
+            self.order.push_back(id);
+        }
+    }
+
+    //移除指定获取者，返回获取者是否仍在等待，已被唤醒则返回false
+    fn remove(&mut self, id: usize) -> bool {
+        if self.wakers.remove(&id).is_none() {
+            return false;
+        }
+
+        if let Some(index) = self.order.iter().position(|key| *key == id) {
+            self.order.remove(index);
+        }
+        true
+    }
+
+    //移除最早开始等待的获取者，并返回它的唤醒器
+    fn pop_oldest(&mut self) -> Option<Waker> {
+        while let Some(id) = self.order.pop_front() {
+            if let Some(waker) = self.wakers.remove(&id) {
+                return Some(waker);
+            }
+        }
+        None
+    }
+}
+
+/*
+* 虚拟机工厂异步生成虚拟机的报告
+*/
+#[derive(Debug, Clone)]
+pub struct ProduceReport {
+    pub factory:    String,                     //虚拟机工厂名
+    pub requested:  usize,                      //请求生成的虚拟机数量
+    pub results:    Vec<Result<usize, String>>, //每个虚拟机的生成结果，成功为虚拟机id，失败为失败原因
+    pub error:      Option<String>,             //生成前的失败原因，例如虚拟机工厂已停止接收调用或代码包清单校验失败
+}
+
+impl ProduceReport {
+    //获取成功生成的虚拟机数量
+    pub fn produced(&self) -> usize {
+        self.results.iter().filter(|r| r.is_ok()).count()
+    }
+
+    //获取生成失败的虚拟机数量
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.produced()
+    }
+
+    //判断是否全部生成成功
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none() && self.produced() == self.requested
+    }
 }
 
 /*
@@ -58,28 +608,142 @@ lazy_static! {
 */
 #[derive(Clone)]
 pub struct VMFactoryLoader {
-    offset: usize,                  //字节码偏移
-    top:    usize,                  //字节码顶指针
-    codes:  Arc<Vec<Arc<Vec<u8>>>>, //字节码缓存
+    offset:     usize,                      //字节码偏移
+    top:        usize,                      //字节码顶指针
+    codes:      Arc<Vec<Arc<Vec<u8>>>>,     //字节码缓存
+    verifier:   Option<Arc<VerifiedCodes>>, //字节码校验器，为空表示不校验
+    decryptor:  Option<Arc<CodeDecryptor>>, //字节码解密器，为空表示不解密
 }
 
 impl VMFactoryLoader {
     //虚拟机加载下个字节码，返回false，表示已加载所有代码
     pub fn load_next(&mut self, vm: &Arc<JS>) -> bool {
+        match self.try_load_next(vm) {
+            Err(e) => {
+                warn!("!!!> Vm Factory Loader Load Error, {}", e);
+                true
+            },
+            Ok(r) => r,
+        }
+    }
+
+    //虚拟机加载下个字节码，返回false，表示已加载所有代码，加载失败则跳过当前字节码并返回失败原因
+    pub fn try_load_next(&mut self, vm: &Arc<JS>) -> Result<bool, LoadError> {
         if self.offset >= self.top {
             //已加载完成
+            return Ok(false);
+        }
+
+        let index = self.offset;
+        self.offset += 1; //更新字节码偏移
+        load_code(vm, index, &self.codes[index], self.verifier.as_ref(), self.decryptor.as_ref())?;
+
+        Ok(true)
+    }
+
+    //获取下个加载的字节码序号
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    //获取剩余未加载的字节码数量
+    pub fn remaining(&self) -> usize {
+        self.top.saturating_sub(self.offset)
+    }
+
+    //重置加载器，下次从第一个字节码开始加载
+    pub fn reset(&mut self) {
+        self.offset = 0;
+    }
+
+    //设置下个加载的字节码序号，用于重试加载失败的字节码，序号超过字节码数量则忽略并返回false
+    pub fn seek(&mut self, index: usize) -> bool {
+        if index > self.top {
             return false;
         }
 
-        if vm.load(self.codes[self.offset].as_slice()) {
-            while !vm.is_ran() {
-                pause();
-            }
+        self.offset = index;
+        true
+    }
+
+    //跳过指定数量的字节码，用于延迟加载可选的字节码，返回实际跳过的数量
+    pub fn skip(&mut self, count: usize) -> usize {
+        let skipped = count.min(self.remaining());
+        self.offset += skipped;
+        skipped
+    }
+}
+
+//虚拟机校验、解密、加载并运行指定序号的字节码，校验、解密或加载失败则返回失败的字节码和原因，
+//校验的是加密字节码，解密后的明文字节码只在加载期间存在于内存中
+fn load_code(vm: &Arc<JS>, index: usize, code: &Arc<Vec<u8>>, verifier: Option<&Arc<VerifiedCodes>>, decryptor: Option<&Arc<CodeDecryptor>>) -> Result<(), LoadError> {
+    if let Some(verifier) = verifier {
+        if let Err(e) = verifier.verify(code) {
+            //校验失败，拒绝加载
+            return Err(LoadError {
+                factory: vm.get_name().to_string(),
+                index: Some(index),
+                code_hash: Some(code_hash(code.as_slice())),
+                reason: format!("code verify failed, {}", e),
+            });
         }
+    }
 
-        self.offset += 1; //更新字节码偏移
+    let mut plain = match decryptor.map(|decryptor| decryptor.decrypt(code.as_slice())) {
+        None if is_encrypted_code(code.as_slice()) => {
+            //加密的字节码没有设置解密器，拒绝加载
+            return Err(LoadError {
+                factory: vm.get_name().to_string(),
+                index: Some(index),
+                code_hash: Some(code_hash(code.as_slice())),
+                reason: "code decrypt failed, decryptor not set".to_string(),
+            });
+        },
+        None | Some(Ok(None)) => None,
+        Some(Ok(Some(plain))) => Some(plain),
+        Some(Err(e)) => {
+            //解密失败，拒绝加载
+            return Err(LoadError {
+                factory: vm.get_name().to_string(),
+                index: Some(index),
+                code_hash: Some(code_hash(code.as_slice())),
+                reason: format!("code decrypt failed, {}", e),
+            });
+        },
+    };
 
-        true
+    vm.take_last_error(); //清除之前的异常
+    let loaded = vm.load(plain.as_ref().map(|plain| plain.as_slice()).unwrap_or(code.as_slice()));
+    if let Some(plain) = &mut plain {
+        clear_code(plain);
+    }
+    if loaded {
+        while !vm.is_ran() {
+            pause();
+        }
+        return Ok(());
+    }
+
+    Err(LoadError {
+        factory: vm.get_name().to_string(),
+        index: Some(index),
+        code_hash: Some(code_hash(code.as_slice())),
+        reason: vm.take_last_error().unwrap_or_else(|| "invalid code or vm busy".to_string()),
+    })
+}
+
+/*
+* 虚拟机堆快照，记录生成快照时加载的字节码，只有加载相同字节码的虚拟机才可以使用快照
+*/
+struct HeapSnapshot {
+    codes:  Arc<Vec<Arc<Vec<u8>>>>, //生成快照时加载的字节码
+    data:   Arc<Vec<u8>>,           //快照数据
+}
+
+impl HeapSnapshot {
+    //判断快照是否由指定字节码生成，内容相同的字节码已被共享，只需要比较字节码的引用
+    fn is_match(&self, codes: &Arc<Vec<Arc<Vec<u8>>>>) -> bool {
+        self.codes.len() == codes.len() && self.codes.iter().zip(codes.iter()).all(|(x, y)| Arc::ptr_eq(x, y))
     }
 }
 
@@ -94,26 +758,105 @@ pub struct VMFactory {
     size:               Arc<AtomicUsize>,                                                       //虚拟机工厂当前虚拟机数量
     alloc_id:           Arc<AtomicUsize>,                                                       //虚拟机分配id
     max_reused_count:   usize,                                                                  //虚拟机最大执行次数，当达到虚拟机最大堆限制后才会检查
+    max_calls:          usize,                                                                  //虚拟机最大调用次数，达到后丢弃虚拟机，为0表示不限制
+    max_age:            Option<Duration>,                                                       //虚拟机最大存活时长，超过后丢弃虚拟机，为空表示不限制
+    heap_high_water:    usize,                                                                  //虚拟机堆高水位，调用后虚拟机堆超过则替换虚拟机，为0表示不限制
     heap_size:          usize,                                                                  //虚拟机堆大小
     max_heap_size:      usize,                                                                  //虚拟机最大堆大小，当达到限制后释放可回收的内存
-    codes:              Arc<Vec<Arc<Vec<u8>>>>,                                                 //字节码列表
+    codes:              Arc<RwLock<Arc<Vec<Arc<Vec<u8>>>>>>,                                    //字节码列表，可以在运行时整体替换
+    code_generation:    Arc<AtomicUsize>,                                                       //字节码代数，每次替换字节码后增加
+    base:               Option<Arc<VMFactory>>,                                                 //派生虚拟机工厂的基础虚拟机工厂，虚拟机先加载基础虚拟机工厂的字节码，再加载自身的字节码
     mods:               Arc<Vec<String>>,                                                       //虚拟机工厂依赖的模块名列表
     pool:               Arc<LFStack<Arc<JS>>>,                                                  //虚拟机池
     scheduling_count:   Arc<AtomicUsize>,                                                       //虚拟机工厂调度次数，调度包括任务队列等待和虚拟机执行
     auth:               Arc<NativeObjsAuth>,                                                    //虚拟机工厂本地对象授权
     vm_buf_sent:        Sender<Arc<JS>>,                                                        //虚拟机临时缓冲发送器
     vm_buf_recv:        Receiver<Arc<JS>>,                                                      //虚拟机临时缓冲接收器
-    queue_sent:         Sender<(Option<usize>, Atom, Box<FnOnce(Arc<JS>) -> usize>, Atom)>,     //虚拟机工厂等待调度的任务队列发送器
-    queue_recv:         Receiver<(Option<usize>, Atom, Box<FnOnce(Arc<JS>) -> usize>, Atom)>,   //虚拟机工厂等待调度的任务队列接收器
+    queue_sent:         Sender<(Option<usize>, PortName, Box<FnOnce(Arc<JS>) -> usize>, TaskInfo)>,   //虚拟机工厂等待调度的任务队列发送器
+    queue_recv:         Receiver<(Option<usize>, PortName, Box<FnOnce(Arc<JS>) -> usize>, TaskInfo)>, //虚拟机工厂等待调度的任务队列接收器
     refuse_count:       Arc<AtomicUsize>,                                                       //虚拟机工厂拒绝任务次数
+    eval_policy:        EvalPolicy,                                                             //虚拟机工厂的动态代码执行策略
+    report_hook:        Option<Arc<Fn(CallReport)>>,                                            //虚拟机工厂的调用资源使用报告回调
+    limits:             FactoryLimits,                                                          //虚拟机工厂聚合资源限制
+    pending:            PendingLimits,                                                          //虚拟机工厂等待调度的任务队列限制
+    total_heap:         Arc<AtomicIsize>,                                                       //虚拟机工厂所有虚拟机的总堆大小
+    cpu_window:         Arc<AtomicUsize>,                                                       //虚拟机工厂当前执行时长统计窗口的开始时间，单位us
+    cpu_used:           Arc<AtomicUsize>,                                                       //虚拟机工厂当前执行时长统计窗口内的累计执行时长，单位us
+    budget_timer:       Arc<AtomicBool>,                                                        //虚拟机工厂是否已设置预算恢复后执行排队任务的定时器
+    routes:             Arc<RwLock<HashMap<PortName, PortName>>>,                               //虚拟机工厂的端口路由表，外部端口名或别名到js函数路径的映射
+    checkout_retries:   usize,                                                                  //没有空闲虚拟机时，构建新虚拟机前重试获取空闲虚拟机的次数，为0表示不重试
+    checkout_backoff:   Duration,                                                               //重试获取空闲虚拟机的基础退避时长，每次重试的最大退避时长加倍，实际退避时长随机
+    wait_count:         Arc<AtomicUsize>,                                                       //虚拟机工厂已开始执行的任务数量
+    wait_time:          Arc<AtomicUsize>,                                                       //虚拟机工厂已开始执行的任务的累计等待时长，单位us
+    waiters:            Arc<Mutex<WaiterTable>>,                                                //等待空闲虚拟机的异步获取者
+    checked_out:        Arc<AtomicUsize>,                                                       //虚拟机工厂被守护者取出的虚拟机数量
+    labels:             Arc<Vec<(String, String)>>,                                             //虚拟机工厂构建的虚拟机的标签
+    code_version:       Atom,                                                                   //虚拟机工厂的代码版本
+    task_priority:      Arc<AtomicUsize>,                                                       //虚拟机工厂的任务优先级，为0表示使用全局基础优先级
+    latency:            Arc<LatencyHistogram>,                                                  //虚拟机工厂调用从提交到完成的延迟直方图
+    latency_window:     Arc<LatencyHistogram>,                                                  //虚拟机工厂上次取出后的调用延迟直方图，用于观察最近的调用延迟
+    poison_count:       Arc<AtomicUsize>,                                                       //虚拟机工厂因状态损坏而无法复用的虚拟机数量
+    call_count:         Arc<AtomicUsize>,                                                       //虚拟机工厂已开始执行的调用总数
+    new_vm_failed:      Arc<AtomicUsize>,                                                       //虚拟机工厂构建虚拟机失败次数
+    last_load_error:    Arc<Mutex<Option<LoadError>>>,                                          //虚拟机工厂最近一次构建虚拟机失败的原因
+    port_calls:         Arc<Mutex<HashMap<Atom, usize>>>,                                       //虚拟机工厂每个端口已开始执行的调用数量
+    phases:             Arc<Mutex<HashMap<(Atom, Atom), PhaseStats>>>,                          //虚拟机工厂每个端口的调用内阶段统计，键为端口和阶段名
+    call_timeout:       Option<Duration>,                                                       //虚拟机工厂调用的默认执行超时时长，为空表示不限制
+    closed:             Arc<AtomicBool>,                                                        //虚拟机工厂是否已停止接收调用
+    validated:          Arc<AtomicBool>,                                                        //虚拟机工厂是否已在首次生成虚拟机前校验代码包清单
+    in_flight:          Arc<AtomicUsize>,                                                       //虚拟机工厂已接收但未完成的调用数量，包括等待调度的调用
+    sources:            Arc<Mutex<HashSet<usize>>>,                                             //虚拟机工厂调用使用过的同步任务队列的源
+    drain_waiters:      Arc<Mutex<Vec<Waker>>>,                                                 //等待虚拟机工厂排空的完成句柄
+    last_call:          Arc<AtomicUsize>,                                                       //虚拟机工厂上次接收调用的时间，单位us
+    task_queues:        Arc<Vec<isize>>,                                                        //虚拟机工厂独占的同步任务队列，为空表示无源调用使用全局异步任务池
+    queue_cursor:       Arc<AtomicUsize>,                                                       //虚拟机工厂独占的同步任务队列的轮询游标
+    stack_limit:        Option<usize>,                                                          //虚拟机工厂构建的虚拟机的调用栈深度限制，为空表示使用虚拟机默认限制
+    native_limit:       Option<usize>,                                                          //虚拟机工厂构建的虚拟机的本地调用递归深度限制，为空表示使用虚拟机默认限制
+    fuel:               Option<usize>,                                                          //虚拟机工厂每次调用的燃料预算，为空表示不计量
+    idle_ttl:           Option<Duration>,                                                       //虚拟机工厂空闲虚拟机的存活时长，为空表示使用全局虚拟机超时时长
+    create_hook:        Option<Arc<Fn(&Arc<JS>)>>,                                              //虚拟机工厂构建虚拟机后的回调
+    destroy_hook:       Option<Arc<Fn(Atom, usize)>>,                                           //虚拟机工厂构建的虚拟机销毁时的回调，参数为虚拟机工厂名和虚拟机id
+    affinity_capacity:  usize,                                                                  //虚拟机工厂源固定的空闲虚拟机的最大数量，为0表示不启用源亲和
+    response_affinity:  bool,                                                                   //虚拟机工厂的通道回应回调是否通过发起调用的源的同步任务队列按序投递
+    late_callback:      LateCallbackPolicy,                                                     //虚拟机工厂的虚拟机重置全局环境后，才执行重置前推送的异步回调的处理方式
+    oom_hook:           Option<Arc<Fn(&OomInfo) -> OomAction>>,                                 //虚拟机工厂的虚拟机内存不足时的回调，返回虚拟机的处理方式
+    affinity:           Arc<Mutex<AffinityTable>>,                                              //虚拟机工厂的源亲和表
+    services:           Option<ServiceRegistry>,                                                //虚拟机工厂的服务注册表，虚拟机工厂的虚拟机发出的通道请求可以获取服务
+    profile:            VmProfile,                                                              //虚拟机工厂构建的虚拟机的堆配置
+    thread_audit:       bool,                                                                   //虚拟机工厂构建的虚拟机是否审计线程访问
+    verifier:           Option<Arc<VerifiedCodes>>,                                             //虚拟机工厂加载字节码前的校验器，为空表示不校验
+    decryptor:          Option<Arc<CodeDecryptor>>,                                             //虚拟机工厂加载加密字节码前的解密器，为空表示不解密
+    compile_cache:      Option<Arc<CompileCache>>,                                              //虚拟机工厂编译源码的磁盘编译缓存，为空表示每次都编译
+    snapshot:           Option<Arc<RwLock<Option<HeapSnapshot>>>>,                              //虚拟机工厂构建虚拟机的堆快照，为空表示不使用堆快照
+    signatures:         Arc<HashMap<Atom, Arc<PortSignature>>>,                                 //虚拟机工厂声明的端口签名表
+    breaker:            Option<Arc<CircuitBreaker>>,                                            //虚拟机工厂的端口熔断器，为空表示不熔断
+    warmups:            Arc<Vec<WarmupCall>>,                                                   //虚拟机工厂构建虚拟机后执行的预热调用列表
 }
 
 unsafe impl Send for VMFactory {}
 unsafe impl Sync for VMFactory {}
 
 impl VMFactory {
+    //兼容旧版本的构建接口，虚拟机工厂名使用字符串，名称无效时只记录警告，仍然构建虚拟机工厂
+    #[deprecated(note = "use VMFactory::new with FactoryName, which rejects invalid names")]
+    pub fn new_str(name: &str,
+                   size: usize,
+                   max_reused_count: usize,
+                   heap_size: usize,
+                   max_heap_size: usize,
+                   auth: Arc<NativeObjsAuth>) -> Self {
+        let name = match FactoryName::new(name) {
+            Err(e) => {
+                warn!("!!!> Vm Factory Name Invalid, name: {:?}, e: {:?}", name, e);
+                FactoryName::new_unchecked(name)
+            },
+            Ok(name) => name,
+        };
+        VMFactory::new(name, size, max_reused_count, heap_size, max_heap_size, auth)
+    }
+
     //构建一个虚拟机工厂
-    pub fn new(name: &str,
+    pub fn new(name: FactoryName,
                mut size: usize,
                max_reused_count: usize,
                heap_size: usize,
@@ -128,15 +871,20 @@ impl VMFactory {
         let (vm_buf_sent, vm_buf_recv) = unbounded();
         let (queue_sent, queue_recv) = unbounded();
         VMFactory {
-            name: Atom::from(name),
+            name: name.into(),
             is_reused,
             limit_capacity: Arc::new(AtomicUsize::new(0)),
             size: Arc::new(AtomicUsize::new(0)),
             alloc_id: Arc::new(AtomicUsize::new(0)),
             max_reused_count,
+            max_calls: 0,
+            max_age: None,
+            heap_high_water: 0,
             heap_size,
             max_heap_size,
-            codes: Arc::new(Vec::new()),
+            codes: Arc::new(RwLock::new(Arc::new(Vec::new()))),
+            code_generation: Arc::new(AtomicUsize::new(0)),
+            base: None,
             mods: Arc::new(Vec::new()),
             pool: Arc::new(LFStack::new()),
             scheduling_count: Arc::new(AtomicUsize::new(0)),
@@ -146,32 +894,517 @@ impl VMFactory {
             queue_sent,
             queue_recv,
             refuse_count: Arc::new(AtomicUsize::new(0)),
+            eval_policy: EvalPolicy::Allow,
+            report_hook: None,
+            limits: FactoryLimits::default(),
+            pending: PendingLimits::default(),
+            total_heap: Arc::new(AtomicIsize::new(0)),
+            cpu_window: Arc::new(AtomicUsize::new(now_utc())),
+            cpu_used: Arc::new(AtomicUsize::new(0)),
+            budget_timer: Arc::new(AtomicBool::new(false)),
+            routes: Arc::new(RwLock::new(HashMap::new())),
+            checkout_retries: 0,
+            checkout_backoff: Duration::from_micros(0),
+            wait_count: Arc::new(AtomicUsize::new(0)),
+            wait_time: Arc::new(AtomicUsize::new(0)),
+            waiters: Arc::new(Mutex::new(WaiterTable {
+                next_id: 0,
+                wakers: HashMap::new(),
+                order: VecDeque::new(),
+            })),
+            checked_out: Arc::new(AtomicUsize::new(0)),
+            labels: Arc::new(Vec::new()),
+            code_version: Atom::from(""),
+            task_priority: Arc::new(AtomicUsize::new(0)),
+            latency: Arc::new(LatencyHistogram::new()),
+            latency_window: Arc::new(LatencyHistogram::new()),
+            poison_count: Arc::new(AtomicUsize::new(0)),
+            call_count: Arc::new(AtomicUsize::new(0)),
+            new_vm_failed: Arc::new(AtomicUsize::new(0)),
+            last_load_error: Arc::new(Mutex::new(None)),
+            port_calls: Arc::new(Mutex::new(HashMap::new())),
+            phases: Arc::new(Mutex::new(HashMap::new())),
+            call_timeout: None,
+            closed: Arc::new(AtomicBool::new(false)),
+            validated: Arc::new(AtomicBool::new(false)),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            sources: Arc::new(Mutex::new(HashSet::new())),
+            drain_waiters: Arc::new(Mutex::new(Vec::new())),
+            last_call: Arc::new(AtomicUsize::new(now_utc())),
+            task_queues: Arc::new(Vec::new()),
+            queue_cursor: Arc::new(AtomicUsize::new(0)),
+            stack_limit: None,
+            native_limit: None,
+            fuel: None,
+            idle_ttl: None,
+            create_hook: None,
+            destroy_hook: None,
+            affinity_capacity: 0,
+            response_affinity: false,
+            late_callback: LateCallbackPolicy::default(),
+            oom_hook: None,
+            affinity: Arc::new(Mutex::new(AffinityTable {
+                parked: HashMap::new(),
+                order: VecDeque::new(),
+            })),
+            services: None,
+            profile: VmProfile::default(),
+            thread_audit: false,
+            verifier: None,
+            decryptor: None,
+            compile_cache: None,
+            snapshot: None,
+            signatures: Arc::new(HashMap::new()),
+            breaker: None,
+            warmups: Arc::new(Vec::new()),
         }
     }
 
-    //为指定虚拟机工厂增加代码，必须使用所有权，以保证运行时不会不安全的增加代码，复制对象将无法增加代码
+    //为指定虚拟机工厂增加代码，内容相同的代码在所有虚拟机工厂间共享，必须使用所有权，以保证运行时不会不安全的增加代码，复制对象将无法增加代码
     pub fn append(mut self, code: Arc<Vec<u8>>) -> Self {
-        match Arc::get_mut(&mut self.codes) {
-            None => (),
-            Some(vec) => {
-                vec.push(code);
+        let code = intern_code(code);
+        if let Some(lock) = Arc::get_mut(&mut self.codes) {
+            if let Ok(codes) = lock.get_mut() {
+                if let Some(vec) = Arc::get_mut(codes) {
+                    vec.push(code);
+                }
             }
         }
         self
     }
 
-    //为指定虚拟机工厂增加指定模块的代码，必须使用所有权，以保证运行时不会不安全的增加代码，复制对象将无法增加代码
-    pub fn append_depend(mut self, module: String) -> Self {
-        match Arc::get_mut(&mut self.mods) {
-            None => (),
-            Some(mods) => {
-                mods.push(module);
-            },
-        }
-        self
+    //为指定虚拟机工厂增加使用指定密钥id的密钥加密的字节码，虚拟机工厂只保存加密字节码，在虚拟机加载前才在内存中解密，必须设置解密器
+    pub fn append_encrypted(self, key_id: &str, cipher_text: &[u8]) -> Result<Self, String> {
+        let code = encrypted_code(key_id, cipher_text)?;
+        Ok(self.append(Arc::new(code)))
     }
 
-    //判断虚拟机工厂是否依赖指定模块
+    //将指定模块的源码包装在独立的模块作用域中并编译，然后为虚拟机工厂增加模块的字节码，模块导出的成员在全局的__bundles[name]中
+    pub fn append_bundle(self, name: &str, source: &str) -> Result<Self, String> {
+        let code = match self.compile_cache {
+            None => compile_bundle(name, source)?,
+            Some(ref cache) => {
+                let wrapped = wrap_bundle(name, source)?;
+                cache.compile(&format!("{}.bundle.js", name), &wrapped)
+                    .map_err(|e| format!("compile bundle failed, bundle: {:?}, e: {}", name, e))?
+            },
+        };
+        Ok(self.append(code))
+    }
+
+    //将指定文件名的js源码编译为字节码，然后为虚拟机工厂增加字节码，用于没有独立编译步骤的项目，设置了磁盘编译缓存则优先使用缓存的字节码
+    pub fn append_source(self, file: &str, source: &str) -> Result<Self, String> {
+        let code = match self.compile_cache {
+            None => compile_source(file, source)?,
+            Some(ref cache) => cache.compile(file, source)?,
+        };
+        Ok(self.append(code))
+    }
+
+    //顺序获取指定字节码源的字节码，并按字节码源顺序为虚拟机工厂增加字节码，任意字节码源获取失败则不增加任何字节码并返回加载报告，
+    //必须使用所有权，以保证运行时不会不安全的增加代码
+    pub fn append_sources(self, sources: &[Arc<CodeSource>]) -> Result<Self, SourceReport> {
+        let (codes, results) = fetch_sources(sources);
+        let report = SourceReport {
+            factory: self.name(),
+            results,
+            generation: None,
+            error: None,
+        };
+        if !report.is_ok() {
+            warn!("!!!> Vm Factory Append Sources Failed, factory: {:?}, failed: {}", self.name(), report.failed());
+            return Err(report);
+        }
+
+        Ok(codes.into_iter().fold(self, |factory, code| factory.append(code)))
+    }
+
+    //按加载顺序加载目录下匹配模式的所有文件，并为虚拟机工厂增加字节码，任意文件加载失败则不增加任何字节码并返回加载报告
+    pub fn append_glob(self, source: GlobSource) -> Result<Self, SourceReport> {
+        self.append_sources(&[Arc::new(source)])
+    }
+
+    //在工作者中并发获取指定字节码源的字节码，全部获取成功后按字节码源顺序替换虚拟机工厂的字节码，完成后回调加载报告
+    pub fn reload_sources(&self, sources: Vec<Arc<CodeSource>>, on_done: Box<FnOnce(SourceReport)>) {
+        let factory = self.clone();
+        fetch_sources_async(sources, self.task_priority(), Box::new(move |codes, results| {
+            let mut report = SourceReport {
+                factory: factory.name(),
+                results,
+                generation: None,
+                error: None,
+            };
+            if report.failed() == 0 {
+                match factory.reload_codes(codes) {
+                    Err(e) => report.error = Some(e.to_string()),
+                    Ok(generation) => report.generation = Some(generation),
+                }
+            } else {
+                warn!("!!!> Vm Factory Reload Sources Failed, factory: {:?}, failed: {}", factory.name(), report.failed());
+            }
+            on_done(report);
+        }));
+    }
+
+    //派生一个指定名称的虚拟机工厂，派生的虚拟机工厂共享当前虚拟机工厂的字节码，并在之后加载指定的附加字节码，
+    //当前虚拟机工厂替换字节码后，派生的虚拟机工厂的虚拟机也会使用新字节码重新生成，派生的虚拟机工厂继承依赖模块、动态代码执行策略、代码版本和虚拟机堆配置
+    pub fn fork(&self, name: FactoryName, extra_codes: Vec<Arc<Vec<u8>>>) -> VMFactory {
+        let mut factory = VMFactory::new(name,
+                                         if self.is_reused { 1 } else { 0 },
+                                         self.max_reused_count,
+                                         self.heap_size,
+                                         self.max_heap_size,
+                                         self.auth.clone());
+        factory.base = Some(Arc::new(self.clone()));
+        factory.codes = Arc::new(RwLock::new(Arc::new(intern_codes(extra_codes))));
+        factory.mods = self.mods.clone();
+        factory.eval_policy = self.eval_policy.clone();
+        factory.code_version = self.code_version.clone();
+        factory.max_calls = self.max_calls;
+        factory.max_age = self.max_age;
+        factory.stack_limit = self.stack_limit;
+        factory.native_limit = self.native_limit;
+        factory.fuel = self.fuel;
+        factory.profile = self.profile;
+        factory.thread_audit = self.thread_audit;
+        factory.verifier = self.verifier.clone();
+        factory.decryptor = self.decryptor.clone();
+        factory.compile_cache = self.compile_cache.clone();
+        factory.signatures = self.signatures.clone();
+        factory.warmups = self.warmups.clone();
+        factory.breaker = self.breaker.as_ref().map(|breaker| Arc::new(CircuitBreaker::new(*breaker.config()))); //派生的虚拟机工厂加载的字节码不同，需要重新统计
+        factory.snapshot = self.snapshot.as_ref().map(|_| Arc::new(RwLock::new(None))); //派生的虚拟机工厂加载的字节码不同，需要重新生成堆快照
+
+        info!("===> Vm Factory Fork Ok, base: {:?}, factory: {:?}", (&self.name).to_string(), name);
+        factory
+    }
+
+    //获取派生虚拟机工厂的基础虚拟机工厂
+    pub fn base(&self) -> Option<&VMFactory> {
+        self.base.as_ref().map(|base| base.as_ref())
+    }
+
+    //线程安全的替换虚拟机工厂的字节码，替换前使用新字节码构建虚拟机并校验清单，失败则不替换，
+    //替换后使用旧字节码的空闲虚拟机会被丢弃并按数量重新生成，正在执行的虚拟机会在归还时被丢弃，返回替换后的字节码代数
+    //派生的虚拟机工厂只替换自身的附加字节码
+    pub fn reload_codes(&self, codes: Vec<Arc<Vec<u8>>>) -> Result<usize, ManifestReport> {
+        let codes = Arc::new(intern_codes(codes));
+        let vm = match self.build_vm(self.auth.clone(), &self.with_base_codes(&codes), self.code_generation() + 1) {
+            Err(e) => {
+                self.throw(1);
+                return Err(ManifestReport {
+                    factory: self.name.clone(),
+                    mismatches: vec![VersionMismatch::Invalid(e.to_string())],
+                });
+            },
+            Ok(vm) => vm,
+        };
+
+        if let Err(report) = check_manifest(&self.name, &vm, is_async_request_registered) {
+            self.throw(1);
+            warn!("!!!> Vm Factory Reload Codes Failed, {}", report);
+            return Err(report);
+        }
+
+        let generation = {
+            let mut current = write_state("vm_factory_codes", &self.codes);
+            *current = codes;
+            self.code_generation.fetch_add(1, Ordering::SeqCst);
+            self.code_generation()
+        };
+        vm.set_code_generation(generation);
+
+        //丢弃使用旧字节码的空闲虚拟机，并使用新字节码重新生成
+        let mut evicted = 0;
+        while let Some(vm) = self.checkout() {
+            self.throw(1);
+            evicted += 1;
+            info!("===> Vm Factory Evict Stale Vm Ok, factory: {:?}, vm: {:?}", (&self.name).to_string(), vm);
+        }
+
+        if self.is_reused {
+            self.pool.push(vm);
+            if evicted > 1 {
+                if let Err(e) = self.produce(evicted - 1) {
+                    warn!("!!!> Vm Factory Reload Codes, produce failed, factory: {:?}, e: {:?}", (&self.name).to_string(), e);
+                }
+            }
+        } else {
+            self.throw(1);
+        }
+
+        VM_CODE_RELOAD_COUNT.sum(1);
+        info!("===> Vm Factory Reload Codes Ok, factory: {:?}, generation: {}, evicted: {}",
+              (&self.name).to_string(), generation, evicted);
+        Ok(generation)
+    }
+
+    //获取虚拟机工厂自身字节码的内容hash列表，不包括基础虚拟机工厂的字节码
+    pub fn code_hashes(&self) -> Vec<u64> {
+        read_state("vm_factory_codes", &self.codes).iter().map(|code| code_hash(code.as_slice())).collect()
+    }
+
+    //判断指定字节码列表是否与虚拟机工厂自身的字节码内容相同，用于在替换字节码前检查是否需要替换
+    pub fn is_same_codes(&self, codes: &[Arc<Vec<u8>>]) -> bool {
+        let current = read_state("vm_factory_codes", &self.codes).clone();
+        current.len() == codes.len()
+            && current.iter().zip(codes.iter()).all(|(x, y)| Arc::ptr_eq(x, y) || x.as_slice() == y.as_slice())
+    }
+
+    //获取虚拟机工厂的字节码代数，派生的虚拟机工厂的字节码代数包括基础虚拟机工厂的字节码代数
+    pub fn code_generation(&self) -> usize {
+        match &self.base {
+            None => self.code_generation.load(Ordering::SeqCst),
+            Some(base) => self.code_generation.load(Ordering::SeqCst) + base.code_generation(),
+        }
+    }
+
+    //判断指定虚拟机是否使用旧字节码
+    pub fn is_stale(&self, vm: &JS) -> bool {
+        vm.code_generation() != self.code_generation()
+    }
+
+    //获取当前字节码和字节码代数
+    fn current_codes(&self) -> (Arc<Vec<Arc<Vec<u8>>>>, usize) {
+        let codes = read_state("vm_factory_codes", &self.codes).clone();
+        (self.with_base_codes(&codes), self.code_generation())
+    }
+
+    //获取基础虚拟机工厂的当前字节码和指定字节码组成的字节码列表，只复制字节码的引用
+    fn with_base_codes(&self, codes: &Arc<Vec<Arc<Vec<u8>>>>) -> Arc<Vec<Arc<Vec<u8>>>> {
+        match &self.base {
+            None => codes.clone(),
+            Some(base) => {
+                let (base_codes, _) = base.current_codes();
+                let mut vec = Vec::with_capacity(base_codes.len() + codes.len());
+                vec.extend(base_codes.iter().cloned());
+                vec.extend(codes.iter().cloned());
+                Arc::new(vec)
+            },
+        }
+    }
+
+    //为指定虚拟机工厂增加指定模块的代码，必须使用所有权，以保证运行时不会不安全的增加代码，复制对象将无法增加代码
+    pub fn append_depend(mut self, module: String) -> Self {
+        match Arc::get_mut(&mut self.mods) {
+            None => (),
+            Some(mods) => {
+                mods.push(module);
+            },
+        }
+        self
+    }
+
+    //设置虚拟机工厂的动态代码执行策略，禁止时虚拟机运行时的eval和new Function会抛出异常，需要启用evalcheck特性构建，否则无法禁止并返回错误，
+    //必须使用所有权，以保证运行时不会不安全的修改策略
+    pub fn set_eval_policy(mut self, policy: EvalPolicy) -> Result<Self, VMFactoryError> {
+        if let (false, EvalPolicy::Deny(_)) = (cfg!(feature = "evalcheck"), &policy) {
+            return Err(VMFactoryError::Unsupported(self.name(), "evalcheck"));
+        }
+
+        self.eval_policy = policy;
+        Ok(self)
+    }
+
+    //获取虚拟机工厂的动态代码执行策略
+    pub fn eval_policy(&self) -> EvalPolicy {
+        self.eval_policy.clone()
+    }
+
+    //设置虚拟机工厂的调用资源使用报告回调，每次调用完成后回调，必须使用所有权，以保证运行时不会不安全的修改回调
+    pub fn set_report_hook(mut self, hook: Arc<Fn(CallReport)>) -> Self {
+        self.report_hook = Some(hook);
+        self
+    }
+
+    //提交调用资源使用报告
+    pub fn report(&self, report: CallReport) {
+        if let Some(hook) = &self.report_hook {
+            hook(report);
+        }
+    }
+
+    //设置虚拟机工厂聚合资源限制，必须使用所有权，以保证运行时不会不安全的修改限制
+    pub fn set_limits(mut self, limits: FactoryLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    //获取虚拟机工厂聚合资源限制
+    pub fn limits(&self) -> &FactoryLimits {
+        &self.limits
+    }
+
+    //设置虚拟机工厂等待调度的任务队列限制，限制了最大长度的任务队列是有界队列，会重建任务队列，所以必须在虚拟机工厂接收任务前设置，
+    //必须使用所有权，以保证运行时不会不安全的修改限制
+    pub fn set_pending_limits(mut self, limits: PendingLimits) -> Self {
+        let (queue_sent, queue_recv) = if limits.max_depth > 0 {
+            bounded(limits.max_depth)
+        } else {
+            unbounded()
+        };
+        self.queue_sent = queue_sent;
+        self.queue_recv = queue_recv;
+        self.pending = limits;
+        self
+    }
+
+    //获取虚拟机工厂等待调度的任务队列限制
+    pub fn pending_limits(&self) -> &PendingLimits {
+        &self.pending
+    }
+
+    //判断是否已达到虚拟机工厂的最大虚拟机数量
+    fn is_vm_limit(&self) -> bool {
+        self.pending.max_vms > 0 && self.size() >= self.pending.max_vms
+    }
+
+    //获取虚拟机工厂所有虚拟机的总堆大小
+    pub fn total_heap(&self) -> usize {
+        let size = self.total_heap.load(Ordering::Relaxed);
+        if size < 0 {
+            0
+        } else {
+            size as usize
+        }
+    }
+
+    //增加虚拟机工厂所有虚拟机的总堆大小，增量可以为负
+    pub fn add_total_heap(&self, delta: isize) {
+        self.total_heap.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    //减少虚拟机工厂所有虚拟机的总堆大小
+    pub fn sub_total_heap(&self, size: isize) {
+        self.total_heap.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    //获取虚拟机工厂当前统计窗口内的累计执行时长
+    pub fn cpu_used(&self) -> Duration {
+        self.roll_cpu_window();
+        Duration::from_micros(self.cpu_used.load(Ordering::Relaxed) as u64)
+    }
+
+    //增加虚拟机工厂当前统计窗口内的累计执行时长，单位us
+    pub fn add_cpu_time(&self, time: usize) {
+        self.roll_cpu_window();
+        self.cpu_used.fetch_add(time, Ordering::Relaxed);
+    }
+
+    //判断虚拟机工厂的聚合资源预算是否已耗尽
+    pub fn is_budget_exhausted(&self) -> bool {
+        if self.limits.max_total_heap > 0 && self.total_heap() >= self.limits.max_total_heap {
+            //已达到总堆大小限制
+            return true;
+        }
+
+        if let Some(budget) = self.limits.cpu_per_minute {
+            //已达到当前统计窗口的执行时长限制
+            return self.cpu_used() >= budget;
+        }
+
+        false
+    }
+
+    //设置虚拟机工厂的代码版本，虚拟机可以通过__vm.version获取，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_code_version(mut self, version: &str) -> Self {
+        self.code_version = Atom::from(version);
+        self
+    }
+
+    //获取虚拟机工厂的代码版本
+    pub fn code_version(&self) -> String {
+        self.code_version.to_string()
+    }
+
+    //设置虚拟机工厂的任务优先级，为0表示使用全局基础优先级
+    pub fn set_task_priority(self, priority: usize) -> Self {
+        self.task_priority.store(priority, Ordering::Relaxed);
+        self
+    }
+
+    //设置虚拟机工厂独占的同步任务队列数量，无源调用会轮询投递到独占的任务队列，每个任务队列同一时间只执行一个调用，
+    //因此队列数量即虚拟机工厂的最大并发调用数量，使虚拟机工厂在调度层与其它虚拟机工厂的负载隔离，为0表示使用全局异步任务池
+    pub fn set_task_queues(mut self, concurrency: usize) -> Self {
+        let priority = self.task_priority();
+        if let Some(queues) = Arc::get_mut(&mut self.task_queues) {
+            for queue in queues.drain(..) {
+                remove_js_task_queue(queue);
+            }
+            for _ in 0..concurrency {
+                queues.push(create_js_task_queue(priority, false));
+            }
+        }
+        self
+    }
+
+    //获取虚拟机工厂独占的同步任务队列数量，为0表示使用全局异步任务池
+    pub fn task_queue_count(&self) -> usize {
+        self.task_queues.len()
+    }
+
+    //运行时调整虚拟机工厂的任务优先级，只影响之后投递的任务，所有复制的虚拟机工厂共享此优先级，返回上个优先级
+    pub fn adjust_task_priority(&self, priority: usize) -> usize {
+        let old = self.task_priority.swap(priority, Ordering::Relaxed);
+        info!("===> Vm Factory Adjust Task Priority, factory: {:?}, old: {}, new: {}",
+              (&self.name).to_string(), old, priority);
+        old
+    }
+
+    //获取虚拟机工厂当前生效的任务优先级
+    pub fn task_priority(&self) -> usize {
+        match self.task_priority.load(Ordering::Relaxed) {
+            0 => default_task_priority(),
+            priority => priority,
+        }
+    }
+
+    //为虚拟机工厂增加标签，虚拟机工厂构建的虚拟机都会带有此标签，必须使用所有权，以保证运行时不会不安全的增加标签
+    pub fn append_label(mut self, key: &str, value: &str) -> Self {
+        match Arc::get_mut(&mut self.labels) {
+            None => (),
+            Some(vec) => vec.push((key.to_string(), value.to_string())),
+        }
+        self
+    }
+
+    //获取虚拟机工厂的标签
+    pub fn labels(&self) -> Vec<(String, String)> {
+        self.labels.as_ref().clone()
+    }
+
+    //设置没有空闲虚拟机时，构建新虚拟机前重试获取空闲虚拟机的次数和基础退避时长，重试期间任务在任务调度队列中等待被归还的虚拟机执行，
+    //重试窗口结束后仍在等待则构建新的虚拟机执行，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_checkout_retry(mut self, retries: usize, backoff: Duration) -> Self {
+        self.checkout_retries = retries;
+        self.checkout_backoff = backoff;
+        self
+    }
+
+    //增加端口路由，将外部端口名或别名映射到js函数路径，别名可以带版本，例如"user.get@v2"，返回被替换的js函数路径
+    pub fn add_route(&self, alias: PortName, path: PortName) -> Option<PortName> {
+        write_state("vm_factory_routes", &self.routes).insert(alias, path)
+    }
+
+    //移除端口路由，返回被移除的js函数路径
+    pub fn remove_route(&self, alias: &PortName) -> Option<PortName> {
+        write_state("vm_factory_routes", &self.routes).remove(alias)
+    }
+
+    //获取端口路由表
+    pub fn routes(&self) -> Vec<(PortName, PortName)> {
+        read_state("vm_factory_routes", &self.routes).iter().map(|(alias, path)| (alias.clone(), path.clone())).collect()
+    }
+
+    //解析端口，存在路由则返回路由的js函数路径，否则返回端口本身
+    pub fn resolve_port(&self, port: &PortName) -> PortName {
+        match read_state("vm_factory_routes", &self.routes).get(port) {
+            None => port.clone(),
+            Some(path) => path.clone(),
+        }
+    }
+
+    //判断虚拟机工厂是否依赖指定模块
     pub fn is_depend(&self, module: &String) -> bool {
         self.mods.contains(module)
     }
@@ -181,6 +1414,16 @@ impl VMFactory {
         (*self.name).to_string()
     }
 
+    //判断是否与指定虚拟机工厂是同一个虚拟机工厂，复制的虚拟机工厂也是同一个虚拟机工厂
+    pub fn is_same(&self, other: &VMFactory) -> bool {
+        Arc::ptr_eq(&self.size, &other.size)
+    }
+
+    //将虚拟机工厂注册到全局虚拟机工厂注册表，以便其它模块通过名称查找，停止后会自动注销
+    pub fn register(&self) -> Result<(), String> {
+        register_factory(self)
+    }
+
     //获取虚拟机池的限制容量
     pub fn limit_capacity(&self) -> usize {
         self.limit_capacity.load(Ordering::Relaxed)
@@ -224,6 +1467,174 @@ impl VMFactory {
         self.max_reused_count
     }
 
+    //设置虚拟机最大调用次数，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_max_calls(mut self, count: usize) -> Self {
+        self.max_calls = count;
+        self
+    }
+
+    //获取虚拟机最大调用次数
+    pub fn max_calls(&self) -> usize {
+        self.max_calls
+    }
+
+    //设置虚拟机最大存活时长，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    //获取虚拟机最大存活时长
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    //获取虚拟机回收策略
+    pub fn recycle_policy(&self) -> RecyclePolicy {
+        RecyclePolicy {
+            max_reused_count: self.max_reused_count,
+            max_calls: self.max_calls,
+            max_heap_size: self.max_heap_size,
+            max_age: self.max_age,
+        }
+    }
+
+    //设置虚拟机堆高水位，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_heap_high_water(mut self, size: usize) -> Self {
+        self.heap_high_water = size;
+        self
+    }
+
+    //获取虚拟机堆高水位
+    pub fn heap_high_water(&self) -> usize {
+        self.heap_high_water
+    }
+
+    //判断指定可复用虚拟机调用后的堆是否超过高水位
+    pub fn over_high_water(&self, vm: &JS) -> bool {
+        self.is_reused && self.heap_high_water > 0 && vm.last_heap_size() > self.heap_high_water
+    }
+
+    //在后台生成指定数量的虚拟机，用于替换被丢弃的虚拟机
+    pub fn replenish(&self, count: usize) {
+        if count == 0 || self.is_closed() {
+            return;
+        }
+
+        let factory = self.clone();
+        let func = Box::new(move |_lock: Option<isize>| {
+            if let Err(e) = factory.produce(count) {
+                warn!("!!!> Vm Factory Replenish Error, factory: {:?}, e: {:?}", factory.name(), e);
+            }
+        });
+        cast_js_task(TaskType::Async(false), self.task_priority(), None, func, FACTORY_REPLENISH_TASK.clone());
+    }
+
+    //在工作者中异步生成指定数量的虚拟机，每个虚拟机的生成是一个独立的任务，生成的虚拟机可以立即被调用使用，
+    //所有虚拟机生成完成后回调生成报告，用于在预热虚拟机的同时开始处理调用
+    pub fn produce_async(&self, count: usize, on_done: Box<FnOnce(ProduceReport)>) {
+        let mut report = ProduceReport {
+            factory: (&self.name).to_string(),
+            requested: count,
+            results: Vec::with_capacity(count),
+            error: None,
+        };
+
+        let factory = self.clone();
+        let func = Box::new(move |_lock: Option<isize>| {
+            //首次生成时校验代码包清单并注册虚拟机工厂
+            if let Err(e) = factory.produce(0) {
+                warn!("!!!> Vm Factory Produce Async Error, factory: {:?}, e: {:?}", factory.name(), e);
+                report.error = Some(e);
+                on_done(report);
+                return;
+            }
+            if count == 0 {
+                on_done(report);
+                return;
+            }
+
+            let state = Arc::new(Mutex::new((report, Some(on_done))));
+            for _ in 0..count {
+                let factory_copy = factory.clone();
+                let state_copy = state.clone();
+                let func = Box::new(move |_lock: Option<isize>| {
+                    let result = match factory_copy.new_vm(factory_copy.auth.clone()) {
+                        Err(e) => {
+                            warn!("!!!> Vm Factory Produce Async Error, factory: {:?}", factory_copy.name());
+                            Err(e.to_string())
+                        },
+                        Ok(vm) => {
+                            let r = vm.free_global(); //预生成的虚拟机，将强制GC
+                            info!("===> Vm Factory Produce Async Ok, gc: {}, vm: {:?}", r, vm);
+                            let id = vm.get_id();
+                            factory_copy.reuse(vm); //立即可用于执行等待调度的任务
+                            Ok(id)
+                        },
+                    };
+
+                    let finished = {
+                        let mut state = lock_state("vm_factory_produce", &state_copy);
+                        state.0.results.push(result);
+                        if state.0.results.len() == count {
+                            state.1.take().map(|on_done| (on_done, state.0.clone()))
+                        } else {
+                            None
+                        }
+                    };
+                    if let Some((on_done, report)) = finished {
+                        //所有虚拟机生成完成
+                        on_done(report);
+                    }
+                });
+                cast_js_task(TaskType::Async(false), factory.task_priority(), None, func, FACTORY_PRODUCE_TASK.clone());
+            }
+        });
+        cast_js_task(TaskType::Async(false), self.task_priority(), None, func, FACTORY_PRODUCE_ASYNC_TASK.clone());
+    }
+
+    //在工作者中并发生成指定数量的虚拟机，每个虚拟机在独立的任务中构建并加载字节码，并阻塞当前线程直到所有虚拟机生成完成，返回生成报告，
+    //超过指定时长未完成则返回超时的生成报告，超时后未完成的虚拟机仍会继续生成，不允许在js工作者线程中调用，否则可能死锁
+    pub fn produce_parallel(&self, count: usize, timeout: Duration) -> ProduceReport {
+        let (sender, receiver) = bounded(1);
+        self.produce_async(count, Box::new(move |report| {
+            let _ = sender.send(report);
+        }));
+
+        match receiver.recv_timeout(timeout) {
+            Ok(report) => report,
+            Err(e) => {
+                warn!("!!!> Vm Factory Produce Parallel Error, factory: {:?}, e: {:?}", self.name(), e);
+                ProduceReport {
+                    factory: self.name(),
+                    requested: count,
+                    results: Vec::new(),
+                    error: Some(match e {
+                        RecvTimeoutError::Timeout => format!("produce timeout, limit: {:?}", timeout),
+                        RecvTimeoutError::Disconnected => "produce discarded".to_string(),
+                    }),
+                }
+            },
+        }
+    }
+
+    //判断指定虚拟机是否达到回收策略的限制，返回达到限制的原因
+    pub fn expired(&self, vm: &JS) -> Option<String> {
+        let served = vm.served_count();
+        if self.max_calls > 0 && served >= self.max_calls {
+            return Some(format!("served {} calls, limit: {}", served, self.max_calls));
+        }
+
+        if let Some(max_age) = self.max_age {
+            let age = vm.age();
+            if age >= max_age {
+                return Some(format!("lived {:?}, limit: {:?}", age, max_age));
+            }
+        }
+
+        None
+    }
+
     //获取虚拟机堆限制
     pub fn heap_size(&self) -> usize {
         self.heap_size
@@ -259,63 +1670,981 @@ impl VMFactory {
         self.refuse_count.store(0, Ordering::SeqCst);
     }
 
-    //生成指定数量的虚拟机，不会检查是否达到虚拟机工厂限制容量上限，由外部调用者在需要时检查，返回生成前虚拟机池中虚拟机数量
-    pub fn produce(&self, count: usize) -> Result<usize, String> {
-        let factory_name = (&self.name).to_string();
-        if !VM_FACTORY_REGISTERS.read().unwrap().contains_key(&factory_name) {
-            //注册虚拟机工厂
-            VM_FACTORY_REGISTERS.write().unwrap().insert(factory_name, self.clone());
-        }
+    //获取并重置虚拟机工厂已开始执行的任务数量和累计等待时长，等待时长为任务构建到开始执行的时长
+    pub fn take_wait_stat(&self) -> (usize, Duration) {
+        let count = self.wait_count.swap(0, Ordering::SeqCst);
+        let time = self.wait_time.swap(0, Ordering::SeqCst);
+        (count, Duration::from_micros(time as u64))
+    }
 
-        if count == 0 {
-            return Ok(count);
+    //设置虚拟机工厂调用的默认执行超时时长，超时后中断虚拟机执行并丢弃虚拟机，需要启用interruptcheck特性构建，否则无法中断虚拟机执行并返回错误，
+    //必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_call_timeout(mut self, timeout: Duration) -> Result<Self, VMFactoryError> {
+        if !cfg!(feature = "interruptcheck") {
+            return Err(VMFactoryError::Unsupported(self.name(), "interruptcheck"));
         }
 
-        for _ in 0..count {
-            match self.new_vm(self.auth.clone()) {
-                None => {
-                    return Err(format!("vm factory, new vm failed, factory: {:?}",
-                                       (&self.name).to_string()))
-                },
-                Some(vm) => {
-                    let r = vm.free_global(); //预生成的虚拟机，将强制GC
-                    info!("===> Vm Factory Produce Ok, gc: {},  vm: {:?}", r, vm);
-                    self.pool.push(vm); //阻塞的推入虚拟机
-                }
-            }
+        self.call_timeout = Some(timeout);
+        Ok(self)
+    }
+
+    //获取虚拟机工厂调用的默认执行超时时长
+    pub fn call_timeout(&self) -> Option<Duration> {
+        self.call_timeout
+    }
+
+    //设置虚拟机工厂构建的虚拟机的调用栈深度限制，当前构建不支持则返回错误，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_stack_limit(mut self, limit: usize) -> Result<Self, VMFactoryError> {
+        if !cfg!(feature = "stacklimit") {
+            return Err(VMFactoryError::Unsupported(self.name(), "stacklimit"));
         }
 
-        return Ok(self.size());
+        self.stack_limit = Some(limit);
+        Ok(self)
     }
 
-    //生成指定数量的虚拟机，只在整理时使用，不会检查是否达到虚拟机工厂限制容量上限，由外部调用者在需要时检查，返回生成前虚拟机池中虚拟机数量
-    pub fn collect_produce(&self) -> Result<usize, String> {
-        match self.new_vm(self.auth.clone()) {
-            None => {
-                return Err(format!("vm factory, new vm failed, factory: {:?}",
-                                   (&self.name).to_string()))
-            },
-            Some(vm) => {
-                let r = vm.free_global(); //预生成的虚拟机，将强制GC
-                info!("===> Vm Factory Produce Ok by Collect, gc: {},  vm: {:?}", r, vm);
-                self.pool.push(vm); //阻塞的推入虚拟机
-            }
+    //获取虚拟机工厂构建的虚拟机的调用栈深度限制
+    pub fn stack_limit(&self) -> Option<usize> {
+        self.stack_limit
+    }
+
+    //设置虚拟机工厂构建的虚拟机的本地调用递归深度限制，使失控的递归抛出可以捕获的RangeError，而不是耗尽线程的本地栈，
+    //当前构建不支持则返回错误，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_native_limit(mut self, limit: usize) -> Result<Self, VMFactoryError> {
+        if !cfg!(feature = "nativelimit") {
+            return Err(VMFactoryError::Unsupported(self.name(), "nativelimit"));
         }
 
-        return Ok(self.size());
+        self.native_limit = Some(limit);
+        Ok(self)
     }
 
-    //复用指定虚拟机
-    pub fn reuse(&self, vm: Arc<JS>) {
-        if let Ok((src, port, args, info)) = self.queue_recv.try_recv() {
-            //当前虚拟机工厂的任务调度队列中有待运行的任务，则立即使用当前虚拟机，异步运行此任务
-            self.async_run(vm, src, port, args, info);
-        } else {
-            //当前虚拟机工厂的任务调度队列中没有待运行的任务，则将当前虚拟机还给当前虚拟机工厂
-            if let Err(_) = self.pool.try_push(vm.clone()) {
-                //虚拟机池已阻塞，则将空闲虚拟机加入虚拟机临时缓冲区
-                self.vm_buf_sent.send(vm);
-            }
+    //获取虚拟机工厂构建的虚拟机的本地调用递归深度限制
+    pub fn native_limit(&self) -> Option<usize> {
+        self.native_limit
+    }
+
+    //设置虚拟机工厂每次调用的燃料预算，燃料耗尽时中断虚拟机执行，并通过调用异常报告，调用消耗的燃料会记录在调用资源使用报告中，
+    //用于为不可信的脚本公平的计量资源，为0表示不限制，需要启用interruptcheck特性构建，当前构建不支持则返回错误，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_fuel(mut self, fuel: usize) -> Result<Self, VMFactoryError> {
+        if fuel > 0 && !cfg!(feature = "interruptcheck") {
+            return Err(VMFactoryError::Unsupported(self.name(), "interruptcheck"));
+        }
+
+        self.fuel = if fuel == 0 {
+            None
+        } else {
+            Some(fuel)
+        };
+        Ok(self)
+    }
+
+    //获取虚拟机工厂每次调用的燃料预算
+    pub fn fuel(&self) -> Option<usize> {
+        self.fuel
+    }
+
+    //设置虚拟机工厂空闲虚拟机的存活时长，空闲超过此时长的虚拟机会在全局整理时被丢弃，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_idle_ttl(mut self, ttl: Duration) -> Self {
+        self.idle_ttl = Some(ttl);
+        self
+    }
+
+    //设置虚拟机工厂的通道回应回调是否通过发起调用的源的同步任务队列按序投递，启用后回应回调先进入源的同步任务队列，
+    //再按序转发到虚拟机消息队列，与源的其它任务保持投递顺序，没有源的调用不受影响，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_response_affinity(mut self, enable: bool) -> Self {
+        self.response_affinity = enable;
+        self
+    }
+
+    //判断虚拟机工厂的通道回应回调是否通过发起调用的源的同步任务队列按序投递
+    pub fn is_response_affinity(&self) -> bool {
+        self.response_affinity
+    }
+
+    //设置虚拟机工厂的虚拟机重置全局环境后，才执行重置前推送的异步回调的处理方式，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_late_callback_policy(mut self, policy: LateCallbackPolicy) -> Self {
+        self.late_callback = policy;
+        self
+    }
+
+    //获取虚拟机工厂的虚拟机重置全局环境后，才执行重置前推送的异步回调的处理方式
+    pub fn late_callback_policy(&self) -> LateCallbackPolicy {
+        self.late_callback
+    }
+
+    //设置虚拟机工厂的虚拟机内存不足时的回调，回调可以记录日志或告警，并返回虚拟机的处理方式，未设置则继续使用虚拟机，
+    //必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_oom_hook(mut self, hook: Arc<Fn(&OomInfo) -> OomAction>) -> Self {
+        self.oom_hook = Some(hook);
+        self
+    }
+
+    //通知虚拟机工厂指定虚拟机内存不足，并按回调返回的处理方式处理虚拟机
+    pub fn notify_oom(&self, vm: &Arc<JS>, port: Option<String>, error: String) -> OomAction {
+        VM_OOM_COUNT.sum(1);
+        let info = OomInfo {
+            vm: vm.get_id(),
+            factory: (&self.name).to_string(),
+            port,
+            heap_size: vm.heap_size(),
+            peak_heap_size: vm.peak_heap_size(),
+            max_heap_size: self.max_heap_size,
+            error,
+        };
+        let action = match &self.oom_hook {
+            None => OomAction::Keep,
+            Some(hook) => hook(&info),
+        };
+        warn!("!!!> Vm Out Of Memory, vm: {:?}, info: {:?}, action: {:?}", vm, info, action);
+
+        match action {
+            OomAction::Keep => (),
+            OomAction::Recycle => {
+                vm.request_recycle("out of memory".to_string());
+            },
+            OomAction::Abort => {
+                //取消所有等待的回调，被取消的回调不会执行回调函数
+                for callback in vm.pending_callbacks() {
+                    vm.cancel_callback(callback.id);
+                }
+                vm.request_recycle("out of memory, aborted".to_string());
+            },
+        }
+        action
+    }
+
+    //获取虚拟机工厂空闲虚拟机的存活时长
+    pub fn idle_ttl(&self) -> Option<Duration> {
+        self.idle_ttl
+    }
+
+    //设置虚拟机工厂源固定的空闲虚拟机的最大数量，同一个源的调用完成后虚拟机会固定给源，源的下次调用优先使用源固定的虚拟机，
+    //虚拟机池没有空闲虚拟机时会驱逐最早固定的空闲虚拟机，只对可以复用的虚拟机有效，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_affinity(mut self, capacity: usize) -> Self {
+        self.affinity_capacity = capacity;
+        self
+    }
+
+    //设置虚拟机工厂的服务注册表，虚拟机工厂的虚拟机发出的通道请求的处理器可以通过通道获取服务，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_services(mut self, registry: ServiceRegistry) -> Self {
+        self.services = Some(registry);
+        self
+    }
+
+    //获取虚拟机工厂的服务注册表
+    pub fn services(&self) -> Option<ServiceRegistry> {
+        self.services.clone()
+    }
+
+    //设置虚拟机工厂构建的虚拟机是否审计线程访问，审计时记录持有虚拟机的线程，多个线程同时访问同一个虚拟机则断言失败，
+    //需要启用threadaudit特性构建，只影响之后构建的虚拟机，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_thread_audit(mut self, enabled: bool) -> Self {
+        self.thread_audit = enabled;
+        self
+    }
+
+    //判断虚拟机工厂构建的虚拟机是否审计线程访问
+    pub fn is_thread_audit(&self) -> bool {
+        cfg!(feature = "threadaudit") && self.thread_audit
+    }
+
+    //设置虚拟机工厂构建的虚拟机的堆配置，只影响之后构建的虚拟机，当前构建不支持则使用标准配置
+    pub fn set_profile(mut self, profile: VmProfile) -> Self {
+        if !profile.is_supported() {
+            warn!("!!!> Set Vm Profile Ignored, factory: {:?}, profile: {:?}, e: unsupported", (&self.name).to_string(), profile);
+            return self;
+        }
+
+        self.profile = profile;
+        self
+    }
+
+    //获取虚拟机工厂构建的虚拟机的堆配置
+    pub fn profile(&self) -> VmProfile {
+        self.profile
+    }
+
+    //设置虚拟机工厂是否使用堆快照构建虚拟机，启用后首个虚拟机加载字节码后生成堆快照，之后的虚拟机直接恢复堆快照，
+    //替换字节码后重新生成堆快照，当前构建不支持堆快照则返回错误
+    pub fn set_snapshot(mut self, enable: bool) -> Result<Self, VMFactoryError> {
+        if enable && !cfg!(feature = "snapshot") {
+            return Err(VMFactoryError::Unsupported(self.name(), "snapshot"));
+        }
+
+        self.snapshot = if enable { Some(Arc::new(RwLock::new(None))) } else { None };
+        Ok(self)
+    }
+
+    //判断虚拟机工厂是否使用堆快照构建虚拟机
+    pub fn is_snapshot(&self) -> bool {
+        self.snapshot.is_some()
+    }
+
+    //获取虚拟机工厂当前堆快照的大小，没有堆快照则返回0
+    pub fn snapshot_size(&self) -> usize {
+        match &self.snapshot {
+            None => 0,
+            Some(snapshot) => read_state("vm_factory_snapshot", snapshot).as_ref().map_or(0, |snapshot| snapshot.data.len()),
+        }
+    }
+
+    //清除虚拟机工厂当前堆快照，下次构建虚拟机时重新生成
+    pub fn clear_snapshot(&self) {
+        if let Some(snapshot) = &self.snapshot {
+            write_state("vm_factory_snapshot", snapshot).take();
+        }
+    }
+
+    //使用当前堆快照恢复指定虚拟机，没有与字节码匹配的堆快照或恢复失败则返回false
+    fn restore_snapshot(&self, vm: &Arc<JS>, codes: &Arc<Vec<Arc<Vec<u8>>>>) -> bool {
+        let data = match &self.snapshot {
+            None => return false,
+            Some(snapshot) => match read_state("vm_factory_snapshot", snapshot).as_ref() {
+                Some(snapshot) if snapshot.is_match(codes) => snapshot.data.clone(),
+                _ => return false,
+            },
+        };
+
+        if let Err(e) = vm.restore_heap_snapshot(data.as_slice()) {
+            VM_SNAPSHOT_FAILED_COUNT.sum(1);
+            warn!("!!!> Vm Restore Snapshot Failed, factory: {:?}, vm: {:?}, e: {}", (&self.name).to_string(), vm, e);
+            return false;
+        }
+        VM_SNAPSHOT_RESTORE_COUNT.sum(1);
+        true
+    }
+
+    //使用已加载指定字节码的虚拟机生成堆快照，已有与字节码匹配的堆快照则忽略
+    fn take_snapshot(&self, vm: &Arc<JS>, codes: &Arc<Vec<Arc<Vec<u8>>>>) {
+        let snapshot = match &self.snapshot {
+            None => return,
+            Some(snapshot) => snapshot,
+        };
+        if read_state("vm_factory_snapshot", snapshot).as_ref().map_or(false, |snapshot| snapshot.is_match(codes)) {
+            return;
+        }
+
+        match vm.heap_snapshot() {
+            Err(e) => {
+                VM_SNAPSHOT_FAILED_COUNT.sum(1);
+                warn!("!!!> Vm Take Snapshot Failed, factory: {:?}, vm: {:?}, e: {}", (&self.name).to_string(), vm, e);
+            },
+            Ok(data) => {
+                info!("===> Vm Take Snapshot Ok, factory: {:?}, vm: {:?}, size: {}", (&self.name).to_string(), vm, data.len());
+                *write_state("vm_factory_snapshot", snapshot) = Some(HeapSnapshot {
+                    codes: codes.clone(),
+                    data: Arc::new(data),
+                });
+            },
+        }
+    }
+
+    //设置虚拟机工厂加载字节码前的校验器，之后构建的虚拟机只加载校验通过的字节码，校验失败则构建虚拟机失败，替换字节码时同样校验
+    pub fn set_verifier(mut self, verifier: Arc<CodeVerifier>) -> Self {
+        self.verifier = Some(Arc::new(VerifiedCodes::new(verifier)));
+        self
+    }
+
+    //设置虚拟机工厂加载加密字节码前的解密器，密钥提供者按密钥id提供密钥，加密字节码可以通过append_encrypted或字节码源增加
+    pub fn set_decryptor(mut self, cipher: Arc<CodeCipher>, keys: Arc<Fn(&str) -> Option<Vec<u8>> + Send + Sync>) -> Self {
+        self.decryptor = Some(Arc::new(CodeDecryptor::new(cipher, keys)));
+        self
+    }
+
+    //设置虚拟机工厂编译源码的磁盘编译缓存，之后通过append_source和append_bundle增加的源码会优先使用缓存的字节码，必须在增加源码前设置
+    pub fn set_compile_cache(mut self, cache: CompileCache) -> Self {
+        self.compile_cache = Some(Arc::new(cache));
+        self
+    }
+
+    //获取虚拟机工厂编译源码的磁盘编译缓存
+    pub fn compile_cache(&self) -> Option<Arc<CompileCache>> {
+        self.compile_cache.clone()
+    }
+
+    //为虚拟机工厂声明指定端口的签名，返回替换签名后的虚拟机工厂，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_port_signature(mut self, port: PortName, signature: PortSignature) -> Self {
+        Arc::make_mut(&mut self.signatures).insert(port.into(), Arc::new(signature));
+        self
+    }
+
+    //设置虚拟机工厂的端口熔断器，端口调用异常的比例超过阈值时，在冷却时长内直接拒绝调用，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_circuit_breaker(mut self, config: BreakerConfig) -> Self {
+        self.breaker = Some(Arc::new(CircuitBreaker::new(config)));
+        self
+    }
+
+    //获取虚拟机工厂的端口熔断器
+    pub fn circuit_breaker(&self) -> Option<Arc<CircuitBreaker>> {
+        self.breaker.clone()
+    }
+
+    //为虚拟机工厂增加预热调用，新构建的虚拟机在进入虚拟机池前，会以指定的参数同步执行指定次数的端口调用，
+    //必须使用所有权，以保证运行时不会不安全的修改
+    pub fn add_warmup(mut self, port: PortName, args: Vec<CallValue>, times: usize) -> Self {
+        Arc::make_mut(&mut self.warmups).push(WarmupCall {
+            port,
+            args,
+            times: times.max(1),
+        });
+        self
+    }
+
+    //获取虚拟机工厂的预热调用列表
+    pub fn warmups(&self) -> Vec<WarmupCall> {
+        self.warmups.as_ref().clone()
+    }
+
+    //获取虚拟机工厂声明的指定端口的签名
+    pub fn port_signature(&self, port: &PortName) -> Option<Arc<PortSignature>> {
+        self.signatures.get(port.as_atom()).cloned()
+    }
+
+    //获取虚拟机工厂加载字节码前的校验器
+    pub fn verifier(&self) -> Option<Arc<CodeVerifier>> {
+        self.verifier.as_ref().map(|verifier| verifier.verifier())
+    }
+
+    //立即校验虚拟机工厂的所有字节码，包括基础虚拟机工厂的字节码，不需要构建虚拟机，返回第一个校验失败的字节码，未设置校验器则通过
+    pub fn verify_codes(&self) -> Result<(), LoadError> {
+        let verifier = match &self.verifier {
+            None => return Ok(()),
+            Some(verifier) => verifier,
+        };
+
+        let (codes, _) = self.current_codes();
+        for (index, code) in codes.iter().enumerate() {
+            if let Err(e) = verifier.verify(code) {
+                return Err(LoadError {
+                    factory: self.name(),
+                    index: Some(index),
+                    code_hash: Some(code_hash(code.as_slice())),
+                    reason: format!("code verify failed, {}", e),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    //获取虚拟机工厂源固定的空闲虚拟机的最大数量
+    pub fn affinity_capacity(&self) -> usize {
+        self.affinity_capacity
+    }
+
+    //获取虚拟机工厂当前源固定的空闲虚拟机数量
+    pub fn pinned_count(&self) -> usize {
+        lock_state("vm_factory_affinity", &self.affinity).parked.len()
+    }
+
+    //判断是否启用源亲和
+    fn is_affinity(&self) -> bool {
+        self.is_reused && self.affinity_capacity > 0
+    }
+
+    //取出指定源固定的空闲虚拟机
+    fn checkout_pinned(&self, src: usize) -> Option<Arc<JS>> {
+        if !self.is_affinity() {
+            return None;
+        }
+
+        let vm = lock_state("vm_factory_affinity", &self.affinity).remove(src)?;
+        if vm.is_destroyed() {
+            return None;
+        }
+        if self.is_stale(&vm) || self.expired(&vm).is_some() {
+            self.throw(1);
+            return None;
+        }
+
+        VM_AFFINITY_HIT_COUNT.sum(1);
+        Some(vm)
+    }
+
+    //驱逐最早固定的空闲虚拟机
+    fn evict_pinned(&self) -> Option<Arc<JS>> {
+        if !self.is_affinity() {
+            return None;
+        }
+
+        loop {
+            let vm = lock_state("vm_factory_affinity", &self.affinity).pop_oldest()?;
+            if vm.is_destroyed() {
+                continue;
+            }
+            if self.is_stale(&vm) || self.expired(&vm).is_some() {
+                self.throw(1);
+                continue;
+            }
+
+            VM_AFFINITY_EVICT_COUNT.sum(1);
+            return Some(vm);
+        }
+    }
+
+    //将完成调用的虚拟机固定给虚拟机的亲和源，没有亲和源则返回虚拟机，固定的空闲虚拟机过多则返回被驱逐的虚拟机
+    fn park(&self, vm: Arc<JS>) -> Option<Arc<JS>> {
+        let src = match vm.take_affinity_src() {
+            None => return Some(vm),
+            Some(src) => src,
+        };
+
+        let mut table = lock_state("vm_factory_affinity", &self.affinity);
+        if let Some(old) = table.remove(src) {
+            //源已固定了其它空闲虚拟机，则替换
+            table.parked.insert(src, vm);
+            table.order.push_back(src);
+            return Some(old);
+        }
+
+        let evicted = if table.parked.len() >= self.affinity_capacity {
+            VM_AFFINITY_EVICT_COUNT.sum(1);
+            table.pop_oldest()
+        } else {
+            None
+        };
+        table.parked.insert(src, vm);
+        table.order.push_back(src);
+        evicted
+    }
+
+    //设置虚拟机工厂构建虚拟机后的回调，在虚拟机加载完所有字节码后调用，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_create_hook(mut self, hook: Arc<Fn(&Arc<JS>)>) -> Self {
+        self.create_hook = Some(hook);
+        self
+    }
+
+    //设置虚拟机工厂构建的虚拟机销毁时的回调，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn set_destroy_hook(mut self, hook: Arc<Fn(Atom, usize)>) -> Self {
+        self.destroy_hook = Some(hook);
+        self
+    }
+
+    //增加虚拟机工厂因状态损坏而无法复用的虚拟机数量
+    pub fn add_poison_count(&self) {
+        self.poison_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //获取虚拟机工厂因状态损坏而无法复用的虚拟机数量
+    pub fn poison_count(&self) -> usize {
+        self.poison_count.load(Ordering::Relaxed)
+    }
+
+    //记录虚拟机工厂指定端口开始执行一次调用
+    fn add_call_count(&self, port: &Atom) {
+        self.call_count.fetch_add(1, Ordering::Relaxed);
+        *lock_state("vm_factory_port_calls", &self.port_calls).entry(port.clone()).or_insert(0) += 1;
+    }
+
+    //获取虚拟机工厂已开始执行的调用总数
+    pub fn call_count(&self) -> usize {
+        self.call_count.load(Ordering::Relaxed)
+    }
+
+    //获取虚拟机工厂构建虚拟机失败次数
+    pub fn new_vm_failed(&self) -> usize {
+        self.new_vm_failed.load(Ordering::Relaxed)
+    }
+
+    //获取虚拟机工厂最近一次构建虚拟机失败的原因，包括失败的字节码序号和虚拟机异常信息
+    pub fn last_load_error(&self) -> Option<LoadError> {
+        lock_state("vm_factory_load_error", &self.last_load_error).clone()
+    }
+
+    //获取虚拟机工厂每个端口已开始执行的调用数量
+    pub fn port_calls(&self) -> HashMap<String, usize> {
+        lock_state("vm_factory_port_calls", &self.port_calls).iter().map(|(port, count)| ((port).to_string(), *count)).collect()
+    }
+
+    //获取虚拟机工厂已空闲的时长，有未完成的调用或等待调度的调用则返回None
+    pub fn idle_time(&self) -> Option<Duration> {
+        if self.in_flight() > 0 || self.queue_len() > 0 {
+            return None;
+        }
+
+        Some(Duration::from_micros(now_utc().saturating_sub(self.last_call.load(Ordering::Relaxed)) as u64))
+    }
+
+    //取出一个空闲虚拟机执行垃圾回收，回收后归还到虚拟机临时缓冲区，以保证下次优先取出未回收的空闲虚拟机，
+    //没有空闲虚拟机或取出的空闲虚拟机在上次回收后未运行过则返回false
+    pub fn gc_idle_vm(&self, compact: bool) -> bool {
+        let vm = match self.checkout() {
+            None => return false,
+            Some(vm) => vm,
+        };
+
+        let collected = vm.need_gc() && vm.gc(compact).unwrap_or(false);
+        if collected {
+            info!("===> Vm Factory Idle Gc Ok, factory: {:?}, vm: {:?}", (&self.name).to_string(), vm);
+        }
+        self.vm_buf_sent.send(vm);
+        self.wake_waiter();
+        collected
+    }
+
+    //判断虚拟机工厂是否已停止接收调用
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    //获取虚拟机工厂已接收但未完成的调用数量
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    //完成一个已接收的调用，在调用完成或未被接收时调用，排空时最后一个调用完成后唤醒所有完成句柄
+    pub fn complete_call(&self) {
+        let mut curr = self.in_flight.load(Ordering::SeqCst);
+        while curr > 0 {
+            match self.in_flight.compare_and_swap(curr, curr - 1, Ordering::SeqCst) {
+                old if old == curr => break,
+                old => curr = old,
+            }
+        }
+
+        if curr <= 1 && self.is_closed() {
+            self.wake_drain_waiters();
+        }
+    }
+
+    //停止接收新的调用，返回在所有已接收的调用完成后完成的句柄，不会销毁虚拟机
+    pub fn drain(&self) -> FactoryDrain {
+        self.close();
+        FactoryDrain {
+            factory: self.clone(),
+            teardown: false,
+        }
+    }
+
+    //停止接收新的调用，返回在所有已接收的调用完成后，销毁所有空闲虚拟机、移除虚拟机工厂使用过的同步任务队列和虚拟机工厂注册后完成的句柄
+    pub fn shutdown(&self) -> FactoryDrain {
+        self.close();
+        FactoryDrain {
+            factory: self.clone(),
+            teardown: true,
+        }
+    }
+
+    //停止接收新的调用
+    fn close(&self) {
+        if !self.closed.compare_and_swap(false, true, Ordering::SeqCst) {
+            info!("===> Vm Factory Closed, factory: {:?}, in flight: {}", (&self.name).to_string(), self.in_flight());
+        }
+    }
+
+    //判断虚拟机工厂是否已排空，排空要求已停止接收调用、已接收的调用都已完成，且没有被守护者取出的虚拟机
+    fn is_drained(&self) -> bool {
+        self.is_closed() && self.in_flight() == 0 && self.queue_recv.is_empty() && self.checked_out() == 0
+    }
+
+    //注册等待虚拟机工厂排空的完成句柄
+    fn add_drain_waiter(&self, waker: Waker) {
+        lock_state("vm_factory_drain_waiters", &self.drain_waiters).push(waker);
+    }
+
+    //唤醒所有等待虚拟机工厂排空的完成句柄
+    fn wake_drain_waiters(&self) {
+        let wakers: Vec<Waker> = lock_state("vm_factory_drain_waiters", &self.drain_waiters).drain(..).collect();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    //销毁虚拟机工厂的所有空闲虚拟机，移除使用过的同步任务队列，并注销虚拟机工厂
+    fn teardown(&self) -> FactoryShutdown {
+        let mut destroyed = 0;
+        while let Some(vm) = self.checkout() {
+            match vm.destroy() {
+                Err(e) => {
+                    warn!("!!!> Vm Factory Shutdown, destroy vm failed, factory: {:?}, vm: {:?}, reason: {}",
+                          (&self.name).to_string(), vm, e);
+                },
+                Ok(_) => destroyed += 1,
+            }
+        }
+
+        let sources: Vec<usize> = lock_state("vm_factory_sources", &self.sources).drain().collect();
+        let mut removed_queues = 0;
+        for src in sources {
+            if remove_queue(src).is_some() {
+                removed_queues += 1;
+            }
+        }
+        for queue in self.task_queues.iter() {
+            if remove_js_task_queue(*queue) {
+                removed_queues += 1;
+            }
+        }
+
+        remove_factory(self);
+        info!("===> Vm Factory Shutdown Ok, factory: {:?}, destroyed: {}, removed queues: {}",
+              (&self.name).to_string(), destroyed, removed_queues);
+        FactoryShutdown {
+            factory: self.name(),
+            destroyed,
+            removed_queues,
+        }
+    }
+
+    //记录一次调用从提交到完成的延迟
+    pub fn record_latency(&self, latency: Duration) {
+        self.latency.record(latency);
+        self.latency_window.record(latency);
+    }
+
+    //记录一次调用内由检查点划分的阶段时长
+    pub fn record_phases(&self, report: &CallReport) {
+        if report.phases.is_empty() {
+            return;
+        }
+
+        let mut phases = lock_state("vm_factory_phases", &self.phases);
+        for (phase, time) in &report.phases {
+            let stats = phases.entry((report.port.clone(), phase.clone())).or_insert_with(|| PhaseStats {
+                port: (&report.port).to_string(),
+                phase: (phase).to_string(),
+                calls: 0,
+                total_time: Duration::from_micros(0),
+                max_time: Duration::from_micros(0),
+            });
+            stats.calls += 1;
+            stats.total_time += *time;
+            if *time > stats.max_time {
+                stats.max_time = *time;
+            }
+        }
+    }
+
+    //记录一次调用的结果，用于端口熔断器统计端口调用异常的比例
+    pub fn record_outcome(&self, report: &CallReport) {
+        if let Some(breaker) = &self.breaker {
+            breaker.record(&report.port, report.errors == 0);
+        }
+    }
+
+    //获取虚拟机工厂每个端口的调用内阶段统计，按端口和阶段名排序
+    pub fn phases(&self) -> Vec<PhaseStats> {
+        let mut phases: Vec<PhaseStats> = lock_state("vm_factory_phases", &self.phases).values().cloned().collect();
+        phases.sort_by(|x, y| (&x.port, &x.phase).cmp(&(&y.port, &y.phase)));
+        phases
+    }
+
+    //获取并重置上次取出后的调用延迟分布
+    pub fn take_latency_window(&self) -> LatencySnapshot {
+        let snapshot = self.latency_window.snapshot();
+        self.latency_window.reset();
+        snapshot
+    }
+
+    //获取虚拟机工厂调用延迟分布的快照
+    pub fn latency(&self) -> LatencySnapshot {
+        self.latency.snapshot()
+    }
+
+    //重置虚拟机工厂调用延迟分布
+    pub fn reset_latency(&self) {
+        self.latency.reset();
+    }
+
+    //获取虚拟机工厂统计
+    pub fn stats(&self) -> FactoryStats {
+        FactoryStats {
+            name: self.name(),
+            size: self.size(),
+            limit_capacity: self.limit_capacity(),
+            free_size: self.free_pool_size() + self.free_buf_size(),
+            checked_out: self.checked_out(),
+            queue_len: self.queue_len(),
+            refuse_count: self.refuse_count(),
+            total_heap: self.total_heap(),
+            cpu_used: self.cpu_used(),
+            latency: self.latency(),
+            total_calls: self.call_count(),
+            in_flight: self.in_flight(),
+            new_vm_failed: self.new_vm_failed(),
+            port_calls: self.port_calls(),
+            phases: self.phases(),
+        }
+    }
+
+    //丢弃指定数量的空闲虚拟机，返回实际丢弃的虚拟机数量
+    pub fn shrink(&self, count: usize) -> usize {
+        let mut shrinked = 0;
+        while shrinked < count {
+            let vm = match self.pool.try_pop() {
+                Ok(vm) => vm,
+                _ => match self.vm_buf_recv.try_recv() {
+                    Ok(vm) => vm,
+                    _ => break, //没有空闲虚拟机
+                },
+            };
+
+            if vm.is_destroyed() {
+                //已显式销毁的虚拟机，已在销毁时减少虚拟机数量
+                continue;
+            }
+
+            self.throw(1);
+            info!("===> Vm Factory Shrink Ok, vm: {:?}", vm);
+            shrinked += 1;
+        }
+
+        shrinked
+    }
+
+    //生成指定数量的虚拟机，不会检查是否达到虚拟机工厂限制容量上限，由外部调用者在需要时检查，返回生成前虚拟机池中虚拟机数量
+    pub fn produce(&self, count: usize) -> Result<usize, String> {
+        if self.is_closed() {
+            return Err(format!("vm factory shutdown, factory: {:?}", (&self.name).to_string()));
+        }
+
+        if !self.validated.load(Ordering::SeqCst) {
+            //首次生成前校验代码包清单，不匹配则快速失败，且不注册虚拟机工厂
+            if let Err(report) = self.validate() {
+                warn!("!!!> {}", report);
+                return Err(report.to_string());
+            }
+            self.validated.store(true, Ordering::SeqCst);
+
+            //注册虚拟机工厂，同名的其它虚拟机工厂已注册则忽略
+            if let Err(e) = register_factory(self) {
+                warn!("!!!> {}", e);
+            }
+        }
+
+        if count == 0 {
+            return Ok(count);
+        }
+
+        for _ in 0..count {
+            match self.new_vm(self.auth.clone()) {
+                Err(e) => {
+                    return Err(e.to_string())
+                },
+                Ok(vm) => {
+                    let r = vm.free_global(); //预生成的虚拟机，将强制GC
+                    info!("===> Vm Factory Produce Ok, gc: {},  vm: {:?}", r, vm);
+                    self.pool.push(vm); //阻塞的推入虚拟机
+                }
+            }
+        }
+
+        return Ok(self.size());
+    }
+
+    //校验虚拟机工厂代码包清单中依赖的本地异步处理器版本，会构建一个临时虚拟机用于读取清单
+    pub fn validate(&self) -> Result<(), ManifestReport> {
+        match self.new_vm(self.auth.clone()) {
+            Err(e) => {
+                Err(ManifestReport {
+                    factory: self.name.clone(),
+                    mismatches: vec![VersionMismatch::Invalid(e.to_string())],
+                })
+            },
+            Ok(vm) => {
+                let report = check_manifest(&self.name, &vm, is_async_request_registered);
+                //校验用的虚拟机不会进入虚拟机池，则销毁虚拟机，并减少虚拟机工厂的虚拟机数量，可复用的虚拟机在销毁时已减少
+                match vm.destroy() {
+                    Ok(_) if self.is_reused => (),
+                    _ => {
+                        self.throw(1);
+                    },
+                }
+                report
+            },
+        }
+    }
+
+    //生成指定数量的虚拟机，只在整理时使用，不会检查是否达到虚拟机工厂限制容量上限，由外部调用者在需要时检查，返回生成前虚拟机池中虚拟机数量
+    pub fn collect_produce(&self) -> Result<usize, String> {
+        match self.new_vm(self.auth.clone()) {
+            Err(e) => {
+                return Err(e.to_string())
+            },
+            Ok(vm) => {
+                let r = vm.free_global(); //预生成的虚拟机，将强制GC
+                info!("===> Vm Factory Produce Ok by Collect, gc: {},  vm: {:?}", r, vm);
+                self.pool.push(vm); //阻塞的推入虚拟机
+            }
+        }
+
+        return Ok(self.size());
+    }
+
+    //复用指定虚拟机
+    pub fn reuse(&self, vm: Arc<JS>) {
+        if vm.is_destroyed() {
+            //已显式销毁的虚拟机，则忽略
+            self.wake_waiter();
+            return;
+        }
+
+        if self.is_budget_exhausted() {
+            //当前虚拟机工厂的聚合资源预算已耗尽，则暂不执行任务调度队列中的任务，并将当前虚拟机还给当前虚拟机工厂
+            if let Err(_) = self.pool.try_push(vm.clone()) {
+                self.vm_buf_sent.send(vm);
+            }
+            self.wake_waiter();
+            if !self.queue_recv.is_empty() {
+                //有等待预算恢复的任务，则在预算可能恢复时执行
+                self.schedule_budget_drain();
+            }
+            return;
+        }
+
+        if let Ok((src, port, args, info)) = self.queue_recv.try_recv() {
+            //当前虚拟机工厂的任务调度队列中有待运行的任务，则立即使用当前虚拟机，异步运行此任务
+            self.async_run(vm, src, port, args, info);
+        } else {
+            //当前虚拟机工厂的任务调度队列中没有待运行的任务，则将当前虚拟机固定给亲和源或还给当前虚拟机工厂
+            let vm = if self.is_affinity() { self.park(vm) } else { Some(vm) };
+            let vm = match vm {
+                None => {
+                    self.wake_waiter();
+                    return;
+                },
+                Some(vm) => vm,
+            };
+            if let Err(_) = self.pool.try_push(vm.clone()) {
+                //虚拟机池已阻塞，则将空闲虚拟机加入虚拟机临时缓冲区
+                self.vm_buf_sent.send(vm);
+            }
+            self.wake_waiter(); //唤醒一个等待空闲虚拟机的异步获取者
+        }
+    }
+
+    //异步获取一个空闲虚拟机，没有空闲虚拟机则等待其它虚拟机被归还，获取的虚拟机在守护者被释放时自动归还
+    pub fn acquire(&self) -> Acquire {
+        Acquire {
+            factory: self.clone(),
+            waiter: None,
+        }
+    }
+
+    //异步获取一个空闲虚拟机，在指定时长内没有获取到空闲虚拟机则返回None
+    pub fn acquire_timeout(&self, timeout: Duration) -> AcquireTimeout {
+        AcquireTimeout {
+            factory: self.clone(),
+            waiter: None,
+            deadline: Instant::now() + timeout,
+            is_timing: false,
+        }
+    }
+
+    //尝试立即获取一个空闲虚拟机，没有空闲虚拟机则返回None
+    pub fn try_acquire(&self) -> Option<PooledVm> {
+        self.checkout().map(|vm| PooledVm::new(self.clone(), vm))
+    }
+
+    //丢弃被守护者取出的虚拟机
+    fn discard(&self, vm: Arc<JS>) {
+        self.check_in();
+        self.add_poison_count();
+        self.throw(1);
+        info!("===> Vm Factory Discard Ok, factory: {:?}, vm: {:?}", (&self.name).to_string(), vm);
+    }
+
+    //从虚拟机池或虚拟机临时缓冲区中取出一个空闲虚拟机
+    fn checkout(&self) -> Option<Arc<JS>> {
+        //跳过已显式销毁的虚拟机，并丢弃使用旧字节码或达到回收策略限制的虚拟机
+        while let Ok(vm) = self.pool.try_pop() {
+            if vm.is_destroyed() {
+                continue;
+            }
+            if self.is_reused && (self.is_stale(&vm) || self.expired(&vm).is_some()) {
+                self.throw(1);
+                continue;
+            }
+            return Some(vm);
+        }
+
+        while let Ok(vm) = self.vm_buf_recv.try_recv() {
+            if vm.is_destroyed() {
+                continue;
+            }
+            if self.is_reused && (self.is_stale(&vm) || self.expired(&vm).is_some()) {
+                self.throw(1);
+                continue;
+            }
+            return Some(vm);
+        }
+
+        //虚拟机池和虚拟机临时缓冲区都没有空闲虚拟机，则驱逐源固定的空闲虚拟机
+        self.evict_pinned()
+    }
+
+    //减少被守护者取出的虚拟机数量，已停止接收调用则唤醒所有等待排空的完成句柄
+    fn check_in(&self) {
+        self.checked_out.fetch_sub(1, Ordering::SeqCst);
+        if self.is_closed() {
+            self.wake_drain_waiters();
+        }
+    }
+
+    //注册或替换等待空闲虚拟机的异步获取者的唤醒器，获取者首次等待时分配获取者id
+    fn add_waiter(&self, id: &mut Option<usize>, waker: Waker) {
+        let mut waiters = lock_state("vm_factory_waiters", &self.waiters);
+        let waiter = match *id {
+            Some(waiter) => waiter,
+            None => {
+                let waiter = waiters.alloc_id();
+                *id = Some(waiter);
+                waiter
+            },
+        };
+        waiters.register(waiter, waker);
+    }
+
+    //移除等待空闲虚拟机的异步获取者，获取者已被唤醒但没有获取虚拟机，则将唤醒转交给下一个获取者，保证唤醒不会丢失
+    fn remove_waiter(&self, id: Option<usize>) {
+        if let Some(waiter) = id {
+            if !lock_state("vm_factory_waiters", &self.waiters).remove(waiter) {
+                self.wake_waiter();
+            }
+        }
+    }
+
+    //异步获取者已获取虚拟机，则不再等待，并返回虚拟机守护者
+    fn acquired(&self, id: &mut Option<usize>, vm: Arc<JS>) -> PooledVm {
+        if let Some(waiter) = id.take() {
+            lock_state("vm_factory_waiters", &self.waiters).remove(waiter);
+        }
+        PooledVm::new(self.clone(), vm)
+    }
+
+    //唤醒最早开始等待空闲虚拟机的异步获取者
+    fn wake_waiter(&self) {
+        let waker = lock_state("vm_factory_waiters", &self.waiters).pop_oldest();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    //归还被守护者取出的虚拟机，可以复用的虚拟机会重置全局环境后复用，否则丢弃
+    fn give_back(&self, vm: Arc<JS>) {
+        if self.is_stale(&vm) {
+            //虚拟机使用旧字节码，则不再复用
+            self.check_in();
+            self.throw(1);
+            info!("===> Vm Factory Throw Stale Vm Ok, factory: {:?}, vm: {:?}", (&self.name).to_string(), vm);
+            return;
+        }
+
+        if let Some(reason) = vm.recycle_requested() {
+            //虚拟机请求回收，则不再复用
+            self.check_in();
+            self.throw(1);
+            info!("===> Vm Factory Recycle Ok, factory: {:?}, vm: {:?}, reason: {:?}", (&self.name).to_string(), vm, reason);
+            return;
+        }
+
+        if self.is_reused && vm.clear_global() && vm.alloc_global() {
+            self.check_in();
+            self.reuse(vm);
+        } else {
+            warn!("!!!> Vm Factory Give Back Failed, vm discarded, factory: {:?}, vm: {:?}",
+                  (&self.name).to_string(), vm);
+            self.discard(vm);
         }
     }
 
@@ -324,64 +2653,624 @@ impl VMFactory {
         self.size.fetch_sub(count, Ordering::SeqCst)
     }
 
-    //重置指定数量的虚拟机，返回生成前虚拟机池中虚拟机数量
-    pub fn reset(&self, count: usize) -> Result<usize, String> {
-        self.size.fetch_sub(count, Ordering::SeqCst);
-        self.produce(count)
+    //重置指定数量的虚拟机，返回生成前虚拟机池中虚拟机数量
+    pub fn reset(&self, count: usize) -> Result<usize, String> {
+        self.size.fetch_sub(count, Ordering::SeqCst);
+        self.produce(count)
+    }
+
+    //获取虚拟机工厂被守护者取出的虚拟机数量
+    pub fn checked_out(&self) -> usize {
+        self.checked_out.load(Ordering::Relaxed)
+    }
+
+    //生成并取出一个无法复用的虚拟机，但未加载字节码，取出的虚拟机不属于虚拟机池，需要从虚拟机池取出虚拟机，只允许通过虚拟机守护者
+    pub fn take(&self) -> Option<Arc<JS>> {
+        let vm = JS::with_profile(self.alloc_id.fetch_add(1, Ordering::Relaxed), self.name.clone(), self.auth.clone(), None, self.profile);
+        if let Some(ref vm) = vm {
+            load_prelude(vm, &self.code_version); //虚拟机内置js代码是可信的，必须在设置动态代码执行策略前执行
+            vm.set_eval_policy(self.eval_policy.clone());
+            vm.set_factory(Arc::new(self.clone()));
+            vm.set_code_generation(self.code_generation());
+            self.init_vm(vm);
+            self.notify_created(vm);
+        }
+        vm
+    }
+
+    //获取虚拟机工厂字节码加载器
+    pub fn loader(&self) -> VMFactoryLoader {
+        let (codes, _) = self.current_codes();
+        VMFactoryLoader {
+            offset: 0,
+            top: codes.len(),
+            codes,
+            verifier: self.verifier.clone(),
+            decryptor: self.decryptor.clone(),
+        }
+    }
+
+    //从虚拟机池中获取一个虚拟机，根据源创建同步任务队列，并调用指定的js全局函数，任务被排队也会返回成功，失败时任务不会被执行
+    pub fn call(&self, src: Option<usize>, port: PortName, args: Box<FnOnce(Arc<JS>) -> usize>, info: TaskInfo) -> Result<(), VMFactoryError> {
+        let args: ArgsFn = match self.admit(src, &[&port])? {
+            None => args,
+            Some(permit) => Box::new(move |vm: Arc<JS>| {
+                vm.hold_permit(permit); //在调用开始执行时由虚拟机持有许可，调用完成时释放
+                args(vm)
+            }),
+        };
+
+        let r = match self.call_timeout {
+            None => self.dispatch(src, port, args, info),
+            Some(timeout) => self.call_with_timeout(src, port, args, info, timeout),
+        };
+        if r.is_err() {
+            //调用未被接收
+            self.complete_call();
+        }
+        r
+    }
+
+    //兼容旧版本稳定接口的调用，端口名和任务信息使用Atom，端口名无效或调用未被接收时只记录警告，任务不会被执行
+    #[deprecated(note = "use VMFactory::call with PortName and TaskInfo, which reports rejected calls")]
+    pub fn call_atom(&self, src: Option<usize>, port: Atom, args: Box<FnOnce(Arc<JS>) -> usize>, info: Atom) {
+        let port = match PortName::new(port.as_str()) {
+            Err(e) => {
+                warn!("!!!> Vm Factory Call Failed, factory: {:?}, e: {}", (&self.name).to_string(), e);
+                return;
+            },
+            Ok(port) => port,
+        };
+
+        if let Err(e) = self.call(src, port, args, TaskInfo::from(info)) {
+            warn!("!!!> Vm Factory Call Failed, factory: {:?}, e: {}", (&self.name).to_string(), e);
+        }
+    }
+
+    //检查是否接收指定源对指定端口的调用，接收则增加未完成的调用数量，并返回调用源的配额许可，不接收则返回拒绝的原因，
+    //所有调用入口都必须先通过接收检查
+    fn admit(&self, src: Option<usize>, ports: &[&PortName]) -> Result<Option<QuotaPermit>, VMFactoryError> {
+        //先增加未完成的调用数量，再检查是否已停止接收调用，保证排空时不会遗漏已接收的调用
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.last_call.store(now_utc(), Ordering::Relaxed);
+        if self.is_closed() {
+            self.complete_call();
+            return Err(VMFactoryError::Shutdown(self.name()));
+        }
+        if let Some(breaker) = &self.breaker {
+            for port in ports {
+                let target = self.resolve_port(port);
+                if let Err(remaining) = breaker.allow(target.as_atom()) {
+                    //调用端口的熔断器已打开
+                    self.complete_call();
+                    warn!("!!!> Vm Factory Call Rejected, circuit open, factory: {:?}, port: {:?}, remaining: {:?}",
+                          (&self.name).to_string(), (&target).to_string(), remaining);
+                    return Err(VMFactoryError::CircuitOpen(self.name(), (&target).to_string()));
+                }
+            }
+        }
+        let permit = match acquire_quota(src) {
+            Err(e) => {
+                //调用源超过配额
+                self.complete_call();
+                warn!("!!!> Vm Factory Call Throttled, factory: {:?}, ports: {:?}, e: {}",
+                      (&self.name).to_string(), ports.iter().map(|port| port.to_string()).collect::<Vec<String>>(), e);
+                return Err(VMFactoryError::Throttled(self.name(), e));
+            },
+            Ok(permit) => permit,
+        };
+        if let Some(src_id) = src {
+            lock_state("vm_factory_sources", &self.sources).insert(src_id);
+        }
+
+        Ok(permit)
+    }
+
+    //从虚拟机池中获取一个虚拟机，并调用指定的js全局函数，调用开始执行后超过指定时长未完成，则中断虚拟机执行，并通过调用异常报告超时
+    pub fn call_with_timeout(&self, src: Option<usize>, port: PortName, args: Box<FnOnce(Arc<JS>) -> usize>, info: TaskInfo, timeout: Duration) -> Result<(), VMFactoryError> {
+        let args = Box::new(move |vm: Arc<JS>| {
+            watch_call(&vm, timeout); //在调用开始执行时启动看门狗
+            args(vm)
+        });
+        self.dispatch(src, port, args, info)
+    }
+
+    //从虚拟机池中获取一个虚拟机，并调用指定的js全局函数，返回可以取消调用的调用句柄
+    pub fn call_cancelable(&self, src: Option<usize>, port: PortName, args: Box<FnOnce(Arc<JS>) -> usize>, info: TaskInfo) -> Result<CallHandle, VMFactoryError> {
+        let handle = CallHandle::new();
+        let handle_copy = handle.clone();
+        let target = (&self.resolve_port(&port)).to_string();
+        let args = Box::new(move |vm: Arc<JS>| {
+            handle_copy.start(vm, &target, args)
+        });
+        self.call(src, port, args, info)?;
+        Ok(handle)
+    }
+
+    //调度指定调用
+    fn dispatch(&self, src: Option<usize>, port: PortName, args: Box<FnOnce(Arc<JS>) -> usize>, info: TaskInfo) -> Result<(), VMFactoryError> {
+        let info = info.submit(); //记录调用的提交时间，用于统计调用从提交到完成的延迟
+        let port = self.resolve_port(&port); //通过端口路由表解析实际调用的js函数路径
+
+        if self.is_budget_exhausted() {
+            //当前虚拟机工厂的聚合资源预算已耗尽，则根据预算耗尽时的处理方式，排队或拒绝任务，只有被拒绝的任务才记录拒绝的次数
+            self.scheduling_count.fetch_add(1, Ordering::Relaxed);
+            return match self.limits.on_exhausted {
+                BudgetExhausted::Queue => {
+                    if let Err(e) = self.enqueue(src, port, args, info) {
+                        self.refuse_count.fetch_add(1, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                    self.schedule_budget_drain(); //在预算可能恢复时执行排队的任务
+                    Ok(())
+                },
+                BudgetExhausted::Reject => {
+                    self.refuse_count.fetch_add(1, Ordering::Relaxed);
+                    warn!("!!!> Vm Factory Call Rejected, budget exhausted, factory: {:?}, port: {:?}, total heap: {}, cpu used: {:?}",
+                          (&self.name).to_string(), (&port).to_string(), self.total_heap(), self.cpu_used());
+                    Err(VMFactoryError::BudgetExhausted(self.name()))
+                },
+            };
+        }
+
+        //弹出虚拟机，以保证同一时间只有一个线程访问同一个虚拟机，优先使用源固定的空闲虚拟机
+        match src.and_then(|src_id| self.checkout_pinned(src_id)).or_else(|| self.checkout()) {
+            Some(vm) => {
+                //虚拟机池或虚拟机临时缓冲区有空闲虚拟机，则运行
+                self.async_run(vm, src, port, args, info);
+            },
+            None => {
+                //当前虚拟机池和虚拟机临时缓冲区都没有空闲虚拟机
+                if is_alloced_limit() || self.is_vm_limit() {
+                    //当前进程内存已达到最大堆限制或已达到最大虚拟机数量，则拒绝任务立即执行，并将任务加入当前虚拟机的任务调度队列中，记录当前拒绝的次数
+                    self.refuse_count.fetch_add(1, Ordering::Relaxed);
+                    if let Err(e) = self.enqueue(src, port, args, info) {
+                        self.scheduling_count.fetch_add(1, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                } else if self.is_checkout_retry() {
+                    //设置了重试，则不阻塞当前线程，将任务加入任务调度队列等待被归还的虚拟机执行，并在重试窗口结束后检查
+                    if let Err(e) = self.enqueue(src, port, args, info) {
+                        self.scheduling_count.fetch_add(1, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                    self.schedule_checkout_retry();
+                } else {
+                    //当前进程内存未达到最大堆限制，则立即构建新的虚拟机
+                    match self.new_vm(self.auth.clone()) {
+                        Err(e) => {
+                            self.scheduling_count.fetch_add(1, Ordering::Relaxed); //增加虚拟机工厂调用次数
+                            warn!("!!!> Vm Factory Call Error, new vm failed, factory: {:?}, port: {:?}",
+                                  (&self.name).to_string(), (&port).to_string());
+                            return Err(e.into());
+                        },
+                        Ok(vm) => {
+                            //构建完成，则运行
+                            self.async_run(vm, src, port, args, info);
+                        },
+                    }
+                }
+            }
+        }
+
+        self.scheduling_count.fetch_add(1, Ordering::Relaxed); //增加虚拟机工厂调度次数
+        Ok(())
+    }
+
+    //将任务加入虚拟机工厂等待调度的任务队列
+    fn enqueue(&self, src: Option<usize>, port: PortName, args: Box<FnOnce(Arc<JS>) -> usize>, info: TaskInfo) -> Result<(), VMFactoryError> {
+        let mut task = (src, port, args, info);
+        loop {
+            //限制了最大长度的任务队列是有界队列，任务队列已满则根据任务队列已满时的处理方式处理
+            task = match self.pending.policy {
+                PendingPolicy::Block(timeout) => {
+                    match self.queue_sent.send_timeout(task, timeout) {
+                        Ok(_) => return Ok(()),
+                        Err(SendTimeoutError::Timeout(task)) => return self.reject_pending(&task.1),
+                        Err(SendTimeoutError::Disconnected(_)) => return self.queue_closed(),
+                    }
+                },
+                _ => {
+                    match self.queue_sent.try_send(task) {
+                        Ok(_) => return Ok(()),
+                        Err(TrySendError::Full(task)) => task,
+                        Err(TrySendError::Disconnected(_)) => return self.queue_closed(),
+                    }
+                },
+            };
+
+            if self.pending.policy != PendingPolicy::DropOldest {
+                return self.reject_pending(&task.1);
+            }
+
+            if let Ok((_, dropped, _, dropped_info)) = self.queue_recv.try_recv() {
+                //丢弃最早等待调度的任务，丢弃的任务不会再执行，任务的完成回调会收到虚拟机工厂忙的异常，并完成虚拟机工厂已接收的调用
+                VM_PENDING_DROP_COUNT.sum(1);
+                warn!("!!!> Vm Factory Pending Task Dropped, factory: {:?}, port: {:?}, task: {}",
+                      (&self.name).to_string(), (&dropped).to_string(), dropped_info);
+                self.complete_call();
+            }
+        }
+    }
+
+    //等待调度的任务队列已关闭
+    fn queue_closed(&self) -> Result<(), VMFactoryError> {
+        warn!("!!!> Vm Factory Enqueue Error, task queue closed, factory: {:?}", (&self.name).to_string());
+        Err(VMFactoryError::QueueClosed(self.name()))
+    }
+
+    //拒绝无法加入等待调度的任务队列的任务
+    fn reject_pending(&self, port: &PortName) -> Result<(), VMFactoryError> {
+        VM_PENDING_REJECT_COUNT.sum(1);
+        warn!("!!!> Vm Factory Call Rejected, pending queue full, factory: {:?}, port: {:?}, queue len: {}",
+              (&self.name).to_string(), port.to_string(), self.queue_len());
+        Err(VMFactoryError::Busy(self.name()))
+    }
+
+    //设置预算恢复后执行排队任务的定时器，执行时长预算耗尽则在当前统计窗口结束时执行，总堆大小预算耗尽则定时重试
+    fn schedule_budget_drain(&self) {
+        if self.budget_timer.compare_and_swap(false, true, Ordering::SeqCst) {
+            //已设置定时器，则忽略
+            return;
+        }
+
+        let timeout = match self.limits.cpu_per_minute {
+            Some(budget) if self.cpu_used() >= budget => {
+                let elapsed = now_utc().saturating_sub(self.cpu_window.load(Ordering::Relaxed));
+                (CPU_BUDGET_WINDOW.saturating_sub(elapsed) / 1000).max(1) as u32
+            },
+            _ => HEAP_BUDGET_RETRY_INTERVAL,
+        };
+
+        let factory = self.clone();
+        let runner = FuncRuner::new(Box::new(move || {
+            factory.budget_timer.store(false, Ordering::SeqCst);
+            factory.drain_budget_queue();
+        }));
+        TIMER.set_timeout(runner, timeout);
+    }
+
+    //预算恢复后，使用空闲或新构建的虚拟机执行等待预算恢复的任务，预算仍然耗尽则重新设置定时器
+    fn drain_budget_queue(&self) {
+        while !self.queue_recv.is_empty() {
+            if self.is_budget_exhausted() {
+                self.schedule_budget_drain();
+                return;
+            }
+
+            if !self.run_pending() {
+                return;
+            }
+        }
+    }
+
+    //使用空闲或新构建的虚拟机执行最早等待调度的任务，没有空闲虚拟机且不允许构建新的虚拟机，则由正在执行的虚拟机在归还时执行，返回是否执行了任务
+    fn run_pending(&self) -> bool {
+        let vm = match self.checkout() {
+            Some(vm) => vm,
+            None if is_alloced_limit() || self.is_vm_limit() => return false,
+            None => match self.new_vm(self.auth.clone()) {
+                Err(e) => {
+                    warn!("!!!> Vm Factory Run Pending Task Error, new vm failed, factory: {:?}, e: {}", (&self.name).to_string(), e);
+                    return false;
+                },
+                Ok(vm) => vm,
+            },
+        };
+
+        match self.queue_recv.try_recv() {
+            Err(_) => {
+                //等待的任务已被其它线程执行，则归还虚拟机
+                self.reuse(vm);
+                false
+            },
+            Ok((src, port, args, info)) => {
+                self.async_run(vm, src, port, args, info);
+                true
+            },
+        }
+    }
+
+    //如果当前执行时长统计窗口已结束，则开始新的统计窗口
+    fn roll_cpu_window(&self) {
+        let now = now_utc();
+        let start = self.cpu_window.load(Ordering::Relaxed);
+        if now.saturating_sub(start) >= CPU_BUDGET_WINDOW
+            && self.cpu_window.compare_and_swap(start, now, Ordering::SeqCst) == start {
+            //当前线程成功开始新的统计窗口，则重置累计执行时长
+            self.cpu_used.store(0, Ordering::Relaxed);
+        }
+    }
+
+    //从虚拟机池中获取一个虚拟机，根据源创建同步任务队列，并调用指定的js全局函数，调用完成后回调执行结果
+    pub fn call_then(&self, src: Option<usize>, port: PortName, args: Box<FnOnce(Arc<JS>) -> usize>, info: TaskInfo, finish: Box<FnOnce(Result<Option<String>, String>)>) {
+        let finish = Arc::new(Mutex::new(FinishGuard::new(self.name(), finish)));
+        let finish_copy = finish.clone();
+        let args = Box::new(move |vm: Arc<JS>| {
+            if let Some(finish) = lock_state("vm_factory_call", &finish_copy).take() {
+                vm.set_finish(finish);
+            }
+            args(vm)
+        });
+
+        if let Err(e) = self.call(src, port, args, info) {
+            //调用失败，任务不会被执行，则立即回调失败原因
+            if let Some(finish) = lock_state("vm_factory_call", &finish).take() {
+                finish(Err(e.to_string()));
+            }
+        }
+    }
+
+    //从虚拟机池中获取一个虚拟机，根据源创建同步任务队列，并调用指定的js全局函数，调用完成后回调js函数的返回值
+    pub fn call_with_result(&self, src: Option<usize>, port: PortName, args: Box<FnOnce(Arc<JS>) -> usize>, info: TaskInfo, callback: Box<FnOnce(Result<CallValue, String>)>) {
+        let callback = Arc::new(Mutex::new(FinishGuard::new(self.name(), callback)));
+        let callback_copy = callback.clone();
+        let args = Box::new(move |vm: Arc<JS>| {
+            if let Some(callback) = lock_state("vm_factory_call", &callback_copy).take() {
+                vm.set_finish_value(callback);
+            }
+            args(vm)
+        });
+
+        if let Err(e) = self.call(src, port, args, info) {
+            //调用失败，任务不会被执行，则立即回调失败原因
+            if let Some(callback) = lock_state("vm_factory_call", &callback).take() {
+                callback(Err(e.to_string()));
+            }
+        }
+    }
+
+    //从虚拟机池中获取一个虚拟机，按端口签名校验参数并构建参数后调用指定的js全局函数，调用完成后校验返回值并回调，
+    //端口未声明签名或参数不匹配签名则返回错误，调用不会被执行
+    pub fn call_typed(&self, src: Option<usize>, port: PortName, args: Vec<CallValue>, info: TaskInfo, callback: Box<FnOnce(Result<CallValue, PortCallError>)>) -> Result<(), SignatureError> {
+        let signature = match self.port_signature(&port) {
+            None => return Err(SignatureError::UnknownPort((&port).to_string())),
+            Some(signature) => signature,
+        };
+        let args = signature.build_args(port.as_str(), args).map_err(|e| {
+            warn!("!!!> Vm Factory Call Typed Error, factory: {:?}, e: {}", (&self.name).to_string(), e);
+            e
+        })?;
+
+        let port_name = (&port).to_string();
+        self.call_with_result(src, port, args, info, Box::new(move |result| {
+            callback(match result {
+                Err(e) => Err(PortCallError::Call(e)),
+                Ok(value) => match signature.check_result(&port_name, &value) {
+                    Err(e) => {
+                        warn!("!!!> Vm Factory Call Typed Error, e: {}", e);
+                        Err(PortCallError::Signature(e))
+                    },
+                    Ok(_) => Ok(value),
+                },
+            })
+        }));
+        Ok(())
+    }
+
+    //从虚拟机池中获取一个虚拟机，在同一个任务中依次调用多个js全局函数，所有调用完成后归还虚拟机，并回调每个调用的返回值，
+    //单个调用异常不会影响后续调用，与单个调用使用相同的接收检查，但批量调用无法加入等待调度的任务队列，需要排队时直接拒绝
+    pub fn call_batch(&self, calls: Vec<(PortName, ArgsFn)>, info: TaskInfo, finish: Box<FnOnce(Vec<Result<CallValue, String>>)>) -> Result<(), VMFactoryError> {
+        let permit = {
+            let ports: Vec<&PortName> = calls.iter().map(|(port, _)| port).collect();
+            self.admit(None, &ports)?
+        };
+
+        if self.is_budget_exhausted() {
+            //当前虚拟机工厂的聚合资源预算已耗尽
+            self.refuse_count.fetch_add(1, Ordering::Relaxed);
+            self.complete_call();
+            warn!("!!!> Vm Factory Call Batch Rejected, budget exhausted, factory: {:?}, total heap: {}, cpu used: {:?}",
+                  (&self.name).to_string(), self.total_heap(), self.cpu_used());
+            return Err(VMFactoryError::BudgetExhausted(self.name()));
+        }
+
+        let vm = match self.checkout() {
+            Some(vm) => vm,
+            None if is_alloced_limit() || self.is_vm_limit() => {
+                //当前进程内存已达到最大堆限制或已达到最大虚拟机数量
+                self.refuse_count.fetch_add(1, Ordering::Relaxed);
+                self.complete_call();
+                VM_PENDING_REJECT_COUNT.sum(1);
+                warn!("!!!> Vm Factory Call Batch Rejected, no free vm, factory: {:?}, size: {}", (&self.name).to_string(), self.size());
+                return Err(VMFactoryError::Busy(self.name()));
+            },
+            None => {
+                match self.new_vm(self.auth.clone()) {
+                    Err(e) => {
+                        self.complete_call();
+                        warn!("!!!> Vm Factory Call Batch Error, new vm failed, factory: {:?}", (&self.name).to_string());
+                        return Err(e.into());
+                    },
+                    Ok(vm) => vm,
+                }
+            },
+        };
+        //可以复用的虚拟机在批量调用完成后由守护者归还，无法复用的虚拟机在批量调用完成后丢弃
+        let guard = if self.is_reused { Some(PooledVm::new(self.clone(), vm.clone())) } else { None };
+
+        let borrow = vm.borrow_thread("checkout"); //投递任务前由调度线程持有虚拟机
+        let factory = self.clone();
+        let count = calls.len();
+        let submitted_at = info.submitted_at();
+        let func = Box::new(move |lock: Option<isize>| {
+            let _permit = permit; //批量调用执行期间持有源配额许可
+            let borrow = vm.borrow_thread("task");
+            let mut results = Vec::with_capacity(calls.len());
+            for (port, args) in calls {
+                let port = factory.resolve_port(&port);
+                factory.prepare_call(&vm, &port, submitted_at, lock); //每个批量调用都与普通调用一样准备执行环境
+                vm.get_link_function((&port).to_string());
+                let args_size = args(vm.clone());
+                let ret = vm.invoke(args_size);
+                if ret.is_none() {
+                    results.push(Err(format!("vm factory call batch error, invoke failed, port: {:?}, e: {}",
+                                             (&port).to_string(), vm.stack_top_string().unwrap_or("invoke error".to_string()))));
+                } else {
+                    results.push(Ok(CallValue::from(&ret)));
+                }
+                vm.report_call(); //结束本次调用的统计，并提交资源使用报告
+            }
+            drop(borrow); //必须在归还或丢弃虚拟机前释放，虚拟机可能立即被其它线程获取
+
+            match guard {
+                Some(guard) => drop(guard),
+                None => {
+                    factory.throw(1);
+                },
+            }
+            if let Some(queue) = lock {
+                //解锁批量调用锁住的虚拟机工厂独占的同步任务队列
+                if !unlock_js_task_queue(queue) {
+                    warn!("!!!> Vm Factory Call Batch Error, unlock task queue failed, queue: {:?}", queue);
+                }
+            }
+            finish(results);
+            factory.complete_call();
+        });
+        drop(borrow); //必须在投递任务前释放，任务可能立即在其它线程开始执行
+        let priority = if info.priority() == 0 { self.task_priority() } else { info.priority() };
+        if self.task_queues.is_empty() {
+            cast_js_task(TaskType::Async(false), priority, None, func, info.name());
+        } else {
+            //轮询投递到虚拟机工厂独占的同步任务队列
+            let index = self.queue_cursor.fetch_add(1, Ordering::Relaxed) % self.task_queues.len();
+            cast_js_task(TaskType::Sync(true), 0, Some(self.task_queues[index]), func, info.name());
+        }
+
+        self.scheduling_count.fetch_add(1, Ordering::Relaxed);
+        VM_CALL_COUNT.sum(count);
+        Ok(())
+    }
+
+    //从虚拟机池中获取一个虚拟机，调用指定的js全局函数，并阻塞当前线程直到调用及其等待的异步回调都完成，返回js函数的返回值，
+    //调用开始执行后超过指定时长未完成，则中断虚拟机执行，不允许在js工作者线程中调用，否则可能死锁
+    pub fn call_sync(&self, port: PortName, args: Box<FnOnce(Arc<JS>) -> usize>, timeout: Duration) -> Result<CallValue, CallError> {
+        let (sender, receiver) = bounded(1);
+        let mut finish = FinishGuard::new(self.name(), Box::new(move |result| {
+            let _ = sender.send(result);
+        }));
+        let args = Box::new(move |vm: Arc<JS>| {
+            if let Some(finish) = finish.take() {
+                vm.set_finish_value(finish);
+            }
+            watch_call(&vm, timeout); //在调用开始执行时启动看门狗
+            args(vm)
+        });
+        self.call(None, port, args, TaskInfo::from(&*FACTORY_SYNC_CALL_TASK)).map_err(CallError::Call)?;
+
+        match receiver.recv_timeout(timeout) {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(e)) => Err(CallError::Failed(e)),
+            Err(RecvTimeoutError::Timeout) => Err(CallError::Timeout(timeout)),
+            Err(RecvTimeoutError::Disconnected) => Err(CallError::Disconnected),
+        }
+    }
+
+    //从虚拟机池中获取一个虚拟机，根据源创建同步任务队列，并调用指定的js全局函数，返回接收js函数返回值的通道
+    pub fn call_result(&self, src: Option<usize>, port: PortName, args: Box<FnOnce(Arc<JS>) -> usize>, info: TaskInfo) -> Receiver<Result<CallValue, String>> {
+        let (sender, receiver) = bounded(1);
+        self.call_with_result(src, port, args, info, Box::new(move |result| {
+            let _ = sender.send(result);
+        }));
+        receiver
+    }
+
+    //初始化虚拟机工厂构建的虚拟机的标签，并注册虚拟机
+    fn init_vm(&self, vm: &Arc<JS>) {
+        for (key, value) in self.labels.iter() {
+            vm.set_label(key, value);
+        }
+        if let Some(limit) = self.stack_limit {
+            if let Err(e) = vm.set_stack_limit(limit) {
+                warn!("!!!> Vm Set Stack Limit Failed, factory: {:?}, vm: {:?}, e: {}", (&self.name).to_string(), vm, e);
+            }
+        }
+        if let Some(limit) = self.native_limit {
+            if let Err(e) = vm.set_native_limit(limit) {
+                warn!("!!!> Vm Set Native Limit Failed, factory: {:?}, vm: {:?}, e: {}", (&self.name).to_string(), vm, e);
+            }
+        }
+        vm.set_thread_audit(self.thread_audit);
+        if let Some(hook) = self.destroy_hook.clone() {
+            vm.on_destroy(Box::new(move |name, id| hook(name, id)));
+        }
+        register_vm(vm);
+    }
+
+    //在新构建的虚拟机上同步执行预热调用，预热调用失败不影响虚拟机构建，返回成功执行的预热调用次数
+    fn warmup_vm(&self, vm: &Arc<JS>) -> usize {
+        let start = VM_WARMUP_TIME.start();
+        let mut count = 0;
+        for warmup in self.warmups.iter() {
+            let port = self.resolve_port(&warmup.port);
+            for _ in 0..warmup.times {
+                //与普通调用相同，先链接端口函数，再压入参数并调用
+                if !vm.get_link_function((&port).to_string()) {
+                    VM_WARMUP_ERROR_COUNT.sum(1);
+                    warn!("!!!> Vm Warmup Error, link function failed, factory: {:?}, vm: {:?}, port: {:?}",
+                          (&self.name).to_string(), vm, (&port).to_string());
+                    break;
+                }
+                for arg in warmup.args.iter().cloned() {
+                    push_value(vm, arg);
+                }
+                if vm.invoke(warmup.args.len()).is_none() {
+                    VM_WARMUP_ERROR_COUNT.sum(1);
+                    warn!("!!!> Vm Warmup Error, factory: {:?}, vm: {:?}, port: {:?}, e: {}",
+                          (&self.name).to_string(), vm, (&port).to_string(), vm.stack_top_string().unwrap_or("invoke error".to_string()));
+                    break;
+                }
+                count += 1;
+            }
+        }
+        VM_WARMUP_TIME.timing(start);
+        count
+    }
+
+    //通知虚拟机工厂已构建虚拟机
+    fn notify_created(&self, vm: &Arc<JS>) {
+        if let Some(hook) = &self.create_hook {
+            hook(vm);
+        }
     }
 
-    //生成并取出一个无法复用的虚拟机，但未加载字节码
-    pub fn take(&self) -> Option<Arc<JS>> {
-        JS::new(self.alloc_id.fetch_add(1, Ordering::Relaxed), self.name.clone(), self.auth.clone(), None)
+    //判断没有空闲虚拟机时，是否在构建新虚拟机前重试获取空闲虚拟机
+    fn is_checkout_retry(&self) -> bool {
+        self.checkout_retries > 0 && self.checkout_backoff.as_micros() > 0
     }
 
-    //获取虚拟机工厂字节码加载器
-    pub fn loader(&self) -> VMFactoryLoader {
-        VMFactoryLoader {
-            offset: 0,
-            top: self.codes.len(),
-            codes: self.codes.clone(),
+    //设置重试获取空闲虚拟机的定时器，重试窗口为所有重试的最大退避时长之和内的随机时长，以避免竞争的调用者同时构建新虚拟机
+    fn schedule_checkout_retry(&self) {
+        let backoff = self.checkout_backoff.as_micros() as u64;
+        let mut max: u64 = 0;
+        for retry in 0..self.checkout_retries {
+            max = max.saturating_add(backoff.saturating_mul(1 << retry.min(16)));
         }
+        let wait = thread_rng().gen_range(0, max + 1);
+        let timeout = (wait.saturating_add(999) / 1000).max(1).min(u32::max_value() as u64) as u32; //定时器的精度为ms
+
+        let factory = self.clone();
+        let runner = FuncRuner::new(Box::new(move || {
+            factory.retry_checkout();
+        }));
+        TIMER.set_timeout(runner, timeout);
     }
 
-    //从虚拟机池中获取一个虚拟机，根据源创建同步任务队列，并调用指定的js全局函数
-    pub fn call(&self, src: Option<usize>, port: Atom, args: Box<FnOnce(Arc<JS>) -> usize>, info: Atom) {
-        //弹出虚拟机，以保证同一时间只有一个线程访问同一个虚拟机
-        match self.pool.try_pop() {
-            Ok(vm) => {
-                //有空闲虚拟机，则运行
-                self.async_run(vm, src, port, args, info);
-            },
-            _ => {
-                //当前虚拟机池没有空闲虚拟机，或当前虚拟机池已阻塞
-                if let Ok(vm) = self.vm_buf_recv.try_recv() {
-                    //虚拟机临时缓冲区，有空闲虚拟机，则运行
-                    self.async_run(vm, src, port, args, info);
-                } else {
-                    //虚拟机临时缓冲区，没有空闲虚拟机
-                    if is_alloced_limit() {
-                        //当前进程内存已达到最大堆限制，则拒绝任务立即执行，并将任务加入当前虚拟机的任务调度队列中，记录当前拒绝的次数
-                        self.queue_sent.send((src, port, args, info));
-                        self.refuse_count.fetch_add(1, Ordering::Relaxed);
-                    } else {
-                        //当前进程内存未达到最大堆限制，则立即构建新的虚拟机
-                        match self.new_vm(self.auth.clone()) {
-                            None => {
-                                self.scheduling_count.fetch_add(1, Ordering::Relaxed); //增加虚拟机工厂调用次数
-                                panic!("Vm Factory Call Error, new vm failed, factory: {:?}",
-                                       (&self.name).to_string());
-                            },
-                            Some(vm) => {
-                                //构建完成，则运行
-                                self.async_run(vm, src, port, args, info);
-                            },
-                        }
-                    }
-                }
-            }
+    //重试窗口结束，等待的任务已被归还的虚拟机执行则重试成功，否则使用空闲或新构建的虚拟机执行最早等待调度的任务
+    fn retry_checkout(&self) {
+        if self.queue_recv.is_empty() {
+            VM_CHECKOUT_RETRY_HIT_COUNT.sum(1);
+            return;
         }
 
-        self.scheduling_count.fetch_add(1, Ordering::Relaxed); //增加虚拟机工厂调度次数
+        VM_CHECKOUT_RETRY_MISS_COUNT.sum(1);
+        if self.is_budget_exhausted() {
+            //聚合资源预算已耗尽，则在预算恢复后执行
+            self.schedule_budget_drain();
+            return;
+        }
+        self.run_pending();
     }
 
     //整理虚拟机工厂的虚拟机池
@@ -395,7 +3284,34 @@ impl VMFactory {
     }
 
     //构建一个虚拟机，加载所有字节码，并提供虚拟机本地对象授权，不会检查是否达到虚拟机工厂限制容量上限
-    fn new_vm(&self, auth: Arc<NativeObjsAuth>) -> Option<Arc<JS>> {
+    fn new_vm(&self, auth: Arc<NativeObjsAuth>) -> Result<Arc<JS>, LoadError> {
+        let (codes, generation) = self.current_codes();
+        self.build_vm(auth, &codes, generation)
+    }
+
+    //使用指定字节码构建虚拟机，失败则记录并返回失败原因
+    fn build_vm(&self, auth: Arc<NativeObjsAuth>, codes: &Arc<Vec<Arc<Vec<u8>>>>, generation: usize) -> Result<Arc<JS>, LoadError> {
+        let result = self.try_build_vm(auth, codes, generation);
+        if let Err(e) = &result {
+            self.new_vm_failed.fetch_add(1, Ordering::Relaxed);
+            warn!("!!!> Vm Factory Create Vm Error, {}", e);
+            *lock_state("vm_factory_load_error", &self.last_load_error) = Some(e.clone());
+        }
+        result
+    }
+
+    //构建虚拟机失败的原因
+    fn load_error(&self, reason: &str) -> LoadError {
+        LoadError {
+            factory: self.name(),
+            index: None,
+            code_hash: None,
+            reason: reason.to_string(),
+        }
+    }
+
+    //使用指定字节码构建虚拟机
+    fn try_build_vm(&self, auth: Arc<NativeObjsAuth>, codes: &Arc<Vec<Arc<Vec<u8>>>>, generation: usize) -> Result<Arc<JS>, LoadError> {
         let start = VM_NEW_TIME.start();
 
         let mut curr_size = self.size();
@@ -412,49 +3328,60 @@ impl VMFactory {
                 }
             }
         }
+        let mut slot = SizeSlot::new(&self.size); //构建失败时释放已占用的虚拟机数量
 
         let result = if !self.is_reused {
             //构建一个无法复用的虚拟机
-            JS::new(self.alloc_id.fetch_add(1, Ordering::Relaxed), self.name.clone(), auth.clone(), None)
+            JS::with_profile(self.alloc_id.fetch_add(1, Ordering::Relaxed), self.name.clone(), auth.clone(), None, self.profile)
         } else {
             //构建一个可以复用的虚拟机
-            JS::new(self.alloc_id.fetch_add(1, Ordering::Relaxed), self.name.clone(), auth.clone(), Some((Arc::new(AtomicBool::new(false)), Arc::new(self.clone()))))
+            JS::with_profile(self.alloc_id.fetch_add(1, Ordering::Relaxed), self.name.clone(), auth.clone(), Some((Arc::new(AtomicBool::new(false)), Arc::new(self.clone()))), self.profile)
         };
 
         match result {
-            None => None,
+            None => Err(self.load_error("create vm failed")),
             Some(vm) => {
                 VM_NEW_TIME.timing(start);
                 let start = VM_LOAD_TIME.start();
 
-                //为当前虚拟机加载当前虚拟机工厂绑定的所有字节码
-                for code in self.codes.iter() {
-                    if vm.load(code.as_slice()) {
-                        while !vm.is_ran() {
-                            pause();
-                        }
-                        continue;
+                if !load_prelude(&vm, &self.code_version) {
+                    //虚拟机内置js代码是可信的，必须在设置动态代码执行策略前执行
+                    return Err(self.load_error("load prelude failed"));
+                }
+                vm.set_eval_policy(self.eval_policy.clone()); //必须在加载字节码前设置动态代码执行策略
+                vm.set_factory(Arc::new(self.clone()));
+                self.init_vm(&vm);
+
+                //有与字节码匹配的堆快照则直接恢复，否则为当前虚拟机加载当前虚拟机工厂绑定的所有字节码，并生成堆快照
+                if !self.restore_snapshot(&vm, codes) {
+                    for (index, code) in codes.iter().enumerate() {
+                        load_code(&vm, index, code, self.verifier.as_ref(), self.decryptor.as_ref())?;
                     }
-                    return None;
+                    self.take_snapshot(&vm, codes);
                 }
 
                 //如果是可以复用的虚拟机，则需要创建全局对象模板，并替换当前全局对象
                 if self.is_reused {
                     if !vm.new_global_template() {
-                        warn!("!!!> Vm Factory Create Vm Error, new global template failed, factory: {:?}",
-                                 (&self.name).to_string());
-                        return None;
+                        return Err(self.load_error("new global template failed"));
                     }
 
                     if !vm.alloc_global() {
-                        warn!("!!!> Vm Factory Create Vm Error, alloc global failed, factory: {:?}",
-                                 (&self.name).to_string());
-                        return None;
+                        return Err(self.load_error("alloc global failed"));
                     }
 
                     vm.unlock_collection(); //解锁回收器，必须在虚拟机初始化、加载代码、运行代码等操作后解锁
                 }
 
+                if !self.warmups.is_empty() {
+                    self.warmup_vm(&vm);
+                    if self.is_reused && (!vm.clear_global() || !vm.alloc_global()) {
+                        //预热后重置全局环境失败
+                        return Err(self.load_error("reset global after warmup failed"));
+                    }
+                }
+
+                vm.set_code_generation(generation);
                 vm.update_last_heap_size(); //更新初始化后虚拟机的堆大小和内存占用
 
                 info!("===> Vm Factory Create Vm Ok, factory: {:?}, vm: {:?}",
@@ -462,30 +3389,73 @@ impl VMFactory {
 
                 VM_LOAD_TIME.timing(start);
                 VM_COUNT.sum(1);
+                slot.commit();
+                self.notify_created(&vm);
 
-                Some(vm)
+                Ok(vm)
             }
         }
     }
 
+    //为指定虚拟机准备一次端口调用，在任务线程中执行，调用完成后应该结束本次调用的统计
+    fn prepare_call(&self, vm: &Arc<JS>, port: &PortName, submitted_at: usize, lock: Option<isize>) {
+        self.add_call_count(port.as_atom());
+        if let Some(queue) = lock {
+            //为虚拟机设置当前任务的队列，将会重置可复用虚拟机的当前任务队列
+            vm.set_tasks(queue);
+        }
+        vm.start_call(port.as_atom().clone(), submitted_at); //开始统计本次调用的资源使用
+        if let Some(fuel) = self.fuel() {
+            vm.set_fuel(fuel); //为本次调用设置燃料预算
+        }
+    }
+
     //异步运行指定虚拟机
-    fn async_run(&self, vm: Arc<JS>, src: Option<usize>, port: Atom, args: Box<FnOnce(Arc<JS>) -> usize>, info: Atom) {
+    fn async_run(&self, vm: Arc<JS>, src: Option<usize>, port: PortName, args: Box<FnOnce(Arc<JS>) -> usize>, mut info: TaskInfo) {
+        if info.priority() == 0 {
+            //未指定本次调用的任务优先级，则使用虚拟机工厂的任务优先级
+            info = info.with_priority(self.task_priority());
+        }
+        if let (None, Some(src_id)) = (info.source(), src) {
+            info = info.with_source(src_id);
+        }
+
+        let borrow = vm.borrow_thread("checkout"); //投递任务前由调度线程持有虚拟机
+        if self.is_affinity() {
+            vm.set_affinity_src(src); //记录当前调用的亲和源，调用完成后将虚拟机固定给亲和源
+        }
+
         let vm_copy = vm.clone();
+        let task = vm.enqueue_task(&info);
+        let task_id = task.id();
+        let created_at = info.created_at();
+        let submitted_at = info.submitted_at();
+        let wait_count = self.wait_count.clone();
+        let wait_time = self.wait_time.clone();
+        let factory = self.clone();
         let func = Box::new(move |lock: Option<isize>| {
-            if let Some(queue) = lock {
-                //为虚拟机设置当前任务的队列，将会重置可复用虚拟机的当前任务队列
-                vm_copy.set_tasks(queue);
-            }
+            let _task = task; //任务执行结束或被丢弃时释放任务守护者
+            let _borrow = vm_copy.borrow_thread("task");
+            wait_count.fetch_add(1, Ordering::Relaxed);
+            wait_time.fetch_add(now_utc().saturating_sub(created_at), Ordering::Relaxed);
+            vm_copy.start_task(task_id);
+            factory.prepare_call(&vm_copy, &port, submitted_at, lock);
             vm_copy.get_link_function((&port).to_string());
             let args_size = args(vm_copy.clone());
             vm_copy.call(args_size);
         });
+        drop(borrow); //必须在投递任务前释放，任务可能立即在其它线程开始执行
         match src {
+            None if !self.task_queues.is_empty() => {
+                //轮询投递到虚拟机工厂独占的同步任务队列
+                let index = self.queue_cursor.fetch_add(1, Ordering::Relaxed) % self.task_queues.len();
+                cast_js_task(TaskType::Sync(true), 0, Some(self.task_queues[index]), func, info.name());
+            },
             None => {
-                cast_js_task(TaskType::Async(false), JS_TASK_PRIORITY, None, func, info);
+                cast_js_task(TaskType::Async(false), info.priority(), None, func, info.name());
             },
             Some(src_id) => {
-                cast_js_task(TaskType::Sync(true), 0, Some(new_queue(src_id)), func, info);
+                cast_js_task(TaskType::Sync(true), 0, Some(new_queue_with_priority(src_id, info.priority())), func, info.name());
             },
         }
 
@@ -493,6 +3463,216 @@ impl VMFactory {
     }
 }
 
+/*
+* 虚拟机守护者，在被释放时自动将虚拟机归还给虚拟机工厂
+*/
+pub struct PooledVm {
+    factory:    VMFactory,      //虚拟机所属的虚拟机工厂
+    vm:         Option<Arc<JS>>,//被守护的虚拟机
+}
+
+impl Deref for PooledVm {
+    type Target = Arc<JS>;
+
+    fn deref(&self) -> &Arc<JS> {
+        self.vm.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledVm {
+    fn drop(&mut self) {
+        if let Some(vm) = self.vm.take() {
+            if thread::panicking() {
+                //持有守护者的线程已异常，虚拟机状态未知，则丢弃虚拟机
+                warn!("!!!> Pooled Vm Dropped By Panic, vm discarded, vm: {:?}", vm);
+                self.factory.discard(vm);
+            } else {
+                self.factory.give_back(vm);
+            }
+        }
+    }
+}
+
+impl PooledVm {
+    //构建一个虚拟机守护者
+    fn new(factory: VMFactory, vm: Arc<JS>) -> Self {
+        factory.checked_out.fetch_add(1, Ordering::SeqCst);
+        PooledVm {
+            factory,
+            vm: Some(vm),
+        }
+    }
+
+    //获取虚拟机所属的虚拟机工厂
+    pub fn factory(&self) -> &VMFactory {
+        &self.factory
+    }
+
+    //丢弃被守护的虚拟机，不再归还给虚拟机工厂，用于虚拟机状态已被破坏的情况
+    pub fn discard(mut self) {
+        if let Some(vm) = self.vm.take() {
+            self.factory.discard(vm);
+        }
+    }
+}
+
+/*
+* 异步获取空闲虚拟机
+*/
+pub struct Acquire {
+    factory:    VMFactory,      //虚拟机工厂
+    waiter:     Option<usize>,  //获取者id，未开始等待则为空
+}
+
+impl Future for Acquire {
+    type Output = PooledVm;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if let Some(vm) = this.factory.checkout() {
+            return Poll::Ready(this.factory.acquired(&mut this.waiter, vm));
+        }
+
+        this.factory.add_waiter(&mut this.waiter, cx.waker().clone());
+        if let Some(vm) = this.factory.checkout() {
+            //注册后再次检查，防止在注册前已归还的虚拟机无法唤醒当前获取者
+            return Poll::Ready(this.factory.acquired(&mut this.waiter, vm));
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire {
+    fn drop(&mut self) {
+        self.factory.remove_waiter(self.waiter.take());
+    }
+}
+
+/*
+* 有超时的异步获取空闲虚拟机
+*/
+pub struct AcquireTimeout {
+    factory:    VMFactory,      //虚拟机工厂
+    waiter:     Option<usize>,  //获取者id，未开始等待则为空
+    deadline:   Instant,        //超时时间
+    is_timing:  bool,           //是否已注册超时定时器
+}
+
+impl Future for AcquireTimeout {
+    type Output = Option<PooledVm>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if let Some(vm) = this.factory.checkout() {
+            return Poll::Ready(Some(this.factory.acquired(&mut this.waiter, vm)));
+        }
+
+        let now = Instant::now();
+        if now >= this.deadline {
+            //已超时，则不再等待
+            this.factory.remove_waiter(this.waiter.take());
+            return Poll::Ready(None);
+        }
+
+        this.factory.add_waiter(&mut this.waiter, cx.waker().clone());
+        if !this.is_timing {
+            //注册超时定时器，超时后唤醒当前获取者
+            let waker = cx.waker().clone();
+            let timeout = (this.deadline - now).as_millis() as u32 + 1;
+            TIMER.set_timeout(FuncRuner::new(Box::new(move || waker.wake())), timeout);
+            this.is_timing = true;
+        }
+
+        if let Some(vm) = this.factory.checkout() {
+            //注册后再次检查，防止在注册前已归还的虚拟机无法唤醒当前获取者
+            return Poll::Ready(Some(this.factory.acquired(&mut this.waiter, vm)));
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for AcquireTimeout {
+    fn drop(&mut self) {
+        self.factory.remove_waiter(self.waiter.take());
+    }
+}
+
+/*
+* 虚拟机工厂排空结果
+*/
+#[derive(Debug, Clone)]
+pub struct FactoryShutdown {
+    pub factory:        String, //虚拟机工厂名
+    pub destroyed:      usize,  //销毁的空闲虚拟机数量
+    pub removed_queues: usize,  //移除的同步任务队列数量
+}
+
+/*
+* 虚拟机工厂排空的完成句柄，在虚拟机工厂已接收的调用都完成后完成
+*/
+pub struct FactoryDrain {
+    factory:    VMFactory,  //虚拟机工厂
+    teardown:   bool,       //排空后是否销毁虚拟机工厂的资源
+}
+
+impl Future for FactoryDrain {
+    type Output = FactoryShutdown;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if !self.factory.is_drained() {
+            self.factory.add_drain_waiter(cx.waker().clone());
+            if !self.factory.is_drained() {
+                //注册后再次检查，防止在注册前已完成的调用无法唤醒当前句柄
+                return Poll::Pending;
+            }
+        }
+
+        if self.teardown {
+            Poll::Ready(self.factory.teardown())
+        } else {
+            Poll::Ready(FactoryShutdown {
+                factory: self.factory.name(),
+                destroyed: 0,
+                removed_queues: 0,
+            })
+        }
+    }
+}
+
+/*
+* 虚拟机数量占用守护者，构建虚拟机前占用虚拟机数量，构建成功后提交，未提交时释放已占用的虚拟机数量，
+* 保证构建虚拟机的任意失败路径都不会永久占用虚拟机池的容量
+*/
+struct SizeSlot<'a> {
+    size:       &'a AtomicUsize,    //虚拟机工厂的当前虚拟机数量
+    committed:  bool,               //是否已提交
+}
+
+impl<'a> Drop for SizeSlot<'a> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.size.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl<'a> SizeSlot<'a> {
+    //构建一个已占用的虚拟机数量守护者
+    fn new(size: &'a AtomicUsize) -> Self {
+        SizeSlot {
+            size,
+            committed: false,
+        }
+    }
+
+    //提交已占用的虚拟机数量
+    fn commit(&mut self) {
+        self.committed = true;
+    }
+}
+
 /*
 * 阻塞调用错误
 */
@@ -503,11 +3683,57 @@ pub enum BlockError {
     SetGlobalVar(String),
 }
 
-//线程安全的构建指定源的同步任务队列，如果已存在，则忽略
+//为虚拟机的当前调用启动看门狗，当前调用在指定时长后仍未完成，则中断虚拟机执行，当前调用已完成则看门狗不会影响虚拟机的后续调用
+pub fn watch_call(vm: &Arc<JS>, timeout: Duration) {
+    let start = match vm.call_start_time() {
+        None => return, //当前没有统计中的调用，则忽略
+        Some(start) => start,
+    };
+
+    let vm = vm.clone();
+    let runner = FuncRuner::new(Box::new(move || {
+        if vm.interrupt_call(InterruptReason::Timeout, Some(start)) {
+            //启动看门狗的调用仍未完成，则中断虚拟机执行
+            VM_CALL_TIMEOUT_COUNT.sum(1);
+            warn!("!!!> Vm Call Timeout, vm: {:?}, timeout: {:?}", vm, timeout);
+        }
+    }));
+    TIMER.set_timeout(runner, timeout.as_millis() as u32);
+}
+
+//获取虚拟机任务全局基础优先级
+pub fn default_task_priority() -> usize {
+    DEFAULT_JS_TASK_PRIORITY.load(Ordering::Relaxed)
+}
+
+//设置虚拟机任务全局基础优先级，只影响之后投递的任务和之后创建的同步任务队列，优先级不允许为0，返回上个优先级
+pub fn set_default_task_priority(priority: usize) -> Result<usize, String> {
+    if priority == 0 {
+        return Err("invalid task priority, priority: 0".to_string());
+    }
+
+    let old = DEFAULT_JS_TASK_PRIORITY.swap(priority, Ordering::Relaxed);
+    info!("===> Set Default Vm Task Priority, old: {}, new: {}", old, priority);
+    Ok(old)
+}
+
+//运行时调整指定名称的已注册虚拟机工厂的任务优先级，返回上个优先级，虚拟机工厂不存在则返回None
+pub fn adjust_factory_task_priority(name: &FactoryName, priority: usize) -> Option<usize> {
+    read_state("vm_factory_registers", &VM_FACTORY_REGISTERS)
+        .get(name.as_str())
+        .map(|factory| factory.adjust_task_priority(priority))
+}
+
+//线程安全的构建指定源的同步任务队列，使用全局基础优先级，如果已存在，则忽略
 pub fn new_queue(src: usize) -> isize {
+    new_queue_with_priority(src, default_task_priority())
+}
+
+//线程安全的构建指定源和优先级的同步任务队列，优先级为0则使用全局基础优先级，如果已存在，则忽略，已存在队列的优先级不会改变
+pub fn new_queue_with_priority(src: usize, priority: usize) -> isize {
     //检查指定源的同步任务队列是否存在
     {
-        let queues = VM_FACTORY_QUEUES.read().unwrap();
+        let queues = read_state("vm_factory_queues", &VM_FACTORY_QUEUES);
         if let Some(q) = (*queues).get(&src) {
             //存在，则返回
             return q.clone();
@@ -516,16 +3742,26 @@ pub fn new_queue(src: usize) -> isize {
 
     //为指定源创建同步任务队列
     {
-        let queue = create_js_task_queue(JS_TASK_PRIORITY, false);
-        let mut queues = VM_FACTORY_QUEUES.write().unwrap();
+        let mut queues = write_state("vm_factory_queues", &VM_FACTORY_QUEUES);
+        if let Some(q) = (*queues).get(&src) {
+            //已被其它线程创建，则返回
+            return q.clone();
+        }
+
+        let queue = create_js_task_queue(if priority == 0 { default_task_priority() } else { priority }, false);
         (*queues).insert(src, queue.clone());
         queue
     }
 }
 
+//线程安全的获取同步任务队列的数量
+pub fn source_queue_count() -> usize {
+    read_state("vm_factory_queues", &VM_FACTORY_QUEUES).len()
+}
+
 //线程安全的移除指定源的同步任务队列，如果不存在，则忽略
 pub fn remove_queue(src: usize) -> Option<isize> {
-    let mut queues = VM_FACTORY_QUEUES.write().unwrap();
+    let mut queues = write_state("vm_factory_queues", &VM_FACTORY_QUEUES);
     if let Some(q) = (*queues).remove(&src) {
         if remove_js_task_queue(q) {
             return Some(q);
@@ -538,10 +3774,25 @@ pub fn remove_queue(src: usize) -> Option<isize> {
 * 线程安全的在阻塞调用中设置全局变量，设置成功后执行下一个操作
 * 全局变量构建函数执行成功后，当前值栈必须存在且只允许存在一个值，失败则必须移除在值栈上的构建的所有值
 */
-pub fn block_set_global_var(js: Arc<JS>, name: String, var: Box<FnOnce(Arc<JS>) -> Result<JSType, String>>, next: Box<FnOnce(Result<Arc<JS>, BlockError>)>, info: Atom) {
+pub fn block_set_global_var(js: Arc<JS>, name: String, var: Box<FnOnce(Arc<JS>) -> Result<JSType, String>>, next: Box<FnOnce(Result<Arc<JS>, BlockError>)>, info: TaskInfo) {
+    if js.is_destroyed() {
+        //虚拟机已销毁，则立即返回错误
+        next(Err(BlockError::Unknow(format!("vm destroyed, vm: {}", js.get_id()))));
+        return;
+    }
+
     let copy_js = js.clone();
     let copy_info = info.clone();
+    let task = js.enqueue_task(&info);
+    let task_id = task.id();
     let func = Box::new(move |_lock| {
+        let _task = task; //任务执行结束或被丢弃时释放任务守护者
+        if copy_js.is_destroyed() {
+            //虚拟机已销毁，则返回错误
+            next(Err(BlockError::Unknow(format!("vm destroyed, vm: {}", copy_js.get_id()))));
+            return;
+        }
+
         unsafe {
             if dukc_vm_status_check(copy_js.get_vm(), JSStatus::WaitBlock as i8) > 0 ||
                 dukc_vm_status_check(copy_js.get_vm(), JSStatus::SingleTask as i8) > 0 {
@@ -550,6 +3801,7 @@ pub fn block_set_global_var(js: Arc<JS>, name: String, var: Box<FnOnce(Arc<JS>)
             } else {
                 if dukc_vm_status_check(copy_js.get_vm(), JSStatus::MultiTask as i8) > 0 {
                     //同步任务已阻塞虚拟机，则继续执行下一个操作
+                    copy_js.start_task(task_id);
                     match var(copy_js.clone()) {
                         Err(reason) => {
                             //构建全局变量错误
@@ -575,7 +3827,7 @@ pub fn block_set_global_var(js: Arc<JS>, name: String, var: Box<FnOnce(Arc<JS>)
     });
 
     let queue = js.get_queue();
-    cast_js_task(TaskType::Sync(false), 0, Some(queue), func, info); //将任务投递到虚拟机消息队列
+    cast_js_task(TaskType::Sync(false), 0, Some(queue), func, info.name()); //将任务投递到虚拟机消息队列
     js.add_queue_len(); //增加虚拟机消息队列长度
     //解锁虚拟机的消息队列
     if !unlock_js_task_queue(queue) {
@@ -587,32 +3839,32 @@ pub fn block_set_global_var(js: Arc<JS>, name: String, var: Box<FnOnce(Arc<JS>)
 * 线程安全的回应阻塞调用
 * 返回值构建函数执行完成后，当前值栈必须存在且只允许存在一个值
 */
-pub fn block_reply(js: Arc<JS>, result: Box<FnOnce(Arc<JS>)>, info: Atom) {
+pub fn block_reply(js: Arc<JS>, result: Box<FnOnce(Arc<JS>)>, info: TaskInfo) {
+    if js.is_destroyed() {
+        //虚拟机已销毁，则忽略
+        return;
+    }
+
     let copy_js = js.clone();
     let copy_info = info.clone();
+    let task = js.enqueue_task(&info);
+    let task_id = task.id();
     let func = Box::new(move |_lock| {
-        unsafe {
-            if dukc_vm_status_check(copy_js.get_vm(), JSStatus::WaitBlock as i8) > 0 || 
-                dukc_vm_status_check(copy_js.get_vm(), JSStatus::SingleTask as i8) > 0 {
-                //同步任务还未阻塞虚拟机，重新投递当前异步任务，并等待同步任务阻塞虚拟机
-                block_reply(copy_js, result, copy_info);
-            } else {
-                let status = dukc_vm_status_switch(copy_js.get_vm(), JSStatus::MultiTask as i8, JSStatus::SingleTask as i8);
-                if status == JSStatus::MultiTask as i8 {
-                    //同步任务已阻塞虚拟机，则返回指定的值，并唤醒虚拟机继续同步执行
-                    dukc_wakeup(copy_js.get_vm(), 0);
-                    result(copy_js.clone());
-                    dukc_continue(copy_js.get_vm(), js_reply_callback);
-                } else {
-                    //再次检查同步任务还未阻塞虚拟机，重新投递当前异步任务，并等待同步任务阻塞虚拟机
-                    block_reply(copy_js, result, copy_info);
-                }
-            }
+        let _task = task; //任务执行结束或被丢弃时释放任务守护者
+        if copy_js.is_destroyed() {
+            //虚拟机已销毁，则忽略已推送的任务
+            return;
+        }
+
+        //同步任务已阻塞虚拟机，则唤醒虚拟机，返回指定的值，并继续同步执行
+        if let Err(result) = JS::resume_block(copy_js.clone(), task_id, false, result) {
+            //同步任务还未阻塞虚拟机，重新投递当前异步任务，并等待同步任务阻塞虚拟机
+            block_reply(copy_js, result, copy_info);
         }
     });
 
     let queue = js.get_queue();
-    cast_js_task(TaskType::Sync(false), 0, Some(queue), func, info); //将任务投递到虚拟机消息队列
+    cast_js_task(TaskType::Sync(false), 0, Some(queue), func, info.name()); //将任务投递到虚拟机消息队列
     js.add_queue_len(); //增加虚拟机消息队列长度
     //解锁虚拟机的消息队列
     if !unlock_js_task_queue(queue) {
@@ -620,37 +3872,51 @@ pub fn block_reply(js: Arc<JS>, result: Box<FnOnce(Arc<JS>)>, info: Atom) {
     }
 }
 
+//将错误原因转换为C字符串，错误原因中的空字符会被移除
+fn to_error_reason(reason: String) -> CString {
+    match CString::new(reason) {
+        Ok(reason) => reason,
+        Err(e) => {
+            let mut bytes = e.into_vec();
+            bytes.retain(|b| *b != 0);
+            CString::new(bytes).unwrap_or_default()
+        },
+    }
+}
+
 /*
 * 线程安全的为阻塞调用抛出异常
 */
-pub fn block_throw(js: Arc<JS>, reason: String, info: Atom) {
+pub fn block_throw(js: Arc<JS>, reason: String, info: TaskInfo) {
+    if js.is_destroyed() {
+        //虚拟机已销毁，则忽略
+        return;
+    }
+
     let copy_js = js.clone();
     let copy_info = info.clone();
+    let task = js.enqueue_task(&info);
+    let task_id = task.id();
     let func = Box::new(move |_lock| {
-        unsafe {
-            if dukc_vm_status_check(copy_js.get_vm(), JSStatus::WaitBlock as i8) > 0 || 
-                dukc_vm_status_check(copy_js.get_vm(), JSStatus::SingleTask as i8) > 0 {
-                //同步任务还未阻塞虚拟机，重新投递当前异步任务，并等待同步任务阻塞虚拟机
-                block_throw(copy_js, reason, copy_info);
-            } else {
-                let status = dukc_vm_status_switch(copy_js.get_vm(), JSStatus::MultiTask as i8, JSStatus::SingleTask as i8);
-                if status == JSStatus::MultiTask as i8 {
-                    //同步任务已阻塞虚拟机，则抛出指定原因的错误，并唤醒虚拟机继续同步执行
-                    let reason_ptr = CString::into_raw(CString::new(reason).unwrap());
-                    dukc_wakeup(copy_js.get_vm(), 1);
-                    dukc_new_error(copy_js.get_vm(), reason_ptr as *const c_char);
-                    dukc_continue(copy_js.get_vm(), js_reply_callback);
-                    CString::from_raw(reason_ptr);
-                } else {
-                    //再次检查同步任务还未阻塞虚拟机，重新投递当前异步任务，并等待同步任务阻塞虚拟机
-                    block_throw(copy_js, reason, copy_info);
-                }
-            }
+        let _task = task; //任务执行结束或被丢弃时释放任务守护者
+        if copy_js.is_destroyed() {
+            //虚拟机已销毁，则忽略已推送的任务
+            return;
+        }
+
+        //同步任务已阻塞虚拟机，则唤醒虚拟机，抛出指定原因的错误，并继续同步执行
+        let error = to_error_reason(reason.clone());
+        let throw = move |vm: Arc<JS>| {
+            unsafe { dukc_new_error(vm.get_vm(), error.as_ptr()); }
+        };
+        if let Err(_) = JS::resume_block(copy_js.clone(), task_id, true, throw) {
+            //同步任务还未阻塞虚拟机，重新投递当前异步任务，并等待同步任务阻塞虚拟机
+            block_throw(copy_js, reason, copy_info);
         }
     });
 
     let queue = js.get_queue();
-    cast_js_task(TaskType::Sync(false), 0, Some(queue), func, info); //将任务投递到虚拟机消息队列
+    cast_js_task(TaskType::Sync(false), 0, Some(queue), func, info.name()); //将任务投递到虚拟机消息队列
     js.add_queue_len(); //增加虚拟机消息队列长度
     //解锁虚拟机的消息队列
     if !unlock_js_task_queue(queue) {
@@ -661,22 +3927,78 @@ pub fn block_throw(js: Arc<JS>, reason: String, info: Atom) {
 /*
 * 线程安全的向虚拟机推送异步回调函数，延迟任务必须返回任务句柄，其它任务根据是否是动态任务确定是否返回任务句柄
 */
-pub fn push_callback(js: Arc<JS>, callback: u32, args: Box<FnOnce(Arc<JS>) -> usize>, timeout: Option<u32>, info: Atom) -> Option<isize> {
+pub fn push_callback(js: Arc<JS>, callback: u32, args: Box<FnOnce(Arc<JS>) -> usize>, timeout: Option<u32>, info: TaskInfo) -> Option<isize> {
+    push_callback_with_reject(js, callback, args, timeout, info, None)
+}
+
+/*
+* 线程安全的向虚拟机推送异步回调函数，如果执行回调时虚拟机已重置推送回调时的全局环境，则拒绝执行回调，并使用原因调用拒绝回调
+*/
+pub fn push_callback_with_reject(js: Arc<JS>, callback: u32, args: Box<FnOnce(Arc<JS>) -> usize>, timeout: Option<u32>, info: TaskInfo,
+                                 reject: Option<Box<FnOnce(String)>>) -> Option<isize> {
     VM_PUSH_CALLBACK_COUNT.sum(1);
+    js.add_callback_count();
+
+    if let (None, Some(queue)) = (timeout, response_queue(&js, &info)) {
+        //虚拟机工厂启用了回应亲和，则在源的同步任务队列中按序转发异步回调
+        let name = info.name();
+        let func = Box::new(move |lock: Option<isize>| {
+            JS::callback_with_reject(js, TaskType::Sync(true), callback, args, None, info, reject);
+            if let Some(queue) = lock {
+                //已转发到虚拟机消息队列，则解锁源的同步任务队列，以执行源的下一个任务
+                if !unlock_js_task_queue(queue) {
+                    warn!("!!!> Push Callback Error, unlock source task queue failed, queue: {:?}", queue);
+                }
+            }
+        });
+        return cast_js_task(TaskType::Sync(true), 0, Some(queue), func, name);
+    }
 
     if timeout.is_some() {
         //推送延迟异步任务，禁止直接执行异步任务
-        JS::callback(js.clone(), TaskType::Sync(true), callback, args, timeout, info)
+        JS::callback_with_reject(js.clone(), TaskType::Sync(true), callback, args, timeout, info, reject)
     } else {
         //推送异步任务，禁止直接执行异步任务
-        JS::callback(js.clone(), TaskType::Sync(true), callback, args, timeout, info)
+        JS::callback_with_reject(js.clone(), TaskType::Sync(true), callback, args, timeout, info, reject)
+    }
+}
+
+//获取异步回调的源的同步任务队列，只有任务有源且虚拟机所属虚拟机工厂启用了回应亲和时才返回
+fn response_queue(js: &Arc<JS>, info: &TaskInfo) -> Option<isize> {
+    match (info.source(), js.get_factory()) {
+        (Some(src), Some(factory)) if factory.is_response_affinity() => Some(new_queue_with_priority(src, info.priority())),
+        _ => None,
+    }
+}
+
+/*
+* 线程安全的向指定代数的虚拟机推送异步回调函数，用于跨越异步边界持有虚拟机的处理器和定时器，
+* 虚拟机已被回收重置或销毁则不推送并返回过期错误，推送后虚拟机被回收重置则拒绝执行回调
+*/
+pub fn push_callback_checked(js: Arc<JS>, epoch: usize, callback: u32, args: Box<FnOnce(Arc<JS>) -> usize>, timeout: Option<u32>, info: TaskInfo) -> Result<Option<isize>, VmError> {
+    if let Err(e) = js.check_epoch(epoch) {
+        VM_PUSH_STALE_COUNT.sum(1);
+        warn!("!!!> Push Callback Error, vm: {:?}, callback: {}, e: {}", js, callback, e);
+        return Err(e);
     }
+
+    Ok(push_callback(js, callback, args, timeout, info))
+}
+
+/*
+* 线程安全的向虚拟机分片推送异步回调，每个分片作为一个独立的任务执行回调函数，虚拟机可以在分片之间执行其它任务，适用于处理大的回调数据
+*/
+pub fn push_callback_sliced(js: Arc<JS>, callback: u32, payload: Arc<Vec<u8>>, slice_size: usize, info: TaskInfo) -> Option<isize> {
+    VM_PUSH_CALLBACK_COUNT.sum(1);
+    js.add_callback_count();
+
+    JS::callback_sliced(js, callback, payload, slice_size, info)
 }
 
 /*
 * 线程安全的向虚拟机推送异步消息，正数表示使用指定的回调执行消息，负数表示移除指定的回调
 */
-pub fn push_msg(js: Arc<JS>, callback: u32, args: Box<FnOnce(Arc<JS>) -> usize>, info: Atom) -> Option<isize> {
+pub fn push_msg(js: Arc<JS>, callback: u32, args: Box<FnOnce(Arc<JS>) -> usize>, info: TaskInfo) -> Option<isize> {
     JS::push(js.clone(), TaskType::Sync(true), callback, args, info) //禁止直接执行异步消息
 }
 
@@ -684,45 +4006,87 @@ pub fn push_msg(js: Arc<JS>, callback: u32, args: Box<FnOnce(Arc<JS>) -> usize>,
 * 线程安全的获取虚拟机通道灰度值
 */
 pub fn get_channels_gray() -> usize {
-    let ref lock = &**VM_CHANNELS;
-    let channels = lock.read().unwrap();
-    (*channels).get_gray()
+    VM_CHANNELS.get_gray()
 }
 
 /*
 * 线程安全的设置虚拟机通道灰度值
 */
 pub fn set_channels_gray(gray: usize) -> usize {
-    let ref lock = &**VM_CHANNELS;
-    let mut channels = lock.write().unwrap();
-    (*channels).set_gray(gray)
+    VM_CHANNELS.set_gray(gray)
 }
 
 /*
 * 线程安全的获取虚拟机通道异步调用数量
 */
 pub fn get_async_request_size() -> usize {
-    let ref lock = &**VM_CHANNELS;
-    let channels = lock.read().unwrap();
-    (*channels).size()
+    VM_CHANNELS.size()
 }
 
 /*
 * 线程安全的在虚拟机通道注册异步调用
 */
-pub fn register_async_request(name: Atom, handler: Arc<Handler<A = Arc<Vec<u8>>, B = Vec<JSType>, C = Option<u32>, D = (), E = (), F = (), G = (), H = (), HandleResult = ()>>) -> Option<Arc<Handler<A = Arc<Vec<u8>>, B = Vec<JSType>, C = Option<u32>, D = (), E = (), F = (), G = (), H = (), HandleResult = ()>>> {
-    let ref lock = &**VM_CHANNELS;
-    let mut channels = lock.write().unwrap();
-    (*channels).set(name, handler)
+pub fn register_async_request(name: Atom, handler: GenericChannelHandler) -> Option<Arc<ChannelHandler>> {
+    VM_CHANNELS.set(name, handler)
+}
+
+/*
+* 线程安全的在虚拟机通道注册简化的异步调用处理器
+*/
+pub fn register_channel_handler(name: Atom, handler: Arc<ChannelHandler>) -> Option<Arc<ChannelHandler>> {
+    VM_CHANNELS.set_handler(name, handler)
+}
+
+/*
+* 线程安全的在虚拟机通道注册异步调用，并声明异步调用提供的版本，用于与js代码包清单中依赖的版本协商
+*/
+pub fn register_async_request_with_version(name: Atom, version: &str, handler: GenericChannelHandler) -> Result<Option<Arc<ChannelHandler>>, String> {
+    let version = HandlerVersion::parse(version)?;
+    provide_handler_version(name.clone(), version);
+    Ok(register_async_request(name, handler))
+}
+
+/*
+* 线程安全的判断虚拟机通道是否注册了指定异步调用
+*/
+pub fn is_async_request_registered(name: &Atom) -> bool {
+    VM_CHANNELS.contains(name)
 }
 
 /*
 * 线程安全的在虚拟机通道注销异步调用
 */
-pub fn unregister_async_request(name: Atom) -> Option<Arc<Handler<A = Arc<Vec<u8>>, B = Vec<JSType>, C = Option<u32>, D = (), E = (), F = (), G = (), H = (), HandleResult = ()>>> {
-    let ref lock = &**VM_CHANNELS;
-    let mut channels = lock.write().unwrap();
-    (*channels).remove(name)
+pub fn unregister_async_request(name: Atom) -> Option<Arc<ChannelHandler>> {
+    remove_handler_version(&name);
+    VM_CHANNELS.remove(name)
+}
+
+/*
+* 线程安全的获取虚拟机通道所有处理器的执行统计
+*/
+pub fn channel_handler_stats() -> Vec<HandlerStats> {
+    VM_CHANNELS.handler_stats()
+}
+
+/*
+* 线程安全的设置虚拟机通道是否开启解释模式，开启后记录每个请求选择处理器时的灰度和路由决策，返回上次是否开启
+*/
+pub fn set_channels_explain(explain: bool) -> bool {
+    VM_CHANNELS.set_explain(explain)
+}
+
+/*
+* 线程安全的获取指定关联id的所有虚拟机通道请求的路由解释
+*/
+pub fn explain_channel_requests(correlation_id: u64) -> Vec<RouteTrace> {
+    VM_CHANNELS.explain(correlation_id)
+}
+
+/*
+* 线程安全的设置虚拟机通道默认的服务注册表，返回上一个默认的服务注册表
+*/
+pub fn set_channel_services(registry: Option<ServiceRegistry>) -> Option<ServiceRegistry> {
+    VM_CHANNELS.set_services(registry)
 }
 
 /*
@@ -730,8 +4094,27 @@ pub fn unregister_async_request(name: Atom) -> Option<Arc<Handler<A = Arc<Vec<u8
 */
 pub fn async_request(js: Arc<JS>, name: Atom, msg: Arc<Vec<u8>>, native_objs: Vec<usize>, callback: Option<u32>) -> bool {
     VM_ASYNC_REQUEST_COUNT.sum(1);
+    js.add_bytes_out(msg.len());
+
+    VM_CHANNELS.request(js, name, msg, native_objs, callback)
+}
+
+/*
+* 线程安全的通过虚拟机通道向对端发送通道消息的异步请求，不超过内联长度的消息不会分配共享缓冲区
+*/
+pub fn async_request_msg(js: Arc<JS>, name: Atom, msg: ChannelMsg, native_objs: Vec<usize>, callback: Option<u32>) -> bool {
+    VM_ASYNC_REQUEST_COUNT.sum(1);
+    js.add_bytes_out(msg.len());
+
+    VM_CHANNELS.request_msg(js, name, msg, native_objs, callback)
+}
+
+/*
+* 线程安全的通过虚拟机通道向对端发送异步请求，并返回请求的回执
+*/
+pub fn async_request_with_receipt(js: Arc<JS>, name: Atom, msg: Arc<Vec<u8>>, native_objs: Vec<usize>, callback: Option<u32>) -> RequestReceipt {
+    VM_ASYNC_REQUEST_COUNT.sum(1);
+    js.add_bytes_out(msg.len());
 
-    let ref lock = &**VM_CHANNELS;
-    let channels = lock.read().unwrap();
-    (*channels).request(js, name, msg, native_objs, callback)
+    VM_CHANNELS.request_with_receipt(js, name, msg, native_objs, callback)
 }
@@ -0,0 +1,257 @@
+use std::sync::{Arc, RwLock};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use adapter::JS;
+use pi_vm_impl::VMFactory;
+use names::PortName;
+use task_info::TaskInfo;
+use health::{read_state, write_state};
+
+/*
+* 管道注册表
+*/
+lazy_static! {
+    static ref PIPELINES: Arc<RwLock<HashMap<Atom, Arc<Pipeline>>>> = Arc::new(RwLock::new(HashMap::new()));
+}
+
+lazy_static! {
+    //管道调用数量
+    static ref PIPELINE_CALL_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("pipeline_call_count"), 0).unwrap();
+    //管道调用失败数量
+    static ref PIPELINE_ERROR_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("pipeline_error_count"), 0).unwrap();
+}
+
+/*
+* 管道阶段的完成回调
+*/
+pub type PipeNext = Box<FnOnce(Result<String, String>)>;
+
+/*
+* 管道阶段的补偿器，参数为已完成阶段的输入、输出和补偿完成回调
+*/
+pub type PipeCompensator = Arc<Fn(String, String, Box<FnOnce(Result<(), String>)>) + Send + Sync>;
+
+/*
+* 管道阶段
+*/
+#[derive(Clone)]
+pub enum PipeStage {
+    Port(Arc<VMFactory>, PortName),                 //js端口阶段，将输入作为字符串参数调用指定虚拟机工厂的端口，返回值的字符串作为输出
+    Native(Atom, Arc<Fn(String, PipeNext) + Send + Sync>),  //本地处理器阶段，异步回调输出
+    Map(Atom, Arc<Fn(String) -> Result<String, String> + Send + Sync>), //数据映射阶段，同步转换上个阶段的输出
+}
+
+impl PipeStage {
+    //获取阶段名
+    pub fn name(&self) -> String {
+        match self {
+            PipeStage::Port(_, port) => port.to_string(),
+            PipeStage::Native(name, _) => name.to_string(),
+            PipeStage::Map(name, _) => name.to_string(),
+        }
+    }
+}
+
+/*
+* 管道阶段的补偿结果
+*/
+#[derive(Debug, Clone)]
+pub struct CompensateResult {
+    pub index:  usize,              //阶段序号
+    pub stage:  String,             //阶段名
+    pub result: Result<(), String>, //补偿结果
+}
+
+/*
+* 管道错误
+*/
+#[derive(Debug, Clone)]
+pub enum PipelineError {
+    NotFound(String),                                       //指定管道不存在
+    Stage(String, usize, String, String),                   //管道阶段执行失败，(管道名, 阶段序号, 阶段名, 原因)
+    Compensated(Box<PipelineError>, Vec<CompensateResult>), //管道阶段执行失败，且已按逆序补偿已完成的阶段，(阶段错误, 补偿结果列表)
+}
+
+impl Display for PipelineError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            PipelineError::NotFound(name) => write!(f, "pipeline not found, pipeline: {}", name),
+            PipelineError::Stage(name, index, stage, reason) => write!(f, "pipeline stage failed, pipeline: {}, stage: {}({}), reason: {}", name, stage, index, reason),
+            PipelineError::Compensated(e, results) => {
+                let failed = results.iter().filter(|r| r.result.is_err()).count();
+                write!(f, "{}, compensated: {}, compensate failed: {}", e, results.len() - failed, failed)
+            },
+        }
+    }
+}
+
+/*
+* 管道，由js端口、本地处理器和数据映射依次组成，上个阶段的输出是下个阶段的输入
+*/
+#[derive(Clone)]
+pub struct Pipeline {
+    name:           Atom,                           //管道名
+    stages:         Vec<PipeStage>,                 //管道阶段列表
+    compensators:   Vec<Option<PipeCompensator>>,   //管道阶段的补偿器列表，与管道阶段一一对应
+}
+
+impl Pipeline {
+    //构建一个管道
+    pub fn new(name: &str) -> Self {
+        Pipeline {
+            name: Atom::from(name),
+            stages: Vec::new(),
+            compensators: Vec::new(),
+        }
+    }
+
+    //增加js端口阶段
+    pub fn port(mut self, factory: Arc<VMFactory>, port: PortName) -> Self {
+        self.stages.push(PipeStage::Port(factory, port));
+        self.compensators.push(None);
+        self
+    }
+
+    //增加本地处理器阶段
+    pub fn native(mut self, name: &str, handler: Arc<Fn(String, PipeNext) + Send + Sync>) -> Self {
+        self.stages.push(PipeStage::Native(Atom::from(name), handler));
+        self.compensators.push(None);
+        self
+    }
+
+    //增加数据映射阶段
+    pub fn map(mut self, name: &str, mapper: Arc<Fn(String) -> Result<String, String> + Send + Sync>) -> Self {
+        self.stages.push(PipeStage::Map(Atom::from(name), mapper));
+        self.compensators.push(None);
+        self
+    }
+
+    //为最近增加的阶段设置补偿器，当后续阶段失败时，已完成阶段的补偿器会按逆序执行
+    pub fn compensate(mut self, compensator: PipeCompensator) -> Self {
+        if let Some(last) = self.compensators.last_mut() {
+            *last = Some(compensator);
+        }
+        self
+    }
+
+    //获取管道名
+    pub fn name(&self) -> String {
+        self.name.to_string()
+    }
+
+    //获取管道阶段数量
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+}
+
+//注册管道，返回同名的旧管道
+pub fn register_pipeline(pipeline: Pipeline) -> Option<Arc<Pipeline>> {
+    write_state("pipelines", &PIPELINES).insert(pipeline.name.clone(), Arc::new(pipeline))
+}
+
+//注销指定管道
+pub fn unregister_pipeline(name: &str) -> Option<Arc<Pipeline>> {
+    write_state("pipelines", &PIPELINES).remove(&Atom::from(name))
+}
+
+//获取指定管道
+pub fn get_pipeline(name: &str) -> Option<Arc<Pipeline>> {
+    read_state("pipelines", &PIPELINES).get(&Atom::from(name)).cloned()
+}
+
+//调用指定管道，依次执行所有阶段，并回调最后阶段的输出，任意阶段失败则停止执行，并回调失败的阶段和原因
+pub fn call_pipeline(name: &str, input: String, callback: Box<FnOnce(Result<String, PipelineError>)>) {
+    match get_pipeline(name) {
+        None => callback(Err(PipelineError::NotFound(name.to_string()))),
+        Some(pipeline) => {
+            PIPELINE_CALL_COUNT.sum(1);
+            run_stage(pipeline, 0, input, Vec::new(), callback);
+        },
+    }
+}
+
+//执行管道的指定阶段，completed为已完成且需要补偿的阶段列表，(阶段序号, 输入, 输出)
+fn run_stage(pipeline: Arc<Pipeline>, index: usize, input: String, mut completed: Vec<(usize, String, String)>, callback: Box<FnOnce(Result<String, PipelineError>)>) {
+    if index >= pipeline.stages.len() {
+        //已执行所有阶段
+        return callback(Ok(input));
+    }
+
+    let stage = pipeline.stages[index].clone();
+    let pipeline_copy = pipeline.clone();
+    let stage_input = input.clone();
+    let next: PipeNext = Box::new(move |result: Result<String, String>| {
+        match result {
+            Ok(output) => {
+                if pipeline_copy.compensators[index].is_some() {
+                    //当前阶段有补偿器，则记录当前阶段的输入和输出
+                    completed.push((index, stage_input, output.clone()));
+                }
+                run_stage(pipeline_copy, index + 1, output, completed, callback);
+            },
+            Err(reason) => {
+                PIPELINE_ERROR_COUNT.sum(1);
+                warn!("!!!> Pipeline Call Error, pipeline: {:?}, stage: {:?}({}), reason: {:?}",
+                      pipeline_copy.name(), pipeline_copy.stages[index].name(), index, reason);
+                let e = PipelineError::Stage(pipeline_copy.name(), index, pipeline_copy.stages[index].name(), reason);
+                if completed.is_empty() {
+                    //没有需要补偿的阶段
+                    return callback(Err(e));
+                }
+
+                compensate_stage(pipeline_copy, completed, Vec::new(), Box::new(move |results| {
+                    callback(Err(PipelineError::Compensated(Box::new(e), results)));
+                }));
+            },
+        }
+    });
+
+    match stage {
+        PipeStage::Port(factory, port) => {
+            let args = Box::new(move |vm: Arc<JS>| -> usize {
+                vm.new_str(input);
+                1
+            });
+            let finish = Box::new(move |result: Result<Option<String>, String>| {
+                next(result.map(|ret| ret.unwrap_or_default()));
+            });
+            let info = TaskInfo::from(format!("pipeline {} stage {} task", pipeline.name(), index));
+            factory.call_then(None, port, args, info, finish);
+        },
+        PipeStage::Native(_, handler) => handler(input, next),
+        PipeStage::Map(_, mapper) => next(mapper(input)),
+    }
+}
+
+//按逆序补偿已完成的阶段，补偿器失败不会中止后续补偿，完成后回调所有补偿结果
+fn compensate_stage(pipeline: Arc<Pipeline>, mut completed: Vec<(usize, String, String)>, mut results: Vec<CompensateResult>, callback: Box<FnOnce(Vec<CompensateResult>)>) {
+    let (index, input, output) = match completed.pop() {
+        None => return callback(results),
+        Some(stage) => stage,
+    };
+
+    let compensator = match pipeline.compensators[index] {
+        None => return compensate_stage(pipeline, completed, results, callback),
+        Some(ref compensator) => compensator.clone(),
+    };
+
+    let pipeline_copy = pipeline.clone();
+    compensator(input, output, Box::new(move |result: Result<(), String>| {
+        if let Err(ref reason) = result {
+            warn!("!!!> Pipeline Compensate Error, pipeline: {:?}, stage: {:?}({}), reason: {:?}",
+                  pipeline_copy.name(), pipeline_copy.stages[index].name(), index, reason);
+        }
+
+        results.push(CompensateResult {
+            index,
+            stage: pipeline_copy.stages[index].name(),
+            result,
+        });
+        compensate_stage(pipeline_copy, completed, results, callback);
+    }));
+}
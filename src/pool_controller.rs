@@ -0,0 +1,263 @@
+use std::sync::Arc;
+use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use atom::Atom;
+use adapter::now_utc;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use pi_vm_impl::VMFactory;
+use maintenance::schedule_maintenance;
+
+lazy_static! {
+    //虚拟机池自适应增加虚拟机数量
+    static ref VM_POOL_GROW_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_pool_grow_count"), 0).unwrap();
+    //虚拟机池自适应减少虚拟机数量
+    static ref VM_POOL_SHRINK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_pool_shrink_count"), 0).unwrap();
+}
+
+/*
+* 虚拟机池调整原因
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum PoolAdjustReason {
+    BelowMin,           //虚拟机数量小于最小值
+    AboveMax,           //虚拟机数量大于最大值
+    WaitTime(Duration), //任务平均等待时长超过阈值
+    Rejected(usize),    //有任务被拒绝立即执行
+    Idle(usize),        //空闲虚拟机数量超过阈值
+    Latency(Duration),  //调用延迟的99分位超过阈值
+    QueueDepth(usize),  //等待调度的任务数量超过阈值
+}
+
+/*
+* 虚拟机池调整事件
+*/
+#[derive(Debug, Clone)]
+pub enum PoolEvent {
+    Grow(String, usize, usize, PoolAdjustReason),   //增加虚拟机，(虚拟机工厂名, 调整前数量, 调整后数量, 原因)
+    Shrink(String, usize, usize, PoolAdjustReason), //减少虚拟机，(虚拟机工厂名, 调整前数量, 调整后数量, 原因)
+}
+
+/*
+* 虚拟机池自适应控制器，定时观察虚拟机工厂的任务等待时长、拒绝次数、任务队列、调用延迟和空闲虚拟机数量，在最小和最大数量之间自动调整虚拟机数量，
+* 每次因负载或空闲调整后，在对应的冷却期内不会再次调整
+*/
+#[derive(Clone)]
+pub struct PoolController {
+    factory:        VMFactory,                      //被控制的虚拟机工厂
+    min:            usize,                          //最小虚拟机数量
+    max:            usize,                          //最大虚拟机数量
+    interval:       usize,                          //调整间隔时长，单位ms
+    wait_threshold: Duration,                       //任务平均等待时长阈值，超过则增加虚拟机
+    idle_threshold: usize,                          //空闲虚拟机数量阈值，超过则减少虚拟机
+    step:           usize,                          //每次调整的最大虚拟机数量
+    latency_threshold:  Option<Duration>,           //调用延迟的99分位阈值，超过则增加虚拟机，为空表示不观察调用延迟
+    queue_threshold:    Option<usize>,              //等待调度的任务数量阈值，超过则增加虚拟机，为空表示不观察任务队列
+    up_cooldown:    Duration,                       //增加虚拟机后的冷却时长，冷却期内不会再因负载增加虚拟机
+    down_cooldown:  Duration,                       //减少虚拟机后的冷却时长，冷却期内不会再因空闲减少虚拟机
+    listener:       Option<Arc<Fn(PoolEvent) + Send + Sync>>,   //调整事件监听器
+    last_refuse:    Arc<AtomicUsize>,               //上次观察时虚拟机工厂的拒绝次数
+    last_grow:      Arc<AtomicUsize>,               //上次增加虚拟机的时间，单位us
+    last_shrink:    Arc<AtomicUsize>,               //上次减少虚拟机的时间，单位us
+    running:        Arc<AtomicBool>,                //控制器是否运行中
+}
+
+impl PoolController {
+    //构建一个虚拟机池自适应控制器
+    pub fn new(factory: VMFactory, min: usize, max: usize) -> Self {
+        PoolController {
+            factory,
+            min,
+            max: if max < min { min } else { max },
+            interval: 1000,
+            wait_threshold: Duration::from_millis(10),
+            idle_threshold: 1,
+            step: 1,
+            latency_threshold: None,
+            queue_threshold: None,
+            up_cooldown: Duration::from_millis(0),
+            down_cooldown: Duration::from_millis(0),
+            listener: None,
+            last_refuse: Arc::new(AtomicUsize::new(0)),
+            last_grow: Arc::new(AtomicUsize::new(0)),
+            last_shrink: Arc::new(AtomicUsize::new(0)),
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    //设置调整间隔时长，单位ms
+    pub fn set_interval(mut self, interval: usize) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    //设置任务平均等待时长阈值
+    pub fn set_wait_threshold(mut self, threshold: Duration) -> Self {
+        self.wait_threshold = threshold;
+        self
+    }
+
+    //设置空闲虚拟机数量阈值
+    pub fn set_idle_threshold(mut self, threshold: usize) -> Self {
+        self.idle_threshold = threshold;
+        self
+    }
+
+    //设置每次调整的最大虚拟机数量
+    pub fn set_step(mut self, step: usize) -> Self {
+        self.step = if step == 0 { 1 } else { step };
+        self
+    }
+
+    //设置调用延迟的99分位阈值
+    pub fn set_latency_threshold(mut self, threshold: Duration) -> Self {
+        self.latency_threshold = Some(threshold);
+        self
+    }
+
+    //设置等待调度的任务数量阈值
+    pub fn set_queue_threshold(mut self, threshold: usize) -> Self {
+        self.queue_threshold = Some(threshold);
+        self
+    }
+
+    //设置增加和减少虚拟机后的冷却时长
+    pub fn set_cooldown(mut self, up: Duration, down: Duration) -> Self {
+        self.up_cooldown = up;
+        self.down_cooldown = down;
+        self
+    }
+
+    //设置调整事件监听器
+    pub fn set_listener(mut self, listener: Arc<Fn(PoolEvent) + Send + Sync>) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    //判断控制器是否运行中
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    //启动控制器，返回控制器是否启动成功
+    pub fn start(&self) -> bool {
+        if self.running.compare_and_swap(false, true, Ordering::SeqCst) {
+            //已启动
+            return false;
+        }
+
+        self.last_refuse.store(self.factory.refuse_count(), Ordering::Relaxed);
+        self.factory.take_wait_stat(); //丢弃启动前的等待统计
+        self.factory.take_latency_window(); //丢弃启动前的调用延迟统计
+        self.schedule();
+        true
+    }
+
+    //停止控制器，下次调整时生效
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    //立即观察并调整一次，返回本次调整事件
+    pub fn adjust(&self) -> Option<PoolEvent> {
+        let name = self.factory.name();
+        let size = self.factory.size();
+        let idle = self.factory.free_pool_size() + self.factory.free_buf_size();
+        let refuse = self.factory.refuse_count();
+        let rejected = refuse.saturating_sub(self.last_refuse.swap(refuse, Ordering::Relaxed));
+        let (wait_count, wait_time) = self.factory.take_wait_stat();
+        let avg_wait = if wait_count == 0 {
+            Duration::from_micros(0)
+        } else {
+            wait_time / wait_count as u32
+        };
+
+        let latency = self.factory.take_latency_window();
+        let queue_len = self.factory.queue_len();
+
+        //按负载增加虚拟机的原因，依次观察拒绝次数、任务队列、调用延迟和等待时长
+        let load = if rejected > 0 {
+            Some(PoolAdjustReason::Rejected(rejected))
+        } else if self.queue_threshold.map_or(false, |threshold| queue_len > threshold) {
+            Some(PoolAdjustReason::QueueDepth(queue_len))
+        } else if self.latency_threshold.map_or(false, |threshold| latency.count > 0 && latency.p99 > threshold) {
+            Some(PoolAdjustReason::Latency(latency.p99))
+        } else if avg_wait > self.wait_threshold {
+            Some(PoolAdjustReason::WaitTime(avg_wait))
+        } else {
+            None
+        };
+
+        let now = now_utc();
+        let event = if size < self.min {
+            self.grow(name, size, self.min - size, PoolAdjustReason::BelowMin)
+        } else if size > self.max {
+            self.shrink(name, size, size - self.max, PoolAdjustReason::AboveMax)
+        } else if load.is_some() && idle == 0 && size < self.max {
+            if self.is_cooling(&self.last_grow, self.up_cooldown, now) {
+                None
+            } else {
+                self.grow(name, size, (self.max - size).min(self.step), load.unwrap())
+            }
+        } else if load.is_none() && idle > self.idle_threshold && size > self.min && queue_len == 0 {
+            if self.is_cooling(&self.last_shrink, self.down_cooldown, now) {
+                None
+            } else {
+                let count = (idle - self.idle_threshold).min(size - self.min).min(self.step);
+                self.shrink(name, size, count, PoolAdjustReason::Idle(idle))
+            }
+        } else {
+            None
+        };
+
+        match &event {
+            Some(PoolEvent::Grow(..)) => self.last_grow.store(now, Ordering::Relaxed),
+            Some(PoolEvent::Shrink(..)) => self.last_shrink.store(now, Ordering::Relaxed),
+            None => (),
+        }
+
+        if let (Some(e), Some(listener)) = (&event, &self.listener) {
+            listener(e.clone());
+        }
+        event
+    }
+
+    //判断是否在上次调整后的冷却期内
+    fn is_cooling(&self, last: &AtomicUsize, cooldown: Duration, now: usize) -> bool {
+        let last = last.load(Ordering::Relaxed);
+        last > 0 && now.saturating_sub(last) < cooldown.as_micros() as usize
+    }
+
+    //增加指定数量的虚拟机
+    fn grow(&self, name: String, size: usize, count: usize, reason: PoolAdjustReason) -> Option<PoolEvent> {
+        if let Err(e) = self.factory.produce(count) {
+            warn!("!!!> Pool Controller Grow Error, factory: {:?}, e: {:?}", name, e);
+            return None;
+        }
+
+        VM_POOL_GROW_COUNT.sum(count);
+        info!("===> Pool Controller Grow Ok, factory: {:?}, size: {} -> {}, reason: {:?}", name, size, self.factory.size(), reason);
+        Some(PoolEvent::Grow(name, size, self.factory.size(), reason))
+    }
+
+    //减少指定数量的空闲虚拟机
+    fn shrink(&self, name: String, size: usize, count: usize, reason: PoolAdjustReason) -> Option<PoolEvent> {
+        let count = self.factory.shrink(count);
+        if count == 0 {
+            return None;
+        }
+
+        VM_POOL_SHRINK_COUNT.sum(count);
+        info!("===> Pool Controller Shrink Ok, factory: {:?}, size: {} -> {}, reason: {:?}", name, size, self.factory.size(), reason);
+        Some(PoolEvent::Shrink(name, size, self.factory.size(), reason))
+    }
+
+    //注册周期性的调整
+    fn schedule(&self) {
+        let controller = self.clone();
+        schedule_maintenance(self.running.clone(), self.interval as u32, "vm pool controller task", Arc::new(move || {
+            controller.adjust();
+        }));
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use adapter::now_utc;
+use health::{lock_state, read_state, write_state};
+
+/*
+* 源调用频率的统计窗口，单位us
+*/
+const QUOTA_WINDOW: usize = 1000000;
+
+/*
+* 配额管理器记录的源数量超过此值时，移除空闲源的统计
+*/
+const QUOTA_PRUNE_THRESHOLD: usize = 1024;
+
+lazy_static! {
+    //全局配额管理器，为空表示不限制
+    static ref QUOTA_MANAGER: RwLock<Option<QuotaManager>> = RwLock::new(None);
+    //源调用因超过配额被限流的次数
+    static ref VM_QUOTA_THROTTLED_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_quota_throttled_count"), 0).unwrap();
+}
+
+/*
+* 源配额，为0表示不限制
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceQuota {
+    pub calls_per_second:   usize,  //源每秒允许开始的调用数量
+    pub max_concurrent:     usize,  //源允许同时未完成的调用数量
+}
+
+impl Default for SourceQuota {
+    fn default() -> Self {
+        SourceQuota {
+            calls_per_second: 0,
+            max_concurrent: 0,
+        }
+    }
+}
+
+/*
+* 源调用限流错误
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum Throttled {
+    Rate(usize, usize),         //源调用频率超过配额，(源, 每秒允许的调用数量)
+    Concurrency(usize, usize),  //源未完成的调用数量超过配额，(源, 允许同时未完成的调用数量)
+}
+
+impl Display for Throttled {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Throttled::Rate(src, limit) => write!(f, "throttled, calls per second over quota, src: {}, limit: {}", src, limit),
+            Throttled::Concurrency(src, limit) => write!(f, "throttled, concurrent calls over quota, src: {}, limit: {}", src, limit),
+        }
+    }
+}
+
+/*
+* 源的调用统计
+*/
+struct SourceState {
+    window_start:   usize,  //当前统计窗口的开始时间，单位us
+    window_calls:   usize,  //当前统计窗口内已开始的调用数量
+    concurrent:     usize,  //未完成的调用数量
+}
+
+/*
+* 源配额管理器，按源限制虚拟机工厂调用和虚拟机通道异步请求的频率和并发数量，以避免单个租户的大量调用饿死虚拟机池
+*/
+#[derive(Clone)]
+pub struct QuotaManager {
+    default:    SourceQuota,                                //默认的源配额
+    quotas:     Arc<RwLock<HashMap<usize, SourceQuota>>>,   //指定源的配额
+    states:     Arc<Mutex<HashMap<usize, SourceState>>>,    //源的调用统计
+}
+
+impl QuotaManager {
+    //构建一个指定默认源配额的配额管理器
+    pub fn new(default: SourceQuota) -> Self {
+        QuotaManager {
+            default,
+            quotas: Arc::new(RwLock::new(HashMap::new())),
+            states: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    //获取默认的源配额
+    pub fn default_quota(&self) -> SourceQuota {
+        self.default
+    }
+
+    //设置指定源的配额，返回指定源的上个配额
+    pub fn set_quota(&self, src: usize, quota: SourceQuota) -> Option<SourceQuota> {
+        write_state("vm_quotas", &self.quotas).insert(src, quota)
+    }
+
+    //移除指定源的配额，移除后使用默认的源配额
+    pub fn remove_quota(&self, src: usize) -> Option<SourceQuota> {
+        write_state("vm_quotas", &self.quotas).remove(&src)
+    }
+
+    //获取指定源的配额
+    pub fn quota(&self, src: usize) -> SourceQuota {
+        read_state("vm_quotas", &self.quotas).get(&src).cloned().unwrap_or(self.default)
+    }
+
+    //获取指定源未完成的调用数量
+    pub fn concurrent(&self, src: usize) -> usize {
+        lock_state("vm_quota_states", &self.states).get(&src).map_or(0, |state| state.concurrent)
+    }
+
+    //为指定源开始一次调用，超过配额则返回限流错误，返回的许可在释放时完成调用
+    pub fn acquire(&self, src: usize) -> Result<QuotaPermit, Throttled> {
+        let quota = self.quota(src);
+        let now = now_utc();
+        let mut states = lock_state("vm_quota_states", &self.states);
+        if states.len() > QUOTA_PRUNE_THRESHOLD {
+            prune(&mut states, now);
+        }
+
+        let state = states.entry(src).or_insert_with(|| SourceState {
+            window_start: now,
+            window_calls: 0,
+            concurrent: 0,
+        });
+        if now.saturating_sub(state.window_start) >= QUOTA_WINDOW {
+            //当前统计窗口已结束，则开始新的统计窗口
+            state.window_start = now;
+            state.window_calls = 0;
+        }
+
+        if quota.calls_per_second > 0 && state.window_calls >= quota.calls_per_second {
+            VM_QUOTA_THROTTLED_COUNT.sum(1);
+            return Err(Throttled::Rate(src, quota.calls_per_second));
+        }
+        if quota.max_concurrent > 0 && state.concurrent >= quota.max_concurrent {
+            VM_QUOTA_THROTTLED_COUNT.sum(1);
+            return Err(Throttled::Concurrency(src, quota.max_concurrent));
+        }
+
+        state.window_calls += 1;
+        state.concurrent += 1;
+        Ok(QuotaPermit {
+            manager: self.clone(),
+            src,
+        })
+    }
+
+    //完成指定源的一次调用
+    fn release(&self, src: usize) {
+        if let Some(state) = lock_state("vm_quota_states", &self.states).get_mut(&src) {
+            state.concurrent = state.concurrent.saturating_sub(1);
+        }
+    }
+}
+
+/*
+* 源配额许可，在释放时完成调用
+*/
+pub struct QuotaPermit {
+    manager:    QuotaManager,   //所属的配额管理器
+    src:        usize,          //源
+}
+
+impl Drop for QuotaPermit {
+    fn drop(&mut self) {
+        self.manager.release(self.src);
+    }
+}
+
+impl QuotaPermit {
+    //获取源
+    pub fn src(&self) -> usize {
+        self.src
+    }
+}
+
+/*
+* 线程安全的设置全局配额管理器，为空表示不限制，返回上个全局配额管理器
+*/
+pub fn set_quota_manager(manager: Option<QuotaManager>) -> Option<QuotaManager> {
+    let mut current = write_state("vm_quota_manager", &QUOTA_MANAGER);
+    let old = current.take();
+    *current = manager;
+    old
+}
+
+/*
+* 线程安全的获取全局配额管理器
+*/
+pub fn quota_manager() -> Option<QuotaManager> {
+    read_state("vm_quota_manager", &QUOTA_MANAGER).clone()
+}
+
+/*
+* 线程安全的通过全局配额管理器为指定源开始一次调用，没有源或没有全局配额管理器则不限制
+*/
+pub fn acquire_quota(src: Option<usize>) -> Result<Option<QuotaPermit>, Throttled> {
+    match (src, quota_manager()) {
+        (Some(src), Some(manager)) => manager.acquire(src).map(Some),
+        _ => Ok(None),
+    }
+}
+
+//移除没有未完成的调用且统计窗口已结束的源的统计
+fn prune(states: &mut HashMap<usize, SourceState>, now: usize) {
+    states.retain(|_, state| state.concurrent > 0 || now.saturating_sub(state.window_start) < QUOTA_WINDOW);
+}
@@ -0,0 +1,222 @@
+use std::sync::Arc;
+use std::cell::RefCell;
+use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use atom::Atom;
+use timer::{TIMER, FuncRuner};
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use adapter::{JS, InterruptReason, pause};
+use bonmgr::NativeObjsAuth;
+use native_bind::load_prelude;
+
+/*
+* 临时虚拟机名
+*/
+const SCRATCH_VM_NAME: &'static str = "scratch vm";
+
+lazy_static! {
+    //临时虚拟机执行次数
+    static ref VM_SCRATCH_EVAL_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_scratch_eval_count"), 0).unwrap();
+    //临时虚拟机执行超时次数
+    static ref VM_SCRATCH_TIMEOUT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_scratch_timeout_count"), 0).unwrap();
+    //临时虚拟机丢弃次数
+    static ref VM_SCRATCH_THROW_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_scratch_throw_count"), 0).unwrap();
+}
+
+thread_local! {
+    //当前线程的临时虚拟机
+    static SCRATCH_VM: RefCell<Option<Arc<JS>>> = RefCell::new(None);
+}
+
+/*
+* 临时虚拟机执行限制
+*/
+#[derive(Debug, Clone)]
+pub struct ScratchLimits {
+    pub timeout:        Duration,   //单次执行的最大时长，超时后中断执行
+    pub max_heap_size:  usize,      //执行后虚拟机的最大堆大小，超过则执行失败，为0表示不限制
+}
+
+impl Default for ScratchLimits {
+    fn default() -> Self {
+        ScratchLimits {
+            timeout: Duration::from_millis(100),
+            max_heap_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/*
+* 临时虚拟机执行结果，只支持基础类型
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScratchValue {
+    Undefined,
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+}
+
+/*
+* 使用当前线程的临时虚拟机同步执行指定脚本，不占用虚拟机工厂的虚拟机，也不经过异步任务调度，适用于格式化、表达式求值等简单的执行
+*/
+pub fn scratch_eval(source: &str) -> Result<ScratchValue, String> {
+    scratch_eval_with(source, &ScratchLimits::default())
+}
+
+/*
+* 使用当前线程的临时虚拟机同步执行指定脚本，并指定执行限制
+*/
+pub fn scratch_eval_with(source: &str, limits: &ScratchLimits) -> Result<ScratchValue, String> {
+    with_scratch_vm(limits, |vm| eval_value(vm, source))
+}
+
+/*
+* 使用当前线程的临时虚拟机同步加载指定字节码后执行指定脚本，字节码只在本次执行中有效
+*/
+pub fn scratch_eval_code(code: &[u8], source: &str, limits: &ScratchLimits) -> Result<ScratchValue, String> {
+    with_scratch_vm(limits, |vm| {
+        if !vm.load(code) {
+            return Err("scratch eval failed, e: load code failed".to_string());
+        }
+        while !vm.is_ran() {
+            if vm.interrupted().is_some() {
+                break;
+            }
+            pause();
+        }
+
+        eval_value(vm, source)
+    })
+}
+
+/*
+* 使用当前线程的临时虚拟机同步加载指定函数的字节码，并设置为指定名称的全局函数后执行指定脚本，函数只在本次执行中有效
+*/
+pub fn scratch_eval_function(name: &str, code: &[u8], source: &str, limits: &ScratchLimits) -> Result<ScratchValue, String> {
+    with_scratch_vm(limits, |vm| {
+        if let Err(e) = vm.load_function(name, code) {
+            return Err(format!("scratch eval failed, e: {}", e));
+        }
+
+        eval_value(vm, source)
+    })
+}
+
+/*
+* 丢弃当前线程的临时虚拟机，下次执行时重新构建
+*/
+pub fn reset_scratch_vm() {
+    SCRATCH_VM.with(|scratch| {
+        scratch.borrow_mut().take();
+    });
+}
+
+//使用当前线程的临时虚拟机执行，执行后重置虚拟机的全局环境，超时、超过堆限制或重置失败则丢弃虚拟机
+fn with_scratch_vm<F>(limits: &ScratchLimits, func: F) -> Result<ScratchValue, String>
+    where F: FnOnce(&Arc<JS>) -> Result<ScratchValue, String> {
+    let vm = SCRATCH_VM.with(|scratch| {
+        let mut scratch = scratch.borrow_mut();
+        if scratch.is_none() {
+            *scratch = new_scratch_vm();
+        }
+        scratch.clone()
+    });
+    let vm = match vm {
+        None => return Err("scratch eval failed, e: create vm failed".to_string()),
+        Some(vm) => vm,
+    };
+
+    VM_SCRATCH_EVAL_COUNT.sum(1);
+    let done = Arc::new(AtomicBool::new(false));
+    watch_scratch(&vm, &done, limits.timeout);
+    let result = func(&vm);
+    done.store(true, Ordering::SeqCst);
+
+    if vm.interrupted().is_some() {
+        //执行超时，虚拟机的执行状态不可信
+        VM_SCRATCH_TIMEOUT_COUNT.sum(1);
+        throw_scratch_vm(&vm);
+        return Err(format!("scratch eval failed, e: timeout, limit: {:?}", limits.timeout));
+    }
+
+    let heap_size = vm.heap_size();
+    if limits.max_heap_size > 0 && heap_size > limits.max_heap_size {
+        //执行后超过堆限制
+        throw_scratch_vm(&vm);
+        return Err(format!("scratch eval failed, e: heap exceeded, size: {}, limit: {}", heap_size, limits.max_heap_size));
+    }
+
+    if !vm.clear_global() || !vm.alloc_global() {
+        warn!("!!!> Scratch Vm Reset Error, vm: {:?}", vm);
+        throw_scratch_vm(&vm);
+    }
+
+    result
+}
+
+//构建临时虚拟机
+fn new_scratch_vm() -> Option<Arc<JS>> {
+    let vm = JS::new(0, Atom::from(SCRATCH_VM_NAME), Arc::new(NativeObjsAuth::new(None, None)), None)?;
+    if !load_prelude(&vm, "") {
+        return None;
+    }
+
+    //创建全局对象模板，用于在每次执行后重置全局环境
+    if !vm.new_global_template() || !vm.alloc_global() {
+        warn!("!!!> Scratch Vm Create Error, new global template failed, vm: {:?}", vm);
+        return None;
+    }
+
+    info!("===> Scratch Vm Create Ok, vm: {:?}", vm);
+    Some(vm)
+}
+
+//丢弃当前线程的指定临时虚拟机
+fn throw_scratch_vm(vm: &Arc<JS>) {
+    SCRATCH_VM.with(|scratch| {
+        let mut scratch = scratch.borrow_mut();
+        if scratch.as_ref().map_or(false, |current| Arc::ptr_eq(current, vm)) {
+            scratch.take();
+        }
+    });
+    VM_SCRATCH_THROW_COUNT.sum(1);
+    info!("===> Scratch Vm Throw Ok, vm: {:?}", vm);
+}
+
+//为临时虚拟机的本次执行启动看门狗，本次执行在指定时长后仍未完成，则中断虚拟机执行
+fn watch_scratch(vm: &Arc<JS>, done: &Arc<AtomicBool>, timeout: Duration) {
+    let vm = vm.clone();
+    let done = done.clone();
+    let runner = FuncRuner::new(Box::new(move || {
+        if !done.load(Ordering::SeqCst) && vm.interrupt_call(InterruptReason::Timeout, None) {
+            warn!("!!!> Scratch Vm Eval Timeout, vm: {:?}, timeout: {:?}", vm, timeout);
+        }
+    }));
+    TIMER.set_timeout(runner, timeout.as_millis() as u32);
+}
+
+//执行指定脚本，并将结果转换为临时虚拟机执行结果
+fn eval_value(vm: &Arc<JS>, source: &str) -> Result<ScratchValue, String> {
+    let value = vm.eval(source.to_string());
+    if value.is_none() {
+        return Err(format!("scratch eval failed, e: {}", vm.stack_top_string().unwrap_or("eval error".to_string())));
+    }
+
+    if value.is_undefined() {
+        Ok(ScratchValue::Undefined)
+    } else if value.is_null() {
+        Ok(ScratchValue::Null)
+    } else if value.is_boolean() {
+        Ok(ScratchValue::Boolean(value.get_boolean()))
+    } else if value.is_number() {
+        Ok(ScratchValue::Number(value.get_f64()))
+    } else if value.is_string() {
+        Ok(ScratchValue::String(value.get_str()))
+    } else {
+        Err("scratch eval failed, e: unsupported result type".to_string())
+    }
+}
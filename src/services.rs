@@ -0,0 +1,101 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use atom::Atom;
+
+use health::{read_state, write_state};
+
+/*
+* 虚拟机通道中保存服务注册表id的属性名
+*/
+pub const SERVICES_ATTR: &'static str = "_$services";
+
+lazy_static! {
+    //服务注册表的分配id
+    static ref SERVICE_REGISTRY_ID: AtomicUsize = AtomicUsize::new(1);
+    //所有未关闭的服务注册表，键为服务注册表id，虚拟机通道通过属性中的服务注册表id查找服务注册表
+    static ref SERVICE_REGISTRIES: Arc<RwLock<HashMap<usize, ServiceRegistry>>> = Arc::new(RwLock::new(HashMap::new()));
+}
+
+/*
+* 服务注册表，在启动时注册处理器依赖的本地服务，例如数据库连接池和缓存，处理器通过虚拟机通道按类型获取服务，
+* 而不需要依赖进程范围的单例，测试时可以为虚拟机工厂或虚拟机通道表注册不同的服务注册表
+*/
+#[derive(Clone)]
+pub struct ServiceRegistry {
+    id:         usize,                                                          //服务注册表id
+    services:   Arc<RwLock<HashMap<(TypeId, Atom), Arc<Any + Send + Sync>>>>,   //服务表，键为(服务类型, 服务名)
+}
+
+impl ServiceRegistry {
+    //构建一个服务注册表，服务注册表在关闭前可以通过id查找
+    pub fn new() -> Self {
+        let registry = ServiceRegistry {
+            id: SERVICE_REGISTRY_ID.fetch_add(1, Ordering::Relaxed),
+            services: Arc::new(RwLock::new(HashMap::new())),
+        };
+        write_state("service_registries", &SERVICE_REGISTRIES).insert(registry.id, registry.clone());
+        registry
+    }
+
+    //获取服务注册表id
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    //获取服务数量
+    pub fn len(&self) -> usize {
+        read_state("services", &self.services).len()
+    }
+
+    //注册指定类型的服务，返回同类型的上一个服务
+    pub fn provide<T: Any + Send + Sync>(&self, service: Arc<T>) -> Option<Arc<T>> {
+        self.provide_named("", service)
+    }
+
+    //注册指定类型和名称的服务，用于同一类型有多个服务的情况，返回同类型同名的上一个服务
+    pub fn provide_named<T: Any + Send + Sync>(&self, name: &str, service: Arc<T>) -> Option<Arc<T>> {
+        write_state("services", &self.services)
+            .insert((TypeId::of::<T>(), Atom::from(name)), service)
+            .and_then(|old| old.downcast::<T>().ok())
+    }
+
+    //获取指定类型的服务
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.get_named("")
+    }
+
+    //获取指定类型和名称的服务
+    pub fn get_named<T: Any + Send + Sync>(&self, name: &str) -> Option<Arc<T>> {
+        read_state("services", &self.services)
+            .get(&(TypeId::of::<T>(), Atom::from(name)))
+            .cloned()
+            .and_then(|service| service.downcast::<T>().ok())
+    }
+
+    //判断是否注册了指定类型的服务
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        read_state("services", &self.services).contains_key(&(TypeId::of::<T>(), Atom::from("")))
+    }
+
+    //移除指定类型的服务
+    pub fn remove<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        write_state("services", &self.services)
+            .remove(&(TypeId::of::<T>(), Atom::from("")))
+            .and_then(|service| service.downcast::<T>().ok())
+    }
+
+    //关闭服务注册表，关闭后无法再通过id查找，已获取服务注册表的处理器不受影响
+    pub fn close(&self) {
+        write_state("service_registries", &SERVICE_REGISTRIES).remove(&self.id);
+    }
+}
+
+/*
+* 通过id查找未关闭的服务注册表
+*/
+pub fn find_service_registry(id: usize) -> Option<ServiceRegistry> {
+    read_state("service_registries", &SERVICE_REGISTRIES).get(&id).cloned()
+}
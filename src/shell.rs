@@ -18,6 +18,7 @@ use worker::impls::{create_js_task_queue, cast_js_task, unlock_js_task_queue};
 use adapter::{JSStatus, JS, dukc_vm_status_check, dukc_vm_status_switch, dukc_vm_status_sub, dukc_callback_count, dukc_top, dukc_to_string, dukc_pop, handle_async_callback};
 use pi_vm_impl::{VMFactoryLoader, VMFactory, new_queue, remove_queue};
 use bonmgr::{NativeObjsAuth, ptr_jstype};
+use names::FactoryName;
 
 /*
 * shell源最小值
@@ -148,7 +149,7 @@ impl ShellManager {
         let init_code = Arc::new(tmp.compile(SHELL_SET_GLOBAL_ENV_FILE_NAME.to_string(), SHELL_SET_GLOBAL_ENV_CODE.to_string()).unwrap());
 
         //顺序加载全局环境初始化代码和其它代码
-        let mut factory = VMFactory::new("native shell", 0, 0, 0, 0, Arc::new(NativeObjsAuth::new(None, None)));
+        let mut factory = VMFactory::new(FactoryName::new("native shell").unwrap(), 0, 0, 0, 0, Arc::new(NativeObjsAuth::new(None, None)));
         factory = factory.append(init_code);
         if let Some(list) = codes {
             for code in list {
@@ -0,0 +1,518 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::sync::Arc;
+use std::str::Chars;
+use std::iter::Peekable;
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use adapter::{JS, JSType, CallValue};
+use pi_vm_impl::ArgsFn;
+
+/*
+* JSON的最大嵌套深度，超过则解析失败，以避免恶意的参数耗尽本地栈
+*/
+const MAX_JSON_DEPTH: usize = 64;
+
+lazy_static! {
+    //端口签名校验失败的次数
+    static ref VM_SIGNATURE_MISMATCH_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_signature_mismatch_count"), 0).unwrap();
+}
+
+/*
+* 端口参数或返回值的类型
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueType {
+    Any,        //任意类型，不校验
+    Undefined,
+    Null,
+    Boolean,
+    Number,
+    String,
+    Bytes,      //ArrayBuffer或Uint8Array
+    Json,       //js对象或数组，参数为JSON文本，在虚拟机中构建为对应的js对象
+}
+
+impl Display for ValueType {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let name = match self {
+            ValueType::Any => "any",
+            ValueType::Undefined => "undefined",
+            ValueType::Null => "null",
+            ValueType::Boolean => "boolean",
+            ValueType::Number => "number",
+            ValueType::String => "string",
+            ValueType::Bytes => "bytes",
+            ValueType::Json => "json",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ValueType {
+    //判断参数是否匹配类型，json类型的参数为JSON文本
+    fn is_arg(&self, value: &CallValue) -> bool {
+        match (self, value) {
+            (ValueType::Json, CallValue::String(_)) => true,
+            _ => self.is_return(value),
+        }
+    }
+
+    //判断返回值是否匹配类型，json类型的返回值为js对象或数组
+    fn is_return(&self, value: &CallValue) -> bool {
+        match (self, value) {
+            (ValueType::Any, _) => true,
+            (ValueType::Undefined, CallValue::Undefined) => true,
+            (ValueType::Null, CallValue::Null) => true,
+            (ValueType::Boolean, CallValue::Boolean(_)) => true,
+            (ValueType::Number, CallValue::Number(_)) => true,
+            (ValueType::String, CallValue::String(_)) => true,
+            (ValueType::Bytes, CallValue::Bytes(_)) => true,
+            (ValueType::Json, CallValue::Other(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/*
+* 端口签名错误
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignatureError {
+    UnknownPort(String),                        //端口未声明签名，(端口)
+    ArgCount(String, usize, usize),             //参数数量不匹配，(端口, 声明的参数数量, 实际的参数数量)
+    ArgType(String, usize, ValueType, String),  //参数类型不匹配，(端口, 参数序号, 声明的类型, 实际的参数)
+    Schema(String, usize, String),              //json参数不符合JSON Schema，(端口, 参数序号, 原因)
+    ReturnType(String, ValueType, String),      //返回值类型不匹配，(端口, 声明的类型, 实际的返回值)
+}
+
+impl Display for SignatureError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            SignatureError::UnknownPort(port) => write!(f, "port signature error, unknown port, port: {:?}", port),
+            SignatureError::ArgCount(port, expected, actual) =>
+                write!(f, "port signature error, arg count mismatch, port: {:?}, expected: {}, actual: {}", port, expected, actual),
+            SignatureError::ArgType(port, index, expected, actual) =>
+                write!(f, "port signature error, arg type mismatch, port: {:?}, index: {}, expected: {}, actual: {}", port, index, expected, actual),
+            SignatureError::Schema(port, index, reason) =>
+                write!(f, "port signature error, arg schema mismatch, port: {:?}, index: {}, e: {}", port, index, reason),
+            SignatureError::ReturnType(port, expected, actual) =>
+                write!(f, "port signature error, return type mismatch, port: {:?}, expected: {}, actual: {}", port, expected, actual),
+        }
+    }
+}
+
+/*
+* 带签名的端口调用错误
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum PortCallError {
+    Signature(SignatureError),  //参数或返回值不匹配端口签名
+    Call(String),               //调用未被接收或执行异常，参数为异常信息
+}
+
+impl Display for PortCallError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            PortCallError::Signature(e) => write!(f, "{}", e),
+            PortCallError::Call(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/*
+* 端口签名，声明端口的参数类型、返回值类型和json参数的JSON Schema，虚拟机工厂在投递调用时校验参数并生成参数构建函数，
+* 在调用完成时校验返回值，以便在rust和js的边界发现双方约定的变化，而不是在脚本深处出错
+*/
+#[derive(Debug, Clone)]
+pub struct PortSignature {
+    args:       Vec<ValueType>,         //参数类型
+    ret:        ValueType,              //返回值类型
+    schemas:    Vec<Option<Json>>,      //json参数的JSON Schema，序号与参数相同
+}
+
+impl PortSignature {
+    //构建一个指定参数类型和返回值类型的端口签名
+    pub fn new(args: Vec<ValueType>, ret: ValueType) -> Self {
+        let schemas = vec![None; args.len()];
+        PortSignature {
+            args,
+            ret,
+            schemas,
+        }
+    }
+
+    //为指定序号的json参数设置JSON Schema，支持type、enum、required、properties和items，必须使用所有权，以保证运行时不会不安全的修改
+    pub fn with_schema(mut self, index: usize, schema: &str) -> Result<Self, String> {
+        match self.args.get(index) {
+            Some(ValueType::Json) => (),
+            _ => return Err(format!("set port schema failed, index: {}, e: not json arg", index)),
+        }
+
+        let schema = parse_json(schema).map_err(|e| format!("set port schema failed, index: {}, e: {}", index, e))?;
+        match schema {
+            Json::Object(_) => (),
+            _ => return Err(format!("set port schema failed, index: {}, e: schema not object", index)),
+        }
+        self.schemas[index] = Some(schema);
+        Ok(self)
+    }
+
+    //获取参数类型
+    pub fn args(&self) -> &[ValueType] {
+        &self.args
+    }
+
+    //获取返回值类型
+    pub fn ret(&self) -> ValueType {
+        self.ret
+    }
+
+    //校验指定端口的参数，并生成参数构建函数
+    pub fn build_args(&self, port: &str, args: Vec<CallValue>) -> Result<ArgsFn, SignatureError> {
+        if args.len() != self.args.len() {
+            VM_SIGNATURE_MISMATCH_COUNT.sum(1);
+            return Err(SignatureError::ArgCount(port.to_string(), self.args.len(), args.len()));
+        }
+
+        let mut values = Vec::with_capacity(args.len());
+        for (index, (ty, arg)) in self.args.iter().zip(args.into_iter()).enumerate() {
+            if !ty.is_arg(&arg) {
+                VM_SIGNATURE_MISMATCH_COUNT.sum(1);
+                return Err(SignatureError::ArgType(port.to_string(), index, *ty, format!("{:?}", arg)));
+            }
+
+            match (ty, arg) {
+                (ValueType::Json, CallValue::String(text)) => {
+                    let json = parse_json(&text).map_err(|e| {
+                        VM_SIGNATURE_MISMATCH_COUNT.sum(1);
+                        SignatureError::Schema(port.to_string(), index, e)
+                    })?;
+                    if let Some(schema) = &self.schemas[index] {
+                        if let Err(e) = validate(schema, &json, "$") {
+                            VM_SIGNATURE_MISMATCH_COUNT.sum(1);
+                            return Err(SignatureError::Schema(port.to_string(), index, e));
+                        }
+                    }
+                    values.push(ArgValue::Json(json));
+                },
+                (_, arg) => values.push(ArgValue::Value(arg)),
+            }
+        }
+
+        Ok(Box::new(move |vm: Arc<JS>| {
+            let count = values.len();
+            for value in values {
+                match value {
+                    ArgValue::Value(value) => push_value(&vm, value),
+                    ArgValue::Json(json) => {
+                        push_json(&vm, &json);
+                    },
+                }
+            }
+            count
+        }))
+    }
+
+    //校验指定端口的返回值
+    pub fn check_result(&self, port: &str, value: &CallValue) -> Result<(), SignatureError> {
+        if self.ret.is_return(value) {
+            return Ok(());
+        }
+
+        VM_SIGNATURE_MISMATCH_COUNT.sum(1);
+        Err(SignatureError::ReturnType(port.to_string(), self.ret, format!("{:?}", value)))
+    }
+}
+
+/*
+* 已校验的参数
+*/
+enum ArgValue {
+    Value(CallValue),   //非json参数
+    Json(Json),         //已解析的json参数
+}
+
+/*
+* 已解析的JSON值
+*/
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    //获取JSON值的JSON Schema类型名
+    fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Boolean(_) => "boolean",
+            Json::Number(_) => "number",
+            Json::String(_) => "string",
+            Json::Array(_) => "array",
+            Json::Object(_) => "object",
+        }
+    }
+
+    //获取对象的指定域
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+//将参数压入虚拟机栈
+pub(crate) fn push_value(vm: &Arc<JS>, value: CallValue) {
+    match value {
+        CallValue::Undefined => {
+            vm.new_undefined();
+        },
+        CallValue::Null => {
+            vm.new_null();
+        },
+        CallValue::Boolean(b) => {
+            vm.new_boolean(b);
+        },
+        CallValue::Number(n) => {
+            vm.new_f64(n);
+        },
+        CallValue::String(s) | CallValue::Other(s) => {
+            if vm.new_str(s).is_err() {
+                vm.new_undefined();
+            }
+        },
+        CallValue::Bytes(bytes) => {
+            let buffer = vm.new_uint8_array(bytes.len() as u32);
+            buffer.from_bytes(&bytes);
+        },
+    }
+}
+
+//在虚拟机中构建JSON值对应的js值，并压入虚拟机栈
+fn push_json(vm: &Arc<JS>, json: &Json) -> JSType {
+    match json {
+        Json::Null => vm.new_null(),
+        Json::Boolean(b) => vm.new_boolean(*b),
+        Json::Number(n) => vm.new_f64(*n),
+        Json::String(s) => vm.new_str(s.clone()).unwrap_or_else(|_| vm.new_undefined()),
+        Json::Array(items) => {
+            let array = vm.new_array();
+            for (index, item) in items.iter().enumerate() {
+                let mut value = push_json(vm, item);
+                vm.set_index(&array, index as u32, &mut value);
+            }
+            array
+        },
+        Json::Object(fields) => {
+            let object = vm.new_object();
+            for (key, field) in fields {
+                let mut value = push_json(vm, field);
+                vm.set_field(&object, key.clone(), &mut value);
+            }
+            object
+        },
+    }
+}
+
+//使用JSON Schema校验JSON值，失败则返回不匹配的路径和原因
+fn validate(schema: &Json, value: &Json, path: &str) -> Result<(), String> {
+    if let Some(ty) = schema.get("type") {
+        let matched = match ty {
+            Json::String(name) => is_type(name, value),
+            Json::Array(names) => names.iter().any(|name| match name {
+                Json::String(name) => is_type(name, value),
+                _ => false,
+            }),
+            _ => true,
+        };
+        if !matched {
+            return Err(format!("type mismatch, path: {}, actual: {}", path, value.type_name()));
+        }
+    }
+
+    if let Some(Json::Array(values)) = schema.get("enum") {
+        if !values.iter().any(|item| item == value) {
+            return Err(format!("enum mismatch, path: {}", path));
+        }
+    }
+
+    if let Json::Object(_) = value {
+        if let Some(Json::Array(required)) = schema.get("required") {
+            for key in required {
+                if let Json::String(key) = key {
+                    if value.get(key).is_none() {
+                        return Err(format!("required field missing, path: {}.{}", path, key));
+                    }
+                }
+            }
+        }
+
+        if let Some(Json::Object(properties)) = schema.get("properties") {
+            for (key, sub_schema) in properties {
+                if let Some(field) = value.get(key) {
+                    validate(sub_schema, field, &format!("{}.{}", path, key))?;
+                }
+            }
+        }
+    }
+
+    if let Json::Array(items) = value {
+        if let Some(item_schema) = schema.get("items") {
+            for (index, item) in items.iter().enumerate() {
+                validate(item_schema, item, &format!("{}[{}]", path, index))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+//判断JSON值是否匹配指定的JSON Schema类型名
+fn is_type(name: &str, value: &Json) -> bool {
+    match (name, value) {
+        ("integer", Json::Number(n)) => n.fract() == 0.0,
+        (name, value) => name == value.type_name(),
+    }
+}
+
+//解析JSON文本
+fn parse_json(text: &str) -> Result<Json, String> {
+    let mut chars = text.chars().peekable();
+    let json = parse_value(&mut chars, 0)?;
+    skip_whitespace(&mut chars);
+    if chars.peek().is_some() {
+        return Err("invalid json, e: trailing characters".to_string());
+    }
+    Ok(json)
+}
+
+//解析JSON值
+fn parse_value(chars: &mut Peekable<Chars>, depth: usize) -> Result<Json, String> {
+    if depth > MAX_JSON_DEPTH {
+        return Err("invalid json, e: too deep".to_string());
+    }
+
+    skip_whitespace(chars);
+    match chars.peek().cloned() {
+        None => Err("invalid json, e: unexpected end".to_string()),
+        Some('n') => parse_literal(chars, "null", Json::Null),
+        Some('t') => parse_literal(chars, "true", Json::Boolean(true)),
+        Some('f') => parse_literal(chars, "false", Json::Boolean(false)),
+        Some('"') => parse_string(chars).map(Json::String),
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            skip_whitespace(chars);
+            if chars.peek() == Some(&']') {
+                chars.next();
+                return Ok(Json::Array(items));
+            }
+            loop {
+                items.push(parse_value(chars, depth + 1)?);
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(']') => return Ok(Json::Array(items)),
+                    _ => return Err("invalid json, e: expect ',' or ']'".to_string()),
+                }
+            }
+        },
+        Some('{') => {
+            chars.next();
+            let mut fields = Vec::new();
+            skip_whitespace(chars);
+            if chars.peek() == Some(&'}') {
+                chars.next();
+                return Ok(Json::Object(fields));
+            }
+            loop {
+                skip_whitespace(chars);
+                let key = parse_string(chars)?;
+                skip_whitespace(chars);
+                if chars.next() != Some(':') {
+                    return Err("invalid json, e: expect ':'".to_string());
+                }
+                fields.push((key, parse_value(chars, depth + 1)?));
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some('}') => return Ok(Json::Object(fields)),
+                    _ => return Err("invalid json, e: expect ',' or '}'".to_string()),
+                }
+            }
+        },
+        Some(_) => parse_number(chars),
+    }
+}
+
+//解析JSON字面量
+fn parse_literal(chars: &mut Peekable<Chars>, literal: &str, value: Json) -> Result<Json, String> {
+    for expected in literal.chars() {
+        if chars.next() != Some(expected) {
+            return Err(format!("invalid json, e: expect {}", literal));
+        }
+    }
+    Ok(value)
+}
+
+//解析JSON字符串
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    if chars.next() != Some('"') {
+        return Err("invalid json, e: expect string".to_string());
+    }
+
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            None => return Err("invalid json, e: unterminated string".to_string()),
+            Some('"') => return Ok(s),
+            Some('\\') => match chars.next() {
+                Some('"') => s.push('"'),
+                Some('\\') => s.push('\\'),
+                Some('/') => s.push('/'),
+                Some('b') => s.push('\u{8}'),
+                Some('f') => s.push('\u{c}'),
+                Some('n') => s.push('\n'),
+                Some('r') => s.push('\r'),
+                Some('t') => s.push('\t'),
+                Some('u') => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&code, 16).ok().and_then(::std::char::from_u32).unwrap_or('\u{fffd}');
+                    s.push(c);
+                },
+                _ => return Err("invalid json, e: invalid escape".to_string()),
+            },
+            Some(c) => s.push(c),
+        }
+    }
+}
+
+//解析JSON数字
+fn parse_number(chars: &mut Peekable<Chars>) -> Result<Json, String> {
+    let mut s = String::new();
+    while let Some(&c) = chars.peek() {
+        if c.is_ascii_digit() || "+-.eE".contains(c) {
+            s.push(c);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+
+    s.parse::<f64>().map(Json::Number).map_err(|_| format!("invalid json, e: invalid value {:?}", s))
+}
+
+//跳过空白字符
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().map_or(false, |c| c.is_whitespace()) {
+        chars.next();
+    }
+}
@@ -0,0 +1,186 @@
+use std::thread;
+use std::sync::{Arc, Mutex};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+
+use health::lock_state;
+use pi_vm_impl::VMFactory;
+use task_info::TaskInfo;
+use names::PortName;
+
+/*
+* 虚拟机池压力测试配置
+*/
+#[derive(Clone)]
+pub struct StressConfig {
+    pub threads:    usize,                          //并发线程数
+    pub iterations: usize,                          //每个线程的操作次数
+    pub capacity:   usize,                          //生成操作允许的虚拟机数量上限
+    pub seed:       u64,                            //随机种子，相同种子的操作序列相同，便于复现
+    pub hold:       Duration,                       //取出虚拟机后持有的时长
+    pub reload:     Option<Vec<Arc<Vec<u8>>>>,      //替换字节码操作使用的字节码，为空表示不执行替换字节码操作
+    pub call:       Option<PortName>,               //调用操作使用的端口，为空表示不执行调用操作，调用需要任务池
+}
+
+impl Default for StressConfig {
+    fn default() -> Self {
+        StressConfig {
+            threads: 8,
+            iterations: 1000,
+            capacity: 16,
+            seed: 0,
+            hold: Duration::from_micros(0),
+            reload: None,
+            call: None,
+        }
+    }
+}
+
+/*
+* 虚拟机池压力测试报告
+*/
+#[derive(Debug, Clone)]
+pub struct StressReport {
+    pub factory:    String,         //虚拟机工厂名
+    pub operations: usize,          //执行的操作总数
+    pub produced:   usize,          //生成操作次数
+    pub acquired:   usize,          //取出并归还操作次数
+    pub shrinked:   usize,          //丢弃的空闲虚拟机数量
+    pub reloaded:   usize,          //替换字节码操作次数
+    pub called:     usize,          //调用操作次数
+    pub elapsed:    Duration,       //压力测试耗时
+    pub violations: Vec<String>,    //违反的不变式
+}
+
+impl StressReport {
+    //判断是否没有违反任何不变式
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/*
+* 在多个线程中随机的并发生成、取出归还、丢弃、替换字节码和调用虚拟机工厂，并检查虚拟机池的不变式：
+* 虚拟机数量不超过上限，同一个虚拟机不会同时被取出两次，结束时所有虚拟机都在空闲虚拟机池中且没有重复
+* 压力测试会修改虚拟机工厂，只允许使用独立的可复用虚拟机工厂，调用操作完成前虚拟机工厂不是静止的，所以会在结束时等待调用完成
+*/
+pub fn stress_factory(factory: &VMFactory, config: StressConfig) -> StressReport {
+    let start = Instant::now();
+    let checked_out: Arc<Mutex<HashSet<usize>>> = Arc::new(Mutex::new(HashSet::new()));
+    let violations: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    let counters: Arc<Vec<AtomicUsize>> = Arc::new((0..5).map(|_| AtomicUsize::new(0)).collect());
+
+    let mut handles = Vec::with_capacity(config.threads);
+    for index in 0..config.threads {
+        let factory = factory.clone();
+        let config = config.clone();
+        let checked_out = checked_out.clone();
+        let violations = violations.clone();
+        let counters = counters.clone();
+        handles.push(thread::spawn(move || {
+            let mut rng = SmallRng::seed_from_u64(config.seed.wrapping_add(index as u64));
+            for _ in 0..config.iterations {
+                match rng.gen_range(0, 5) {
+                    0 => {
+                        if factory.size() < config.capacity && factory.produce(1).is_ok() {
+                            counters[0].fetch_add(1, Ordering::Relaxed);
+                        }
+                    },
+                    1 => {
+                        if let Some(vm) = factory.try_acquire() {
+                            let id = vm.get_id();
+                            if !lock_state("vm_stress", &checked_out).insert(id) {
+                                lock_state("vm_stress", &violations).push(format!("vm acquired twice, id: {}", id));
+                            }
+                            if config.hold > Duration::from_micros(0) {
+                                thread::sleep(config.hold);
+                            }
+                            lock_state("vm_stress", &checked_out).remove(&id);
+                            drop(vm); //归还虚拟机
+                            counters[1].fetch_add(1, Ordering::Relaxed);
+                        }
+                    },
+                    2 => {
+                        counters[2].fetch_add(factory.shrink(1), Ordering::Relaxed);
+                    },
+                    3 => {
+                        if let Some(codes) = &config.reload {
+                            if factory.reload_codes(codes.clone()).is_ok() {
+                                counters[3].fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    },
+                    _ => {
+                        if let Some(port) = &config.call {
+                            if factory.call(None, port.clone(), Box::new(|_vm| 0), TaskInfo::from("vm stress call")).is_ok() {
+                                counters[4].fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    },
+                }
+
+                //生成操作先检查再生成，所以并发时最多超过上限线程数个虚拟机
+                let size = factory.size();
+                if size > config.capacity + config.threads {
+                    lock_state("vm_stress", &violations).push(format!("vm size exceeded, size: {}, capacity: {}", size, config.capacity));
+                }
+            }
+        }));
+    }
+    for handle in handles {
+        if handle.join().is_err() {
+            lock_state("vm_stress", &violations).push("stress thread panic".to_string());
+        }
+    }
+
+    //等待调用完成，以使虚拟机工厂静止
+    while factory.in_flight() > 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let mut violations = lock_state("vm_stress", &violations).clone();
+    check_quiescent(factory, &mut violations);
+
+    let count = |index: usize| counters[index].load(Ordering::Relaxed);
+    StressReport {
+        factory: factory.name(),
+        operations: config.threads * config.iterations,
+        produced: count(0),
+        acquired: count(1),
+        shrinked: count(2),
+        reloaded: count(3),
+        called: count(4),
+        elapsed: start.elapsed(),
+        violations,
+    }
+}
+
+//检查静止的虚拟机工厂，所有虚拟机都必须是空闲的，且空闲虚拟机没有重复
+fn check_quiescent(factory: &VMFactory, violations: &mut Vec<String>) {
+    if factory.checked_out() != 0 {
+        violations.push(format!("vm not returned, checked out: {}", factory.checked_out()));
+    }
+
+    let size = factory.size();
+    let free = factory.free_pool_size() + factory.free_buf_size() + factory.pinned_count();
+    if free != size {
+        violations.push(format!("vm lost, size: {}, free: {}", size, free));
+    }
+
+    //取出所有空闲虚拟机，检查是否有被重复归还的虚拟机
+    let mut ids = HashSet::new();
+    let mut guards = Vec::new();
+    while let Some(vm) = factory.try_acquire() {
+        if !ids.insert(vm.get_id()) {
+            violations.push(format!("vm returned twice, id: {}", vm.get_id()));
+        }
+        guards.push(vm);
+    }
+    if ids.len() != size {
+        violations.push(format!("vm lost, size: {}, acquired: {}", size, ids.len()));
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use atom::Atom;
+
+use adapter::now_utc;
+
+/*
+* 任务id分配器
+*/
+lazy_static! {
+    static ref TASK_ID_ALLOCATOR: AtomicUsize = AtomicUsize::new(1);
+}
+
+/*
+* 预先构建的热路径任务名，避免每次派发任务时重复构建任务名
+*/
+lazy_static! {
+    //异步调用回应任务名
+    pub static ref ASYNC_CALL_RESPONSE_TASK: Atom = Atom::from("vm async call response task");
+    //异步阻塞调用回应任务名
+    pub static ref ASYNC_BLOCK_CALL_RESPONSE_TASK: Atom = Atom::from("vm async block call response task");
+    //捕获js异常任务名
+    pub static ref CATCH_THROW_TASK: Atom = Atom::from("js catch throw task");
+    //虚拟机工厂同步调用任务名
+    pub static ref FACTORY_SYNC_CALL_TASK: Atom = Atom::from("vm factory sync call task");
+    //虚拟机工厂补充虚拟机任务名
+    pub static ref FACTORY_REPLENISH_TASK: Atom = Atom::from("vm factory replenish task");
+    //虚拟机工厂生成虚拟机任务名
+    pub static ref FACTORY_PRODUCE_TASK: Atom = Atom::from("vm factory produce task");
+    //虚拟机工厂异步生成虚拟机任务名
+    pub static ref FACTORY_PRODUCE_ASYNC_TASK: Atom = Atom::from("vm factory produce async task");
+}
+
+/*
+* 任务元信息，在投递任务时构建，可以用于查询虚拟机正在执行和等待执行的任务
+*/
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    id:             usize,          //任务唯一id
+    name:           Atom,           //任务名
+    correlation_id: Option<u64>,    //任务关联id，用于关联同一个请求产生的多个任务
+    source:         Option<usize>,  //任务源
+    priority:       usize,          //任务优先级
+    created_at:     usize,          //任务构建时间，单位us
+    submitted_at:   usize,          //任务提交到虚拟机工厂的时间，单位us，未提交则为任务构建时间
+}
+
+impl Display for TaskInfo {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.name.as_str())
+    }
+}
+
+impl From<Atom> for TaskInfo {
+    fn from(name: Atom) -> Self {
+        let now = now_utc();
+        TaskInfo {
+            id: TASK_ID_ALLOCATOR.fetch_add(1, Ordering::Relaxed),
+            name,
+            correlation_id: None,
+            source: None,
+            priority: 0,
+            created_at: now,
+            submitted_at: now,
+        }
+    }
+}
+
+//使用预先构建的任务名，只复制任务名的引用
+impl From<&'static Atom> for TaskInfo {
+    fn from(name: &'static Atom) -> Self {
+        TaskInfo::from(name.clone())
+    }
+}
+
+impl<'a> From<&'a str> for TaskInfo {
+    fn from(name: &'a str) -> Self {
+        TaskInfo::from(Atom::from(name))
+    }
+}
+
+impl From<String> for TaskInfo {
+    fn from(name: String) -> Self {
+        TaskInfo::from(Atom::from(name))
+    }
+}
+
+impl TaskInfo {
+    //构建一个任务元信息
+    pub fn new(name: &str) -> Self {
+        TaskInfo::from(name)
+    }
+
+    //设置任务关联id
+    pub fn with_correlation_id(mut self, correlation_id: u64) -> Self {
+        self.correlation_id = Some(correlation_id);
+        self
+    }
+
+    //设置任务源
+    pub fn with_source(mut self, source: usize) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    //设置任务优先级
+    pub fn with_priority(mut self, priority: usize) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    //记录任务提交到虚拟机工厂的时间
+    pub fn submit(mut self) -> Self {
+        self.submitted_at = now_utc();
+        self
+    }
+
+    //获取任务唯一id
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    //获取任务名
+    pub fn name(&self) -> Atom {
+        self.name.clone()
+    }
+
+    //获取任务关联id
+    pub fn correlation_id(&self) -> Option<u64> {
+        self.correlation_id
+    }
+
+    //获取任务源
+    pub fn source(&self) -> Option<usize> {
+        self.source
+    }
+
+    //获取任务优先级
+    pub fn priority(&self) -> usize {
+        self.priority
+    }
+
+    //获取任务构建时间，单位us
+    pub fn created_at(&self) -> usize {
+        self.created_at
+    }
+
+    //获取任务提交到虚拟机工厂的时间，单位us
+    pub fn submitted_at(&self) -> usize {
+        self.submitted_at
+    }
+
+    //获取任务已等待或已执行的时长，单位us
+    pub fn elapsed(&self) -> usize {
+        now_utc().saturating_sub(self.created_at)
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use atom::Atom;
+use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
+
+use health::lock_state;
+
+lazy_static! {
+    //虚拟机被多个线程同时访问的次数
+    static ref VM_THREAD_VIOLATION_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_thread_violation_count"), 0).unwrap();
+}
+
+/*
+* 虚拟机的线程持有记录，启用threadaudit特性构建并为虚拟机启用审计后，记录当前持有虚拟机的线程和持有位置，
+* 其它线程在持有期间访问虚拟机时断言失败，用于检查同一时间只有一个线程访问同一个虚拟机的约定，未启用时不记录
+*/
+pub struct VmBorrow {
+    enabled:    AtomicBool,                                     //是否启用审计
+    owner:      Mutex<Option<(ThreadId, &'static str, usize)>>, //当前持有虚拟机的线程、持有位置和同一线程的重入深度
+}
+
+impl VmBorrow {
+    //构建一个未启用审计的线程持有记录
+    pub fn new() -> Self {
+        VmBorrow {
+            enabled: AtomicBool::new(false),
+            owner: Mutex::new(None),
+        }
+    }
+
+    //判断是否启用审计，未启用threadaudit特性构建时总是未启用
+    pub fn is_enabled(&self) -> bool {
+        cfg!(feature = "threadaudit") && self.enabled.load(Ordering::Relaxed)
+    }
+
+    //设置是否启用审计
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    //获取当前持有虚拟机的线程和持有位置
+    pub fn owner(&self) -> Option<(ThreadId, &'static str)> {
+        lock_state("vm_thread_owner", &self.owner).as_ref().map(|&(thread, site, _)| (thread, site))
+    }
+
+    //当前线程在指定位置持有虚拟机，同一线程可以重入，其它线程正在持有则断言失败
+    fn acquire(&self, vm: usize, site: &'static str) {
+        let current = thread::current().id();
+        let violation = {
+            let mut owner = lock_state("vm_thread_owner", &self.owner);
+            match *owner {
+                None => {
+                    *owner = Some((current, site, 1));
+                    None
+                },
+                Some((thread, _, ref mut depth)) if thread == current => {
+                    *depth += 1;
+                    None
+                },
+                Some((thread, holder, _)) => Some((thread, holder)),
+            }
+        };
+
+        if let Some((thread, holder)) = violation {
+            //必须在释放锁后断言失败，以避免毒化线程持有记录
+            VM_THREAD_VIOLATION_COUNT.sum(1);
+            warn!("!!!> Vm Thread Violation, vm: {}, site: {}, thread: {:?}, owner site: {}, owner thread: {:?}",
+                  vm, site, current, holder, thread);
+            panic!("vm accessed by two threads at the same time, vm: {}, site: {}, owner site: {}", vm, site, holder);
+        }
+    }
+
+    //当前线程交出虚拟机，无论重入深度都立即释放当前线程的持有，用于在执行完成后将虚拟机交给其它线程
+    pub fn hand_off(&self) {
+        let current = thread::current().id();
+        let mut owner = lock_state("vm_thread_owner", &self.owner);
+        if owner.as_ref().map_or(false, |&(thread, _, _)| thread == current) {
+            *owner = None;
+        }
+    }
+
+    //当前线程释放一次持有
+    fn release(&self) {
+        let current = thread::current().id();
+        let mut owner = lock_state("vm_thread_owner", &self.owner);
+        let is_released = match *owner {
+            Some((thread, _, ref mut depth)) if thread == current => {
+                *depth -= 1;
+                *depth == 0
+            },
+            _ => false,
+        };
+        if is_released {
+            *owner = None;
+        }
+    }
+}
+
+/*
+* 线程持有虚拟机的守护者，在被释放时释放持有
+*/
+pub struct VmBorrowGuard {
+    borrow: Option<Arc<VmBorrow>>,  //被持有的线程持有记录，未启用审计则为空
+}
+
+impl Drop for VmBorrowGuard {
+    fn drop(&mut self) {
+        if let Some(borrow) = self.borrow.take() {
+            borrow.release();
+        }
+    }
+}
+
+/*
+* 当前线程在指定位置持有虚拟机，返回的守护者被释放时释放持有，未启用审计则忽略
+*/
+pub fn borrow_vm(borrow: &Arc<VmBorrow>, vm: usize, site: &'static str) -> VmBorrowGuard {
+    if !borrow.is_enabled() {
+        return VmBorrowGuard {
+            borrow: None,
+        };
+    }
+
+    borrow.acquire(vm, site);
+    VmBorrowGuard {
+        borrow: Some(borrow.clone()),
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::{Arc, Weak, RwLock};
+use std::collections::HashMap;
+
+use atom::Atom;
+
+use adapter::{JSStatus, JS, vm_status_batch};
+use health::{read_state, write_state};
+
+/*
+* 虚拟机注册表，记录所有虚拟机工厂构建的存活虚拟机，键为(虚拟机工厂名, 虚拟机id)
+*/
+lazy_static! {
+    static ref VM_REGISTRY: Arc<RwLock<HashMap<(Atom, usize), Weak<JS>>>> = Arc::new(RwLock::new(HashMap::new()));
+}
+
+/*
+* 虚拟机统计
+*/
+#[derive(Debug, Clone)]
+pub struct VmStat {
+    pub id:         usize,                      //虚拟机id
+    pub name:       String,                     //虚拟机所属虚拟机工厂名
+    pub labels:     HashMap<String, String>,    //虚拟机标签
+    pub heap_size:  usize,                      //虚拟机当前堆大小
+    pub queue_len:  usize,                      //虚拟机当前消息队列长度
+    pub last_time:  usize,                      //虚拟机上次运行时间，单位us
+    pub status:     Option<JSStatus>,           //虚拟机当前状态，未知的状态为空
+}
+
+//注册虚拟机
+pub fn register_vm(vm: &Arc<JS>) {
+    write_state("vm_registry", &VM_REGISTRY).insert((vm.get_name(), vm.get_id()), Arc::downgrade(vm));
+}
+
+//注销虚拟机
+pub fn unregister_vm(name: &Atom, id: usize) {
+    write_state("vm_registry", &VM_REGISTRY).remove(&(name.clone(), id));
+}
+
+//获取存活虚拟机数量
+pub fn vm_count() -> usize {
+    read_state("vm_registry", &VM_REGISTRY).len()
+}
+
+//线程安全的终止指定虚拟机正在执行的脚本，虚拟机不存在、空闲或已被中断则返回false
+pub fn terminate_vm(name: &Atom, id: usize) -> bool {
+    let vm = read_state("vm_registry", &VM_REGISTRY).get(&(name.clone(), id)).and_then(|vm| vm.upgrade());
+    match vm {
+        None => false,
+        Some(vm) => vm.terminate(),
+    }
+}
+
+//获取与所有指定标签匹配的存活虚拟机，标签为空则获取所有存活虚拟机
+pub fn find_vms(labels: &[(&str, &str)]) -> Vec<Arc<JS>> {
+    //必须在释放注册表的锁后再过滤，被过滤的虚拟机可能是最后一个强引用，释放时会注销虚拟机并获取注册表的写锁
+    let vms: Vec<Arc<JS>> = read_state("vm_registry", &VM_REGISTRY)
+        .values()
+        .filter_map(|vm| vm.upgrade())
+        .collect();
+
+    vms.into_iter()
+        .filter(|vm| vm.match_labels(labels))
+        .collect()
+}
+
+//获取与所有指定标签匹配的存活虚拟机的统计，批量获取所有虚拟机的状态
+pub fn vm_stats(labels: &[(&str, &str)]) -> Vec<VmStat> {
+    let vms = find_vms(labels);
+    let status = vm_status_batch(&vms);
+    vms.iter().zip(status.into_iter()).map(|(vm, status)| {
+        VmStat {
+            id: vm.get_id(),
+            name: vm.get_name().to_string(),
+            labels: vm.labels(),
+            heap_size: vm.heap_size(),
+            queue_len: vm.get_queue_len(),
+            last_time: vm.last_time(),
+            status,
+        }
+    }).collect()
+}
@@ -1,3 +1,5 @@
+#![cfg(feature = "debugger")]
+
 extern crate atom;
 extern crate worker;
 extern crate pi_vm;
@@ -22,6 +24,7 @@ use atom::Atom;
 use pi_vm::pi_vm_impl::{block_reply, push_callback};
 use pi_vm::adapter::{JSType, JS, register_native_object};
 use pi_vm::shell::SHELL_MANAGER;
+use pi_vm::task_info::TaskInfo;
 use pi_vm::bonmgr::{BON_MGR, NativeObjsAuth, FnMeta, CallResult};
 
 //测试shell的代码
@@ -166,7 +169,7 @@ fn shell_block_call(js: Arc<JS>, _args: Vec<JSType>) -> Option<CallResult> {
     let result = Box::new(|vm: Arc<JS>| {
         vm.new_str("Hello World!".to_string());
     });
-    block_reply(js, result, Atom::from("block reply task"));
+    block_reply(js, result, TaskInfo::from("block reply task"));
     None
 }
 
@@ -177,7 +180,7 @@ fn shell_async_callback(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
         vm.new_str("Hello World!".to_string());
         1
     });
-    push_callback(js.clone(), args[0].get_u32(), func, None, Atom::from("register callback task"));
+    push_callback(js.clone(), args[0].get_u32(), func, None, TaskInfo::from("register callback task"));
     js.new_boolean(true);
     Some(CallResult::Ok)
 }
\ No newline at end of file
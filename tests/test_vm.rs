@@ -7,13 +7,14 @@ extern crate atom;
 extern crate task_pool;
 extern crate timer;
 extern crate handler;
+extern crate gray;
 extern crate pi_vm;
 extern crate apm;
 
 use std::mem;
 use std::thread;
 use std::ffi::CString;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Instant, Duration};
 use std::sync::{Arc, Mutex, Condvar};
 
@@ -40,14 +41,36 @@ use worker::task::TaskType;
 use worker::worker::WorkerType;
 use worker::worker_pool::WorkerPool;
 use worker::impls::{TASK_POOL_TIMER, JS_WORKER_WALKER, JS_TASK_POOL, create_js_task_queue, lock_js_task_queue, unlock_js_task_queue, cast_js_task};
-use pi_vm::pi_vm_impl::{VMFactory, block_reply, block_throw, push_callback, register_async_request};
-use pi_vm::adapter::{load_lib_backtrace, register_native_object, dukc_remove_value, dukc_top, JS, JSType, set_vm_timeout};
-use pi_vm::channel_map::VMChannel;
+use pi_vm::pi_vm_impl::{VMFactory, VMFactoryError, FactoryLimits, BudgetExhausted, RecyclePolicy, PendingLimits, PendingPolicy, LateCallbackPolicy, block_reply, block_throw, push_callback, register_async_request};
+use pi_vm::adapter::{load_lib_backtrace, register_native_object, vm_status_batch, dukc_remove_value, dukc_top, JS, JSType, JSStatus, CallValue, VmProfile, set_vm_timeout};
+use pi_vm::channel_map::{INLINE_MSG_SIZE, ChannelMsg, ChannelHandler, VMChannel, VMChannelPeer, VMChannelMap, RequestStatus};
+#[cfg(feature = "cluster")]
 use pi_vm::proc::{Process, ProcInfo, ProcessFactory};
 use apm::allocator::set_max_alloced_limit;
 use pi_vm::bonmgr::{CallResult, NativeObjsAuth, FnMeta, BON_MGR};
+#[cfg(feature = "cluster")]
 use pi_vm::proc_pool::{set_factory, spawn_process, name_to_pid, set_receiver, set_catcher, close_process, pid_send, name_send};
+#[cfg(feature = "cluster")]
 use pi_vm::duk_proc::{DukProcess, DukProcessFactory};
+use pi_vm::task_info::TaskInfo;
+use pi_vm::pipeline::{Pipeline, PipelineError, PipeNext, register_pipeline, call_pipeline};
+use pi_vm::manifest::HandlerVersion;
+use pi_vm::health::{health, write_state, read_state};
+use pi_vm::histogram::LatencyHistogram;
+use pi_vm::factory_builder::VMFactoryBuilder;
+use pi_vm::names::{FactoryName, PortName};
+use pi_vm::factory_registry::{get_factory, remove_factory, factory_names, registry_stats};
+use pi_vm::metrics::factory_stats;
+use pi_vm::scratch::{ScratchValue, scratch_eval};
+use pi_vm::expression::ExpressionEngine;
+use pi_vm::bundle::{compile_bundle, compile_source, bundle_port};
+use pi_vm::code_cache::{code_hash, intern_code};
+use pi_vm::code_source::{CodeSource, DirSource, GlobSource, LoadOrder, FnSource};
+use pi_vm::code_verify::SignatureVerifier;
+use pi_vm::code_crypt::{CodeCipher, is_encrypted_code};
+use pi_vm::bytecode::{BytecodeError, duk_version, stamp_bytecode, bytecode_version, check_bytecode};
+use pi_vm::leak_detector::LeakDetector;
+use pi_vm::services::ServiceRegistry;
 
 // // #[test]
 // fn njsc_test() {
@@ -290,7 +313,7 @@ fn test_vm_factory() {
 
     //要测试虚拟机复用，需要将factory capacity设置为大于0，且produce生成的虚拟机数量应该大于0
     //如果需要测试虚拟机不复用，需要将factory capacity和produce都设置为0
-    let factory = VMFactory::new("test vm", 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let factory = VMFactory::new(FactoryName::new("test vm").unwrap(), 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
     let factory = factory.append(Arc::new(code));
     match factory.produce(3) {
         Err(e) => println!("factory produce failed, e: {:?}", e),
@@ -304,9 +327,9 @@ fn test_vm_factory() {
                     2usize
                 });
                 factory.call(None,
-                             Atom::from("call"),
+                             PortName::new("call").unwrap(),
                              func,
-                             Atom::from("test factory call task"));
+                             TaskInfo::from("test factory call task")).unwrap();
                 thread::sleep(Duration::from_millis(1000));
             }
             println!("!!!!!!time: {:?}", Instant::now() - now);
@@ -345,7 +368,7 @@ fn test_vm_factory_sync_call() {
 
     //要测试虚拟机复用，需要将factory capacity设置为大于0，且produce生成的虚拟机数量应该大于0
     //如果需要测试虚拟机不复用，需要将factory capacity和produce都设置为0
-    let factory = VMFactory::new("test vm", 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let factory = VMFactory::new(FactoryName::new("test vm").unwrap(), 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
     let factory = factory.append(Arc::new(code));
     match factory.produce(3) {
         Err(e) => println!("factory produce failed, e: {:?}", e),
@@ -359,9 +382,9 @@ fn test_vm_factory_sync_call() {
                     2usize
                 });
                 factory.call(None,
-                             Atom::from("call"),
+                             PortName::new("call").unwrap(),
                              func,
-                             Atom::from("test factory call task"));
+                             TaskInfo::from("test factory call task")).unwrap();
                 thread::sleep(Duration::from_millis(1000));
             }
             println!("!!!!!!time: {:?}", Instant::now() - now);
@@ -401,7 +424,7 @@ fn test_vm_factory_block_call() {
 
     //要测试虚拟机复用，需要将factory capacity设置为大于0，且produce生成的虚拟机数量应该大于0
     //如果需要测试虚拟机不复用，需要将factory capacity和produce都设置为0
-    let factory = VMFactory::new("test vm", 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let factory = VMFactory::new(FactoryName::new("test vm").unwrap(), 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
     let factory = factory.append(Arc::new(code));
     match factory.produce(3) {
         Err(e) => println!("factory produce failed, e: {:?}", e),
@@ -415,9 +438,9 @@ fn test_vm_factory_block_call() {
                     2usize
                 });
                 factory.call(None,
-                             Atom::from("call"),
+                             PortName::new("call").unwrap(),
                              func,
-                             Atom::from("test factory call task"));
+                             TaskInfo::from("test factory call task")).unwrap();
                 thread::sleep(Duration::from_millis(1000));
             }
             println!("!!!!!!time: {:?}", Instant::now() - now);
@@ -430,12 +453,12 @@ fn js_test_vm_factory_block_call(js: Arc<JS>, _args: Vec<JSType>) -> Option<Call
     let result = Box::new(|vm: Arc<JS>| {
         vm.new_u32(0xffffffff);
     });
-    block_reply(js, result, Atom::from("block reply task"));
+    block_reply(js, result, TaskInfo::from("block reply task"));
     None
 }
 
 fn js_test_vm_factory_block_throw(js: Arc<JS>, _args: Vec<JSType>) -> Option<CallResult> {
-    block_throw(js, "test block throw".to_string(), Atom::from("block throw task"));
+    block_throw(js, "test block throw".to_string(), TaskInfo::from("block throw task"));
     None
 }
 
@@ -467,7 +490,7 @@ fn test_vm_factory_async_callback() {
 
     //要测试虚拟机复用，需要将factory capacity设置为大于0，且produce生成的虚拟机数量应该大于0
     //如果需要测试虚拟机不复用，需要将factory capacity和produce都设置为0
-    let factory = VMFactory::new("test vm", 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let factory = VMFactory::new(FactoryName::new("test vm").unwrap(), 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
     let factory = factory.append(Arc::new(code));
     match factory.produce(3) {
         Err(e) => println!("factory produce failed, e: {:?}", e),
@@ -481,9 +504,9 @@ fn test_vm_factory_async_callback() {
                     2usize
                 });
                 factory.call(None,
-                             Atom::from("call"),
+                             PortName::new("call").unwrap(),
                              func,
-                             Atom::from("test factory call task"));
+                             TaskInfo::from("test factory call task")).unwrap();
                 thread::sleep(Duration::from_millis(2000));
             }
             println!("!!!!!!time: {:?}", Instant::now() - now);
@@ -502,7 +525,7 @@ fn js_async_callback_register_push(js: Arc<JS>, args: Vec<JSType>) -> Option<Cal
         vm.new_u32(callback);
         3
     });
-    if let Some(handle) = push_callback(js.clone(), args[0].get_u32(), func, Some(timeout), Atom::from("register callback task")) {
+    if let Some(handle) = push_callback(js.clone(), args[0].get_u32(), func, Some(timeout), TaskInfo::from("register callback task")) {
         js.new_i32(handle as i32);
         Some(CallResult::Ok)
     } else {
@@ -534,7 +557,7 @@ fn test_vm_sync_load_mod() {
     assert!(opts.is_some());
     let code = opts.unwrap();
 
-    let factory = VMFactory::new("test vm", 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let factory = VMFactory::new(FactoryName::new("test vm").unwrap(), 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
     let factory = factory.append(Arc::new(code));
     match factory.produce(1) {
         Err(e) => println!("factory produce failed, e: {:?}", e),
@@ -543,9 +566,9 @@ fn test_vm_sync_load_mod() {
                 0usize
             });
             factory.call(None,
-                         Atom::from("test_call"),
+                         PortName::new("test_call").unwrap(),
                          func,
-                         Atom::from("test sync load module task"));
+                         TaskInfo::from("test sync load module task")).unwrap();
         },
     }
     thread::sleep(Duration::from_millis(100000));
@@ -588,7 +611,7 @@ fn test_vm_async_load_mod() {
     assert!(opts.is_some());
     let code = opts.unwrap();
 
-    let factory = VMFactory::new("test vm", 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let factory = VMFactory::new(FactoryName::new("test vm").unwrap(), 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
     let factory = factory.append(Arc::new(code));
     match factory.produce(1) {
         Err(e) => println!("factory produce failed, e: {:?}", e),
@@ -597,9 +620,9 @@ fn test_vm_async_load_mod() {
                 0usize
             });
             factory.call(None,
-                         Atom::from("test_call"),
+                         PortName::new("test_call").unwrap(),
                          func,
-                         Atom::from("test async load module task"));
+                         TaskInfo::from("test async load module task")).unwrap();
         },
     }
     thread::sleep(Duration::from_millis(100000));
@@ -627,13 +650,14 @@ fn js_test_vm_async_load_mod(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResul
             }
             2
         });
-        push_callback(js_copy.clone(), callback, func, None, Atom::from("register async load module callback task"));
+        push_callback(js_copy.clone(), callback, func, None, TaskInfo::from("register async load module callback task"));
     });
 
     js.new_undefined();
     Some(CallResult::Ok)
 }
 
+#[cfg(feature = "cluster")]
 #[test]
 fn test_process() {
     env_logger::builder()
@@ -674,7 +698,7 @@ fn test_process() {
     assert!(opts.is_some());
     let code = opts.unwrap();
 
-    let factory = VMFactory::new("test vm", 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let factory = VMFactory::new(FactoryName::new("test vm").unwrap(), 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
     let factory = factory.append(Arc::new(code));
     match factory.produce(1) {
         Err(e) => println!("factory produce failed, e: {:?}", e),
@@ -683,14 +707,15 @@ fn test_process() {
                 0usize
             });
             factory.call(None,
-                         Atom::from("test_call"),
+                         PortName::new("test_call").unwrap(),
                          func,
-                         Atom::from("test sync load module task"));
+                         TaskInfo::from("test sync load module task")).unwrap();
         },
     }
     thread::sleep(Duration::from_millis(100000));
 }
 
+#[cfg(feature = "cluster")]
 fn js_test_process_spawn(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
     let factory_name = args[0].get_str();
     let process_name = args[1].get_str();
@@ -718,6 +743,7 @@ fn js_test_process_spawn(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
     }
 }
 
+#[cfg(feature = "cluster")]
 fn js_test_process_register_receiver(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
     let pid = args[0].get_u32() as u64;
     let callback = args[1].get_u32();
@@ -730,6 +756,7 @@ fn js_test_process_register_receiver(js: Arc<JS>, args: Vec<JSType>) -> Option<C
     Some(CallResult::Ok)
 }
 
+#[cfg(feature = "cluster")]
 fn js_test_process_register_catcher(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
     let pid = args[0].get_u32() as u64;
     let callback = args[1].get_u32();
@@ -742,6 +769,7 @@ fn js_test_process_register_catcher(js: Arc<JS>, args: Vec<JSType>) -> Option<Ca
     Some(CallResult::Ok)
 }
 
+#[cfg(feature = "cluster")]
 fn js_test_process_send(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
     let pid = args[0].get_u32() as u64;
     let array = &args[1];
@@ -762,6 +790,7 @@ fn js_test_process_send(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
     Some(CallResult::Ok)
 }
 
+#[cfg(feature = "cluster")]
 fn js_test_process_close(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
     let pid = args[0].get_u32() as u64;
 
@@ -910,3 +939,2458 @@ fn test_js_this() {
 
 
 
+
+#[test]
+fn test_pipeline_compensate() {
+    let compensated = Arc::new(Mutex::new(Vec::new()));
+    let compensated0 = compensated.clone();
+    let compensated1 = compensated.clone();
+    let pipeline = Pipeline::new("test_pipeline")
+        .native("reserve", Arc::new(|input: String, next: PipeNext| {
+            next(Ok(input + ":reserved"));
+        }))
+        .compensate(Arc::new(move |_input: String, output: String, done: Box<FnOnce(Result<(), String>)>| {
+            compensated0.lock().unwrap().push(output);
+            done(Ok(()));
+        }))
+        .map("upper", Arc::new(|input: String| Ok(input.to_uppercase())))
+        .compensate(Arc::new(move |_input: String, output: String, done: Box<FnOnce(Result<(), String>)>| {
+            compensated1.lock().unwrap().push(output);
+            done(Err("compensate failed".to_string()));
+        }))
+        .native("pay", Arc::new(|_input: String, next: PipeNext| {
+            next(Err("pay failed".to_string()));
+        }));
+    register_pipeline(pipeline);
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    call_pipeline("test_pipeline", "order".to_string(), Box::new(move |result| {
+        sender.send(result).unwrap();
+    }));
+    match receiver.recv().unwrap() {
+        Err(PipelineError::Compensated(e, results)) => {
+            match *e {
+                PipelineError::Stage(_, index, stage, _) => {
+                    assert_eq!(index, 2);
+                    assert_eq!(stage, "pay");
+                },
+                _ => panic!("invalid stage error"),
+            }
+            assert_eq!(results.len(), 2);
+            assert_eq!(results[0].index, 1);
+            assert!(results[0].result.is_err());
+            assert_eq!(results[1].index, 0);
+            assert!(results[1].result.is_ok());
+        },
+        _ => panic!("pipeline should be compensated"),
+    }
+    assert_eq!(*compensated.lock().unwrap(), vec!["ORDER:RESERVED".to_string(), "order:reserved".to_string()]);
+}
+
+#[test]
+fn test_handler_version() {
+    let required = HandlerVersion::parse("1.2").unwrap();
+    assert_eq!(required, HandlerVersion(1, 2, 0));
+    assert!(HandlerVersion::parse("1.2.3").unwrap().satisfies(&required));
+    assert!(!HandlerVersion::parse("1.1.9").unwrap().satisfies(&required));
+    assert!(!HandlerVersion::parse("2.0").unwrap().satisfies(&required));
+    assert!(HandlerVersion::parse("1.x").is_err());
+}
+
+#[test]
+fn test_poisoned_state_recover() {
+    let lock = Arc::new(std::sync::RwLock::new(0));
+    let lock_copy = lock.clone();
+    let _ = thread::spawn(move || {
+        let mut value = write_state("test_poisoned_state", &lock_copy);
+        *value = 1;
+        panic!("poison test state");
+    }).join();
+
+    assert!(lock.is_poisoned());
+    assert_eq!(*read_state("test_poisoned_state", &lock), 1);
+    assert!(health().degraded.iter().any(|(name, count)| *name == "test_poisoned_state" && *count == 1));
+
+    //被毒化的锁会一直保持毒化，之后的访问只增加恢复次数
+    assert_eq!(*read_state("test_poisoned_state", &lock), 1);
+    assert!(health().degraded.iter().any(|(name, count)| *name == "test_poisoned_state" && *count == 2));
+}
+
+#[test]
+fn test_latency_histogram() {
+    let histogram = LatencyHistogram::new();
+    for us in 1..1001 {
+        histogram.record(Duration::from_micros(us));
+    }
+
+    let snapshot = histogram.snapshot();
+    assert_eq!(snapshot.count, 1000);
+    assert_eq!(snapshot.min, Duration::from_micros(1));
+    assert_eq!(snapshot.max, Duration::from_micros(1000));
+    assert!(snapshot.p50 >= Duration::from_micros(500) && snapshot.p50 <= Duration::from_micros(516));
+    assert!(snapshot.p999 >= Duration::from_micros(999) && snapshot.p999 <= Duration::from_micros(1000));
+
+    histogram.reset();
+    assert_eq!(histogram.count(), 0);
+}
+
+#[test]
+fn test_vm_destroy() {
+    register_native_object();
+
+    let js = JS::new(1, Atom::from("test vm destroy"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    assert!(!js.is_destroyed());
+    assert!(js.destroy().is_ok());
+    assert!(js.is_destroyed());
+    assert!(js.destroy().is_ok()); //重复销毁则忽略
+    assert_eq!(js.heap_size(), 0);
+    assert!(push_callback(js.clone(), 1, Box::new(|_vm: Arc<JS>| 0), None, TaskInfo::from("test destroyed vm callback")).is_none());
+}
+
+#[test]
+fn test_vm_destroy_notify() {
+    register_native_object();
+
+    let js = JS::new(2, Atom::from("test vm destroy notify"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let channel = VMChannel::new(VMChannelPeer::VM(js.clone()), VMChannelPeer::Any);
+    let notified = Arc::new(AtomicUsize::new(0));
+    let notified_copy = notified.clone();
+    assert!(channel.on_peer_destroy(Box::new(move |_name, id| {
+        notified_copy.store(id, Ordering::SeqCst);
+    })));
+
+    assert!(js.destroy().is_ok());
+    assert!(channel.is_peer_destroyed());
+    assert_eq!(notified.load(Ordering::SeqCst), 2);
+    assert!(!channel.on_peer_destroy(Box::new(|_name, _id| {})));
+    assert!(!channel.response(Some(1), Arc::new(vec![]), vec![]));
+}
+
+#[test]
+fn test_cancel_callback() {
+    register_native_object();
+
+    let js = JS::new(3, Atom::from("test cancel callback"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let info = TaskInfo::from("test pending callback");
+    let id = info.id();
+    assert!(push_callback(js.clone(), 1, Box::new(|_vm: Arc<JS>| 0), None, info).is_some());
+
+    let callbacks = js.pending_callbacks();
+    assert_eq!(callbacks.len(), 1);
+    assert_eq!(callbacks[0].id, id);
+    assert_eq!(callbacks[0].callback, 1);
+
+    assert!(js.cancel_callback(id));
+    assert!(!js.cancel_callback(id)); //重复取消则忽略
+    assert!(js.pending_callbacks().is_empty());
+}
+
+#[test]
+fn test_factory_drain() {
+    let factory = VMFactory::new(FactoryName::new("test factory drain").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert!(!factory.is_closed());
+
+    let _drain = factory.shutdown();
+    assert!(factory.is_closed());
+    assert_eq!(factory.in_flight(), 0);
+    match factory.call(None, PortName::new("call").unwrap(), Box::new(|_vm: Arc<JS>| 0), TaskInfo::from("test factory drain call")) {
+        Err(VMFactoryError::Shutdown(_)) => (),
+        _ => panic!("call after shutdown must be rejected"),
+    }
+    assert_eq!(factory.in_flight(), 0);
+    assert!(factory.produce(1).is_err());
+}
+
+#[test]
+fn test_factory_drain_checked_out() {
+    use std::task::Poll;
+    use pi_vm::pi_vm_impl::FactoryDrain;
+
+    //排空的完成句柄可以跨线程移动
+    fn assert_send<T: Send>() {}
+    assert_send::<FactoryDrain>();
+
+    register_native_object();
+    let factory = VMFactory::new(FactoryName::new("test factory drain checked out").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert_eq!(factory.produce(1).unwrap(), 1);
+    let vm = factory.try_acquire().unwrap();
+
+    //被守护者取出的虚拟机归还后才完成排空
+    let mut drain = Box::pin(factory.drain());
+    assert!(poll_once(&mut drain).is_pending());
+    drop(vm);
+    match poll_once(&mut drain) {
+        Poll::Ready(shutdown) => assert_eq!(shutdown.destroyed, 0),
+        Poll::Pending => panic!("drain must be ready after give back"),
+    }
+}
+
+#[test]
+fn test_reload_codes() {
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test reload codes").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert_eq!(factory.code_generation(), 0);
+    let vm = factory.take().unwrap();
+    assert!(!factory.is_stale(&vm));
+
+    assert_eq!(factory.reload_codes(vec![]).unwrap(), 1);
+    assert_eq!(factory.code_generation(), 1);
+    assert!(factory.is_stale(&vm));
+    assert_eq!(factory.size(), 0);
+}
+
+#[test]
+fn test_validate_size() {
+    register_native_object();
+
+    //校验用的虚拟机不会占用虚拟机工厂的虚拟机数量
+    let factory = VMFactory::new(FactoryName::new("test validate size").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert!(factory.validate().is_ok());
+    assert_eq!(factory.size(), 0);
+    assert!(factory.produce(1).is_ok());
+    assert_eq!(factory.size(), 1);
+}
+
+#[test]
+fn test_pending_drop_oldest() {
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test pending drop oldest").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_pending_limits(PendingLimits { max_vms: 1, max_depth: 1, policy: PendingPolicy::DropOldest });
+    assert_eq!(factory.produce(1).unwrap(), 1);
+    let _vm = factory.try_acquire().unwrap(); //取出唯一的虚拟机，之后的调用都需要等待调度
+
+    let results = Arc::new(Mutex::new(Vec::new()));
+    for index in 0..2 {
+        let results_copy = results.clone();
+        factory.call_then(None, PortName::new("call").unwrap(), Box::new(|_vm: Arc<JS>| 0), TaskInfo::from("test pending drop oldest"), Box::new(move |result| {
+            results_copy.lock().unwrap().push((index, result.is_err()));
+        }));
+    }
+
+    //被丢弃的最早等待调度的任务会收到虚拟机工厂忙的异常
+    assert_eq!(factory.queue_len(), 1);
+    assert_eq!(results.lock().unwrap().as_slice(), &[(0, true)]);
+}
+
+#[test]
+fn test_pending_tasks() {
+    register_native_object();
+
+    let js = JS::new(1, Atom::from("test pending tasks"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let info = TaskInfo::from("test pending tasks");
+    let task = js.enqueue_task(&info);
+    let retry = js.enqueue_task(&info); //重新投递同一任务
+    assert_eq!(js.pending_tasks().len(), 1);
+
+    //同一任务的所有守护者都释放后，未执行的任务被移除
+    drop(task);
+    assert_eq!(js.pending_tasks().len(), 1);
+    drop(retry);
+    assert!(js.pending_tasks().is_empty());
+
+    //已开始执行的任务不在等待执行的任务表中
+    let task = js.enqueue_task(&info);
+    js.start_task(task.id());
+    assert!(js.pending_tasks().is_empty());
+    assert_eq!(js.finish_task().map(|info| info.id()), Some(task.id()));
+}
+
+#[test]
+fn test_vm_prelude_global() {
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test vm prelude global").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let vm = factory.take().unwrap();
+    assert_eq!(CallValue::from(&*vm.eval("typeof vm".to_string())), CallValue::String("undefined".to_string()));
+    assert_eq!(CallValue::from(&*vm.eval("typeof __pi_vm.usage".to_string())), CallValue::String("function".to_string()));
+    assert_eq!(CallValue::from(&*vm.eval("Object.keys(this).indexOf('__pi_vm')".to_string())), CallValue::Number(-1.0)); //内置全局对象不可枚举
+    assert_eq!(CallValue::from(&*vm.eval("__pi_vm = null; typeof __pi_vm".to_string())), CallValue::String("object".to_string())); //内置全局对象不可替换
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_call_atom() {
+    let factory = VMFactory::new(FactoryName::new("test call atom").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    factory.call_atom(None, Atom::from("1call"), Box::new(|_vm: Arc<JS>| 0), Atom::from("test call atom")); //端口名无效，调用被忽略
+    assert_eq!(factory.in_flight(), 0);
+}
+
+//使用空唤醒器轮询一次异步任务
+fn poll_once<F: std::future::Future>(future: &mut std::pin::Pin<Box<F>>) -> std::task::Poll<F::Output> {
+    use std::task::{Context, RawWaker, RawWakerVTable, Waker};
+
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker { noop_raw_waker() }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut cx = Context::from_waker(&waker);
+    future.as_mut().poll(&mut cx)
+}
+
+#[test]
+fn test_idle_collector() {
+    use pi_vm::api::IdleCollector;
+
+    //未启用vmgc特性构建时无法显式回收，回收器启动失败，且不会回收空闲虚拟机
+    let factory = VMFactory::new(FactoryName::new("test idle collector").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let collector = IdleCollector::new(factory, Duration::from_millis(0));
+    assert_eq!(collector.start(), cfg!(feature = "vmgc"));
+    assert_eq!(collector.is_running(), cfg!(feature = "vmgc"));
+    collector.stop();
+    if !cfg!(feature = "vmgc") {
+        assert!(!collector.collect());
+    }
+}
+
+#[test]
+fn test_factory_acquire() {
+    use std::task::Poll;
+    use pi_vm::pi_vm_impl::{Acquire, AcquireTimeout};
+
+    //异步获取者可以跨线程移动
+    fn assert_send<T: Send>() {}
+    assert_send::<Acquire>();
+    assert_send::<AcquireTimeout>();
+
+    register_native_object();
+    let factory = VMFactory::new(FactoryName::new("test factory acquire").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert_eq!(factory.produce(1).unwrap(), 1);
+    let vm = factory.try_acquire().unwrap(); //取出唯一的虚拟机
+
+    //没有空闲虚拟机时等待，虚拟机被归还后获取成功
+    let mut acquire = Box::pin(factory.acquire());
+    assert!(poll_once(&mut acquire).is_pending());
+    drop(vm);
+    let vm = match poll_once(&mut acquire) {
+        Poll::Ready(vm) => vm,
+        Poll::Pending => panic!("acquire must be ready after give back"),
+    };
+
+    //超时仍没有空闲虚拟机则返回None
+    let mut acquire = Box::pin(factory.acquire_timeout(Duration::from_millis(10)));
+    assert!(poll_once(&mut acquire).is_pending());
+    thread::sleep(Duration::from_millis(20));
+    match poll_once(&mut acquire) {
+        Poll::Ready(None) => (),
+        _ => panic!("acquire timeout must be ready with none"),
+    }
+    drop(vm);
+    assert!(factory.try_acquire().is_some());
+}
+
+#[test]
+fn test_pooled_vm_panic() {
+    use pi_vm::pi_vm_impl::PooledVm;
+
+    //虚拟机守护者可以跨线程移动
+    fn assert_send<T: Send>() {}
+    assert_send::<PooledVm>();
+
+    register_native_object();
+    let factory = VMFactory::new(FactoryName::new("test pooled vm panic").unwrap(), 2, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert_eq!(factory.produce(2).unwrap(), 2);
+
+    //正常释放的守护者归还虚拟机
+    drop(factory.try_acquire().unwrap());
+    assert_eq!(factory.size(), 2);
+    assert_eq!(factory.checked_out(), 0);
+
+    //持有守护者的线程异常时，丢弃状态未知的虚拟机
+    let vm = factory.try_acquire().unwrap();
+    assert_eq!(factory.checked_out(), 1);
+    let _ = thread::spawn(move || {
+        let _vm = vm;
+        panic!("pooled vm panic test");
+    }).join();
+    assert_eq!(factory.size(), 1);
+    assert_eq!(factory.checked_out(), 0);
+    assert_eq!(factory.poison_count(), 1);
+}
+
+#[test]
+fn test_checkout_retry() {
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test checkout retry").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_checkout_retry(3, Duration::from_millis(100));
+    assert_eq!(factory.produce(1).unwrap(), 1);
+    let vm = factory.try_acquire().unwrap(); //取出唯一的虚拟机
+
+    //没有空闲虚拟机时，调用不会阻塞当前线程，而是等待被归还的虚拟机执行
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results_copy = results.clone();
+    let start = Instant::now();
+    factory.call_then(None, PortName::new("call").unwrap(), Box::new(|_vm: Arc<JS>| 0), TaskInfo::from("test checkout retry"), Box::new(move |result| {
+        results_copy.lock().unwrap().push(result.is_ok());
+    }));
+    assert!(start.elapsed() < Duration::from_millis(100));
+    assert_eq!(factory.queue_len(), 1);
+
+    //归还虚拟机后，等待的任务使用归还的虚拟机执行，不会构建新的虚拟机
+    drop(vm);
+    thread::sleep(Duration::from_millis(1000));
+    assert_eq!(factory.queue_len(), 0);
+    assert_eq!(factory.size(), 1);
+    assert_eq!(results.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_budget_queue() {
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test budget queue").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_limits(FactoryLimits { max_total_heap: 1 << 40, cpu_per_minute: None, on_exhausted: BudgetExhausted::Queue });
+    assert_eq!(factory.produce(1).unwrap(), 1);
+    factory.add_total_heap(1 << 40); //耗尽总堆大小预算
+
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results_copy = results.clone();
+    factory.call_then(None, PortName::new("call").unwrap(), Box::new(|_vm: Arc<JS>| 0), TaskInfo::from("test budget queue"), Box::new(move |result| {
+        results_copy.lock().unwrap().push(result.is_ok());
+    }));
+
+    //排队的任务不是被拒绝的任务
+    assert_eq!(factory.queue_len(), 1);
+    assert_eq!(factory.refuse_count(), 0);
+
+    //预算恢复后，排队的任务由定时器执行，不需要等待其它调用归还虚拟机
+    factory.add_total_heap(-(1 << 40));
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(factory.queue_len(), 0);
+    assert_eq!(results.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_factory_task_queues() {
+    let factory = VMFactory::new(FactoryName::new("test factory task queues").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert_eq!(factory.task_queue_count(), 0);
+
+    let factory = factory.set_task_queues(4);
+    assert_eq!(factory.task_queue_count(), 4);
+    let copy = factory.clone().set_task_queues(8); //复制的虚拟机工厂无法修改任务队列
+    assert_eq!(copy.task_queue_count(), 4);
+}
+
+#[test]
+fn test_factory_builder() {
+    let factory = VMFactoryBuilder::new(FactoryName::new("test factory builder").unwrap(), Arc::new(NativeObjsAuth::new(None, None)))
+        .pool_size(3)
+        .heap_size(1073741824)
+        .recycle_policy(RecyclePolicy { max_reused_count: 27, max_calls: 0, max_heap_size: 1073741824, max_age: None })
+        .task_priority(10)
+        .task_queues(2)
+        .idle_ttl(Duration::from_millis(500))
+        .label("role", "test")
+        .affinity(16)
+        .pending_limits(PendingLimits { max_vms: 8, max_depth: 64, policy: PendingPolicy::DropOldest })
+        .profile(VmProfile::LowMemory)
+        .build()
+        .unwrap();
+
+    assert_eq!(factory.max_reused_count(), 27);
+    assert_eq!(factory.max_heap_size(), 1073741824);
+    assert_eq!(factory.task_priority(), 10);
+    assert_eq!(factory.task_queue_count(), 2);
+    assert_eq!(factory.idle_ttl(), Some(Duration::from_millis(500)));
+    assert_eq!(factory.affinity_capacity(), 16);
+    assert_eq!(factory.pinned_count(), 0);
+    assert_eq!(factory.pending_limits().max_depth, 64);
+    assert_eq!(factory.pending_limits().policy, PendingPolicy::DropOldest);
+    if VmProfile::LowMemory.is_supported() {
+        assert_eq!(factory.profile(), VmProfile::LowMemory);
+    } else {
+        assert_eq!(factory.profile(), VmProfile::Standard);
+    }
+}
+
+#[test]
+fn test_recycle_policy() {
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test recycle policy").unwrap(), 0, 2, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_max_age(Duration::from_millis(100));
+    assert_eq!(factory.recycle_policy().max_reused_count, 2);
+    assert_eq!(factory.recycle_policy().max_calls, 0); //最大执行次数不会限制调用次数
+    assert_eq!(factory.recycle_policy().max_age, Some(Duration::from_millis(100)));
+
+    let vm = factory.take().unwrap();
+    assert_eq!(vm.served_count(), 0);
+    assert!(factory.expired(&vm).is_none());
+
+    thread::sleep(Duration::from_millis(150));
+    assert!(factory.expired(&vm).is_some()); //超过最大存活时长
+
+    let factory = VMFactory::new(FactoryName::new("test recycle policy calls").unwrap(), 0, 2, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_max_calls(3);
+    assert_eq!(factory.recycle_policy().max_reused_count, 2);
+    assert_eq!(factory.recycle_policy().max_calls, 3);
+    assert!(factory.expired(&factory.take().unwrap()).is_none());
+}
+
+#[test]
+fn test_scratch_eval() {
+    register_native_object();
+
+    assert_eq!(scratch_eval("1 + 2"), Ok(ScratchValue::Number(3.0)));
+    assert_eq!(scratch_eval("'a' + 'b'"), Ok(ScratchValue::String("ab".to_string())));
+
+    //每次执行后重置全局环境
+    assert_eq!(scratch_eval("var x = 1; x"), Ok(ScratchValue::Number(1.0)));
+    assert_eq!(scratch_eval("typeof x"), Ok(ScratchValue::String("undefined".to_string())));
+
+    assert!(scratch_eval("throw new Error('scratch')").is_err());
+}
+
+#[test]
+fn test_expression_engine() {
+    register_native_object();
+
+    let engine = ExpressionEngine::new().set_capacity(2);
+    let expr = engine.compile("level >= 10 && name == 'pi'").unwrap();
+    assert_eq!(engine.eval(&expr, &[("level", ScratchValue::Number(12.0)), ("name", ScratchValue::String("pi".to_string()))]),
+               Ok(ScratchValue::Boolean(true)));
+    assert_eq!(engine.eval(&expr, &[("level", ScratchValue::Number(1.0)), ("name", ScratchValue::String("pi".to_string()))]),
+               Ok(ScratchValue::Boolean(false)));
+
+    //相同的表达式只编译一次
+    let copy = engine.compile("level >= 10 && name == 'pi'").unwrap();
+    assert_eq!(copy.hash(), expr.hash());
+    assert_eq!(engine.cached_count(), 1);
+
+    assert_eq!(engine.eval_str("x * 2", &[("x", ScratchValue::Number(21.0))]), Ok(ScratchValue::Number(42.0)));
+    engine.compile("1").unwrap();
+    assert_eq!(engine.cached_count(), 2); //超过缓存容量，则淘汰最早缓存的表达式
+}
+
+#[test]
+fn test_heap_high_water() {
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test heap high water").unwrap(), 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_heap_high_water(1);
+    assert_eq!(factory.heap_high_water(), 1);
+
+    let vm = factory.take().unwrap();
+    assert!(vm.last_heap_size() > 0);
+    assert!(vm.peak_heap_size() >= vm.last_heap_size());
+    assert!(factory.over_high_water(&vm));
+}
+
+#[test]
+fn test_names() {
+    assert!(FactoryName::new("test vm").is_ok());
+    assert!(FactoryName::new("").is_err());
+    assert!(FactoryName::new(" test vm").is_err());
+    assert!(FactoryName::new("test\nvm").is_err());
+
+    assert_eq!(PortName::new("user.get@v2").unwrap().as_str(), "user.get@v2");
+    assert!(PortName::new("_$async").is_ok());
+    assert!(PortName::new("").is_err());
+    assert!(PortName::new("test factory call task").is_err()); //任务信息不是有效的端口名
+    assert!(PortName::new("user..get").is_err());
+    assert!(PortName::new("1call").is_err());
+}
+
+#[test]
+fn test_factory_registry() {
+    let factory = VMFactory::new(FactoryName::new("test factory registry").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert!(get_factory(&Atom::from("test factory registry")).is_none());
+
+    factory.register().unwrap();
+    factory.clone().register().unwrap(); //重复注册同一个虚拟机工厂则忽略
+    assert!(get_factory(&Atom::from("test factory registry")).unwrap().is_same(&factory));
+    assert!(factory_names().contains(&Atom::from("test factory registry")));
+    assert!(registry_stats().factory_count >= 1);
+    assert!(factory_stats().iter().any(|stats| stats.name == "test factory registry")); //指标和注册表使用同一个虚拟机工厂注册表
+
+    let other = VMFactory::new(FactoryName::new("test factory registry").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert!(other.register().is_err()); //同名的其它虚拟机工厂已注册
+    assert!(!remove_factory(&other));
+
+    assert!(remove_factory(&factory));
+    assert!(get_factory(&Atom::from("test factory registry")).is_none());
+}
+
+#[test]
+fn test_call_value() {
+    register_native_object();
+
+    let js = JS::new(1, Atom::from("test call value"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    assert_eq!(CallValue::from(&*js.eval("1 + 2".to_string())), CallValue::Number(3.0));
+    assert_eq!(CallValue::from(&*js.eval("'pi' + '_vm'".to_string())), CallValue::String("pi_vm".to_string()));
+    assert_eq!(CallValue::from(&*js.eval("null".to_string())), CallValue::Null);
+    assert_eq!(CallValue::from(&*js.eval("new Uint8Array([1, 2, 3])".to_string())), CallValue::Bytes(vec![1, 2, 3]));
+}
+
+#[test]
+fn test_request_receipt() {
+    register_native_object();
+
+    let js = JS::new(1, Atom::from("test request receipt"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let map = VMChannelMap::new(0);
+    map.set_handler(Atom::from("ok"), Arc::new(|_: Arc<VMChannel>, _: Atom, _: Arc<Vec<u8>>, _: Vec<JSType>, _: Option<u32>| {}));
+    map.set_handler(Atom::from("panic"), Arc::new(|_: Arc<VMChannel>, _: Atom, _: Arc<Vec<u8>>, _: Vec<JSType>, _: Option<u32>| panic!("bad request")));
+
+    let receipt = map.request_with_receipt(js.clone(), Atom::from("none"), Arc::new(vec![]), vec![], None);
+    assert_eq!(receipt.status(), Some(RequestStatus::NotFound));
+
+    let receipt = map.request_with_receipt(js.clone(), Atom::from("ok"), Arc::new(vec![]), vec![], None);
+    assert_eq!(receipt.status(), Some(RequestStatus::Delivered));
+    assert!(!receipt.is_finished()); //处理器未回应请求
+
+    let receipt = map.request_with_receipt(js, Atom::from("panic"), Arc::new(vec![]), vec![], None);
+    assert_eq!(receipt.status(), Some(RequestStatus::HandlerError("bad request".to_string())));
+    assert!(receipt.is_finished());
+
+    let stats = map.handler_stats();
+    assert_eq!(stats.len(), 2);
+    assert_eq!((stats[0].name.as_str(), stats[0].calls, stats[0].errors), ("ok", 1, 0));
+    assert_eq!((stats[1].name.as_str(), stats[1].calls, stats[1].errors), ("panic", 1, 1));
+
+    //替换同名处理器时保留执行统计，未执行过和被移除的处理器不统计
+    map.set_handler(Atom::from("ok"), Arc::new(|_: Arc<VMChannel>, _: Atom, _: Arc<Vec<u8>>, _: Vec<JSType>, _: Option<u32>| {}));
+    map.set_handler(Atom::from("idle"), Arc::new(|_: Arc<VMChannel>, _: Atom, _: Arc<Vec<u8>>, _: Vec<JSType>, _: Option<u32>| {}));
+    assert!(map.remove(Atom::from("panic")).is_some());
+    let stats = map.handler_stats();
+    assert_eq!(stats.len(), 1);
+    assert_eq!((stats[0].name.as_str(), stats[0].calls), ("ok", 1));
+}
+
+#[test]
+fn test_channel_map_concurrent() {
+    let map = Arc::new(VMChannelMap::with_shard(0, 4));
+    map.set_handler(Atom::from("fixed"), Arc::new(|_: Arc<VMChannel>, _: Atom, _: Arc<Vec<u8>>, _: Vec<JSType>, _: Option<u32>| {}));
+
+    //持续读取的同时替换快照，读者总能看到完整的快照
+    let mut readers = Vec::new();
+    for _ in 0..4 {
+        let map_copy = map.clone();
+        readers.push(thread::spawn(move || {
+            for _ in 0..100000 {
+                assert!(map_copy.contains(&Atom::from("fixed")));
+                map_copy.get(&Atom::from("dynamic"));
+            }
+        }));
+    }
+
+    for index in 0..10000 {
+        let name = Atom::from(format!("dynamic{}", index % 16));
+        map.set_handler(name.clone(), Arc::new(|_: Arc<VMChannel>, _: Atom, _: Arc<Vec<u8>>, _: Vec<JSType>, _: Option<u32>| {}));
+        map.set_handler(Atom::from("dynamic"), Arc::new(|_: Arc<VMChannel>, _: Atom, _: Arc<Vec<u8>>, _: Vec<JSType>, _: Option<u32>| {}));
+        map.remove(name);
+    }
+
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    assert_eq!(map.size(), 2);
+    assert!(map.contains(&Atom::from("fixed")));
+    assert!(map.contains(&Atom::from("dynamic")));
+}
+
+#[test]
+fn test_bundle_isolation() {
+    register_native_object();
+
+    let auth = compile_bundle("auth", "var secret = 1; exports.login = function() { return secret + 1; };").unwrap();
+    let shop = compile_bundle("shop", "var secret = 10; module.exports = { buy: function() { return secret; } };").unwrap();
+    assert!(compile_bundle("1auth", "").is_err());
+
+    let js = JS::new(1, Atom::from("test bundle isolation"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    for code in &[auth, shop] {
+        assert!(js.load(code.as_slice()));
+        while !js.is_ran() {
+            thread::yield_now();
+        }
+    }
+
+    assert_eq!(CallValue::from(&*js.eval("__bundles.auth.login()".to_string())), CallValue::Number(2.0));
+    assert_eq!(CallValue::from(&*js.eval("__bundles.shop.buy()".to_string())), CallValue::Number(10.0));
+    assert_eq!(CallValue::from(&*js.eval("typeof secret".to_string())), CallValue::String("undefined".to_string()));
+    assert_eq!(bundle_port("auth", "login").unwrap().as_str(), "__bundles.auth.login");
+}
+
+#[test]
+fn test_service_registry() {
+    register_native_object();
+
+    let registry = ServiceRegistry::new();
+    registry.provide(Arc::new(AtomicUsize::new(27)));
+    registry.provide_named("db", Arc::new("test db".to_string()));
+    assert_eq!(registry.len(), 2);
+    assert!(registry.get::<String>().is_none());
+
+    let js = JS::new(1, Atom::from("test service registry"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let map = VMChannelMap::new(0);
+    map.set_services(Some(registry.clone()));
+    let resolved = Arc::new(AtomicUsize::new(0));
+    let resolved_copy = resolved.clone();
+    map.set_handler(Atom::from("service"), Arc::new(move |channel: Arc<VMChannel>, _: Atom, _: Arc<Vec<u8>>, _: Vec<JSType>, _: Option<u32>| {
+        let counter = channel.service::<AtomicUsize>().unwrap();
+        assert_eq!(channel.named_service::<String>("db").unwrap().as_str(), "test db");
+        resolved_copy.store(counter.load(Ordering::Relaxed), Ordering::Relaxed);
+    }));
+
+    assert!(map.request(js, Atom::from("service"), Arc::new(vec![]), vec![], None));
+    assert_eq!(resolved.load(Ordering::Relaxed), 27);
+
+    //替换默认的服务注册表后返回上一个服务注册表，之后的请求不再使用
+    assert_eq!(map.services().map(|services| services.id()), Some(registry.id()));
+    assert_eq!(map.set_services(None).map(|services| services.id()), Some(registry.id()));
+    assert!(map.services().is_none());
+    registry.close();
+}
+
+#[test]
+fn test_factory_fork() {
+    register_native_object();
+
+    let base = VMFactory::new(FactoryName::new("test_base").unwrap(), 1, 0, 16 * 1024 * 1024, 32 * 1024 * 1024, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(compile_bundle("base", "exports.value = function() { return 1; };").unwrap());
+    //附加字节码在加载时依赖基础字节码，加载失败则无法生成虚拟机
+    let layer = compile_bundle("layer", "var value = __bundles.base.value; exports.run = function() { return value() + 1; };").unwrap();
+    let child = base.fork(FactoryName::new("test_child").unwrap(), vec![layer]);
+
+    assert_eq!(child.base().unwrap().name(), "test_base");
+    assert_eq!(child.code_generation(), base.code_generation());
+    assert!(child.produce(1).is_ok());
+    assert_eq!(child.size(), 1);
+    assert_eq!(base.size(), 0);
+}
+
+#[test]
+fn test_code_cache() {
+    let x = intern_code(Arc::new(vec![1, 2, 3]));
+    let y = intern_code(Arc::new(vec![1, 2, 3]));
+    assert!(Arc::ptr_eq(&x, &y));
+    assert_eq!(code_hash(x.as_slice()), code_hash(&[1, 2, 3]));
+
+    let factory = VMFactory::new(FactoryName::new("test_code_cache").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(vec![1, 2, 3]));
+    assert!(factory.is_same_codes(&[y.clone()]));
+    assert!(!factory.is_same_codes(&[Arc::new(vec![3, 2, 1])]));
+    assert_eq!(factory.code_hashes(), vec![code_hash(&[1, 2, 3])]);
+}
+
+#[cfg(feature = "nopanic")]
+#[test]
+fn test_nopanic_channel_request() {
+    register_native_object();
+
+    let js = JS::new(1, Atom::from("test nopanic channel request"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let map = VMChannelMap::new(0);
+    map.set_handler(Atom::from("panic"), Arc::new(|_: Arc<VMChannel>, _: Atom, _: Arc<Vec<u8>>, _: Vec<JSType>, _: Option<u32>| {
+        panic!("handler panic");
+    }));
+
+    //处理器执行异常不会中止调用者，并返回请求失败
+    assert!(!map.request(js, Atom::from("panic"), Arc::new(vec![]), vec![], None));
+    let stats = map.handler_stats();
+    assert_eq!(stats[0].calls, 1);
+    assert_eq!(stats[0].errors, 1);
+}
+
+#[cfg(feature = "fuzzing")]
+#[test]
+fn test_fuzz_entries() {
+    use pi_vm::api::fuzz::{fuzz_load_code, fuzz_decode_json, fuzz_decode_cbor, fuzz_channel_payload};
+
+    register_native_object();
+
+    fuzz_load_code(&[0xff, 0x00, 0x01]); //无效字节码不允许导致进程中止
+    assert!(fuzz_decode_json(b"{\"a\": [1, 2, 3]}"));
+    assert!(!fuzz_decode_json(b"{\"a\": "));
+    assert!(fuzz_decode_cbor(&[0xa1, 0x61, 0x61, 0x83, 0x01, 0x02, 0xf9, 0x3c, 0x00])); //{"a": [1, 2, 1.0]}
+    assert!(!fuzz_decode_cbor(&[0xa1, 0x61, 0x61, 0x83, 0x01])); //不完整的数组
+    assert!(!fuzz_decode_cbor(&[0x9f, 0x01, 0xff])); //不定长编码
+    assert!(!fuzz_decode_cbor(&[0x01, 0x02])); //尾部数据
+    assert!(fuzz_channel_payload(&[0, 1, 2, 255]));
+    assert!(fuzz_channel_payload(&[]));
+}
+
+#[test]
+fn test_code_sources() {
+    let dir = std::env::temp_dir().join("pi_vm_test_code_sources");
+    let _ = std::fs::create_dir_all(&dir);
+    std::fs::write(dir.join("b.js"), vec![2]).unwrap();
+    std::fs::write(dir.join("a.js"), vec![1]).unwrap();
+    std::fs::write(dir.join("c.txt"), vec![3]).unwrap();
+
+    let dir_source = DirSource::new(dir.clone()).with_extension("js");
+    assert_eq!(dir_source.fetch().unwrap(), vec![Arc::new(vec![1]), Arc::new(vec![2])]);
+
+    let sources: Vec<Arc<CodeSource>> = vec![Arc::new(dir_source),
+                                             Arc::new(FnSource::new("remote", Arc::new(|| Ok(vec![Arc::new(vec![4])]))))];
+    let factory = VMFactory::new(FactoryName::new("test_code_sources").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append_sources(&sources)
+        .unwrap();
+    assert_eq!(factory.code_hashes(), vec![code_hash(&[1]), code_hash(&[2]), code_hash(&[4])]);
+
+    let failed: Vec<Arc<CodeSource>> = vec![Arc::new(FnSource::new("db", Arc::new(|| Err("db offline".to_string()))))];
+    let report = factory.append_sources(&failed).err().unwrap();
+    assert_eq!(report.failed(), 1);
+    assert_eq!(report.results[0].0, "db");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[cfg(feature = "stress")]
+#[test]
+fn test_stress_factory() {
+    use pi_vm::api::stress::{StressConfig, stress_factory};
+
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test_stress_factory").unwrap(), 16, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let report = stress_factory(&factory, StressConfig {
+        threads: 4,
+        iterations: 200,
+        capacity: 8,
+        ..StressConfig::default()
+    });
+    assert!(report.is_ok(), "{:?}", report.violations);
+    assert_eq!(report.operations, 800);
+}
+
+#[test]
+fn test_append_source() {
+    register_native_object();
+
+    let factory = VMFactoryBuilder::new(FactoryName::new("test_append_source").unwrap(), Arc::new(NativeObjsAuth::new(None, None)))
+        .source("add.js", "function add(x, y) { return x + y; }")
+        .build()
+        .unwrap();
+    assert_eq!(factory.code_hashes().len(), 1);
+    assert!(factory.produce(1).is_ok());
+
+    assert!(VMFactoryBuilder::new(FactoryName::new("test_append_source_error").unwrap(), Arc::new(NativeObjsAuth::new(None, None)))
+        .source("error.js", "function (")
+        .build()
+        .is_err());
+}
+
+#[test]
+fn test_glob_source() {
+    register_native_object();
+
+    let dir = std::env::temp_dir().join("pi_vm_test_glob_source");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("lib/sub")).unwrap();
+    std::fs::write(dir.join("lib/b.js"), "var b = a + 1;").unwrap();
+    std::fs::write(dir.join("lib/a.js"), "var a = 1;").unwrap();
+    std::fs::write(dir.join("lib/sub/c.js"), "var c = b + 1;").unwrap();
+    std::fs::write(dir.join("lib/readme.md"), "").unwrap();
+    std::fs::write(dir.join("order.txt"), "# boot order\nlib/sub/c.js\n").unwrap();
+
+    let source = GlobSource::new(dir.clone(), &["lib/**/*.js"]);
+    assert_eq!(source.matched().unwrap(), vec!["lib/a.js", "lib/b.js", "lib/sub/c.js"]);
+    let source = source.with_order(LoadOrder::Manifest("order.txt".into()));
+    assert_eq!(source.matched().unwrap(), vec!["lib/sub/c.js", "lib/a.js", "lib/b.js"]);
+
+    let factory = VMFactory::new(FactoryName::new("test_glob_source").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append_glob(GlobSource::new(dir.clone(), &["lib/*.js", "lib/sub/?.js"]))
+        .unwrap();
+    assert_eq!(factory.code_hashes().len(), 3);
+    assert!(factory.produce(1).is_ok());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_leak_detector() {
+    let detector = LeakDetector::new().set_window(3);
+    let factory = VMFactory::new(FactoryName::new("test_leak_detector").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert!(factory.produce(1).is_ok());
+    detector.sample();
+    assert!(detector.report().is_ok()); //采样窗口未满
+
+    //每次采样前都增加虚拟机，存活虚拟机数量单调增长
+    assert!(factory.produce(1).is_ok());
+    detector.sample();
+    assert!(factory.produce(1).is_ok());
+    detector.sample();
+    let report = detector.report();
+    assert_eq!(report.samples, 3);
+    assert!(report.growing.iter().any(|trend| trend.metric == "vms" && trend.last >= trend.first + 2));
+
+    //丢弃虚拟机后不再单调增长
+    factory.shrink(3);
+    detector.sample();
+    assert!(!detector.report().growing.iter().any(|trend| trend.metric == "vms"));
+}
+
+#[test]
+fn test_vm_scratch() {
+    register_native_object();
+
+    let js = JS::new(0, Atom::from("test_vm_scratch"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    assert!(js.new_str("a\0b".to_string()).is_err());
+    for index in 0..100 {
+        let str = js.new_str(format!("scratch {}", index)).unwrap();
+        assert_eq!(str.get_str(), format!("scratch {}", index));
+    }
+
+    //重入时使用新分配的缓冲区
+    let len = js.with_scratch(|outer| {
+        outer.extend_from_slice(b"outer");
+        js.with_scratch(|inner| {
+            assert!(inner.is_empty());
+            inner.extend_from_slice(b"inner");
+        });
+        outer.len()
+    });
+    assert_eq!(len, 5);
+    js.with_scratch(|buf| assert!(buf.is_empty()));
+}
+
+#[test]
+fn test_load_error() {
+    register_native_object();
+
+    let code = compile_source("ok.js", "var ok = true;").unwrap();
+    let factory = VMFactory::new(FactoryName::new("test_load_error").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(code)
+        .append(Arc::new(vec![0xff, 0x00, 0x01, 0x02]));
+    assert!(factory.last_load_error().is_none());
+
+    let e = factory.produce(1).unwrap_err();
+    assert!(e.contains("code index: 1"), "{}", e);
+    let load_error = factory.last_load_error().unwrap();
+    assert_eq!(load_error.factory, "test_load_error");
+    assert_eq!(load_error.index, Some(1));
+    assert_eq!(load_error.code_hash, Some(code_hash(&[0xff, 0x00, 0x01, 0x02])));
+    assert!(factory.new_vm_failed() > 0);
+
+    match factory.call(None, PortName::new("call").unwrap(), Box::new(|_vm: Arc<JS>| 0), TaskInfo::from("test load error")) {
+        Err(VMFactoryError::LoadFailed(e)) => assert_eq!(e.index, Some(1)),
+        r => panic!("unexpected call result: {:?}", r),
+    }
+}
+
+#[test]
+fn test_channel_inline_msg() {
+    register_native_object();
+
+    let small = ChannelMsg::new(&[1, 2, 3]);
+    assert!(small.is_inline());
+    assert_eq!(&small[..], &[1, 2, 3]);
+    let big = ChannelMsg::from(vec![0xff; INLINE_MSG_SIZE + 1]);
+    assert!(!big.is_inline());
+    assert_eq!(big.len(), INLINE_MSG_SIZE + 1);
+    assert_eq!(small.clone().into_shared().as_slice(), &[1, 2, 3]);
+
+    //处理器直接处理通道消息
+    struct InlineHandler(Arc<AtomicUsize>);
+    impl ChannelHandler for InlineHandler {
+        fn handle(&self, _: Arc<VMChannel>, _: Atom, _: Arc<Vec<u8>>, _: Vec<JSType>, _: Option<u32>) {
+            panic!("inline message converted");
+        }
+
+        fn handle_msg(&self, _: Arc<VMChannel>, _: Atom, payload: ChannelMsg, _: Vec<JSType>, _: Option<u32>) {
+            assert!(payload.is_inline());
+            self.0.fetch_add(payload.len(), Ordering::SeqCst);
+        }
+    }
+
+    let js = JS::new(1, Atom::from("test channel inline msg"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let map = VMChannelMap::new(0);
+    let count = Arc::new(AtomicUsize::new(0));
+    map.set_handler(Atom::from("inline"), Arc::new(InlineHandler(count.clone())));
+    map.set_handler(Atom::from("shared"), Arc::new(move |_: Arc<VMChannel>, _: Atom, payload: Arc<Vec<u8>>, _: Vec<JSType>, _: Option<u32>| {
+        assert_eq!(payload.as_slice(), &[1, 2, 3]);
+    }));
+    assert!(map.request_msg(js.clone(), Atom::from("inline"), small.clone(), vec![], None));
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+    assert!(map.request_msg(js, Atom::from("shared"), small, vec![], None)); //未直接处理通道消息的处理器透明的转换为共享消息
+}
+
+#[test]
+fn test_handler_adapter() {
+    use gray::GrayVersion;
+    use pi_vm::channel_map::HandlerAdapter;
+
+    //通用的处理器必须可以在线程间安全的共享，适配后不需要额外的不安全实现
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<HandlerAdapter>();
+
+    struct CountHandler(Arc<AtomicUsize>);
+    impl Handler for CountHandler {
+        type A = Arc<Vec<u8>>;
+        type B = Vec<JSType>;
+        type C = Option<u32>;
+        type D = ();
+        type E = ();
+        type F = ();
+        type G = ();
+        type H = ();
+        type HandleResult = ();
+
+        fn handle(&self, _env: Arc<dyn GrayVersion>, _name: Atom, args: Args<Self::A, Self::B, Self::C, Self::D, Self::E, Self::F, Self::G, Self::H>) -> Self::HandleResult {
+            if let Args::ThreeArgs(payload, _, _) = args {
+                self.0.fetch_add(payload.len(), Ordering::SeqCst);
+            }
+        }
+    }
+
+    register_native_object();
+    let js = JS::new(1, Atom::from("test handler adapter"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let map = VMChannelMap::new(0);
+    let count = Arc::new(AtomicUsize::new(0));
+    assert!(map.set(Atom::from("generic"), Arc::new(CountHandler(count.clone()))).is_none());
+    assert!(map.request_msg(js, Atom::from("generic"), ChannelMsg::new(&[1, 2, 3]), vec![], None));
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_factory_loader() {
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test_factory_loader").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(compile_source("a.js", "var a = 1;").unwrap())
+        .append(Arc::new(vec![0xff, 0x00]))
+        .append(compile_source("c.js", "var c = 3;").unwrap());
+    let js = JS::new(1, Atom::from("test factory loader"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+
+    let mut loader = factory.loader();
+    assert_eq!(loader.remaining(), 3);
+    assert_eq!(loader.try_load_next(&js).unwrap(), true);
+    let e = loader.try_load_next(&js).unwrap_err();
+    assert_eq!(e.index, Some(1));
+    assert_eq!(loader.offset(), 2);
+
+    //重试加载失败的字节码
+    assert!(loader.seek(e.index.unwrap()));
+    assert!(loader.try_load_next(&js).is_err());
+    assert!(!loader.seek(4));
+
+    //跳过可选的字节码
+    assert_eq!(loader.skip(5), 1);
+    assert_eq!(loader.remaining(), 0);
+    assert_eq!(loader.try_load_next(&js).unwrap(), false);
+
+    loader.reset();
+    assert_eq!(loader.remaining(), 3);
+    assert_eq!(loader.skip(2), 2);
+    assert_eq!(loader.try_load_next(&js).unwrap(), true);
+}
+
+#[test]
+fn test_produce_parallel() {
+    let worker_pool = Box::new(WorkerPool::new("js test".to_string(), WorkerType::Js, 4, 1024 * 1024, 30000, JS_WORKER_WALKER.clone()));
+    worker_pool.run(JS_TASK_POOL.clone());
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test_produce_parallel").unwrap(), 4, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(compile_source("a.js", "var a = 1;").unwrap());
+    let report = factory.produce_parallel(4, Duration::from_millis(30000));
+    assert!(report.is_ok(), "{:?}", report);
+    assert_eq!(report.produced(), 4);
+    assert_eq!(factory.size(), 4);
+
+    let factory = factory.append(Arc::new(vec![0xff, 0x00]));
+    let report = factory.produce_parallel(2, Duration::from_millis(30000));
+    assert_eq!(report.failed(), 2);
+}
+
+#[test]
+fn test_call_batch() {
+    use pi_vm::pi_vm_impl::{ArgsFn, CallReport};
+
+    let worker_pool = Box::new(WorkerPool::new("js test".to_string(), WorkerType::Js, 1, 1024 * 1024, 30000, JS_WORKER_WALKER.clone()));
+    worker_pool.run(JS_TASK_POOL.clone());
+    register_native_object();
+
+    //批量调用中的每个调用都会与普通调用一样开始和结束资源使用统计
+    let ports = Arc::new(Mutex::new(Vec::new()));
+    let ports_copy = ports.clone();
+    let factory = VMFactory::new(FactoryName::new("test_call_batch").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(compile_source("test_call_batch.js", "function add(x, y) { return x + y; } function fail() { throw new Error('fail'); }").unwrap())
+        .set_report_hook(Arc::new(move |report: CallReport| {
+            ports_copy.lock().unwrap().push(report.port.to_string());
+        }));
+    assert_eq!(factory.produce(1).unwrap(), 1);
+
+    let add: ArgsFn = Box::new(|vm: Arc<JS>| {
+        vm.new_u32(1);
+        vm.new_u32(2);
+        2
+    });
+    let fail: ArgsFn = Box::new(|_vm: Arc<JS>| 0);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    factory.call_batch(vec![(PortName::new("add").unwrap(), add), (PortName::new("fail").unwrap(), fail)],
+                       TaskInfo::from("test call batch task"),
+                       Box::new(move |results| {
+                           let _ = sender.send(results);
+                       })).unwrap();
+    let results = receiver.recv_timeout(Duration::from_millis(30000)).unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0], Ok(CallValue::Number(3.0)));
+    assert!(results[1].is_err());
+    assert_eq!(*ports.lock().unwrap(), vec!["add".to_string(), "fail".to_string()]);
+    assert_eq!(factory.size(), 1); //可以复用的虚拟机在批量调用完成后归还
+}
+
+#[test]
+fn test_code_verify() {
+    register_native_object();
+
+    //测试用的签名算法，签名为字节码长度的小端字节
+    let verifier = Arc::new(SignatureVerifier::new(Arc::new(|code: &[u8], signature: &[u8]| {
+        signature == &(code.len() as u32).to_le_bytes()[..]
+    })));
+    let code = compile_source("signed.js", "var signed = true;").unwrap();
+    let factory = VMFactory::new(FactoryName::new("test_code_verify").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(code.clone())
+        .set_verifier(verifier.clone());
+
+    //未签名的字节码
+    let e = factory.verify_codes().unwrap_err();
+    assert_eq!(e.index, Some(0));
+    assert!(e.reason.contains("unsigned code"), "{}", e);
+    assert!(factory.produce(1).is_err());
+    assert_eq!(factory.size(), 0); //构建失败不会占用虚拟机数量
+
+    //被篡改的字节码
+    verifier.add_signature(code.as_slice(), vec![0xff; 4]);
+    assert!(factory.verify_codes().unwrap_err().reason.contains("invalid signature"));
+
+    verifier.add_signature(code.as_slice(), (code.len() as u32).to_le_bytes().to_vec());
+    assert!(factory.verify_codes().is_ok());
+    assert_eq!(factory.produce(1).unwrap(), 1);
+}
+
+#[test]
+fn test_vm_status_batch() {
+    register_native_object();
+
+    let vms: Vec<Arc<JS>> = (0..3).map(|id| {
+        JS::new(id, Atom::from("test_vm_status_batch"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap()
+    }).collect();
+    assert_eq!(vms[0].status(), Some(JSStatus::NoTask));
+
+    assert!(vms[1].destroy().is_ok());
+    assert_eq!(vm_status_batch(&vms), vec![Some(JSStatus::NoTask), Some(JSStatus::Destroy), Some(JSStatus::NoTask)]);
+    assert!(vm_status_batch(&[]).is_empty());
+}
+
+#[test]
+fn test_code_crypt() {
+    register_native_object();
+
+    //测试用的解密算法，按字节与密钥异或
+    struct XorCipher;
+    impl CodeCipher for XorCipher {
+        fn decrypt(&self, key: &[u8], cipher_text: &[u8]) -> Result<Vec<u8>, String> {
+            if key.is_empty() {
+                return Err("empty key".to_string());
+            }
+            Ok(cipher_text.iter().enumerate().map(|(index, b)| b ^ key[index % key.len()]).collect())
+        }
+    }
+
+    let key = vec![0x5a, 0xa5];
+    let code = compile_source("encrypted.js", "var encrypted = 0x7f;").unwrap();
+    let cipher_text: Vec<u8> = code.iter().enumerate().map(|(index, b)| b ^ key[index % key.len()]).collect();
+    let factory = VMFactory::new(FactoryName::new("test_code_crypt").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append_encrypted("test key", cipher_text.as_slice()).unwrap()
+        .set_decryptor(Arc::new(XorCipher), Arc::new(move |key_id: &str| {
+            if key_id == "test key" {
+                Some(key.clone())
+            } else {
+                None
+            }
+        }));
+    assert!(!is_encrypted_code(code.as_slice()));
+    assert_eq!(factory.produce(1).unwrap(), 1);
+    let vm = factory.try_acquire().unwrap();
+    assert_eq!(vm.eval("encrypted".to_string()).get_u32(), 0x7f);
+    drop(vm);
+
+    //密钥不存在
+    let factory = VMFactory::new(FactoryName::new("test_code_crypt_no_key").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append_encrypted("unknown key", cipher_text.as_slice()).unwrap()
+        .set_decryptor(Arc::new(XorCipher), Arc::new(|_: &str| None));
+    assert!(factory.produce(1).unwrap_err().contains("key not found"));
+    assert!(factory.last_load_error().unwrap().reason.starts_with("code decrypt failed"));
+    assert_eq!(factory.size(), 0); //解密失败不会占用虚拟机数量
+
+    //没有设置解密器
+    let factory = VMFactory::new(FactoryName::new("test_code_crypt_no_decryptor").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append_encrypted("test key", cipher_text.as_slice()).unwrap();
+    assert!(factory.produce(1).unwrap_err().contains("decryptor not set"));
+    assert_eq!(factory.size(), 0);
+}
+
+#[test]
+fn test_response_affinity() {
+    let factory = VMFactory::new(FactoryName::new("test_response_affinity").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert!(!factory.is_response_affinity());
+    let factory = factory.set_response_affinity(true);
+    assert!(factory.is_response_affinity());
+
+    //非虚拟机发起的请求没有源
+    let channel = VMChannel::new(VMChannelPeer::Any, VMChannelPeer::Any);
+    assert!(channel.source().is_none());
+}
+
+#[test]
+fn test_bytecode_version() {
+    register_native_object();
+
+    let code = compile_source("version.js", "var version = 1;").unwrap();
+    let version = duk_version().unwrap();
+    assert_eq!(bytecode_version(&code), Some(version));
+    assert!(check_bytecode(&code, || Some(version)).is_ok());
+
+    //不同Duktape版本编译的字节码
+    let stale = stamp_bytecode(&code, version + 1);
+    assert_eq!(check_bytecode(&stale, || Some(version)), Err(BytecodeError::VersionMismatch(version, version + 1)));
+    let js = JS::new(0, Atom::from("test_bytecode_version"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    assert!(!js.load(&stale));
+    assert!(js.take_last_error().unwrap().starts_with("bytecode version mismatch"));
+
+    assert_eq!(check_bytecode(&[0x00, 0x01], || None), Err(BytecodeError::InvalidMarker(0x00)));
+    assert_eq!(check_bytecode(&[], || None), Err(BytecodeError::Empty));
+}
+
+#[test]
+fn test_late_callback_policy() {
+    let factory = VMFactory::new(FactoryName::new("test_late_callback_policy").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert_eq!(factory.late_callback_policy(), LateCallbackPolicy::Reject);
+    let factory = factory.set_late_callback_policy(LateCallbackPolicy::Throw);
+    assert_eq!(factory.late_callback_policy(), LateCallbackPolicy::Throw);
+
+    //清理全局环境后，重置次数增加，清理前推送的异步回调会被拒绝
+    let js = JS::new(0, Atom::from("test_late_callback_policy"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    assert_eq!(js.recycle_epoch(), 0);
+    assert!(js.clear_global());
+    assert_eq!(js.recycle_epoch(), 1);
+}
+
+#[test]
+fn test_compile_cache() {
+    use pi_vm::api::CompileCache;
+
+    register_native_object();
+
+    let dir = std::env::temp_dir().join("pi_vm_test_compile_cache");
+    let _ = std::fs::remove_dir_all(&dir);
+    let cache = CompileCache::new(&dir).unwrap().with_options("es5");
+    assert!(cache.get("cache.js", "var cached = 1;").is_none());
+    let code = cache.compile("cache.js", "var cached = 1;").unwrap();
+    assert_eq!(cache.get("cache.js", "var cached = 1;").unwrap().as_slice(), code.as_slice());
+
+    //编译选项不同的源码不共享缓存
+    let other = CompileCache::new(&dir).unwrap().with_options("es6");
+    assert!(other.get("cache.js", "var cached = 1;").is_none());
+
+    //缓存键在不同的缓存实例间保持一致，且文件名和源码的边界不会混淆
+    assert_eq!(CompileCache::new(&dir).unwrap().with_options("es5").key("cache.js", "var cached = 1;"), cache.key("cache.js", "var cached = 1;"));
+    assert_ne!(cache.key("cache.js", "var cached = 1;"), cache.key("cache.jsvar", " cached = 1;"));
+
+    //损坏的缓存文件会被移除并重新编译
+    std::fs::write(dir.join(format!("{:016x}.{}", cache.key("cache.js", "var cached = 1;"), pi_vm::api::COMPILE_CACHE_EXT)), b"broken").unwrap();
+    assert!(cache.get("cache.js", "var cached = 1;").is_none());
+
+    let factory = VMFactory::new(FactoryName::new("test_compile_cache").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_compile_cache(cache.clone())
+        .append_source("cache.js", "var cached = 1;")
+        .unwrap();
+    assert_eq!(factory.loader().remaining(), 1);
+    assert!(cache.get("cache.js", "var cached = 1;").is_some());
+    assert_eq!(cache.clear(), 1);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_stale_vm() {
+    use pi_vm::api::{VmError, push_callback_checked};
+
+    let js = JS::new(0, Atom::from("test_stale_vm"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let epoch = js.recycle_epoch();
+    assert!(js.check_epoch(epoch).is_ok());
+
+    //虚拟机被回收重置后，之前持有的代数已过期
+    assert!(js.clear_global());
+    assert_eq!(js.check_epoch(epoch), Err(VmError::Stale(epoch, epoch + 1)));
+    let args = Box::new(|_vm: Arc<JS>| -> usize { 0 });
+    assert_eq!(push_callback_checked(js.clone(), epoch, 0, args, None, TaskInfo::new("test_stale_vm")), Err(VmError::Stale(epoch, epoch + 1)));
+}
+
+#[test]
+fn test_heap_snapshot() {
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test_heap_snapshot").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(compile_source("snapshot.js", "var snapshot = 1;").unwrap());
+    assert!(factory.clone().set_snapshot(false).is_ok());
+    //未启用snapshot特性构建时，拒绝启用堆快照
+    match factory.set_snapshot(true) {
+        Err(_) => assert!(!cfg!(feature = "snapshot")),
+        Ok(factory) => {
+            assert!(factory.is_snapshot());
+            assert_eq!(factory.snapshot_size(), 0);
+        },
+    }
+
+    let js = JS::new(0, Atom::from("test_heap_snapshot"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    //未启用snapshot特性构建时，明确返回不支持的错误
+    match js.heap_snapshot() {
+        Err(e) => assert!(!cfg!(feature = "snapshot") && e.contains("snapshot feature disabled"), "{}", e),
+        Ok(_) => assert!(cfg!(feature = "snapshot")),
+    }
+    assert!(js.restore_heap_snapshot(&[]).is_err()); //空的堆快照无法恢复
+}
+
+#[test]
+fn test_maintenance_priority() {
+    use pi_vm::api::{default_task_priority, maintenance_priority, set_maintenance_busy_threshold, maintenance_busy_threshold};
+
+    //内部维护任务的优先级总是低于用户调用
+    assert!(maintenance_priority() < default_task_priority());
+
+    let old = set_maintenance_busy_threshold(8);
+    assert_eq!(maintenance_busy_threshold(), 8);
+    assert_eq!(set_maintenance_busy_threshold(old), 8);
+}
+
+#[test]
+fn test_vm_state_snapshot() {
+    register_native_object();
+
+    let js = JS::new(0, Atom::from("test_vm_state_snapshot"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    js.eval("var world = { level: 3, players: ['a', 'b'] }; var __internal = 1; function tick() { return 1; }".to_string());
+    let snapshot = js.snapshot().unwrap();
+
+    //恢复到新的虚拟机，只恢复可以序列化的全局变量
+    let copy = JS::new(1, Atom::from("test_vm_state_snapshot_copy"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    assert!(copy.restore(&snapshot).is_ok());
+    assert_eq!(copy.eval("world.level + world.players.length".to_string()).get_u32(), 5);
+    assert_eq!(copy.eval("typeof __internal".to_string()).get_str(), "undefined");
+    assert_eq!(copy.eval("typeof tick".to_string()).get_str(), "undefined");
+
+    assert!(copy.restore(b"invalid").is_err());
+}
+
+#[test]
+fn test_lower_task_priority() {
+    let js = JS::new(0, Atom::from("test_lower_task_priority"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    assert_eq!(js.lower_task_priority(10), None);
+
+    let task = js.enqueue_task(&TaskInfo::new("test_lower_task_priority").with_priority(50));
+    js.start_task(task.id());
+    assert_eq!(js.lower_task_priority(10), Some(10));
+    //只允许降低优先级
+    assert_eq!(js.lower_task_priority(80), Some(10));
+    assert_eq!(js.running_task().unwrap().priority(), 10);
+    js.finish_task();
+}
+
+#[test]
+fn test_oom_hook() {
+    use pi_vm::api::{OomInfo, OomAction};
+
+    let ports = Arc::new(Mutex::new(Vec::new()));
+    let ports_copy = ports.clone();
+    let factory = VMFactory::new(FactoryName::new("test_oom_hook").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_oom_hook(Arc::new(move |info: &OomInfo| {
+            ports_copy.lock().unwrap().push(info.port.clone());
+            OomAction::Recycle
+        }));
+
+    let js = JS::new(0, Atom::from("test_oom_hook"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    assert_eq!(factory.notify_oom(&js, Some("main".to_string()), "RangeError: alloc failed".to_string()), OomAction::Recycle);
+    assert_eq!(ports.lock().unwrap().as_slice(), &[Some("main".to_string())]);
+    assert_eq!(js.recycle_requested(), Some("out of memory".to_string()));
+}
+
+#[test]
+fn test_recursion_limit() {
+    use pi_vm::adapter::VmError;
+
+    let factory = VMFactory::new(FactoryName::new("test_recursion_limit").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    //未启用stacklimit或nativelimit特性构建时，拒绝设置调用栈深度限制或本地调用递归深度限制
+    match factory.clone().set_stack_limit(64) {
+        Err(_) => assert!(!cfg!(feature = "stacklimit")),
+        Ok(factory) => assert_eq!(factory.stack_limit(), Some(64)),
+    }
+    match factory.set_native_limit(32) {
+        Err(_) => assert!(!cfg!(feature = "nativelimit")),
+        Ok(factory) => assert_eq!(factory.native_limit(), Some(32)),
+    }
+    assert_eq!(VMFactoryBuilder::new(FactoryName::new("test_recursion_limit_builder").unwrap(), Arc::new(NativeObjsAuth::new(None, None)))
+        .stack_limit(64)
+        .build()
+        .is_ok(), cfg!(feature = "stacklimit"));
+
+    //失控的递归抛出可以捕获的RangeError
+    let js = JS::new(0, Atom::from("test_recursion_limit"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    match js.set_stack_limit(64) {
+        Err(e) => assert_eq!(e, VmError::Unsupported("stacklimit")),
+        Ok(()) => assert!(cfg!(feature = "stacklimit")),
+    }
+    let caught = js.eval("(function() { try { (function f(n) { return f(n + 1) + 1; })(0); return false; } catch(e) { return e instanceof RangeError; } })()".to_string());
+    assert!(caught.get_boolean());
+}
+
+#[test]
+fn test_call_checkpoint() {
+    let js = JS::new(0, Atom::from("test_call_checkpoint"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    //没有统计中的调用时忽略检查点
+    assert!(!js.checkpoint("load"));
+
+    js.start_call(Atom::from("render"), 0);
+    assert!(js.checkpoint("load"));
+    thread::sleep(Duration::from_millis(10));
+    assert!(js.checkpoint("template-render"));
+    thread::sleep(Duration::from_millis(20));
+    let report = js.finish_call().unwrap();
+    let names: Vec<String> = report.phases.iter().map(|(name, _)| (name).to_string()).collect();
+    assert_eq!(names, vec!["load".to_string(), "template-render".to_string()]);
+    assert!(report.phases[1].1 >= Duration::from_millis(20));
+
+    let factory = VMFactory::new(FactoryName::new("test_call_checkpoint").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    factory.record_phases(&report);
+    let phases = factory.phases();
+    assert_eq!(phases.len(), 2);
+    assert_eq!(phases[1].phase, "template-render");
+    assert_eq!(phases[1].calls, 1);
+}
+
+#[test]
+fn test_channel_explain() {
+    let js = JS::new(1, Atom::from("test_channel_explain"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let map = VMChannelMap::new(2);
+    map.set_handler(Atom::from("render"), Arc::new(|channel: Arc<VMChannel>, _: Atom, _: Arc<Vec<u8>>, _: Vec<JSType>, _: Option<u32>| {
+        channel.explain("gray >= 2: new template", &["gray < 2: old template"]);
+    }));
+
+    let task = js.enqueue_task(&TaskInfo::new("test_channel_explain").with_correlation_id(7));
+    js.start_task(task.id());
+    //未开启解释模式时不记录路由解释
+    assert!(map.request(js.clone(), Atom::from("render"), Arc::new(vec![]), vec![], None));
+    assert!(map.explain(7).is_empty());
+
+    map.set_explain(true);
+    assert!(map.request(js.clone(), Atom::from("render"), Arc::new(vec![]), vec![], None));
+    assert!(!map.request(js.clone(), Atom::from("none"), Arc::new(vec![]), vec![], None));
+    js.finish_task();
+
+    let traces = map.explain(7);
+    assert_eq!(traces.len(), 2);
+    assert_eq!((traces[0].name.as_str(), traces[0].found, traces[0].gray), ("render", true, 2));
+    assert_eq!(traces[0].rule, Some("gray >= 2: new template".to_string()));
+    assert_eq!(traces[0].alternatives, vec!["gray < 2: old template".to_string()]);
+    assert_eq!((traces[1].found, traces[1].rule.clone()), (false, None));
+    assert!(map.explain(8).is_empty());
+}
+
+#[cfg(feature = "interruptcheck")]
+#[test]
+fn test_call_fuel() {
+    use pi_vm::adapter::InterruptReason;
+
+    let factory = VMFactory::new(FactoryName::new("test_call_fuel").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_fuel(2)
+        .unwrap();
+    assert_eq!(factory.fuel(), Some(2));
+
+    let js = JS::new(0, Atom::from("test_call_fuel"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    js.start_call(Atom::from("spin"), 0);
+    assert_eq!(js.fuel_remaining(), None);
+    js.set_fuel(2);
+    assert_eq!(js.fuel_remaining(), Some(2));
+
+    //燃料耗尽时中断虚拟机执行
+    js.eval("(function() { var n = 0; for(var i = 0; i < 100000000; i++) { n += i; } return n; })()".to_string());
+    assert_eq!(js.interrupted(), Some(InterruptReason::FuelExhausted));
+    assert_eq!(js.fuel_remaining(), Some(0));
+    let report = js.finish_call().unwrap();
+    assert!(report.fuel >= 2);
+    js.clear_interrupt();
+}
+
+#[test]
+fn test_source_quota() {
+    use pi_vm::api::{SourceQuota, QuotaManager, Throttled};
+
+    let manager = QuotaManager::new(SourceQuota { calls_per_second: 2, max_concurrent: 1 });
+    let permit = manager.acquire(1).unwrap();
+    assert_eq!(manager.acquire(1).err(), Some(Throttled::Concurrency(1, 1)));
+    assert!(manager.acquire(2).is_ok()); //其它源不受影响
+    drop(permit);
+    assert_eq!(manager.concurrent(1), 0);
+
+    drop(manager.acquire(1).unwrap());
+    assert_eq!(manager.acquire(1).err(), Some(Throttled::Rate(1, 2)));
+
+    //指定源的配额
+    manager.set_quota(1, SourceQuota::default());
+    assert!(manager.acquire(1).is_ok());
+}
+
+#[test]
+fn test_port_signature() {
+    use pi_vm::api::{ValueType, SignatureError, PortSignature};
+    use pi_vm::names::PortName;
+
+    let signature = PortSignature::new(vec![ValueType::String, ValueType::Json], ValueType::Number)
+        .with_schema(1, r#"{"type": "object", "required": ["id"], "properties": {"id": {"type": "integer"}}}"#).unwrap();
+    assert!(PortSignature::new(vec![ValueType::String], ValueType::Any).with_schema(0, "{}").is_err()); //只允许为json参数设置JSON Schema
+
+    assert!(signature.build_args("user.get", vec![CallValue::String("a".to_string()), CallValue::String(r#"{"id": 1}"#.to_string())]).is_ok());
+    assert_eq!(signature.build_args("user.get", vec![CallValue::String("a".to_string())]).err(),
+               Some(SignatureError::ArgCount("user.get".to_string(), 2, 1)));
+    match signature.build_args("user.get", vec![CallValue::Number(1.0), CallValue::String("{}".to_string())]) {
+        Err(SignatureError::ArgType(_, 0, ValueType::String, _)) => (),
+        _ => panic!("arg type not checked"),
+    }
+    match signature.build_args("user.get", vec![CallValue::String("a".to_string()), CallValue::String(r#"{"id": 1.5}"#.to_string())]) {
+        Err(SignatureError::Schema(_, 1, _)) => (),
+        _ => panic!("arg schema not checked"),
+    }
+
+    assert!(signature.check_result("user.get", &CallValue::Number(1.0)).is_ok());
+    assert!(signature.check_result("user.get", &CallValue::Undefined).is_err());
+
+    let port = PortName::new("user.get").unwrap();
+    let factory = VMFactory::new(FactoryName::new("test_port_signature").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert_eq!(factory.call_typed(None, port.clone(), vec![], TaskInfo::new("test_port_signature"), Box::new(|_| {})).err(),
+               Some(SignatureError::UnknownPort("user.get".to_string())));
+    let factory = factory.set_port_signature(port.clone(), signature);
+    assert!(factory.port_signature(&port).is_some());
+}
+
+#[test]
+fn test_circuit_breaker() {
+    use pi_vm::api::{BreakerConfig, BreakerState, CircuitBreaker};
+
+    let breaker = CircuitBreaker::new(BreakerConfig {
+        error_rate: 0.5,
+        min_calls: 2,
+        window: Duration::from_secs(10),
+        cool_down: Duration::from_millis(50),
+        half_open_calls: 1,
+    });
+    let port = Atom::from("render");
+    breaker.record(&port, false);
+    assert_eq!(breaker.state(&port), BreakerState::Closed); //未达到最少调用数量
+    breaker.record(&port, false);
+    assert_eq!(breaker.state(&port), BreakerState::Open);
+    assert!(breaker.allow(&port).is_err());
+    assert!(breaker.allow(&Atom::from("other")).is_ok());
+
+    //冷却后只允许探测调用
+    thread::sleep(Duration::from_millis(60));
+    assert!(breaker.allow(&port).is_ok());
+    assert_eq!(breaker.state(&port), BreakerState::HalfOpen);
+    assert!(breaker.allow(&port).is_err());
+    breaker.record(&port, true);
+    assert_eq!(breaker.state(&port), BreakerState::Closed);
+    assert!(breaker.allow(&port).is_ok());
+}
+
+#[test]
+fn test_function_cache() {
+    use pi_vm::api::{FunctionCache, capabilities};
+
+    let vm = JS::new(1, Atom::from("test function cache"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let cache = FunctionCache::new().set_capacity(2);
+    let result = cache.get_or_dump(&vm, "test_function_cache.js", "function add(a, b) { return a + b; }");
+    assert_eq!(result.is_ok(), cfg!(feature = "funcdump"));
+    assert_eq!(cache.len(), if cfg!(feature = "funcdump") { 1 } else { 0 });
+    assert_eq!(capabilities().funcdump, cfg!(feature = "funcdump"));
+
+    let engine = ExpressionEngine::new();
+    let expr = engine.compile("a + b").unwrap();
+    assert_eq!(expr.is_dumped(), cfg!(feature = "funcdump"));
+    assert_eq!(engine.eval(&expr, &[("a", ScratchValue::Number(1.0)), ("b", ScratchValue::Number(2.0))]).unwrap(), ScratchValue::Number(3.0));
+}
+
+#[test]
+fn test_call_cancel_flag() {
+    use std::sync::atomic::AtomicBool;
+    use pi_vm::adapter::InterruptReason;
+
+    let js = JS::new(0, Atom::from("test_call_cancel_flag"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    js.start_call(Atom::from("render"), 0);
+    assert!(!js.is_call_canceled()); //当前调用不可取消
+
+    let canceled = Arc::new(AtomicBool::new(false));
+    js.set_cancel_flag(canceled.clone());
+    assert!(!js.is_call_canceled());
+    canceled.store(true, Ordering::SeqCst);
+    assert!(js.is_call_canceled());
+    assert!(js.interrupt(InterruptReason::Canceled));
+    assert_eq!(js.clear_interrupt(), Some(InterruptReason::Canceled));
+
+    //新的调用不会继承上个调用的取消标记
+    js.start_call(Atom::from("render"), 0);
+    assert!(!js.is_call_canceled());
+}
+
+#[test]
+fn test_factory_warmup() {
+    register_native_object();
+    let js = JS::new(1, Atom::from("test_factory_warmup"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let code = js.compile("test_factory_warmup.js".to_string(), "var warmed = 0; var label = ''; function render(n) { warmed += n; return warmed; } function named(s) { label = s; }".to_string()).unwrap();
+
+    let warmed = Arc::new(AtomicUsize::new(0));
+    let warmed_copy = warmed.clone();
+    let factory = VMFactory::new(FactoryName::new("test_factory_warmup").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code))
+        .add_warmup(PortName::new("render").unwrap(), vec![CallValue::Number(2.0)], 3)
+        .add_warmup(PortName::new("named").unwrap(), vec![CallValue::String("]); warmed = 100; ([".to_string())], 1)
+        .add_warmup(PortName::new("named").unwrap(), vec![CallValue::String("a\0b".to_string())], 1)
+        .set_create_hook(Arc::new(move |vm: &Arc<JS>| {
+            warmed_copy.store(vm.eval("warmed".to_string()).get_f64() as usize, Ordering::SeqCst);
+        }));
+    assert_eq!(factory.warmups().len(), 3);
+    assert!(factory.produce(1).is_ok());
+    assert_eq!(warmed.load(Ordering::SeqCst), 6); //不可复用的虚拟机不会重置全局环境，预热参数不会作为脚本执行
+}
+
+#[test]
+fn test_thread_audit() {
+    let js = JS::new(0, Atom::from("test_thread_audit"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    {
+        let _borrow = js.borrow_thread("test");
+        assert!(js.thread_owner().is_none()); //未审计则不记录
+    }
+
+    js.set_thread_audit(true);
+    let borrow = js.borrow_thread("test");
+    let nested = js.borrow_thread("nested"); //同一线程可以重入
+    if cfg!(feature = "threadaudit") {
+        assert_eq!(js.thread_owner(), Some((thread::current().id(), "test")));
+    } else {
+        assert!(js.thread_owner().is_none());
+    }
+
+    //其它线程在持有期间访问虚拟机则断言失败
+    let js_copy = js.clone();
+    let other = thread::spawn(move || {
+        let _borrow = js_copy.borrow_thread("other");
+    });
+    assert_eq!(other.join().is_err(), cfg!(feature = "threadaudit"));
+
+    drop(nested);
+    drop(borrow);
+    assert!(js.thread_owner().is_none());
+}
+
+#[cfg(feature = "interruptcheck")]
+#[test]
+fn test_vm_terminate() {
+    use pi_vm::adapter::InterruptReason;
+
+    let js = JS::new(0, Atom::from("test_vm_terminate"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let js_copy = js.clone();
+    let terminator = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        js_copy.terminate()
+    });
+
+    //在其它线程终止死循环
+    assert!(js.eval("while(true) {}".to_string()).is_none());
+    assert!(terminator.join().unwrap());
+    assert_eq!(js.interrupted(), Some(InterruptReason::Terminated));
+    assert!(!js.terminate()); //已被中断则忽略
+    js.clear_interrupt();
+}
+
+#[test]
+fn test_vm_terminate_idle() {
+    use pi_vm::adapter::InterruptReason;
+
+    //空闲的虚拟机不会被终止，之后的调用不受影响
+    let js = JS::new(0, Atom::from("test_vm_terminate_idle"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    assert!(!js.terminate());
+    assert!(js.interrupted().is_none());
+    assert_eq!(js.eval("1 + 1".to_string()).get_u32(), 2);
+
+    //指定的调用已完成则忽略中断请求
+    js.start_call(Atom::from("render"), 0);
+    let start = js.call_start_time().unwrap();
+    assert!(js.finish_call().is_some());
+    assert!(!js.interrupt_call(InterruptReason::Canceled, Some(start)));
+    assert!(!js.interrupt_call(InterruptReason::Canceled, None));
+    assert!(js.interrupted().is_none());
+
+    //新的调用会清除上个调用遗留的中断请求
+    js.start_call(Atom::from("render"), 0);
+    assert!(js.interrupt_call(InterruptReason::Canceled, None));
+    js.finish_call();
+    js.start_call(Atom::from("render"), 0);
+    assert!(js.interrupted().is_none());
+    js.finish_call();
+}
+
+#[test]
+fn test_eval_policy() {
+    use pi_vm::adapter::EvalPolicy;
+
+    let factory = VMFactory::new(FactoryName::new("test_eval_policy").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert!(factory.clone().set_eval_policy(EvalPolicy::Allow).is_ok());
+    //未启用evalcheck特性构建时无法禁止执行动态代码，拒绝设置禁止策略
+    assert_eq!(factory.set_eval_policy(EvalPolicy::Deny(None)).is_ok(), cfg!(feature = "evalcheck"));
+}
+
+#[test]
+fn test_call_timeout() {
+    //未启用interruptcheck特性构建时无法中断虚拟机执行，拒绝设置调用超时
+    let factory = VMFactory::new(FactoryName::new("test_call_timeout").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_call_timeout(Duration::from_millis(1000));
+    assert_eq!(factory.is_ok(), cfg!(feature = "interruptcheck"));
+    if let Ok(factory) = factory {
+        assert_eq!(factory.call_timeout(), Some(Duration::from_millis(1000)));
+    }
+
+    let factory = VMFactoryBuilder::new(FactoryName::new("test_call_timeout_builder").unwrap(), Arc::new(NativeObjsAuth::new(None, None)))
+        .call_timeout(Duration::from_millis(1000))
+        .build();
+    assert_eq!(factory.is_ok(), cfg!(feature = "interruptcheck"));
+
+    //未启用interruptcheck特性构建时无法计量燃料，拒绝设置燃料预算
+    let factory = VMFactory::new(FactoryName::new("test_call_timeout_fuel").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert!(factory.clone().set_fuel(0).is_ok());
+    assert_eq!(factory.set_fuel(2).is_ok(), cfg!(feature = "interruptcheck"));
+}
+
+#[cfg(feature = "interruptcheck")]
+#[test]
+fn test_call_watchdog() {
+    use pi_vm::adapter::InterruptReason;
+    use pi_vm::pi_vm_impl::watch_call;
+
+    //超时仍未完成的调用会被中断
+    let js = JS::new(0, Atom::from("test_call_watchdog"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    js.start_call(Atom::from("spin"), 0);
+    watch_call(&js, Duration::from_millis(50));
+    assert!(js.eval("while(true) {}".to_string()).is_none());
+    assert_eq!(js.interrupted(), Some(InterruptReason::Timeout));
+    js.finish_call();
+
+    //超时前已完成的调用，看门狗不会中断虚拟机的后续调用
+    js.start_call(Atom::from("render"), 0);
+    assert!(js.interrupted().is_none());
+    watch_call(&js, Duration::from_millis(20));
+    js.finish_call();
+    thread::sleep(Duration::from_millis(1)); //保证调用的开始时间不同
+    js.start_call(Atom::from("render"), 0);
+    thread::sleep(Duration::from_millis(60));
+    assert!(js.interrupted().is_none());
+    assert_eq!(js.eval("1 + 1".to_string()).get_u32(), 2);
+    js.finish_call();
+}
+
+#[cfg(feature = "hotreload")]
+#[test]
+fn test_hot_reload() {
+    use pi_vm::api::hot_reload::HotReloader;
+
+    register_native_object();
+
+    let dir = std::env::temp_dir().join("pi_vm_test_hot_reload");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.js"), "var version = 1;").unwrap();
+
+    let source: Arc<CodeSource> = Arc::new(GlobSource::new(dir.clone(), &["*.js"]));
+    let factory = VMFactory::new(FactoryName::new("test_hot_reload").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append_sources(&[source.clone()])
+        .unwrap();
+    let reloader = HotReloader::new(factory.clone(), vec![source]);
+    assert!(reloader.check().is_none()); //首次检查只记录文件指纹
+    assert!(reloader.check().is_none());
+
+    std::fs::write(dir.join("a.js"), "var version = 22;").unwrap();
+    let report = reloader.check().unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.generation, Some(factory.code_generation()));
+
+    //编译失败时保留原字节码
+    std::fs::write(dir.join("a.js"), "var version = ;").unwrap();
+    assert!(!reloader.check().unwrap().is_ok());
+    assert_eq!(report.generation, Some(factory.code_generation()));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_call_report() {
+    use pi_vm::pi_vm_impl::CallReport;
+
+    let reports: Arc<Mutex<Vec<CallReport>>> = Arc::new(Mutex::new(Vec::new()));
+    let reports_copy = reports.clone();
+    let factory = VMFactory::new(FactoryName::new("test_call_report").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_report_hook(Arc::new(move |report: CallReport| {
+            reports_copy.lock().unwrap().push(report);
+        }));
+    let vm = factory.take().unwrap();
+
+    //没有统计中的调用则不提交报告
+    assert!(!vm.report_call());
+    assert!(reports.lock().unwrap().is_empty());
+
+    vm.start_call(Atom::from("render"), 0);
+    vm.add_callback_count();
+    vm.add_bytes_in(3);
+    vm.add_bytes_out(5);
+    assert!(vm.report_call());
+    assert!(!vm.report_call()); //调用只报告一次
+
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].vm_id, vm.get_id());
+    assert_eq!(reports[0].port, Atom::from("render"));
+    assert_eq!(reports[0].callbacks, 1);
+    assert_eq!(reports[0].errors, 0);
+    assert_eq!(reports[0].bytes_in, 3);
+    assert_eq!(reports[0].bytes_out, 5);
+    assert!(reports[0].latency >= reports[0].wall_time);
+}
+
+#[test]
+fn test_vm_usage() {
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test_vm_usage").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_limits(FactoryLimits {
+            max_total_heap: 2147483648,
+            cpu_per_minute: Some(Duration::from_secs(60)),
+            on_exhausted: BudgetExhausted::Queue,
+        });
+    let vm = factory.take().unwrap();
+    assert_eq!(CallValue::from(&*vm.eval("typeof __pi_vm.usage()".to_string())), CallValue::String("object".to_string()));
+    assert!(vm.eval("__pi_vm.usage().heapSize".to_string()).get_f64() > 0.0);
+    assert_eq!(vm.eval("__pi_vm.usage().heapLimit".to_string()).get_f64(), 1073741824.0);
+    assert_eq!(vm.eval("__pi_vm.usage().factoryHeapLimit".to_string()).get_f64(), 2147483648.0);
+    let cpu_remaining = vm.eval("__pi_vm.usage().cpuRemaining".to_string()).get_f64();
+    assert!(cpu_remaining > 0.0 && cpu_remaining <= 60000.0);
+    assert_eq!(vm.eval("__pi_vm.usage().callTime".to_string()).get_f64(), 0.0); //没有统计中的调用
+    assert_eq!(vm.eval("__pi_vm.usage().queueLength".to_string()).get_f64(), 0.0);
+
+    //未限制的资源返回-1
+    let factory = VMFactory::new(FactoryName::new("test_vm_usage_unlimited").unwrap(), 0, 0, 1073741824, 0, Arc::new(NativeObjsAuth::new(None, None)));
+    let vm = factory.take().unwrap();
+    assert_eq!(vm.eval("__pi_vm.usage().heapLimit".to_string()).get_f64(), -1.0);
+    assert_eq!(vm.eval("__pi_vm.usage().factoryHeapLimit".to_string()).get_f64(), -1.0);
+    assert_eq!(vm.eval("__pi_vm.usage().cpuRemaining".to_string()).get_f64(), -1.0);
+}
+
+#[test]
+fn test_vm_identity() {
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test_vm_identity").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_code_version("1.2.\"3\"");
+    let vm = factory.take().unwrap();
+    assert_eq!(vm.eval("__vm.id".to_string()).get_f64(), vm.get_id() as f64);
+    assert_eq!(CallValue::from(&*vm.eval("__vm.factory".to_string())), CallValue::String("test_vm_identity".to_string()));
+    assert_eq!(CallValue::from(&*vm.eval("__vm.version".to_string())), CallValue::String("1.2.\"3\"".to_string())); //版本号会被转义为js字符串
+    assert!(vm.eval("__vm.createdAt".to_string()).get_f64() > 0.0);
+
+    //虚拟机标识不可修改、不可替换且不可枚举
+    assert_eq!(CallValue::from(&*vm.eval("Object.isFrozen(__vm)".to_string())), CallValue::Boolean(true));
+    assert_eq!(vm.eval("__vm.id = -1; __vm.id".to_string()).get_f64(), vm.get_id() as f64);
+    assert_eq!(CallValue::from(&*vm.eval("__vm = null; typeof __vm".to_string())), CallValue::String("object".to_string()));
+    assert_eq!(CallValue::from(&*vm.eval("Object.keys(this).indexOf('__vm')".to_string())), CallValue::Number(-1.0));
+
+    //同一虚拟机工厂构建的虚拟机有不同的标识
+    let other = factory.take().unwrap();
+    assert_ne!(other.get_id(), vm.get_id());
+    assert_eq!(other.eval("__vm.id".to_string()).get_f64(), other.get_id() as f64);
+}
+
+#[test]
+fn test_port_route() {
+    let factory = VMFactory::new(FactoryName::new("test_port_route").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let alias = PortName::new("user.get@v2").unwrap();
+    assert_eq!(factory.resolve_port(&alias), alias); //没有路由则解析为端口本身
+
+    assert!(factory.add_route(alias.clone(), PortName::new("user_get_v2").unwrap()).is_none());
+    assert_eq!(factory.resolve_port(&alias), PortName::new("user_get_v2").unwrap());
+    assert_eq!(factory.add_route(alias.clone(), PortName::new("user_get").unwrap()), Some(PortName::new("user_get_v2").unwrap()));
+    assert_eq!(factory.routes(), vec![(alias.clone(), PortName::new("user_get").unwrap())]);
+
+    //复制的虚拟机工厂共享路由表
+    let copy = factory.clone();
+    assert_eq!(copy.resolve_port(&alias), PortName::new("user_get").unwrap());
+
+    assert_eq!(factory.remove_route(&alias), Some(PortName::new("user_get").unwrap()));
+    assert!(factory.remove_route(&alias).is_none());
+    assert_eq!(copy.resolve_port(&alias), alias);
+    assert!(factory.routes().is_empty());
+}
+
+#[test]
+fn test_vm_labels() {
+    use pi_vm::api::{find_vms, vm_stats};
+
+    let factory = VMFactory::new(FactoryName::new("test_vm_labels").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append_label("tenant", "test_vm_labels")
+        .append_label("shard", "7");
+    assert_eq!(factory.labels(), vec![("tenant".to_string(), "test_vm_labels".to_string()), ("shard".to_string(), "7".to_string())]);
+
+    //虚拟机工厂构建的虚拟机带有虚拟机工厂的标签
+    let vm = factory.take().unwrap();
+    assert_eq!(vm.get_label("shard"), Some("7".to_string()));
+    assert!(vm.get_label("region").is_none());
+    assert!(vm.match_labels(&[("tenant", "test_vm_labels"), ("shard", "7")]));
+    assert!(!vm.match_labels(&[("tenant", "test_vm_labels"), ("shard", "8")]));
+
+    let vms = find_vms(&[("tenant", "test_vm_labels")]);
+    assert_eq!(vms.len(), 1);
+    assert_eq!(vms[0].get_id(), vm.get_id());
+    assert!(find_vms(&[("tenant", "test_vm_labels"), ("shard", "8")]).is_empty());
+    drop(vms);
+
+    let stats = vm_stats(&[("tenant", "test_vm_labels")]);
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].id, vm.get_id());
+    assert_eq!(stats[0].name, "test_vm_labels");
+    assert_eq!(stats[0].labels.get("shard"), Some(&"7".to_string()));
+
+    //释放的虚拟机会从虚拟机注册表中注销
+    drop(vm);
+    assert!(find_vms(&[("tenant", "test_vm_labels")]).is_empty());
+}
+
+#[test]
+fn test_call_error() {
+    //构建虚拟机失败时返回错误，调用不会被执行
+    let factory = VMFactory::new(FactoryName::new("test_call_error").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(vec![0xff, 0x00]));
+    match factory.call(None, PortName::new("call").unwrap(), Box::new(|_vm: Arc<JS>| panic!("call must not run")), TaskInfo::from("test call error")) {
+        Err(VMFactoryError::LoadFailed(_)) | Err(VMFactoryError::NewVmFailed(_)) => (),
+        r => panic!("call with invalid codes must fail, r: {:?}", r.err()),
+    }
+    assert_eq!(factory.in_flight(), 0);
+
+    //聚合资源预算已耗尽且拒绝任务时返回错误
+    let factory = VMFactory::new(FactoryName::new("test_call_error_budget").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_limits(FactoryLimits {
+            max_total_heap: 0,
+            cpu_per_minute: Some(Duration::from_millis(0)),
+            on_exhausted: BudgetExhausted::Reject,
+        });
+    let refused = factory.refuse_count();
+    match factory.call(None, PortName::new("call").unwrap(), Box::new(|_vm: Arc<JS>| panic!("call must not run")), TaskInfo::from("test call error budget")) {
+        Err(VMFactoryError::BudgetExhausted(name)) => assert_eq!(name, "test_call_error_budget"),
+        r => panic!("call with exhausted budget must be rejected, r: {:?}", r.err()),
+    }
+    assert_eq!(factory.refuse_count(), refused + 1);
+    assert_eq!(factory.in_flight(), 0);
+
+    //调用失败时立即回调失败原因
+    let result = Arc::new(Mutex::new(None));
+    let result_copy = result.clone();
+    factory.call_then(None,
+                      PortName::new("call").unwrap(),
+                      Box::new(|_vm: Arc<JS>| panic!("call must not run")),
+                      TaskInfo::from("test call error then"),
+                      Box::new(move |r: Result<Option<String>, String>| {
+                          *result_copy.lock().unwrap() = Some(r);
+                      }));
+    match result.lock().unwrap().take() {
+        Some(Err(e)) => assert!(e.contains("budget exhausted")),
+        r => panic!("call then must report the rejection, r: {:?}", r),
+    }
+}
+
+#[test]
+fn test_pool_controller() {
+    use pi_vm::api::{PoolController, PoolEvent, PoolAdjustReason};
+
+    register_native_object();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events_copy = events.clone();
+    let factory = VMFactory::new(FactoryName::new("test_pool_controller").unwrap(), 4, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let controller = PoolController::new(factory.clone(), 2, 3)
+        .set_listener(Arc::new(move |event: PoolEvent| {
+            events_copy.lock().unwrap().push(event);
+        }));
+
+    //虚拟机数量小于最小值则增加
+    match controller.adjust() {
+        Some(PoolEvent::Grow(name, 0, 2, PoolAdjustReason::BelowMin)) => assert_eq!(name, "test_pool_controller"),
+        e => panic!("pool must grow to the min size, e: {:?}", e),
+    }
+    assert_eq!(factory.size(), 2);
+    assert!(controller.adjust().is_none()); //已是最小值，不会因空闲减少
+
+    //虚拟机数量大于最大值则减少
+    factory.produce(2).unwrap();
+    match controller.adjust() {
+        Some(PoolEvent::Shrink(_, 4, 3, PoolAdjustReason::AboveMax)) => (),
+        e => panic!("pool must shrink to the max size, e: {:?}", e),
+    }
+
+    //空闲虚拟机数量超过阈值则逐步减少
+    match controller.adjust() {
+        Some(PoolEvent::Shrink(_, 3, 2, PoolAdjustReason::Idle(3))) => (),
+        e => panic!("pool must shrink idle vms, e: {:?}", e),
+    }
+    assert_eq!(factory.size(), 2);
+    assert_eq!(events.lock().unwrap().len(), 3);
+
+    //启动后周期性调整，重复启动被忽略
+    assert!(controller.start());
+    assert!(controller.is_running());
+    assert!(!controller.start());
+    controller.stop();
+    assert!(!controller.is_running());
+}
+
+#[test]
+fn test_pool_autoscale() {
+    use pi_vm::api::{PoolController, PoolEvent, PoolAdjustReason};
+
+    register_native_object();
+
+    //任务被拒绝时增加虚拟机，增加后的冷却期内不会再因负载增加
+    let factory = VMFactory::new(FactoryName::new("test_pool_autoscale_up").unwrap(), 3, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_limits(FactoryLimits {
+            max_total_heap: 0,
+            cpu_per_minute: Some(Duration::from_millis(0)),
+            on_exhausted: BudgetExhausted::Reject,
+        });
+    let controller = PoolController::new(factory.clone(), 0, 3)
+        .set_cooldown(Duration::from_secs(60), Duration::from_secs(60));
+    assert!(factory.call(None, PortName::new("call").unwrap(), Box::new(|_vm: Arc<JS>| 0), TaskInfo::from("test pool autoscale up")).is_err());
+    match controller.adjust() {
+        Some(PoolEvent::Grow(_, 0, 1, PoolAdjustReason::Rejected(1))) => (),
+        e => panic!("pool must grow on rejected calls, e: {:?}", e),
+    }
+    let _vm = factory.try_acquire().unwrap(); //没有空闲虚拟机
+    assert!(factory.call(None, PortName::new("call").unwrap(), Box::new(|_vm: Arc<JS>| 0), TaskInfo::from("test pool autoscale up")).is_err());
+    assert!(controller.adjust().is_none());
+    assert_eq!(factory.size(), 1);
+
+    //调用延迟的99分位超过阈值时增加虚拟机，空闲时减少虚拟机，减少后的冷却期内不会再因空闲减少
+    let factory = VMFactory::new(FactoryName::new("test_pool_autoscale_down").unwrap(), 3, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let controller = PoolController::new(factory.clone(), 0, 3)
+        .set_latency_threshold(Duration::from_millis(100))
+        .set_idle_threshold(0)
+        .set_cooldown(Duration::from_secs(60), Duration::from_secs(60));
+    factory.produce(1).unwrap();
+    let vm = factory.try_acquire().unwrap();
+    factory.record_latency(Duration::from_millis(500));
+    match controller.adjust() {
+        Some(PoolEvent::Grow(_, 1, 2, PoolAdjustReason::Latency(_))) => (),
+        e => panic!("pool must grow on high latency, e: {:?}", e),
+    }
+    drop(vm);
+    match controller.adjust() {
+        Some(PoolEvent::Shrink(_, 2, 1, PoolAdjustReason::Idle(2))) => (),
+        e => panic!("pool must shrink idle vms, e: {:?}", e),
+    }
+    assert!(controller.adjust().is_none());
+    assert_eq!(factory.size(), 1);
+}
+
+#[test]
+fn test_task_priority() {
+    use pi_vm::api::{default_task_priority, set_default_task_priority, adjust_factory_task_priority};
+
+    //全局基础优先级不允许为0
+    assert!(set_default_task_priority(0).is_err());
+    let old = set_default_task_priority(120).unwrap();
+    assert_eq!(default_task_priority(), 120);
+
+    //未设置任务优先级的虚拟机工厂使用全局基础优先级
+    let factory = VMFactory::new(FactoryName::new("test_task_priority").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert_eq!(factory.task_priority(), 120);
+    assert_eq!(set_default_task_priority(old).unwrap(), 120);
+    assert_eq!(factory.task_priority(), old);
+
+    //运行时调整任务优先级，复制的虚拟机工厂共享此优先级
+    let factory = factory.set_task_priority(50);
+    let copy = factory.clone();
+    assert_eq!(copy.task_priority(), 50);
+    assert_eq!(factory.adjust_task_priority(80), 50);
+    assert_eq!(copy.task_priority(), 80);
+    assert_eq!(factory.adjust_task_priority(0), 80); //为0则恢复使用全局基础优先级
+    assert_eq!(copy.task_priority(), default_task_priority());
+
+    //只能按名称调整已注册的虚拟机工厂
+    let name = FactoryName::new("test_task_priority").unwrap();
+    assert!(adjust_factory_task_priority(&name, 30).is_none());
+    assert!(factory.produce(0).is_ok()); //首次生成时注册虚拟机工厂
+    assert_eq!(adjust_factory_task_priority(&name, 30), Some(0));
+    assert_eq!(copy.task_priority(), 30);
+    assert!(remove_factory(&factory));
+}
+
+#[test]
+fn test_source_affinity() {
+    let worker_pool = Box::new(WorkerPool::new("js test".to_string(), WorkerType::Js, 1, 1024 * 1024, 30000, JS_WORKER_WALKER.clone()));
+    worker_pool.run(JS_TASK_POOL.clone());
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test_source_affinity").unwrap(), 2, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(compile_source("test_source_affinity.js", "function id() { return __vm.id; }").unwrap())
+        .set_affinity(1);
+    assert_eq!(factory.affinity_capacity(), 1);
+    assert_eq!(factory.produce(2).unwrap(), 2);
+
+    //同步调用指定源，返回执行调用的虚拟机id，虚拟机在通知调用完成前已被固定或归还
+    let call = |src: usize| -> f64 {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        factory.call_with_result(Some(src),
+                                 PortName::new("id").unwrap(),
+                                 Box::new(|_vm: Arc<JS>| 0),
+                                 TaskInfo::from("test source affinity task"),
+                                 Box::new(move |r: Result<CallValue, String>| {
+                                     let _ = sender.send(r);
+                                 }));
+        match receiver.recv_timeout(Duration::from_millis(30000)).unwrap() {
+            Ok(CallValue::Number(id)) => id,
+            r => panic!("call must return the vm id, r: {:?}", r),
+        }
+    };
+
+    //完成调用的虚拟机被固定给调用源，同一个源的后续调用优先使用固定的虚拟机
+    let first = call(1);
+    assert_eq!(factory.pinned_count(), 1);
+    assert_eq!(call(1), first);
+    assert_eq!(call(1), first);
+
+    //其它源使用未固定的虚拟机，固定的空闲虚拟机过多时驱逐最早固定的虚拟机
+    let second = call(2);
+    assert_ne!(second, first);
+    assert_eq!(factory.pinned_count(), 1);
+    assert_eq!(call(2), second);
+    assert_eq!(factory.size(), 2);
+}
+
+#[test]
+fn test_produce_async() {
+    use pi_vm::pi_vm_impl::ProduceReport;
+
+    let worker_pool = Box::new(WorkerPool::new("js test".to_string(), WorkerType::Js, 2, 1024 * 1024, 30000, JS_WORKER_WALKER.clone()));
+    worker_pool.run(JS_TASK_POOL.clone());
+    register_native_object();
+
+    //所有虚拟机生成完成后回调生成报告，生成的虚拟机已进入虚拟机池
+    let factory = VMFactory::new(FactoryName::new("test_produce_async").unwrap(), 3, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(compile_source("test_produce_async.js", "var a = 1;").unwrap());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    factory.produce_async(3, Box::new(move |report: ProduceReport| {
+        let _ = sender.send(report);
+    }));
+    let report = receiver.recv_timeout(Duration::from_millis(30000)).unwrap();
+    assert!(report.is_ok(), "{:?}", report);
+    assert_eq!(report.factory, "test_produce_async");
+    assert_eq!(report.produced(), 3);
+    let mut ids: Vec<usize> = report.results.iter().map(|r| *r.as_ref().unwrap()).collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 3);
+    assert_eq!(factory.size(), 3);
+    assert!(factory.try_acquire().is_some());
+
+    //生成前失败则报告失败原因，不会生成虚拟机
+    let factory = VMFactory::new(FactoryName::new("test_produce_async_shutdown").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let _drain = factory.shutdown();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    factory.produce_async(1, Box::new(move |report: ProduceReport| {
+        let _ = sender.send(report);
+    }));
+    let report = receiver.recv_timeout(Duration::from_millis(30000)).unwrap();
+    assert!(!report.is_ok());
+    assert!(report.error.is_some());
+    assert!(report.results.is_empty());
+    assert_eq!(factory.size(), 0);
+}
+
+#[test]
+fn test_factory_failover() {
+    use pi_vm::api::{FactoryPair, FailoverEvent};
+
+    register_native_object();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events_copy = events.clone();
+    //主虚拟机工厂的预算已耗尽，所有调用都会被拒绝
+    let primary = VMFactory::new(FactoryName::new("test_factory_failover_primary").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .set_limits(FactoryLimits {
+            max_total_heap: 0,
+            cpu_per_minute: Some(Duration::from_millis(0)),
+            on_exhausted: BudgetExhausted::Reject,
+        });
+    let standby = VMFactory::new(FactoryName::new("test_factory_failover_standby").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    let pair = FactoryPair::new("test_factory_failover", primary, standby)
+        .set_window_size(2)
+        .set_error_threshold(0.5)
+        .set_listener(Arc::new(move |event: FailoverEvent| {
+            events_copy.lock().unwrap().push(event);
+        }));
+    assert_eq!(pair.warm(1).unwrap(), 1);
+    assert_eq!(pair.standby().size(), 1);
+    assert!(pair.active().is_same(pair.primary()));
+
+    //统计窗口内的调用错误率超过阈值，则切换到备用虚拟机工厂
+    assert!(pair.call(None, PortName::new("call").unwrap(), Box::new(|_vm: Arc<JS>| 0), TaskInfo::from("test factory failover")).is_err());
+    assert!(!pair.is_failed_over()); //窗口未结束
+    let result = Arc::new(Mutex::new(None));
+    let result_copy = result.clone();
+    pair.call_then(None,
+                   PortName::new("call").unwrap(),
+                   Box::new(|_vm: Arc<JS>| 0),
+                   TaskInfo::from("test factory failover then"),
+                   Box::new(move |r: Result<Option<String>, String>| {
+                       *result_copy.lock().unwrap() = Some(r);
+                   }));
+    assert!(result.lock().unwrap().take().unwrap().is_err());
+    assert!(pair.is_failed_over());
+    assert!(pair.active().is_same(pair.standby()));
+    assert_eq!(pair.failover_count(), 1);
+
+    //切换后需要手动回切
+    assert!(pair.failback());
+    assert!(!pair.failback());
+    assert!(pair.active().is_same(pair.primary()));
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 2);
+    match &events[0] {
+        FailoverEvent::Failover(name, error_rate, _) => {
+            assert_eq!(name, "test_factory_failover");
+            assert_eq!(*error_rate, 1.0);
+        },
+        e => panic!("first event must be failover, e: {:?}", e),
+    }
+    match &events[1] {
+        FailoverEvent::Failback(name) => assert_eq!(name, "test_factory_failover"),
+        e => panic!("second event must be failback, e: {:?}", e),
+    }
+}
+
+#[test]
+fn test_vm_recycle() {
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test_vm_recycle").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert_eq!(factory.produce(1).unwrap(), 1);
+
+    //脚本请求回收当前虚拟机，重复请求被忽略
+    let vm = factory.try_acquire().unwrap();
+    assert!(vm.recycle_requested().is_none());
+    assert_eq!(CallValue::from(&*vm.eval("__pi_vm.recycle('leak')".to_string())), CallValue::Boolean(true));
+    assert_eq!(CallValue::from(&*vm.eval("__pi_vm.recycle('again')".to_string())), CallValue::Boolean(false));
+    assert_eq!(vm.recycle_requested(), Some("leak".to_string()));
+
+    //请求回收的虚拟机归还时被丢弃，不会再被复用
+    drop(vm);
+    assert_eq!(factory.size(), 0);
+    assert_eq!(factory.checked_out(), 0);
+    assert!(factory.try_acquire().is_none());
+
+    //未请求回收的虚拟机归还后复用
+    assert_eq!(factory.produce(1).unwrap(), 1);
+    drop(factory.try_acquire().unwrap());
+    assert_eq!(factory.size(), 1);
+    assert!(factory.try_acquire().unwrap().recycle_requested().is_none());
+}
+
+#[test]
+fn test_call_sync() {
+    use pi_vm::pi_vm_impl::CallError;
+
+    let worker_pool = Box::new(WorkerPool::new("js test".to_string(), WorkerType::Js, 1, 1024 * 1024, 30000, JS_WORKER_WALKER.clone()));
+    worker_pool.run(JS_TASK_POOL.clone());
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test_call_sync").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(compile_source("test_call_sync.js", "function add(x, y) { return x + y; } function fail() { throw new Error('fail'); }").unwrap());
+    assert_eq!(factory.produce(1).unwrap(), 1);
+
+    //阻塞等待调用完成，并返回js函数的返回值
+    let r = factory.call_sync(PortName::new("add").unwrap(), Box::new(|vm: Arc<JS>| {
+        vm.new_u32(1);
+        vm.new_u32(2);
+        2
+    }), Duration::from_millis(30000));
+    assert_eq!(r.unwrap(), CallValue::Number(3.0));
+
+    //调用执行异常则返回异常信息
+    match factory.call_sync(PortName::new("fail").unwrap(), Box::new(|_vm: Arc<JS>| 0), Duration::from_millis(30000)) {
+        Err(CallError::Failed(e)) => assert!(e.contains("fail")),
+        r => panic!("failed call must return the error, r: {:?}", r),
+    }
+
+    //调用未被接收则立即返回，不会等待
+    let _drain = factory.shutdown();
+    match factory.call_sync(PortName::new("add").unwrap(), Box::new(|_vm: Arc<JS>| 0), Duration::from_millis(30000)) {
+        Err(CallError::Call(VMFactoryError::Shutdown(_))) => (),
+        r => panic!("call after shutdown must be rejected, r: {:?}", r),
+    }
+}
+
+lazy_static! {
+    //分片回调执行完最后一个分片时记录的所有分片
+    static ref SLICED_CALLBACK_RESULT: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+}
+
+#[test]
+fn test_push_callback_sliced() {
+    use pi_vm::api::push_callback_sliced;
+
+    let worker_pool = Box::new(WorkerPool::new("js test".to_string(), WorkerType::Js, 1, 1024 * 1024, 30000, JS_WORKER_WALKER.clone()));
+    worker_pool.run(JS_TASK_POOL.clone());
+    register_native_object();
+    register_native_function(0x766, js_test_push_callback_sliced);
+
+    let js = JS::new(4, Atom::from("test push callback sliced"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let index = js.eval("var slices = []; callbacks.register(function(buf, offset, total, last) { slices.push(offset + ':' + buf.byteLength + ':' + total + ':' + last); if(last) { NativeObject.call(0x766, [slices.join(',')]); } });".to_string()).get_u32();
+
+    //每个分片作为一个独立的任务按序执行回调函数
+    let payload = Arc::new((0..10).collect::<Vec<u8>>());
+    assert!(push_callback_sliced(js.clone(), index, payload, 4, TaskInfo::from("test push callback sliced")).is_some());
+    let start = Instant::now();
+    loop {
+        if let Some(slices) = SLICED_CALLBACK_RESULT.lock().unwrap().take() {
+            assert_eq!(slices, "0:4:10:false,4:4:10:false,8:2:10:true");
+            break;
+        }
+        assert!(start.elapsed() < Duration::from_millis(30000), "sliced callback timeout");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn js_test_push_callback_sliced(_js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
+    *SLICED_CALLBACK_RESULT.lock().unwrap() = Some(args[0].get_str());
+    Some(CallResult::Ok)
+}
+
+#[test]
+fn test_source_task_priority() {
+    use pi_vm::pi_vm_impl::{new_queue_with_priority, remove_queue};
+
+    let worker_pool = Box::new(WorkerPool::new("js test".to_string(), WorkerType::Js, 1, 1024 * 1024, 30000, JS_WORKER_WALKER.clone()));
+    worker_pool.run(JS_TASK_POOL.clone());
+    register_native_object();
+
+    //源的同步任务队列只在首次创建时设置优先级
+    let queue = new_queue_with_priority(7670001, 7);
+    assert_eq!(new_queue_with_priority(7670001, 70), queue);
+    assert_eq!(remove_queue(7670001), Some(queue));
+    assert!(remove_queue(7670001).is_none());
+
+    //指定源的调用使用虚拟机工厂的任务优先级，调用指定的任务优先级优先
+    let factory = VMFactory::new(FactoryName::new("test_source_task_priority").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(compile_source("test_source_task_priority.js", "function priority() { return __pi_vm.setTaskPriority(1000); }").unwrap())
+        .set_task_priority(50);
+    assert_eq!(factory.produce(1).unwrap(), 1);
+    let call = |src: usize, info: TaskInfo| {
+        let receiver = factory.call_result(Some(src), PortName::new("priority").unwrap(), Box::new(|_vm: Arc<JS>| 0), info);
+        receiver.recv_timeout(Duration::from_millis(30000)).unwrap()
+    };
+    assert_eq!(call(7670002, TaskInfo::from("test source task priority")), Ok(CallValue::Number(50.0)));
+    assert_eq!(call(7670003, TaskInfo::from("test source task priority").with_priority(30)), Ok(CallValue::Number(30.0)));
+    remove_queue(7670002);
+    remove_queue(7670003);
+}
+
+#[test]
+fn test_factory_call_stats() {
+    let worker_pool = Box::new(WorkerPool::new("js test".to_string(), WorkerType::Js, 1, 1024 * 1024, 30000, JS_WORKER_WALKER.clone()));
+    worker_pool.run(JS_TASK_POOL.clone());
+    register_native_object();
+
+    let factory = VMFactory::new(FactoryName::new("test_factory_call_stats").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(compile_source("test_factory_call_stats.js", "function add(x, y) { return x + y; } function fail() { throw new Error('fail'); }").unwrap());
+    assert_eq!(factory.produce(1).unwrap(), 1);
+    let stats = factory.stats();
+    assert_eq!(stats.total_calls, 0);
+    assert_eq!(stats.in_flight, 0);
+    assert!(stats.port_calls.is_empty());
+
+    //每个开始执行的调用都会被统计，包括执行异常的调用
+    assert!(factory.call_sync(PortName::new("add").unwrap(), Box::new(|_vm: Arc<JS>| 0), Duration::from_millis(30000)).is_ok());
+    assert!(factory.call_sync(PortName::new("add").unwrap(), Box::new(|_vm: Arc<JS>| 0), Duration::from_millis(30000)).is_ok());
+    assert!(factory.call_sync(PortName::new("fail").unwrap(), Box::new(|_vm: Arc<JS>| 0), Duration::from_millis(30000)).is_err());
+    let start = Instant::now();
+    while factory.in_flight() > 0 {
+        //调用在通知完成后才会完成
+        assert!(start.elapsed() < Duration::from_millis(30000), "call complete timeout");
+        thread::sleep(Duration::from_millis(1));
+    }
+    let stats = factory.stats();
+    assert_eq!(stats.name, "test_factory_call_stats");
+    assert_eq!(stats.size, 1);
+    assert_eq!(stats.total_calls, 3);
+    assert_eq!(stats.in_flight, 0);
+    assert_eq!(stats.port_calls.get("add"), Some(&2));
+    assert_eq!(stats.port_calls.get("fail"), Some(&1));
+    assert_eq!(stats.latency.count, 3);
+    assert_eq!(stats.new_vm_failed, 0);
+
+    //构建虚拟机失败的调用不会开始执行
+    let factory = VMFactory::new(FactoryName::new("test_factory_call_stats_failed").unwrap(), 0, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(vec![0xff, 0x00]));
+    assert!(factory.call(None, PortName::new("call").unwrap(), Box::new(|_vm: Arc<JS>| 0), TaskInfo::from("test factory call stats")).is_err());
+    let stats = factory.stats();
+    assert_eq!(stats.new_vm_failed, 1);
+    assert_eq!(stats.total_calls, 0);
+    assert_eq!(stats.in_flight, 0);
+}
+
+#[test]
+fn test_resume_block() {
+    let worker_pool = Box::new(WorkerPool::new("js test".to_string(), WorkerType::Js, 1, 1024 * 1024, 30000, JS_WORKER_WALKER.clone()));
+    worker_pool.run(JS_TASK_POOL.clone());
+    register_native_object();
+    register_native_function(0x777, js_test_resume_block_reply);
+    register_native_function(0x778, js_test_resume_block_throw);
+
+    //未被同步任务阻塞的虚拟机不会被唤醒，结果构建函数被返回给调用者
+    let js = JS::new(5, Atom::from("test resume block"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let pushed = Arc::new(AtomicUsize::new(0));
+    let pushed_copy = pushed.clone();
+    assert!(JS::resume_block(js.clone(), 0, false, move |_vm: Arc<JS>| {
+        pushed_copy.fetch_add(1, Ordering::SeqCst);
+    }).is_err());
+    assert_eq!(pushed.load(Ordering::SeqCst), 0);
+    assert_eq!(js.status(), Some(JSStatus::NoTask));
+    assert_eq!(js.eval("1 + 1".to_string()).get_u32(), 2);
+
+    //阻塞调用的结果或异常在唤醒虚拟机后返回给同步执行的js代码
+    let factory = VMFactory::new(FactoryName::new("test_resume_block").unwrap(), 1, 0, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(compile_source("test_resume_block.js", "function reply() { NativeObject.call(0x777, []); return __thread_yield() + 1; } function fail() { NativeObject.call(0x778, []); return __thread_yield(); }").unwrap());
+    assert_eq!(factory.produce(1).unwrap(), 1);
+    let r = factory.call_sync(PortName::new("reply").unwrap(), Box::new(|_vm: Arc<JS>| 0), Duration::from_millis(30000));
+    assert_eq!(r.unwrap(), CallValue::Number(8.0));
+    assert!(factory.call_sync(PortName::new("fail").unwrap(), Box::new(|_vm: Arc<JS>| 0), Duration::from_millis(30000)).is_err());
+    assert!(factory.call_sync(PortName::new("reply").unwrap(), Box::new(|_vm: Arc<JS>| 0), Duration::from_millis(30000)).is_ok());
+}
+
+fn js_test_resume_block_reply(js: Arc<JS>, _args: Vec<JSType>) -> Option<CallResult> {
+    block_reply(js, Box::new(|vm: Arc<JS>| {
+        vm.new_u32(7);
+    }), TaskInfo::from("test resume block reply"));
+    None
+}
+
+fn js_test_resume_block_throw(js: Arc<JS>, _args: Vec<JSType>) -> Option<CallResult> {
+    block_throw(js, "test resume block throw".to_string(), TaskInfo::from("test resume block throw"));
+    None
+}
+
+#[test]
+fn test_pinned_strings() {
+    use pi_vm::api::{set_pinned_strings, pinned_strings};
+
+    //构建虚拟机后不允许设置共享的只读字符串表，未启用pinnedstrings特性构建时总是拒绝设置
+    let _js = JS::new(6, Atom::from("test pinned strings"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    let e = set_pinned_strings(&["name", "value"]).unwrap_err();
+    if cfg!(feature = "pinnedstrings") {
+        assert!(e.contains("vm already created"));
+    } else {
+        assert!(e.contains("pinnedstrings feature disabled"));
+        assert!(pinned_strings().is_empty());
+    }
+}